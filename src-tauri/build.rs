@@ -1,5 +1,30 @@
 fn main() {
     // Read value from `.env` file (compile time)
     dotenv_build::output(dotenv_build::Config::default()).expect("Error reading from envars file at compile time");
+
+    // Embed the git commit when building from a checkout (reported by `get_app_info`)
+    if let Some(hash) = git(&["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env=FLEET_BUILD_HASH={}", hash);
+
+        // Rebuild when HEAD moves to another branch or the current branch gets a new commit
+        for reference in ["HEAD".to_string()]
+            .into_iter()
+            .chain(git(&["symbolic-ref", "-q", "HEAD"]))
+        {
+            if let Some(path) = git(&["rev-parse", "--git-path", &reference]) {
+                println!("cargo:rerun-if-changed={}", path);
+            }
+        }
+    }
+
     tauri_build::build()
 }
+
+/// Trimmed output of a successful git command
+fn git(args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("git").args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -4,20 +4,44 @@ use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
-use super::provider::{AIProvider, ChatMessage as ProviderChatMessage, ChatRequest, Tool, ToolParameters};
+use tauri_plugin_log::log::warn;
+
+use super::component_ids::{
+    describe_duplicates, find_duplicates, namespace_duplicates, record_declarations, ComponentIdPolicy,
+    SurfaceComponents,
+};
+use super::conversation::{apply_event, ConversationEvent, ConversationState, StateTransition};
+use super::output_style::{apply_rewrites, OutputStyle, StyleEnforcement, StyleWarning};
+use super::provider::{
+    AIProvider, ChatMessage as ProviderChatMessage, ChatRequest, ChatResponse, ProviderError, Tool,
+    ToolCall as ProviderToolCall, ToolParameters,
+};
+use super::references::{find_cycle, render_after_components, undefined_roots};
 use super::schema::*;
+use super::text;
+use crate::logging::env_flag;
+use crate::recent_requests::{RequestSource, RECENT_REQUESTS};
+use crate::session_limit::SessionLimit;
+use crate::session_store::SessionStore;
 
 pub struct A2UIAgent {
     pub client: Client,
     pub provider: Arc<dyn AIProvider>,
+    /// Provider given one final attempt when `provider` keeps producing invalid UI
+    pub fallback_provider: Option<Arc<dyn AIProvider>>,
     pub sessions: Arc<RwLock<HashMap<String, A2UISession>>>,
     pub tools: Vec<A2UITool>,
     pub schema_validator: JSONSchema,
     pub templates: A2UITemplates,
+    pub config: A2UIConfig,
+    /// Where sessions are saved on every change; `None` keeps them in memory only
+    pub session_store: Option<SessionStore>,
 }
 
 impl std::fmt::Debug for A2UIAgent {
@@ -25,14 +49,122 @@ impl std::fmt::Debug for A2UIAgent {
         f.debug_struct("A2UIAgent")
             .field("client", &self.client)
             .field("provider", &"<AIProvider>")
+            .field(
+                "fallback_provider",
+                &self.fallback_provider.as_ref().map(|_| "<AIProvider>"),
+            )
             .field("sessions", &self.sessions)
             .field("tools", &self.tools)
             .field("schema_validator", &self.schema_validator)
             .field("templates", &self.templates)
+            .field("config", &self.config)
+            .field("session_store", &self.session_store)
             .finish()
     }
 }
 
+/// Deployment-level configuration for the A2UI agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct A2UIConfig {
+    /// Names of the built-in tools to expose; `None` enables all of them
+    pub enabled_tools: Option<Vec<String>>,
+    /// Collapse blank lines and trailing whitespace in text-mode responses
+    pub normalize_text: bool,
+    /// Render text-mode responses as plain text instead of markdown (implies `normalize_text`)
+    pub strip_markdown: bool,
+    /// Deployment-specific guidance (tone, allowed topics, branding) placed before the built-in instructions
+    pub system_preamble: Option<String>,
+    /// How to handle component ids reused across surfaces or within a response
+    pub component_ids: ComponentIdPolicy,
+    /// Cap on live sessions and what happens when it is reached
+    pub session_limit: SessionLimit,
+    /// Extra attempts the provider gets when its UI fails to parse or validate, each told
+    /// what was wrong with the previous one
+    pub max_ui_retries: usize,
+    /// Seconds a single provider call may take; `None` uses `DEFAULT_PROVIDER_TIMEOUT_SECS`
+    pub provider_timeout_secs: Option<u64>,
+    /// Language and style the copy of generated Text components should follow
+    pub output_style: OutputStyle,
+    /// Send each surface's `beginRendering` after its `surfaceUpdate`s, for renderers
+    /// that don't wait for the whole response
+    pub components_before_render: bool,
+}
+
+impl Default for A2UIConfig {
+    fn default() -> Self {
+        Self {
+            enabled_tools: None,
+            normalize_text: false,
+            strip_markdown: false,
+            system_preamble: None,
+            component_ids: ComponentIdPolicy::default(),
+            session_limit: SessionLimit::default(),
+            max_ui_retries: DEFAULT_MAX_UI_RETRIES,
+            provider_timeout_secs: None,
+            output_style: OutputStyle::default(),
+            components_before_render: false,
+        }
+    }
+}
+
+impl A2UIConfig {
+    /// Read configuration from the environment
+    ///
+    /// - `A2UI_ENABLED_TOOLS`: comma-separated tool names
+    /// - `A2UI_NORMALIZE_TEXT`, `A2UI_STRIP_MARKDOWN`: `1`/`true` to enable
+    /// - `A2UI_SYSTEM_PREAMBLE`: text prepended to every prompt
+    /// - `A2UI_COMPONENT_IDS`: `off`, `reject` or `namespace` for colliding component ids
+    /// - `A2UI_MAX_SESSIONS`, `A2UI_SESSION_LIMIT_POLICY`: session cap and `evict`/`reject`
+    /// - `A2UI_MAX_UI_RETRIES`: extra attempts after invalid UI (default 2)
+    /// - `A2UI_PROVIDER_TIMEOUT_SECS`: per-call provider timeout (default 60)
+    /// - `A2UI_OUTPUT_LANGUAGE`, `A2UI_PLAIN_TEXT_COPY`, `A2UI_OUTPUT_STYLE`: see `OutputStyle::from_env`
+    /// - `A2UI_COMPONENTS_BEFORE_RENDER`: `1`/`true` to reorder `beginRendering` after its components
+    pub fn from_env() -> Self {
+        let enabled_tools = std::env::var("A2UI_ENABLED_TOOLS").ok().map(|value| {
+            value
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect()
+        });
+
+        Self {
+            enabled_tools,
+            normalize_text: env_flag("A2UI_NORMALIZE_TEXT").unwrap_or(false),
+            strip_markdown: env_flag("A2UI_STRIP_MARKDOWN").unwrap_or(false),
+            system_preamble: std::env::var("A2UI_SYSTEM_PREAMBLE")
+                .ok()
+                .filter(|preamble| !preamble.trim().is_empty()),
+            component_ids: std::env::var("A2UI_COMPONENT_IDS")
+                .ok()
+                .and_then(|value| ComponentIdPolicy::parse(&value))
+                .unwrap_or_default(),
+            session_limit: SessionLimit::from_env("A2UI"),
+            max_ui_retries: std::env::var("A2UI_MAX_UI_RETRIES")
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(DEFAULT_MAX_UI_RETRIES),
+            provider_timeout_secs: std::env::var("A2UI_PROVIDER_TIMEOUT_SECS")
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .filter(|secs| *secs > 0),
+            output_style: OutputStyle::from_env(),
+            components_before_render: env_flag("A2UI_COMPONENTS_BEFORE_RENDER").unwrap_or(false),
+        }
+    }
+
+    pub fn provider_timeout(&self) -> Duration {
+        Duration::from_secs(self.provider_timeout_secs.unwrap_or(DEFAULT_PROVIDER_TIMEOUT_SECS))
+    }
+
+    pub fn is_tool_enabled(&self, tool_name: &str) -> bool {
+        self.enabled_tools
+            .as_ref()
+            .map_or(true, |enabled| enabled.iter().any(|name| name == tool_name))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct A2UISession {
     pub id: String,
@@ -42,6 +174,9 @@ pub struct A2UISession {
     pub context: A2UIContext,
     pub tools_used: Vec<String>,
     pub base_url: String,
+    /// Component ids generated so far on each surface
+    #[serde(default)]
+    pub surface_components: SurfaceComponents,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,15 +186,16 @@ pub struct A2UIContext {
     pub session_state: HashMap<String, String>,
     pub conversation_state: ConversationState,
     pub last_tool_call: Option<String>,
+    /// Recent conversation state transitions, oldest first
+    #[serde(default)]
+    pub transitions: Vec<StateTransition>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ConversationState {
-    Initial,
-    ToolCalling,
-    ResponseGeneration,
-    Validation,
-    Complete,
+impl A2UIContext {
+    /// Move the conversation state on `event`, recording the transition; returns whether it applied
+    pub fn apply_event(&mut self, event: ConversationEvent) -> bool {
+        apply_event(&mut self.conversation_state, &mut self.transitions, event)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -97,6 +233,54 @@ pub struct A2UITemplates {
     pub no_results_template: String,
 }
 
+impl A2UITemplates {
+    /// The template registered under `name`, e.g. `contact_list`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let template = match name {
+            "contact_list" => &self.contact_list_template,
+            "contact_card" => &self.contact_card_template,
+            "action_confirmation" => &self.action_confirmation_template,
+            "search_results" => &self.search_results_template,
+            "no_results" => &self.no_results_template,
+            _ => return None,
+        };
+        Some(template)
+    }
+}
+
+/// The template and data presenting a tool's result, for tools whose output maps onto one
+fn template_for_tool(tool_name: &str, data: &serde_json::Value) -> Option<(&'static str, serde_json::Value)> {
+    match tool_name {
+        "get_contact_info" | "create_contact_list" => {
+            let contacts = data.get("contacts")?.as_array()?;
+            // An empty list reads better as a model-written "nothing found" reply
+            if contacts.is_empty() {
+                return None;
+            }
+            let prompt = match data.get("searchTerm").and_then(|term| term.as_str()) {
+                Some(term) => format!("Results for \"{}\"", term),
+                None => data.get("title")?.as_str()?.to_string(),
+            };
+            Some((
+                "contact_list",
+                serde_json::json!({"contacts": contacts, "searchPrompt": prompt}),
+            ))
+        }
+        "display_search_results" => {
+            let results = data.get("results")?.as_array()?;
+            let query = data
+                .get("searchQuery")
+                .and_then(|query| query.as_str())
+                .unwrap_or_default();
+            Some((
+                "search_results",
+                serde_json::json!({"results": results, "searchInfo": format!("Results for \"{}\"", query)}),
+            ))
+        }
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct CreateSessionRequest {
@@ -133,6 +317,46 @@ pub struct ToolResult {
 pub struct GeneratedResponse {
     pub content: String,
     pub a2ui_messages: Vec<A2UIMessageResponse>,
+    /// Messages from the model that could not be converted and were skipped
+    #[serde(default)]
+    pub conversion_warnings: Vec<ConversionWarning>,
+    /// Text components whose copy doesn't match the configured output style
+    #[serde(default)]
+    pub style_warnings: Vec<StyleWarning>,
+    /// Tool calls that failed while producing this response
+    #[serde(default)]
+    pub tool_errors: Vec<ToolError>,
+    /// Provider whose reply produced this response; `None` for canned replies
+    #[serde(default)]
+    pub provider: Option<String>,
+}
+
+/// A user message re-run by `A2UIAgent::replay_session`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayedTurn {
+    /// Position of the user message in the session's `messages`
+    pub index: usize,
+    pub message: String,
+    /// Assistant reply stored for the message when it was first handled
+    pub original: Option<String>,
+    /// Reply generated by the replay; `None` when the turn failed
+    pub response: Option<GeneratedResponse>,
+    pub error: Option<String>,
+}
+
+/// A tool call that failed; its error was passed back to the model instead of aborting the turn
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolError {
+    pub tool: String,
+    pub error: String,
+}
+
+/// A model-generated A2UI message that was skipped during conversion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversionWarning {
+    /// Position of the message in the model's A2UI_MESSAGES array
+    pub index: usize,
+    pub error: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -162,24 +386,165 @@ pub enum A2UIAgentError {
     #[error("Template error: {0}")]
     TemplateError(String),
     #[error("AI Provider error: {0}")]
-    ProviderError(#[from] super::provider::ProviderError),
+    ProviderError(#[from] ProviderError),
     #[error("Message parsing error: {0}")]
     MessageError(String),
     #[error("Validation error: {0}")]
     ValidationError(String),
+    #[error("Cyclic component reference: {0}")]
+    CyclicReference(String),
     #[error("JSON serialization error: {0}")]
     JsonError(#[from] serde_json::Error),
     #[error("HTTP client error: {0}")]
     HttpClientError(#[from] reqwest::Error),
+    #[error("Follow-up limit reached after {0} consecutive follow-ups")]
+    FollowupDepthExceeded(usize),
+    #[error("No final response after {0} tool-calling turns")]
+    ToolIterationsExceeded(usize),
+    #[error("Session limit of {0} reached")]
+    SessionLimitExceeded(usize),
+}
+
+/// Maximum consecutive follow-up turns triggered from the UI before a user message is required
+pub const MAX_FOLLOWUP_DEPTH: usize = 3;
+
+/// Session state key counting consecutive follow-up turns
+const FOLLOWUP_DEPTH_KEY: &str = "followup_depth";
+
+/// Maximum model turns in one generation, so tools calling each other can't loop forever
+pub const MAX_TOOL_ITERATIONS: usize = 4;
+
+/// Sampling temperature of regular turns
+const CHAT_TEMPERATURE: f32 = 0.7;
+
+/// Sampling temperature of replayed turns, so replays differ as little as the provider allows
+const REPLAY_TEMPERATURE: f32 = 0.0;
+
+/// Seconds a provider call may take when `A2UIConfig::provider_timeout_secs` is unset
+pub const DEFAULT_PROVIDER_TIMEOUT_SECS: u64 = 60;
+
+/// Retries after invalid UI when `A2UI_MAX_UI_RETRIES` is unset; one usually fixes a bad component
+pub const DEFAULT_MAX_UI_RETRIES: usize = 2;
+
+/// Prefix of the JSON array of A2UI messages in a model response
+const A2UI_MESSAGES_MARKER: &str = "A2UI_MESSAGES:";
+
+/// Attach the turn's tool failures to a response, falling back to an apology when the
+/// response is unusable and every tool call failed
+fn with_tool_errors(
+    response: Result<GeneratedResponse, A2UIAgentError>,
+    tool_errors: Vec<ToolError>,
+    any_tool_succeeded: bool,
+) -> Result<GeneratedResponse, A2UIAgentError> {
+    match response {
+        Ok(response) => Ok(GeneratedResponse {
+            tool_errors,
+            ..response
+        }),
+        Err(e) if !tool_errors.is_empty() && !any_tool_succeeded => {
+            warn!("Every tool call failed and the response was unusable: {}", e);
+            Ok(GeneratedResponse {
+                content: tool_failure_apology(&tool_errors),
+                a2ui_messages: Vec::new(),
+                conversion_warnings: Vec::new(),
+                style_warnings: Vec::new(),
+                tool_errors,
+                provider: None,
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Record a UI reply and the outcome of validating it
+fn record_validation<T>(events: &mut Vec<ConversationEvent>, result: &Result<T, A2UIAgentError>) {
+    events.push(ConversationEvent::ReplyReceived);
+    match result {
+        Ok(_) => events.push(ConversationEvent::ValidationPassed),
+        Err(e) if e.is_invalid_ui() => events.push(ConversationEvent::ValidationFailed),
+        Err(_) => {}
+    }
+}
+
+/// Text reply for a turn where every tool call failed, naming each failure
+fn tool_failure_apology(tool_errors: &[ToolError]) -> String {
+    let failures: Vec<String> = tool_errors
+        .iter()
+        .map(|failure| format!("{} ({})", failure.tool, failure.error))
+        .collect();
+    format!(
+        "Sorry, I couldn't complete that request because these tools failed: {}",
+        failures.join(", ")
+    )
+}
+
+/// The model's tool-calling turn and the result of each call, to continue the conversation with
+///
+/// Results go back as the provider's own tool messages, matched to their calls by id.
+fn tool_turn_messages(
+    content: &str,
+    tool_calls: &[ProviderToolCall],
+    results: &[(String, ToolResult)],
+) -> Vec<ProviderChatMessage> {
+    let mut messages = vec![ProviderChatMessage::assistant_tool_calls(content, tool_calls.to_vec())];
+    for (call, (_, result)) in tool_calls.iter().zip(results) {
+        let outcome = match (&result.data, &result.error) {
+            (_, Some(error)) => serde_json::json!({ "error": error }),
+            (Some(data), None) => data.clone(),
+            (None, None) => serde_json::json!({ "status": "done" }),
+        };
+        messages.push(ProviderChatMessage::tool_result(call.id.clone(), outcome.to_string()));
+    }
+    messages.push(ProviderChatMessage::user(
+        "Use these tool results to write your final response, with any A2UI messages prefixed with 'A2UI_MESSAGES:'.",
+    ));
+    messages
+}
+
+/// The model's invalid reply and what was wrong with it, asking for a corrected one
+fn ui_retry_messages(content: &str, error: &A2UIAgentError) -> Vec<ProviderChatMessage> {
+    vec![
+        ProviderChatMessage::assistant(content),
+        ProviderChatMessage::user(format!(
+            "Your A2UI messages were rejected: {}\n\n\
+            Reply again with your complete response, fixing this so every message follows the A2UI schema, \
+            with the A2UI messages prefixed with 'A2UI_MESSAGES:'.",
+            error
+        )),
+    ]
+}
+
+impl A2UIAgentError {
+    /// Whether the model replied but its UI couldn't be parsed or failed validation
+    pub fn is_invalid_ui(&self) -> bool {
+        matches!(
+            self,
+            A2UIAgentError::MessageError(_)
+                | A2UIAgentError::ValidationError(_)
+                | A2UIAgentError::CyclicReference(_)
+                | A2UIAgentError::JsonError(_)
+        )
+    }
+
+    /// The provider's requested retry delay when this error is a rate limit
+    pub fn retry_after_ms(&self) -> Option<u64> {
+        match self {
+            A2UIAgentError::ProviderError(e) => e.retry_after_ms(),
+            _ => None,
+        }
+    }
 }
 
 impl A2UIAgent {
     pub fn new(provider: Arc<dyn AIProvider>) -> Result<Self, A2UIAgentError> {
+        Self::with_config(provider, A2UIConfig::default())
+    }
+
+    pub fn with_config(provider: Arc<dyn AIProvider>, config: A2UIConfig) -> Result<Self, A2UIAgentError> {
         let client = Client::new();
 
         // Load A2UI schema for validation
-        let schema_json = include_str!("schema.json");
-        let schema_value: serde_json::Value = serde_json::from_str(schema_json)?;
+        let schema_value = a2ui_schema()?;
         let schema_validator = JSONSchema::compile(&schema_value)
             .map_err(|e| A2UIAgentError::TemplateError(format!("Schema compilation error: {}", e)))?;
 
@@ -261,13 +626,65 @@ impl A2UIAgent {
         Ok(A2UIAgent {
             client,
             provider,
+            fallback_provider: None,
             sessions: Arc::new(RwLock::new(HashMap::new())),
             tools,
             schema_validator,
             templates,
+            config,
+            session_store: None,
         })
     }
 
+    /// Give `provider` one final attempt when the primary provider's UI stays invalid
+    pub fn with_fallback_provider(mut self, provider: Arc<dyn AIProvider>) -> Self {
+        self.fallback_provider = Some(provider);
+        self
+    }
+
+    /// Load the sessions saved in `store`, within the session limit, and save every
+    /// session change to it
+    pub fn with_session_store(mut self, store: SessionStore) -> Self {
+        let mut sessions = store.load_all();
+        self.config
+            .session_limit
+            .trim(&mut sessions, |session: &A2UISession| session.updated_at, Utc::now());
+        self.sessions = Arc::new(RwLock::new(sessions));
+        self.session_store = Some(store);
+        self
+    }
+
+    fn persist(&self, session: &A2UISession) {
+        if let Some(store) = &self.session_store {
+            store.save(&session.id, session);
+        }
+    }
+
+    /// Make room for one more session after expiring idle ones
+    ///
+    /// Only the in-memory sessions are dropped; their saved history stays in the store.
+    fn make_room(&self, sessions: &mut HashMap<String, A2UISession>) -> Result<(), A2UIAgentError> {
+        let limit = &self.config.session_limit;
+        limit.expire_idle(sessions, |session| session.updated_at, Utc::now());
+        limit
+            .make_room(sessions, |session| session.updated_at)
+            .map_err(A2UIAgentError::SessionLimitExceeded)?;
+        Ok(())
+    }
+
+    /// Drop sessions idle for longer than the configured TTL from memory, returning their ids
+    pub async fn expire_idle_sessions(&self) -> Vec<String> {
+        let mut sessions = self.sessions.write().await;
+        self.config
+            .session_limit
+            .expire_idle(&mut sessions, |session| session.updated_at, Utc::now())
+    }
+
+    /// Built-in tools that are enabled by the current configuration
+    fn enabled_tools(&self) -> impl Iterator<Item = &A2UITool> {
+        self.tools.iter().filter(|tool| self.config.is_tool_enabled(&tool.name))
+    }
+
     pub async fn create_session(&self, request: CreateSessionRequest) -> Result<String, A2UIAgentError> {
         let session_id = Uuid::new_v4().to_string();
         self.create_session_with_id(&session_id, request).await?;
@@ -292,12 +709,18 @@ impl A2UIAgent {
                 session_state: request.initial_context.unwrap_or_default(),
                 conversation_state: ConversationState::Initial,
                 last_tool_call: None,
+                transitions: Vec::new(),
             },
             tools_used: Vec::new(),
             base_url: request.base_url.unwrap_or_else(|| "http://localhost:1420".to_string()),
+            surface_components: SurfaceComponents::new(),
         };
 
         let mut sessions = self.sessions.write().await;
+        if !sessions.contains_key(session_id) {
+            self.make_room(&mut sessions)?;
+        }
+        self.persist(&session);
         sessions.insert(session_id.to_string(), session);
 
         Ok(())
@@ -316,9 +739,35 @@ impl A2UIAgent {
         sessions
             .remove(session_id)
             .ok_or_else(|| A2UIAgentError::SessionNotFound(session_id.to_string()))?;
+        if let Some(store) = &self.session_store {
+            store.delete(session_id);
+        }
         Ok(())
     }
 
+    /// Copy an existing session (messages and context) into a new, independent session
+    pub async fn fork_session(&self, session_id: &str) -> Result<String, A2UIAgentError> {
+        let mut sessions = self.sessions.write().await;
+        let source = sessions
+            .get(session_id)
+            .ok_or_else(|| A2UIAgentError::SessionNotFound(session_id.to_string()))?;
+
+        let fork_id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let fork = A2UISession {
+            id: fork_id.clone(),
+            created_at: now,
+            updated_at: now,
+            ..source.clone()
+        };
+
+        // The source may be evicted to make room; it has already been copied
+        self.make_room(&mut sessions)?;
+        self.persist(&fork);
+        sessions.insert(fork_id.clone(), fork);
+        Ok(fork_id)
+    }
+
     pub async fn list_sessions(&self) -> Result<Vec<String>, A2UIAgentError> {
         let sessions = self.sessions.read().await;
         Ok(sessions.keys().cloned().collect())
@@ -330,7 +779,32 @@ impl A2UIAgent {
         message: &str,
         use_ui: bool,
     ) -> Result<GeneratedResponse, A2UIAgentError> {
-        // Auto-create session if it doesn't exist
+        self.handle_message_cancellable(session_id, message, use_ui, &CancellationToken::new())
+            .await
+    }
+
+    /// `handle_message`, abandoning the provider call in flight once `cancel` fires
+    pub async fn handle_message_cancellable(
+        &self,
+        session_id: &str,
+        message: &str,
+        use_ui: bool,
+        cancel: &CancellationToken,
+    ) -> Result<GeneratedResponse, A2UIAgentError> {
+        self.ensure_session(session_id).await?;
+
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| A2UIAgentError::SessionNotFound(session_id.to_string()))?;
+        // Saved even when the turn fails, since the user's message was recorded
+        let response = self.run_turn(session, message, use_ui, CHAT_TEMPERATURE, cancel).await;
+        self.persist(session);
+        response
+    }
+
+    /// Auto-create `session_id` if it doesn't exist
+    async fn ensure_session(&self, session_id: &str) -> Result<(), A2UIAgentError> {
         if !self.sessions.read().await.contains_key(session_id) {
             self.create_session_with_id(
                 session_id,
@@ -343,11 +817,79 @@ impl A2UIAgent {
             )
             .await?;
         }
+        Ok(())
+    }
 
-        let mut sessions = self.sessions.write().await;
-        let session = sessions
-            .get_mut(session_id)
-            .ok_or_else(|| A2UIAgentError::SessionNotFound(session_id.to_string()))?;
+    /// Re-run the user messages of a stored session against the current provider and
+    /// configuration, leaving the session itself untouched
+    ///
+    /// The messages are replayed in order on a scratch copy starting from the session's
+    /// context, so each turn sees the regenerated replies before it. `up_to` stops after the
+    /// message at that position in `messages`. Replies are generated at temperature 0 to keep
+    /// replays as comparable as the provider allows.
+    pub async fn replay_session(
+        &self,
+        session_id: &str,
+        up_to: Option<usize>,
+        use_ui: bool,
+    ) -> Result<Vec<ReplayedTurn>, A2UIAgentError> {
+        let source = self.get_session(session_id).await?;
+        let mut scratch = A2UISession {
+            messages: Vec::new(),
+            context: A2UIContext {
+                conversation_state: ConversationState::Initial,
+                last_tool_call: None,
+                transitions: Vec::new(),
+                ..source.context.clone()
+            },
+            tools_used: Vec::new(),
+            surface_components: SurfaceComponents::new(),
+            ..source.clone()
+        };
+
+        let cancel = CancellationToken::new();
+        let mut turns = Vec::new();
+        for (index, message) in source.messages.iter().enumerate() {
+            if up_to.is_some_and(|up_to| index > up_to) {
+                break;
+            }
+            if message.role != "user" {
+                continue;
+            }
+            let original = source.messages[index + 1..]
+                .iter()
+                .take_while(|reply| reply.role != "user")
+                .find(|reply| reply.role == "assistant")
+                .map(|reply| reply.content.clone());
+            let (response, error) = match self
+                .run_turn(&mut scratch, &message.content, use_ui, REPLAY_TEMPERATURE, &cancel)
+                .await
+            {
+                Ok(response) => (Some(response), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            turns.push(ReplayedTurn {
+                index,
+                message: message.content.clone(),
+                original,
+                response,
+                error,
+            });
+        }
+        Ok(turns)
+    }
+
+    /// Add `message` to `session` and generate the reply to it, recording both in the session
+    async fn run_turn(
+        &self,
+        session: &mut A2UISession,
+        message: &str,
+        use_ui: bool,
+        temperature: f32,
+        cancel: &CancellationToken,
+    ) -> Result<GeneratedResponse, A2UIAgentError> {
+        // Any turn resets the follow-up depth; `handle_followup` sets it again afterwards
+        session.context.session_state.remove(FOLLOWUP_DEPTH_KEY);
 
         // Add user message to history
         let user_message = A2UIMessage {
@@ -362,7 +904,19 @@ impl A2UIAgent {
         session.updated_at = Utc::now();
 
         // Process the message and generate response
-        let response = self.generate_response(&session, message, use_ui).await?;
+        session.context.apply_event(ConversationEvent::MessageReceived);
+        let mut events = Vec::new();
+        let response = self
+            .generate_response(session, message, use_ui, temperature, cancel, &mut events)
+            .await;
+        for event in events {
+            session.context.apply_event(event);
+        }
+        let response = response?;
+        // A canned reply after failed validation still completes the turn
+        session.context.apply_event(ConversationEvent::ResponseCompleted);
+
+        record_declarations(&response.a2ui_messages, &mut session.surface_components);
 
         // Add assistant response to history
         let assistant_message = A2UIMessage {
@@ -379,47 +933,433 @@ impl A2UIAgent {
         Ok(response)
     }
 
+    /// Run a follow-up turn triggered by a UI action such as "Show more" or "Refine"
+    ///
+    /// Follow-ups count towards a per-session depth that resets on the next regular
+    /// message, so generated UIs can't keep re-triggering the agent on their own. The depth is
+    /// checked and bumped under the same session lock as the turn, so concurrent follow-ups
+    /// can't both pass the limit.
+    pub async fn handle_followup(&self, session_id: &str, prompt: &str) -> Result<GeneratedResponse, A2UIAgentError> {
+        self.ensure_session(session_id).await?;
+
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| A2UIAgentError::SessionNotFound(session_id.to_string()))?;
+        let depth = session
+            .context
+            .session_state
+            .get(FOLLOWUP_DEPTH_KEY)
+            .and_then(|depth| depth.parse::<usize>().ok())
+            .unwrap_or(0);
+        if depth >= MAX_FOLLOWUP_DEPTH {
+            return Err(A2UIAgentError::FollowupDepthExceeded(depth));
+        }
+
+        let response = self
+            .run_turn(session, prompt, true, CHAT_TEMPERATURE, &CancellationToken::new())
+            .await;
+        if response.is_ok() {
+            session
+                .context
+                .session_state
+                .insert(FOLLOWUP_DEPTH_KEY.to_string(), (depth + 1).to_string());
+        }
+        self.persist(session);
+        response
+    }
+
+    /// Produce the reply to `query`, pushing the conversation events of the turn onto `events`
     async fn generate_response(
         &self,
         session: &A2UISession,
         query: &str,
         use_ui: bool,
+        temperature: f32,
+        cancel: &CancellationToken,
+        events: &mut Vec<ConversationEvent>,
     ) -> Result<GeneratedResponse, A2UIAgentError> {
         // Build the comprehensive UI prompt
-        let prompt = self.build_ui_prompt(session, query, use_ui).await?;
+        let prompt = self.build_ui_prompt(session, query).await?;
 
         // Create provider chat request with tools
-        let chat_request = self.create_chat_request(&prompt, session, use_ui)?;
+        let mut chat_request = self.create_chat_request(&prompt, session, use_ui, temperature)?;
+
+        // Tool calls are run and their results sent back until the model gives its final answer
+        let mut tool_errors = Vec::new();
+        let mut any_tool_succeeded = false;
+        for _ in 0..MAX_TOOL_ITERATIONS {
+            let provider_response = self.complete(self.provider.as_ref(), &chat_request, cancel).await?;
+
+            // Text mode carries no A2UI messages, only the (optionally normalized) reply
+            if !use_ui {
+                events.push(ConversationEvent::ResponseCompleted);
+                return Ok(GeneratedResponse {
+                    content: self.postprocess_text(provider_response.content),
+                    a2ui_messages: Vec::new(),
+                    conversion_warnings: Vec::new(),
+                    style_warnings: Vec::new(),
+                    tool_errors,
+                    provider: Some(self.provider.provider_name().to_string()),
+                });
+            }
+
+            let tool_calls = provider_response.tool_calls.unwrap_or_default();
+            if tool_calls.is_empty() {
+                let response = self
+                    .ui_response_with_fallback(provider_response.content, &chat_request, session, cancel, events)
+                    .await;
+                return with_tool_errors(response, tool_errors, any_tool_succeeded);
+            }
+
+            // A tool result that maps onto a template is shown with it instead of model-written UI
+            events.push(ConversationEvent::ToolCallsRequested);
+            let results = self.execute_tool_calls(&tool_calls).await;
+            events.push(ConversationEvent::ToolResultsReturned);
+            any_tool_succeeded |= results.iter().any(|(_, result)| result.success);
+            // A call the model retries after it failed is reported once
+            for (tool, result) in results.iter().filter(|(_, result)| !result.success) {
+                let failure = ToolError {
+                    tool: tool.clone(),
+                    error: result.error.clone().unwrap_or_else(|| "Tool failed".to_string()),
+                };
+                if !tool_errors.contains(&failure) {
+                    tool_errors.push(failure);
+                }
+            }
+            if let Some(a2ui_messages) = self.render_tool_results(&results) {
+                let a2ui_messages = self.finalize_messages(a2ui_messages, session);
+                record_validation(events, &a2ui_messages);
+                return Ok(GeneratedResponse {
+                    content: provider_response.content,
+                    a2ui_messages: a2ui_messages?,
+                    conversion_warnings: Vec::new(),
+                    style_warnings: Vec::new(),
+                    tool_errors,
+                    provider: Some(self.provider.provider_name().to_string()),
+                });
+            }
+            if provider_response.content.contains(A2UI_MESSAGES_MARKER) {
+                let response = self
+                    .ui_response_with_fallback(provider_response.content, &chat_request, session, cancel, events)
+                    .await;
+                return with_tool_errors(response, tool_errors, any_tool_succeeded);
+            }
 
-        // Call AI provider
-        let provider_response = self.provider.chat_completion(chat_request).await?;
+            // A tool-only turn is not the answer: send the results back and ask again
+            chat_request
+                .messages
+                .extend(tool_turn_messages(&provider_response.content, &tool_calls, &results));
+        }
+
+        with_tool_errors(
+            Err(A2UIAgentError::ToolIterationsExceeded(MAX_TOOL_ITERATIONS)),
+            tool_errors,
+            any_tool_succeeded,
+        )
+    }
 
+    /// Turn a final model response into UI, asking again while the UI it wrote is invalid
+    ///
+    /// The provider gets `max_ui_retries` more attempts, each with the previous reply and its
+    /// validation error added to the conversation, then the fallback provider (if
+    /// configured) one final one. Transport errors don't trigger the fallback, and if it
+    /// fails too the primary provider's last validation error is returned.
+    async fn ui_response_with_fallback(
+        &self,
+        content: String,
+        chat_request: &ChatRequest,
+        session: &A2UISession,
+        cancel: &CancellationToken,
+        events: &mut Vec<ConversationEvent>,
+    ) -> Result<GeneratedResponse, A2UIAgentError> {
+        let mut retry_request = chat_request.clone();
+        let mut last_content = content.clone();
+        let mut response = self.ui_response(content, self.provider.as_ref(), session, cancel).await;
+        record_validation(events, &response);
+        for _ in 0..self.config.max_ui_retries {
+            match &response {
+                Err(e) if e.is_invalid_ui() => {
+                    warn!("Retrying after invalid UI: {}", e);
+                    retry_request.messages.extend(ui_retry_messages(&last_content, e));
+                }
+                _ => return response,
+            }
+            let reply = self.complete(self.provider.as_ref(), &retry_request, cancel).await?;
+            last_content = reply.content.clone();
+            response = self
+                .ui_response(reply.content, self.provider.as_ref(), session, cancel)
+                .await;
+            record_validation(events, &response);
+        }
+
+        let (Err(e), Some(fallback)) = (&response, &self.fallback_provider) else {
+            return response;
+        };
+        if !e.is_invalid_ui() {
+            return response;
+        }
+        warn!(
+            "{} produced invalid UI ({}), trying {}",
+            self.provider.provider_name(),
+            e,
+            fallback.provider_name()
+        );
+        let fallback_response = match self.complete(fallback.as_ref(), chat_request, cancel).await {
+            Ok(reply) => {
+                let fallback_response = self
+                    .ui_response(reply.content, fallback.as_ref(), session, cancel)
+                    .await;
+                record_validation(events, &fallback_response);
+                fallback_response
+            }
+            Err(e) => Err(e.into()),
+        };
+        match fallback_response {
+            Ok(fallback_response) => Ok(fallback_response),
+            Err(e) => {
+                warn!("Fallback provider {} failed too: {}", fallback.provider_name(), e);
+                response
+            }
+        }
+    }
+
+    /// One provider call, bounded by the configured timeout and the request's `cancel` token
+    /// and recorded in the recent requests feed
+    async fn complete(
+        &self,
+        provider: &dyn AIProvider,
+        chat_request: &ChatRequest,
+        cancel: &CancellationToken,
+    ) -> Result<ChatResponse, ProviderError> {
+        let prompt = chat_request
+            .messages
+            .last()
+            .map(|message| message.content.as_str())
+            .unwrap_or_default();
+        let request = RECENT_REQUESTS.start(
+            RequestSource::A2ui,
+            provider.provider_name(),
+            Some(provider.model()),
+            prompt,
+        );
+        let response = provider
+            .chat_completion_bounded(chat_request.clone(), cancel, self.config.provider_timeout())
+            .await;
+        match &response {
+            Ok(reply) => RECENT_REQUESTS.finish(request, reply.usage.clone(), None),
+            Err(e) => RECENT_REQUESTS.finish(request, None, Some(e.to_string())),
+        }
+        response
+    }
+
+    /// Parse the A2UI messages out of a final response from `provider`
+    async fn ui_response(
+        &self,
+        content: String,
+        provider: &dyn AIProvider,
+        session: &A2UISession,
+        cancel: &CancellationToken,
+    ) -> Result<GeneratedResponse, A2UIAgentError> {
         // Parse and process the response
-        let parsed_response = self.parse_response(&provider_response.content)?;
+        let parsed_response = self.parse_response(&content)?;
 
         // Convert to A2UI messages with auto-fixing
-        let a2ui_messages = self.convert_json_to_a2ui_message(&parsed_response, session).await?;
+        let (a2ui_messages, conversion_warnings) = self.convert_json_to_a2ui_message(&parsed_response, session).await?;
 
-        // Validate A2UI response
-        if use_ui {
-            self.validate_a2ui_response(&a2ui_messages)?;
-        }
+        let mut a2ui_messages = self.finalize_messages(a2ui_messages, session)?;
+        let style_warnings = self.enforce_output_style(&mut a2ui_messages, provider, cancel).await;
 
         Ok(GeneratedResponse {
-            content: provider_response.content,
+            content,
             a2ui_messages,
+            conversion_warnings,
+            style_warnings,
+            tool_errors: Vec::new(),
+            provider: Some(provider.provider_name().to_string()),
         })
     }
 
-    async fn build_ui_prompt(
+    /// Check the copy of `a2ui_messages` against the configured output style, first stripping
+    /// markdown and asking `provider` to rewrite wrong-language copy in rewrite mode; returns
+    /// the mismatches that remain
+    async fn enforce_output_style(
         &self,
+        a2ui_messages: &mut [A2UIMessageResponse],
+        provider: &dyn AIProvider,
+        cancel: &CancellationToken,
+    ) -> Vec<StyleWarning> {
+        let style = &self.config.output_style;
+        if !style.is_enabled() {
+            return Vec::new();
+        }
+        let warnings = style.check(a2ui_messages);
+        if warnings.is_empty() || style.enforcement == StyleEnforcement::Warn {
+            return warnings;
+        }
+
+        style.strip_markdown(a2ui_messages);
+        if let Some(prompt) = style.rewrite_prompt(a2ui_messages, &warnings) {
+            let request = ChatRequest {
+                system: None,
+                messages: vec![ProviderChatMessage::user(prompt)],
+                temperature: 0.0,
+                max_tokens: 4096,
+                tools: None,
+            };
+            match self.complete(provider, &request, cancel).await {
+                Ok(reply) => {
+                    if apply_rewrites(a2ui_messages, &reply.content).is_none() {
+                        warn!("Output style rewrite reply held no JSON object");
+                    }
+                }
+                Err(e) => warn!("Output style rewrite failed: {}", e),
+            }
+        }
+        style.check(a2ui_messages)
+    }
+
+    /// Resolve component ids that collide within the response or with other surfaces, then validate
+    fn finalize_messages(
+        &self,
+        mut a2ui_messages: Vec<A2UIMessageResponse>,
         session: &A2UISession,
-        query: &str,
-        use_ui: bool,
-    ) -> Result<String, A2UIAgentError> {
+    ) -> Result<Vec<A2UIMessageResponse>, A2UIAgentError> {
+        match self.config.component_ids {
+            ComponentIdPolicy::Off => {}
+            ComponentIdPolicy::Reject => {
+                let duplicates = find_duplicates(&a2ui_messages, &session.surface_components);
+                if !duplicates.is_empty() {
+                    return Err(A2UIAgentError::ValidationError(format!(
+                        "Duplicate component ids: {}",
+                        describe_duplicates(&duplicates)
+                    )));
+                }
+            }
+            ComponentIdPolicy::Namespace => namespace_duplicates(&mut a2ui_messages, &session.surface_components),
+        }
+
+        let undefined = undefined_roots(&a2ui_messages, &session.surface_components);
+        if !undefined.is_empty() {
+            let roots: Vec<String> = undefined
+                .iter()
+                .map(|(surface_id, root)| format!("'{}' on surface '{}'", root, surface_id))
+                .collect();
+            return Err(A2UIAgentError::ValidationError(format!(
+                "beginRendering roots never defined by a surfaceUpdate: {}",
+                roots.join(", ")
+            )));
+        }
+
+        self.validate_a2ui_response(&a2ui_messages)?;
+        if self.config.components_before_render {
+            a2ui_messages = render_after_components(a2ui_messages);
+        }
+        Ok(a2ui_messages)
+    }
+
+    /// Run each tool call, turning failures into unsuccessful results the model can see
+    async fn execute_tool_calls(&self, tool_calls: &[ProviderToolCall]) -> Vec<(String, ToolResult)> {
+        let mut results = Vec::new();
+        for call in tool_calls {
+            let parameters = call
+                .arguments
+                .as_object()
+                .map(|arguments| arguments.clone().into_iter().collect())
+                .unwrap_or_default();
+            let result = self
+                .execute_tool(&call.name, parameters)
+                .await
+                .unwrap_or_else(|e| ToolResult {
+                    success: false,
+                    data: None,
+                    error: Some(e.to_string()),
+                });
+            if let Some(error) = &result.error {
+                warn!("Tool '{}' failed: {}", call.name, error);
+            }
+            results.push((call.name.clone(), result));
+        }
+        results
+    }
+
+    /// Render the first successful tool result that has a template
+    ///
+    /// Results without a matching template are skipped, leaving the UI to the model.
+    fn render_tool_results(&self, results: &[(String, ToolResult)]) -> Option<Vec<A2UIMessageResponse>> {
+        results.iter().find_map(|(tool_name, result)| {
+            let data = result.data.as_ref().filter(|_| result.success)?;
+            let (template, data) = template_for_tool(tool_name, data)?;
+            self.render_template(template, &data)
+                .inspect_err(|e| {
+                    warn!(
+                        "Failed to render template '{}' for tool '{}': {}",
+                        template, tool_name, e
+                    )
+                })
+                .ok()
+        })
+    }
+
+    /// Render the built-in template `name` with `data` bound into its data model
+    ///
+    /// Each top-level key of `data` replaces the template's default value at `/<key>`, or is
+    /// added as a new patch. The result is validated like a model-written response.
+    pub fn render_template(
+        &self,
+        name: &str,
+        data: &serde_json::Value,
+    ) -> Result<Vec<A2UIMessageResponse>, A2UIAgentError> {
+        let template = self
+            .templates
+            .get(name)
+            .ok_or_else(|| A2UIAgentError::TemplateError(format!("Unknown template: {}", name)))?;
+        let values = data
+            .as_object()
+            .ok_or_else(|| A2UIAgentError::TemplateError("Template data must be an object".to_string()))?;
+        let mut messages: Vec<A2UIMessageResponse> = serde_json::from_str(template)
+            .map_err(|e| A2UIAgentError::TemplateError(format!("Invalid template {}: {}", name, e)))?;
+
+        for message in &mut messages {
+            if let A2UIMessageResponse::DataModelUpdate(update) = message {
+                for (key, value) in values {
+                    let path = format!("/{}", pointer_token(key));
+                    match update.patches.iter_mut().find(|patch| patch.path == path) {
+                        Some(patch) => patch.value = value.clone(),
+                        None => update.patches.push(DataPatch {
+                            path,
+                            value: value.clone(),
+                        }),
+                    }
+                }
+            }
+        }
+
+        self.validate_a2ui_response(&messages)?;
+        Ok(messages)
+    }
+
+    /// Apply the configured text-mode normalization to a response
+    fn postprocess_text(&self, content: String) -> String {
+        if self.config.strip_markdown {
+            text::strip_markdown(&content)
+        } else if self.config.normalize_text {
+            text::normalize_whitespace(&content)
+        } else {
+            content
+        }
+    }
+
+    /// Instructions for the model: the deployment preamble, the A2UI protocol and response format
+    fn build_system_prompt(&self, use_ui: bool) -> String {
         let mut prompt = String::new();
 
-        // System prompt
+        // Deployment preamble goes first so the A2UI format instructions below stay last
+        if let Some(preamble) = &self.config.system_preamble {
+            prompt.push_str(preamble.trim());
+            prompt.push_str("\n\n");
+        }
+
         prompt.push_str("You are an intelligent UI assistant that can analyze user requests and generate appropriate user interfaces using the A2UI (Agent to UI) protocol.\n\n");
 
         prompt.push_str("A2UI PROTOCOL OVERVIEW:\n");
@@ -437,6 +1377,7 @@ impl A2UIAgent {
         prompt.push_str("- List: Repeating components for data collections\n");
         prompt.push_str("- TextField: Input fields for user data entry\n");
         prompt.push_str("- Tabs: Tab navigation components\n");
+        prompt.push_str("- Image: Images loaded from a URL or data binding\n");
         prompt.push_str("- Icon: Icon components\n");
         prompt.push_str("- Divider: Visual separators\n\n");
 
@@ -450,15 +1391,6 @@ impl A2UIAgent {
             prompt.push_str("- Use surfaceUpdate for component definitions and dataModelUpdate for data\n\n");
         }
 
-        // Context information
-        prompt.push_str(&format!("SESSION CONTEXT:\n"));
-        prompt.push_str(&format!("User ID: {}\n", session.context.user_id));
-        prompt.push_str(&format!("App: {}\n", session.context.app_name));
-        prompt.push_str(&format!(
-            "Conversation State: {:?}\n\n",
-            session.context.conversation_state
-        ));
-
         // Available tools
         if !self.tools.is_empty() {
             prompt.push_str("AVAILABLE TOOLS:\n");
@@ -468,18 +1400,6 @@ impl A2UIAgent {
             prompt.push_str("\n");
         }
 
-        // Conversation history
-        if !session.messages.is_empty() {
-            prompt.push_str("CONVERSATION HISTORY:\n");
-            for msg in &session.messages {
-                prompt.push_str(&format!("{}: {}\n", msg.role.to_uppercase(), msg.content));
-            }
-            prompt.push_str("\n");
-        }
-
-        // Current query
-        prompt.push_str(&format!("CURRENT REQUEST: {}\n\n", query));
-
         if use_ui {
             prompt.push_str("RESPONSE REQUIREMENTS:\n");
             prompt.push_str("1. Provide a helpful conversational response\n");
@@ -504,6 +1424,34 @@ impl A2UIAgent {
         prompt.push_str("  ]}}\n");
         prompt.push_str("]\n\n");
 
+        prompt
+    }
+
+    /// The user turn: session context, conversation history and the current request
+    async fn build_ui_prompt(&self, session: &A2UISession, query: &str) -> Result<String, A2UIAgentError> {
+        let mut prompt = String::new();
+
+        // Context information
+        prompt.push_str(&format!("SESSION CONTEXT:\n"));
+        prompt.push_str(&format!("User ID: {}\n", session.context.user_id));
+        prompt.push_str(&format!("App: {}\n", session.context.app_name));
+        prompt.push_str(&format!(
+            "Conversation State: {:?}\n\n",
+            session.context.conversation_state
+        ));
+
+        // Conversation history
+        if !session.messages.is_empty() {
+            prompt.push_str("CONVERSATION HISTORY:\n");
+            for msg in &session.messages {
+                prompt.push_str(&format!("{}: {}\n", msg.role.to_uppercase(), msg.content));
+            }
+            prompt.push_str("\n");
+        }
+
+        // Current query
+        prompt.push_str(&format!("CURRENT REQUEST: {}\n", query));
+
         Ok(prompt)
     }
 
@@ -512,22 +1460,21 @@ impl A2UIAgent {
         prompt: &str,
         _session: &A2UISession,
         use_ui: bool,
+        temperature: f32,
     ) -> Result<ChatRequest, A2UIAgentError> {
-        let messages = vec![ProviderChatMessage {
-            role: "user".to_string(),
-            content: prompt.to_string(),
-        }];
+        let messages = vec![ProviderChatMessage::user(prompt)];
 
         // Build tools if needed
-        let tools = if use_ui && !self.tools.is_empty() {
+        let tools = if use_ui && self.enabled_tools().next().is_some() {
             Some(self.convert_a2ui_tools_to_provider_tools())
         } else {
             None
         };
 
         let request = ChatRequest {
+            system: Some(self.build_system_prompt(use_ui)),
             messages,
-            temperature: 0.7,
+            temperature,
             max_tokens: 4096,
             tools,
         };
@@ -536,8 +1483,7 @@ impl A2UIAgent {
     }
 
     fn convert_a2ui_tools_to_provider_tools(&self) -> Vec<Tool> {
-        self.tools
-            .iter()
+        self.enabled_tools()
             .map(|tool| {
                 let mut properties = HashMap::new();
                 let mut required = Vec::new();
@@ -592,8 +1538,8 @@ impl A2UIAgent {
 
     fn parse_response(&self, response: &str) -> Result<String, A2UIAgentError> {
         // Extract A2UI messages using delimiter parsing
-        if let Some(start) = response.find("A2UI_MESSAGES:") {
-            let after_marker = &response[start + "A2UI_MESSAGES:".len()..];
+        if let Some(start) = response.find(A2UI_MESSAGES_MARKER) {
+            let after_marker = &response[start + A2UI_MESSAGES_MARKER.len()..];
 
             // Find the start of JSON array
             if let Some(json_start) = after_marker.find('[') {
@@ -632,44 +1578,17 @@ impl A2UIAgent {
         &self,
         json_str: &str,
         session: &A2UISession,
-    ) -> Result<Vec<A2UIMessageResponse>, A2UIAgentError> {
+    ) -> Result<(Vec<A2UIMessageResponse>, Vec<ConversionWarning>), A2UIAgentError> {
         // First, try to parse the JSON directly
         match serde_json::from_str::<Vec<serde_json::Value>>(json_str) {
-            Ok(messages) => {
-                let mut a2ui_messages = Vec::new();
-
-                for message in messages {
-                    match self.convert_single_message(message, session) {
-                        Ok(a2ui_msg) => a2ui_messages.push(a2ui_msg),
-                        Err(e) => {
-                            // Log error but continue with other messages
-                            eprintln!("Error converting message: {}", e);
-                        }
-                    }
-                }
-
-                Ok(a2ui_messages)
-            }
+            Ok(messages) => Ok(self.convert_messages(messages, session)),
             Err(_) => {
                 // Try auto-fixing common JSON issues
                 match self.auto_fix_json(json_str) {
                     Ok(fixed_json) => {
                         // Try parsing again with the fixed JSON (but avoid infinite recursion)
                         match serde_json::from_str::<Vec<serde_json::Value>>(&fixed_json) {
-                            Ok(messages) => {
-                                let mut a2ui_messages = Vec::new();
-
-                                for message in messages {
-                                    match self.convert_single_message(message, session) {
-                                        Ok(a2ui_msg) => a2ui_messages.push(a2ui_msg),
-                                        Err(e) => {
-                                            eprintln!("Error converting message: {}", e);
-                                        }
-                                    }
-                                }
-
-                                Ok(a2ui_messages)
-                            }
+                            Ok(messages) => Ok(self.convert_messages(messages, session)),
                             Err(e) => Err(A2UIAgentError::MessageError(format!(
                                 "Failed to parse JSON even after auto-fixing: {}",
                                 e
@@ -682,6 +1601,31 @@ impl A2UIAgent {
         }
     }
 
+    /// Convert messages in input order, recording a warning for each one that is skipped
+    fn convert_messages(
+        &self,
+        messages: Vec<serde_json::Value>,
+        session: &A2UISession,
+    ) -> (Vec<A2UIMessageResponse>, Vec<ConversionWarning>) {
+        let mut a2ui_messages = Vec::new();
+        let mut warnings = Vec::new();
+
+        for (index, message) in messages.into_iter().enumerate() {
+            match self.convert_single_message(message, session) {
+                Ok(a2ui_msg) => a2ui_messages.push(a2ui_msg),
+                Err(e) => {
+                    warn!("Skipping A2UI message {}: {}", index, e);
+                    warnings.push(ConversionWarning {
+                        index,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        (a2ui_messages, warnings)
+    }
+
     fn convert_single_message(
         &self,
         message: serde_json::Value,
@@ -700,9 +1644,15 @@ impl A2UIAgent {
                 return Ok(A2UIMessageResponse::SurfaceUpdate(surface_update));
             }
 
-            // Check for dataModelUpdate
+            // Check for dataModelUpdate, converting legacy `contents` entries to patches
             if let Some(data_update) = obj.get("dataModelUpdate") {
-                let data_model_update: DataModelUpdate = serde_json::from_value(data_update.clone())?;
+                let data_model_update: DataModelUpdate =
+                    if data_update.get("patches").is_none() && data_update.get("contents").is_some() {
+                        let legacy: LegacyDataModelUpdate = serde_json::from_value(data_update.clone())?;
+                        legacy.try_into().map_err(A2UIAgentError::ValidationError)?
+                    } else {
+                        serde_json::from_value(data_update.clone())?
+                    };
                 return Ok(A2UIMessageResponse::DataModelUpdate(data_model_update));
             }
 
@@ -739,12 +1689,8 @@ impl A2UIAgent {
 
     fn validate_a2ui_response(&self, messages: &[A2UIMessageResponse]) -> Result<(), A2UIAgentError> {
         for message in messages {
-            let json_value = match message {
-                A2UIMessageResponse::BeginRendering(br) => serde_json::to_value(br)?,
-                A2UIMessageResponse::SurfaceUpdate(su) => serde_json::to_value(su)?,
-                A2UIMessageResponse::DataModelUpdate(dmu) => serde_json::to_value(dmu)?,
-                A2UIMessageResponse::DeleteSurface(ds) => serde_json::to_value(ds)?,
-            };
+            // Serialize with the action wrapper (e.g. `{"surfaceUpdate": {...}}`) the schema describes
+            let json_value = serde_json::to_value(message)?;
 
             // Validate against the schema
             let result = self.schema_validator.validate(&json_value);
@@ -761,6 +1707,11 @@ impl A2UIAgent {
             }
         }
 
+        // Well-formed components can still reference each other in a loop
+        if let Some(cycle) = find_cycle(messages) {
+            return Err(A2UIAgentError::CyclicReference(cycle.join(" -> ")));
+        }
+
         Ok(())
     }
 
@@ -771,8 +1722,7 @@ impl A2UIAgent {
         parameters: HashMap<String, serde_json::Value>,
     ) -> Result<ToolResult, A2UIAgentError> {
         let _tool = self
-            .tools
-            .iter()
+            .enabled_tools()
             .find(|t| t.name == tool_name)
             .ok_or_else(|| A2UIAgentError::ToolNotFound(tool_name.to_string()))?;
 
@@ -865,3 +1815,1041 @@ impl A2UIAgent {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::a2ui::provider::MockProvider;
+    use crate::session_limit::SessionLimitPolicy;
+    use async_trait::async_trait;
+
+    struct StaticProvider {
+        content: String,
+    }
+
+    #[async_trait]
+    impl AIProvider for StaticProvider {
+        async fn chat_completion(&self, _request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+            Ok(ChatResponse {
+                content: self.content.clone(),
+                tool_calls: None,
+                usage: None,
+            })
+        }
+
+        fn provider_name(&self) -> &str {
+            "Mock"
+        }
+
+        fn default_model(&self) -> &str {
+            "mock"
+        }
+    }
+
+    fn agent_with_tools(enabled_tools: Option<Vec<&str>>) -> A2UIAgent {
+        let config = A2UIConfig {
+            enabled_tools: enabled_tools.map(|names| names.into_iter().map(String::from).collect()),
+            ..Default::default()
+        };
+        A2UIAgent::with_config(
+            Arc::new(StaticProvider {
+                content: "[]".to_string(),
+            }),
+            config,
+        )
+        .unwrap()
+    }
+
+    async fn text_reply(config: A2UIConfig, reply: &str) -> String {
+        let provider = Arc::new(StaticProvider {
+            content: reply.to_string(),
+        });
+        let agent = A2UIAgent::with_config(provider, config).unwrap();
+        let response = agent.handle_message("text-session", "hello", false).await.unwrap();
+        assert!(response.a2ui_messages.is_empty());
+        response.content
+    }
+
+    async fn advertised_tools(agent: &A2UIAgent) -> Vec<String> {
+        let session_id = agent
+            .create_session(CreateSessionRequest {
+                user_id: "test".to_string(),
+                app_name: "Test".to_string(),
+                base_url: None,
+                initial_context: None,
+            })
+            .await
+            .unwrap();
+        let session = agent.get_session(&session_id).await.unwrap();
+        let request = agent
+            .create_chat_request("show contacts", &session, true, CHAT_TEMPERATURE)
+            .unwrap();
+        request
+            .tools
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tool| tool.name)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_all_tools_enabled_by_default() {
+        let agent = agent_with_tools(None);
+        let tools = advertised_tools(&agent).await;
+        assert_eq!(
+            tools,
+            vec!["get_contact_info", "create_contact_list", "display_search_results"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disabled_tool_is_not_advertised() {
+        let agent = agent_with_tools(Some(vec!["create_contact_list", "display_search_results"]));
+        let tools = advertised_tools(&agent).await;
+        assert!(!tools.contains(&"get_contact_info".to_string()));
+        assert_eq!(tools.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_tool_execution_is_rejected() {
+        let agent = agent_with_tools(Some(vec!["create_contact_list"]));
+
+        let result = agent
+            .execute_tool(
+                "get_contact_info",
+                HashMap::from([("name".to_string(), serde_json::json!("John"))]),
+            )
+            .await;
+        assert!(matches!(result, Err(A2UIAgentError::ToolNotFound(name)) if name == "get_contact_info"));
+
+        let result = agent.execute_tool("create_contact_list", HashMap::new()).await;
+        assert!(result.unwrap().success);
+    }
+
+    #[tokio::test]
+    async fn test_conversion_preserves_order_and_reports_skipped() {
+        let agent = agent_with_tools(None);
+        let session_id = agent
+            .create_session(CreateSessionRequest {
+                user_id: "test".to_string(),
+                app_name: "Test".to_string(),
+                base_url: None,
+                initial_context: None,
+            })
+            .await
+            .unwrap();
+        let session = agent.get_session(&session_id).await.unwrap();
+
+        let json = serde_json::json!([
+            {"beginRendering": {"surfaceId": "main", "root": "root"}},
+            {"unknownAction": {}},
+            {"deleteSurface": {"surfaceId": "old"}},
+            {"surfaceUpdate": {"surfaceId": "main"}},
+            {"dataModelUpdate": {"surfaceId": "main", "patches": []}}
+        ])
+        .to_string();
+
+        let (messages, warnings) = agent.convert_json_to_a2ui_message(&json, &session).await.unwrap();
+
+        let kinds: Vec<&str> = messages
+            .iter()
+            .map(|message| match message {
+                A2UIMessageResponse::BeginRendering(_) => "beginRendering",
+                A2UIMessageResponse::SurfaceUpdate(_) => "surfaceUpdate",
+                A2UIMessageResponse::DataModelUpdate(_) => "dataModelUpdate",
+                A2UIMessageResponse::DeleteSurface(_) => "deleteSurface",
+            })
+            .collect();
+        assert_eq!(kinds, vec!["beginRendering", "deleteSurface", "dataModelUpdate"]);
+
+        let skipped: Vec<usize> = warnings.iter().map(|warning| warning.index).collect();
+        assert_eq!(skipped, vec![1, 3]);
+        assert!(warnings[0].error.contains("Invalid A2UI message format"));
+        assert!(warnings[1].error.contains("components"));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_data_entries_become_patches() {
+        let agent = agent_with_tools(None);
+        let session_id = agent
+            .create_session(CreateSessionRequest {
+                user_id: "test".to_string(),
+                app_name: "Test".to_string(),
+                base_url: None,
+                initial_context: None,
+            })
+            .await
+            .unwrap();
+        let session = agent.get_session(&session_id).await.unwrap();
+
+        let json = serde_json::json!([
+            {"dataModelUpdate": {"surfaceId": "main", "contents": [
+                {"key": "title", "valueString": "Contacts"},
+                {"key": "user", "valueMap": [
+                    {"key": "age", "valueNumber": 30},
+                    {"key": "active", "valueBoolean": true},
+                    {"key": "address", "valueMap": [{"key": "city", "valueString": "Oslo"}]}
+                ]}
+            ]}},
+            {"dataModelUpdate": {"surfaceId": "main", "contents": [
+                {"key": "user", "valueMap": [{"key": "age", "valueString": "30", "valueNumber": 30}]}
+            ]}},
+            {"dataModelUpdate": {"surfaceId": "main", "contents": [{"key": "empty"}]}}
+        ])
+        .to_string();
+
+        let (messages, warnings) = agent.convert_json_to_a2ui_message(&json, &session).await.unwrap();
+
+        assert_eq!(messages.len(), 1);
+        let A2UIMessageResponse::DataModelUpdate(update) = &messages[0] else {
+            panic!("expected a dataModelUpdate");
+        };
+        let patches: Vec<(&str, &serde_json::Value)> = update
+            .patches
+            .iter()
+            .map(|patch| (patch.path.as_str(), &patch.value))
+            .collect();
+        assert_eq!(
+            patches,
+            vec![
+                ("/title", &serde_json::json!("Contacts")),
+                (
+                    "/user",
+                    &serde_json::json!({"age": 30, "active": true, "address": {"city": "Oslo"}})
+                ),
+            ]
+        );
+
+        let skipped: Vec<usize> = warnings.iter().map(|warning| warning.index).collect();
+        assert_eq!(skipped, vec![1, 2]);
+        assert!(warnings[0].error.contains("/user/age"));
+        assert!(warnings[0].error.contains("found: valueString, valueNumber"));
+        assert!(warnings[1].error.contains("/empty"));
+        assert!(warnings[1].error.contains("found: none"));
+    }
+
+    #[tokio::test]
+    async fn test_instructions_go_in_the_system_prompt_after_the_preamble() {
+        let config = A2UIConfig {
+            system_preamble: Some("You work for Acme Corp. Keep answers brief.\n".to_string()),
+            ..Default::default()
+        };
+        let agent = A2UIAgent::with_config(
+            Arc::new(StaticProvider {
+                content: "[]".to_string(),
+            }),
+            config,
+        )
+        .unwrap();
+        let session_id = agent
+            .create_session(CreateSessionRequest {
+                user_id: "test".to_string(),
+                app_name: "Test".to_string(),
+                base_url: None,
+                initial_context: None,
+            })
+            .await
+            .unwrap();
+        let session = agent.get_session(&session_id).await.unwrap();
+
+        let prompt = agent.build_ui_prompt(&session, "show contacts").await.unwrap();
+        let request = agent
+            .create_chat_request(&prompt, &session, true, CHAT_TEMPERATURE)
+            .unwrap();
+        let system = request.system.unwrap();
+        assert!(
+            system.starts_with("You work for Acme Corp. Keep answers brief.\n\nYou are an intelligent UI assistant")
+        );
+
+        let default_system = agent_with_tools(None).build_system_prompt(true);
+        let format_sections = &default_system[default_system.find("A2UI PROTOCOL OVERVIEW:").unwrap()..];
+        assert!(system.ends_with(format_sections));
+        assert!(format_sections.contains("RESPONSE FORMAT:"));
+
+        // The user turn carries only the conversation itself
+        assert_eq!(request.messages.len(), 1);
+        assert_eq!(request.messages[0].content, prompt);
+        assert!(!prompt.contains("A2UI PROTOCOL OVERVIEW:"));
+        assert!(prompt.ends_with("CURRENT REQUEST: show contacts\n"));
+    }
+
+    #[tokio::test]
+    async fn test_text_mode_normalization() {
+        let reply = "# Summary\n\n\n\nYou have **two** contacts.   \n\n\n\nDone.";
+
+        assert_eq!(text_reply(A2UIConfig::default(), reply).await, reply);
+
+        let normalized = A2UIConfig {
+            normalize_text: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            text_reply(normalized, reply).await,
+            "# Summary\n\nYou have **two** contacts.\n\nDone."
+        );
+
+        let stripped = A2UIConfig {
+            strip_markdown: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            text_reply(stripped, reply).await,
+            "Summary\n\nYou have two contacts.\n\nDone."
+        );
+    }
+
+    fn surface_update(components: serde_json::Value) -> Vec<A2UIMessageResponse> {
+        let update: SurfaceUpdate = serde_json::from_value(serde_json::json!({
+            "surfaceId": "main",
+            "components": components
+        }))
+        .unwrap();
+        vec![A2UIMessageResponse::SurfaceUpdate(update)]
+    }
+
+    #[test]
+    fn test_multi_child_card_validates() {
+        let agent = agent_with_tools(None);
+        let messages = surface_update(serde_json::json!([
+            {"id": "card", "component": {"Card": {"children": {"explicitList": ["title", "body"]}}}},
+            {"id": "legacy-card", "component": {"Card": {"child": "title"}}},
+            {"id": "title", "component": {"Text": {"text": {"literalString": "Jane"}, "usageHint": "h2"}}},
+            {"id": "body", "component": {"Text": {"text": {"path": "/contact/email"}}}}
+        ]));
+
+        let A2UIMessageResponse::SurfaceUpdate(update) = &messages[0] else {
+            unreachable!()
+        };
+        match &update.components[0].component {
+            UIComponentType::Card { child, children } => {
+                assert!(child.is_none());
+                assert_eq!(
+                    children.as_ref().unwrap().explicit_list.as_deref(),
+                    Some(&["title".to_string(), "body".to_string()][..])
+                );
+            }
+            other => panic!("Expected Card, got {:?}", other),
+        }
+        assert!(matches!(
+            &update.components[1].component,
+            UIComponentType::Card { child: Some(child), children: None } if child == "title"
+        ));
+
+        agent.validate_a2ui_response(&messages).unwrap();
+    }
+
+    #[test]
+    fn test_explicit_item_list_validates() {
+        let agent = agent_with_tools(None);
+        let messages = surface_update(serde_json::json!([
+            {"id": "list", "component": {"List": {"children": {"explicitList": ["item-1", "item-2"]}, "direction": "vertical"}}},
+            {"id": "item-1", "component": {"Text": {"text": {"literalString": "First"}}}},
+            {"id": "item-2", "component": {"Text": {"text": {"literalString": "Second"}}}}
+        ]));
+
+        agent.validate_a2ui_response(&messages).unwrap();
+    }
+
+    #[test]
+    fn test_card_without_children_is_rejected() {
+        let agent = agent_with_tools(None);
+        let messages = surface_update(serde_json::json!([
+            {"id": "card", "component": {"Card": {}}}
+        ]));
+
+        assert!(matches!(
+            agent.validate_a2ui_response(&messages),
+            Err(A2UIAgentError::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_cyclic_children_are_rejected() {
+        let agent = agent_with_tools(None);
+        let messages = surface_update(serde_json::json!([
+            {"id": "a", "component": {"Column": {"children": {"explicitList": ["b"]}}}},
+            {"id": "b", "component": {"Card": {"child": "a"}}}
+        ]));
+
+        assert!(matches!(
+            agent.validate_a2ui_response(&messages),
+            Err(A2UIAgentError::CyclicReference(cycle)) if cycle == "a -> b -> a"
+        ));
+    }
+
+    fn contact_patches(messages: &[A2UIMessageResponse]) -> Vec<serde_json::Value> {
+        messages
+            .iter()
+            .find_map(|message| match message {
+                A2UIMessageResponse::DataModelUpdate(update) => update
+                    .patches
+                    .iter()
+                    .find(|patch| patch.path == "/contacts")
+                    .and_then(|patch| patch.value.as_array().cloned()),
+                _ => None,
+            })
+            .expect("no /contacts patch")
+    }
+
+    #[test]
+    fn test_contact_list_template_renders_valid_messages() {
+        let agent = agent_with_tools(None);
+        let data = serde_json::json!({
+            "searchPrompt": "Results for \"Engineering\"",
+            "contacts": [
+                {"name": "John Doe", "title": "Software Engineer", "department": "Engineering", "imageUrl": "https://example.com/john.png"},
+                {"name": "Jane Smith", "title": "Product Manager", "department": "Product", "imageUrl": "https://example.com/jane.png"}
+            ]
+        });
+
+        let messages = agent.render_template("contact_list", &data).unwrap();
+
+        assert_eq!(messages.len(), 3);
+        assert!(agent.validate_a2ui_response(&messages).is_ok());
+        for message in &messages {
+            let json = serde_json::to_value(message).unwrap();
+            assert!(agent.schema_validator.is_valid(&json), "{}", json);
+        }
+        let contacts = contact_patches(&messages);
+        assert_eq!(contacts.len(), 2);
+        assert_eq!(contacts[1]["name"], "Jane Smith");
+
+        assert!(matches!(
+            agent.render_template("org_chart", &data),
+            Err(A2UIAgentError::TemplateError(_))
+        ));
+    }
+
+    struct ToolCallingProvider {
+        call: ProviderToolCall,
+    }
+
+    #[async_trait]
+    impl AIProvider for ToolCallingProvider {
+        async fn chat_completion(&self, _request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+            Ok(ChatResponse {
+                content: "Here is who I found.".to_string(),
+                tool_calls: Some(vec![self.call.clone()]),
+                usage: None,
+            })
+        }
+
+        fn provider_name(&self) -> &str {
+            "Mock"
+        }
+
+        fn default_model(&self) -> &str {
+            "mock"
+        }
+    }
+
+    /// Replies in turn with each of `replies`, repeating the last one, and records the requests
+    struct ScriptedProvider {
+        replies: std::sync::Mutex<Vec<ChatResponse>>,
+        requests: std::sync::Mutex<Vec<ChatRequest>>,
+    }
+
+    impl ScriptedProvider {
+        fn new(replies: Vec<ChatResponse>) -> Arc<Self> {
+            Arc::new(Self {
+                replies: std::sync::Mutex::new(replies),
+                requests: std::sync::Mutex::new(Vec::new()),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl AIProvider for ScriptedProvider {
+        async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+            self.requests.lock().unwrap().push(request);
+            let mut replies = self.replies.lock().unwrap();
+            if replies.len() > 1 {
+                Ok(replies.remove(0))
+            } else {
+                Ok(replies[0].clone())
+            }
+        }
+
+        fn provider_name(&self) -> &str {
+            "Mock"
+        }
+
+        fn default_model(&self) -> &str {
+            "mock"
+        }
+    }
+
+    fn tool_only_reply(name: &str) -> ChatResponse {
+        ChatResponse {
+            content: String::new(),
+            tool_calls: Some(vec![ProviderToolCall {
+                id: "call-1".to_string(),
+                name: "get_contact_info".to_string(),
+                arguments: serde_json::json!({ "name": name }),
+            }]),
+            usage: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_only_turn_is_followed_by_final_ui() {
+        let ui_reply = ChatResponse {
+            content: concat!(
+                "Nobody matched that name.\n",
+                r#"A2UI_MESSAGES: [{"beginRendering": {"surfaceId": "main", "root": "note"}}, "#,
+                r#"{"surfaceUpdate": {"surfaceId": "main", "components": [{"id": "note", "component": {"Text": {"text": {"literalString": "No matches"}}}}]}}]"#
+            )
+            .to_string(),
+            tool_calls: None,
+            usage: None,
+        };
+        // No one matches, so there is no template to render and the model has to answer
+        let provider = ScriptedProvider::new(vec![tool_only_reply("nobody"), ui_reply]);
+        let agent = A2UIAgent::new(provider.clone()).unwrap();
+
+        let response = agent.handle_message("tool-session", "find nobody", true).await.unwrap();
+
+        assert!(response.content.starts_with("Nobody matched that name."));
+        assert_eq!(response.a2ui_messages.len(), 2);
+
+        let context = agent.get_session("tool-session").await.unwrap().context;
+        assert_eq!(context.conversation_state, ConversationState::Complete);
+        let events: Vec<ConversationEvent> = context.transitions.iter().map(|t| t.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                ConversationEvent::MessageReceived,
+                ConversationEvent::ToolCallsRequested,
+                ConversationEvent::ToolResultsReturned,
+                ConversationEvent::ReplyReceived,
+                ConversationEvent::ValidationPassed,
+            ]
+        );
+
+        let requests = provider.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let followup = &requests[1].messages;
+        assert_eq!(followup.len(), 4);
+        assert_eq!(followup[1].role, "assistant");
+        assert_eq!(followup[1].tool_calls[0].name, "get_contact_info");
+        assert_eq!(followup[2].role, "tool");
+        assert_eq!(followup[2].tool_call_id.as_deref(), Some("call-1"));
+        assert!(followup[2].content.starts_with(r#"{"contacts":[]"#));
+        assert_eq!(followup[3].role, "user");
+    }
+
+    #[tokio::test]
+    async fn test_endless_tool_calls_hit_the_iteration_limit() {
+        let provider = ScriptedProvider::new(vec![tool_only_reply("nobody")]);
+        let agent = A2UIAgent::new(provider.clone()).unwrap();
+
+        let result = agent.handle_message("loop-session", "find nobody", true).await;
+
+        assert!(matches!(
+            result,
+            Err(A2UIAgentError::ToolIterationsExceeded(MAX_TOOL_ITERATIONS))
+        ));
+        assert_eq!(provider.requests.lock().unwrap().len(), MAX_TOOL_ITERATIONS);
+    }
+
+    #[tokio::test]
+    async fn test_failed_tool_call_is_reported_alongside_successful_data() {
+        let failing_call = ProviderToolCall {
+            id: "call-1".to_string(),
+            name: "lookup_calendar".to_string(),
+            arguments: serde_json::json!({}),
+        };
+        let contact_call = ProviderToolCall {
+            id: "call-2".to_string(),
+            name: "get_contact_info".to_string(),
+            arguments: serde_json::json!({ "name": "jane" }),
+        };
+        let provider = ScriptedProvider::new(vec![ChatResponse {
+            content: "Here is who I found.".to_string(),
+            tool_calls: Some(vec![failing_call.clone(), contact_call]),
+            usage: None,
+        }]);
+        let agent = A2UIAgent::new(provider).unwrap();
+
+        let response = agent
+            .handle_message("partial-session", "find Jane", true)
+            .await
+            .unwrap();
+
+        let contacts = contact_patches(&response.a2ui_messages);
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0]["name"], "Jane Smith");
+        assert_eq!(response.tool_errors.len(), 1);
+        assert_eq!(response.tool_errors[0].tool, "lookup_calendar");
+
+        // With nothing usable left, the turn degrades to an apology naming the failure
+        let provider = ScriptedProvider::new(vec![ChatResponse {
+            content: String::new(),
+            tool_calls: Some(vec![failing_call]),
+            usage: None,
+        }]);
+        let agent = A2UIAgent::new(provider).unwrap();
+
+        let response = agent
+            .handle_message("failed-session", "what's on today", true)
+            .await
+            .unwrap();
+
+        assert!(response.a2ui_messages.is_empty());
+        assert!(response.content.contains("lookup_calendar"));
+        // The model retried the failing call every turn, but it is reported once
+        assert_eq!(response.tool_errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tool_result_is_rendered_with_its_template() {
+        let provider = Arc::new(ToolCallingProvider {
+            call: ProviderToolCall {
+                id: "call-1".to_string(),
+                name: "get_contact_info".to_string(),
+                arguments: serde_json::json!({"name": "jane"}),
+            },
+        });
+        let agent = A2UIAgent::new(provider).unwrap();
+
+        let response = agent
+            .handle_message("template-session", "find Jane", true)
+            .await
+            .unwrap();
+
+        assert_eq!(response.content, "Here is who I found.");
+        assert!(matches!(
+            &response.a2ui_messages[0],
+            A2UIMessageResponse::BeginRendering(rendering) if rendering.surface_id == "contact-list"
+        ));
+        let contacts = contact_patches(&response.a2ui_messages);
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0]["name"], "Jane Smith");
+    }
+
+    #[tokio::test]
+    async fn test_mock_provider_responses_validate() {
+        let agent = A2UIAgent::new(Arc::new(MockProvider::new())).unwrap();
+
+        for query in [
+            "show my contacts",
+            "search for release notes",
+            "I need a sign up form",
+            "hello there",
+        ] {
+            let response = agent.handle_message("mock-session", query, true).await.unwrap();
+            assert!(!response.a2ui_messages.is_empty(), "no UI for {:?}", query);
+            agent.validate_a2ui_response(&response.a2ui_messages).unwrap();
+        }
+
+        let response = agent
+            .handle_message("mock-session", "show my contacts", false)
+            .await
+            .unwrap();
+        assert_eq!(response.content, "Here are your contacts.");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_followups_stop_at_the_depth_limit() {
+        let agent = A2UIAgent::new(Arc::new(MockProvider::new())).unwrap();
+
+        let followups = (0..MAX_FOLLOWUP_DEPTH + 2).map(|_| agent.handle_followup("followups", "show more contacts"));
+        let results = futures::future::join_all(followups).await;
+
+        let accepted = results.iter().filter(|result| result.is_ok()).count();
+        assert_eq!(accepted, MAX_FOLLOWUP_DEPTH);
+        assert!(results
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .all(|e| matches!(e, A2UIAgentError::FollowupDepthExceeded(depth) if *depth == MAX_FOLLOWUP_DEPTH)));
+    }
+
+    fn limited_agent(policy: SessionLimitPolicy) -> A2UIAgent {
+        let config = A2UIConfig {
+            session_limit: SessionLimit {
+                max_sessions: Some(2),
+                policy,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        A2UIAgent::with_config(
+            Arc::new(StaticProvider {
+                content: "ok".to_string(),
+            }),
+            config,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_session_limit_evicts_least_recently_updated() {
+        let agent = limited_agent(SessionLimitPolicy::Evict);
+        agent.handle_message("first", "hello", false).await.unwrap();
+        agent.handle_message("second", "hello", false).await.unwrap();
+
+        // Make "first" the most recently used session
+        let long_ago = Utc::now() - chrono::Duration::minutes(5);
+        agent.sessions.write().await.get_mut("second").unwrap().updated_at = long_ago;
+
+        agent.handle_message("third", "hello", false).await.unwrap();
+        let mut sessions = agent.list_sessions().await.unwrap();
+        sessions.sort();
+        assert_eq!(sessions, vec!["first", "third"]);
+
+        // Messages to an existing session never evict anything
+        agent.handle_message("first", "again", false).await.unwrap();
+        assert_eq!(agent.list_sessions().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_session_limit_rejects_new_sessions() {
+        let agent = limited_agent(SessionLimitPolicy::Reject);
+        agent.handle_message("first", "hello", false).await.unwrap();
+        agent.handle_message("second", "hello", false).await.unwrap();
+
+        assert!(matches!(
+            agent.handle_message("third", "hello", false).await,
+            Err(A2UIAgentError::SessionLimitExceeded(2))
+        ));
+        assert!(matches!(
+            agent.fork_session("first").await,
+            Err(A2UIAgentError::SessionLimitExceeded(2))
+        ));
+        assert_eq!(agent.list_sessions().await.unwrap().len(), 2);
+        agent.handle_message("first", "again", false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_forked_session_continues_independently() {
+        let agent = A2UIAgent::new(Arc::new(StaticProvider {
+            content: "ok".to_string(),
+        }))
+        .unwrap();
+        agent.handle_message("original", "hello", false).await.unwrap();
+
+        let fork_id = agent.fork_session("original").await.unwrap();
+        assert_ne!(fork_id, "original");
+        agent.handle_message(&fork_id, "only in the fork", false).await.unwrap();
+
+        let original = agent.get_session("original").await.unwrap();
+        let fork = agent.get_session(&fork_id).await.unwrap();
+        assert_eq!(original.messages.len(), 2);
+        assert_eq!(fork.messages.len(), 4);
+        assert_eq!(fork.messages[0].content, original.messages[0].content);
+        assert_eq!(fork.messages[2].content, "only in the fork");
+        assert_eq!(fork.id, fork_id);
+        assert!(fork.created_at >= original.created_at);
+        assert!(matches!(
+            agent.fork_session("missing").await,
+            Err(A2UIAgentError::SessionNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_expire_on_sweep_and_before_new_sessions() {
+        let config = A2UIConfig {
+            session_limit: SessionLimit {
+                idle_ttl_secs: Some(60),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let agent = A2UIAgent::with_config(
+            Arc::new(StaticProvider {
+                content: "ok".to_string(),
+            }),
+            config,
+        )
+        .unwrap();
+        for session_id in ["active", "idle", "stale"] {
+            agent.handle_message(session_id, "hello", false).await.unwrap();
+        }
+        let long_ago = Utc::now() - chrono::Duration::minutes(5);
+        agent.sessions.write().await.get_mut("idle").unwrap().updated_at = long_ago;
+
+        assert_eq!(agent.expire_idle_sessions().await, vec!["idle"]);
+        assert!(agent.expire_idle_sessions().await.is_empty());
+
+        // Creating a session sweeps without waiting for the next periodic one
+        agent.sessions.write().await.get_mut("stale").unwrap().updated_at = long_ago;
+        agent.handle_message("new", "hello", false).await.unwrap();
+        let mut sessions = agent.list_sessions().await.unwrap();
+        sessions.sort();
+        assert_eq!(sessions, vec!["active", "new"]);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_survive_a_restart_through_the_store() {
+        let storage: Arc<dyn crate::storage::Storage> = Arc::new(crate::storage::MemoryStorage::new());
+        let store = SessionStore::new(Arc::clone(&storage), "sessions/a2ui");
+
+        let agent = limited_agent(SessionLimitPolicy::Evict).with_session_store(store.clone());
+        agent.handle_message("first", "hello", false).await.unwrap();
+        let fork_id = agent.fork_session("first").await.unwrap();
+        agent.handle_message(&fork_id, "again", false).await.unwrap();
+        // At the limit of 2, this evicts "first" from memory but keeps its saved history
+        agent.handle_message("third", "hello", false).await.unwrap();
+        assert!(agent.get_session("first").await.is_err());
+        agent.delete_session("third").await.unwrap();
+        store.flush();
+
+        let restarted = limited_agent(SessionLimitPolicy::Evict).with_session_store(store);
+        let mut sessions = restarted.list_sessions().await.unwrap();
+        sessions.sort();
+        let mut expected = vec!["first".to_string(), fork_id.clone()];
+        expected.sort();
+        assert_eq!(sessions, expected);
+        let fork = restarted.get_session(&fork_id).await.unwrap();
+        let contents: Vec<_> = fork.messages.iter().map(|message| message.content.as_str()).collect();
+        assert_eq!(contents, vec!["hello", "ok", "again", "ok"]);
+    }
+
+    /// Always replies with `content`, under its own provider name
+    struct NamedProvider {
+        name: &'static str,
+        content: String,
+    }
+
+    #[async_trait]
+    impl AIProvider for NamedProvider {
+        async fn chat_completion(&self, _request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+            Ok(ChatResponse {
+                content: self.content.clone(),
+                tool_calls: None,
+                usage: None,
+            })
+        }
+
+        fn provider_name(&self) -> &str {
+            self.name
+        }
+
+        fn default_model(&self) -> &str {
+            "mock"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_provider_produces_ui_after_invalid_retries() {
+        let invalid_ui = ChatResponse {
+            content: concat!(
+                "Here is a card.\n",
+                r#"A2UI_MESSAGES: [{"beginRendering": {"surfaceId": "main", "root": "card"}}, "#,
+                r#"{"surfaceUpdate": {"surfaceId": "main", "components": [{"id": "card", "component": {"Card": {}}}]}}]"#
+            )
+            .to_string(),
+            tool_calls: None,
+            usage: None,
+        };
+        let primary = ScriptedProvider::new(vec![invalid_ui]);
+        let fallback = Arc::new(NamedProvider {
+            name: "Structured",
+            content: concat!(
+                "Here is a note.\n",
+                r#"A2UI_MESSAGES: [{"beginRendering": {"surfaceId": "main", "root": "note"}}, "#,
+                r#"{"surfaceUpdate": {"surfaceId": "main", "components": [{"id": "note", "component": {"Text": {"text": {"literalString": "Hi"}}}}]}}]"#
+            )
+            .to_string(),
+        });
+        let config = A2UIConfig {
+            max_ui_retries: 2,
+            ..Default::default()
+        };
+
+        let agent = A2UIAgent::with_config(primary.clone(), config.clone()).unwrap();
+        assert!(matches!(
+            agent.handle_message("no-fallback", "show a card", true).await,
+            Err(A2UIAgentError::ValidationError(_))
+        ));
+        assert_eq!(
+            primary.requests.lock().unwrap().len(),
+            3,
+            "first attempt plus two retries"
+        );
+
+        let agent = A2UIAgent::with_config(primary.clone(), config)
+            .unwrap()
+            .with_fallback_provider(fallback);
+        let response = agent.handle_message("fallback", "show a card", true).await.unwrap();
+
+        assert!(response.content.starts_with("Here is a note."));
+        assert_eq!(response.a2ui_messages.len(), 2);
+        agent.validate_a2ui_response(&response.a2ui_messages).unwrap();
+        assert_eq!(response.provider.as_deref(), Some("Structured"));
+        assert_eq!(primary.requests.lock().unwrap().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_ui_is_retried_with_the_validation_error() {
+        let invalid_ui = ChatResponse {
+            content: concat!(
+                "Here is a card.\n",
+                r#"A2UI_MESSAGES: [{"beginRendering": {"surfaceId": "main", "root": "card"}}, "#,
+                r#"{"surfaceUpdate": {"surfaceId": "main", "components": [{"id": "card", "component": {"Card": {}}}]}}]"#
+            )
+            .to_string(),
+            tool_calls: None,
+            usage: None,
+        };
+        let fixed_ui = ChatResponse {
+            content: concat!(
+                "Here is a note.\n",
+                r#"A2UI_MESSAGES: [{"beginRendering": {"surfaceId": "main", "root": "note"}}, "#,
+                r#"{"surfaceUpdate": {"surfaceId": "main", "components": [{"id": "note", "component": {"Text": {"text": {"literalString": "Hi"}}}}]}}]"#
+            )
+            .to_string(),
+            tool_calls: None,
+            usage: None,
+        };
+        let provider = ScriptedProvider::new(vec![invalid_ui.clone(), fixed_ui]);
+        // Retries are on by default, for agents built without a config too
+        let config: A2UIConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.max_ui_retries, DEFAULT_MAX_UI_RETRIES);
+        let agent = A2UIAgent::new(provider.clone()).unwrap();
+
+        let response = agent
+            .handle_message("retry-session", "show a card", true)
+            .await
+            .unwrap();
+        assert!(response.content.starts_with("Here is a note."));
+        assert_eq!(response.a2ui_messages.len(), 2);
+
+        let requests = provider.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let retry = &requests[1].messages;
+        assert_eq!(retry.len(), requests[0].messages.len() + 2);
+        assert_eq!(retry[retry.len() - 2].role, "assistant");
+        assert_eq!(retry[retry.len() - 2].content, invalid_ui.content);
+        let feedback = &retry[retry.len() - 1].content;
+        assert!(feedback.starts_with("Your A2UI messages were rejected: Validation error"));
+        assert!(feedback.contains("A2UI_MESSAGES:"));
+    }
+
+    /// Never answers within a test, recording whether the call ran to completion
+    struct HangingProvider {
+        finished: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    #[async_trait]
+    impl AIProvider for HangingProvider {
+        async fn chat_completion(&self, _request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            self.finished.store(true, std::sync::atomic::Ordering::SeqCst);
+            Err(ProviderError::InvalidResponse("should have been aborted".to_string()))
+        }
+
+        fn provider_name(&self) -> &str {
+            "Hanging"
+        }
+
+        fn default_model(&self) -> &str {
+            "mock"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_aborts_in_flight_provider_call() {
+        let finished = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let agent = A2UIAgent::new(Arc::new(HangingProvider {
+            finished: Arc::clone(&finished),
+        }))
+        .unwrap();
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            trigger.cancel();
+        });
+
+        let started = std::time::Instant::now();
+        let result = agent
+            .handle_message_cancellable("cancelled", "show a card", true, &cancel)
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(A2UIAgentError::ProviderError(ProviderError::Cancelled))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!finished.load(std::sync::atomic::Ordering::SeqCst));
+        let session = agent.get_session("cancelled").await.unwrap();
+        assert_eq!(session.messages.len(), 1, "no assistant reply is recorded");
+    }
+
+    #[tokio::test]
+    async fn test_wrong_language_copy_is_reported_or_rewritten() {
+        let ui_reply = ChatResponse {
+            content: concat!(
+                "Greeting.\n",
+                r#"A2UI_MESSAGES: [{"beginRendering": {"surfaceId": "main", "root": "note"}}, "#,
+                r#"{"surfaceUpdate": {"surfaceId": "main", "components": [{"id": "note", "component": {"Text": {"text": {"literalString": "你好世界"}}}}]}}]"#
+            )
+            .to_string(),
+            tool_calls: None,
+            usage: None,
+        };
+        let rewrite_reply = ChatResponse {
+            content: r#"{"main/note": "Hello world"}"#.to_string(),
+            tool_calls: None,
+            usage: None,
+        };
+        let mut config = A2UIConfig {
+            output_style: OutputStyle {
+                language: Some("en".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let agent = A2UIAgent::with_config(ScriptedProvider::new(vec![ui_reply.clone()]), config.clone()).unwrap();
+        let response = agent.handle_message("warn", "greet me", true).await.unwrap();
+        assert_eq!(response.style_warnings.len(), 1);
+        assert_eq!(response.style_warnings[0].component_id, "note");
+
+        config.output_style.enforcement = StyleEnforcement::Rewrite;
+        let provider = ScriptedProvider::new(vec![ui_reply, rewrite_reply]);
+        let agent = A2UIAgent::with_config(provider.clone(), config).unwrap();
+        let response = agent.handle_message("rewrite", "greet me", true).await.unwrap();
+        assert!(response.style_warnings.is_empty());
+        let serialized = serde_json::to_string(&response.a2ui_messages).unwrap();
+        assert!(serialized.contains("Hello world"));
+        assert!(!serialized.contains("你好世界"));
+
+        let requests = provider.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].tools.is_none());
+        assert!(requests[1].messages[0].content.contains(r#""main/note": "你好世界""#));
+    }
+
+    #[tokio::test]
+    async fn test_replay_regenerates_without_touching_the_session() {
+        let provider = Arc::new(StaticProvider {
+            content: concat!(
+                "Here you go.\n",
+                r#"A2UI_MESSAGES: [{"beginRendering": {"surfaceId": "main", "root": "note"}}, "#,
+                r#"{"surfaceUpdate": {"surfaceId": "main", "components": [{"id": "note", "component": {"Text": {"text": {"literalString": "Hi"}}}}]}}]"#
+            )
+            .to_string(),
+        });
+        let agent = A2UIAgent::new(provider).unwrap();
+        agent.handle_message("replayed", "say hi", true).await.unwrap();
+        let before = serde_json::to_value(agent.get_session("replayed").await.unwrap()).unwrap();
+
+        let turns = agent.replay_session("replayed", None, true).await.unwrap();
+
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].index, 0);
+        assert_eq!(turns[0].message, "say hi");
+        assert!(turns[0].original.as_deref().unwrap().starts_with("Here you go."));
+        assert_eq!(turns[0].response.as_ref().unwrap().a2ui_messages.len(), 2);
+        assert!(turns[0].error.is_none());
+        let after = serde_json::to_value(agent.get_session("replayed").await.unwrap()).unwrap();
+        assert_eq!(before, after);
+        assert_eq!(agent.list_sessions().await.unwrap().len(), 1);
+        assert!(matches!(
+            agent.replay_session("missing", None, true).await,
+            Err(A2UIAgentError::SessionNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,314 @@
+//! Component-id collision handling for agent output
+//!
+//! Models tend to reuse ids like `title` or `container` across surfaces and updates.
+//! A declaration collides when a different surface declares the same id in the
+//! response, or already owns it; redeclaring an id on the same surface is an update.
+//! Collisions can be rejected, or namespaced as `{surfaceId}-{messageIndex}-{id}`,
+//! using the first declaring message, with every reference rewritten to match.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::agent::A2UIMessageResponse;
+use super::schema::{Children, UIComponent, UIComponentType};
+
+/// What to do with colliding component ids in an agent response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentIdPolicy {
+    /// Pass responses through unchanged
+    #[default]
+    Off,
+    /// Fail the response with an error listing the duplicates
+    Reject,
+    /// Rename colliding declarations and their references
+    Namespace,
+}
+
+impl ComponentIdPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "off" | "" => Some(Self::Off),
+            "reject" => Some(Self::Reject),
+            "namespace" => Some(Self::Namespace),
+            _ => None,
+        }
+    }
+}
+
+/// Component ids already declared on each surface, keyed by surface id
+pub type SurfaceComponents = HashMap<String, HashSet<String>>;
+
+/// Ids in `messages` that collide, each with the surfaces declaring or owning it
+pub fn find_duplicates(
+    messages: &[A2UIMessageResponse],
+    existing: &SurfaceComponents,
+) -> BTreeMap<String, Vec<String>> {
+    let declarations = declarations(messages);
+
+    let mut declaring_surfaces: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for (_, surface_id, id) in &declarations {
+        declaring_surfaces
+            .entry(id.as_str())
+            .or_default()
+            .insert(surface_id.as_str());
+    }
+
+    let mut duplicates: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (_, surface_id, id) in &declarations {
+        let other_owners: Vec<&String> = existing
+            .iter()
+            .filter(|(owner, ids)| *owner != surface_id && ids.contains(id))
+            .map(|(owner, _)| owner)
+            .collect();
+
+        if declaring_surfaces[id.as_str()].len() > 1 || !other_owners.is_empty() {
+            let surfaces = duplicates.entry(id.clone()).or_default();
+            for surface in other_owners.into_iter().chain(std::iter::once(surface_id)) {
+                if !surfaces.contains(surface) {
+                    surfaces.push(surface.clone());
+                }
+            }
+        }
+    }
+    for surfaces in duplicates.values_mut() {
+        surfaces.sort();
+    }
+    duplicates
+}
+
+/// Human-readable description of duplicates, e.g. `title (main, sidebar)`
+pub fn describe_duplicates(duplicates: &BTreeMap<String, Vec<String>>) -> String {
+    duplicates
+        .iter()
+        .map(|(id, surfaces)| format!("{} ({})", id, surfaces.join(", ")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Rename colliding declarations and rewrite references to them
+///
+/// Every declaration and reference of a colliding id on one surface gets the same name,
+/// taken from its first declaration there, so later updates on that surface still apply.
+pub fn namespace_duplicates(messages: &mut [A2UIMessageResponse], existing: &SurfaceComponents) {
+    let duplicates = find_duplicates(messages, existing);
+    if duplicates.is_empty() {
+        return;
+    }
+
+    let mut surface_renames: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for (index, surface_id, id) in declarations(messages) {
+        if !duplicates.contains_key(&id) {
+            continue;
+        }
+        let renamed = format!("{}-{}-{}", surface_id, index, id);
+        surface_renames
+            .entry(surface_id)
+            .or_default()
+            .entry(id)
+            .or_insert(renamed);
+    }
+
+    let no_renames = HashMap::new();
+    for message in messages.iter_mut() {
+        match message {
+            A2UIMessageResponse::SurfaceUpdate(update) => {
+                let surface = surface_renames.get(&update.surface_id).unwrap_or(&no_renames);
+                let rename = |id: &mut String| {
+                    if let Some(renamed) = surface.get(id.as_str()) {
+                        *id = renamed.clone();
+                    }
+                };
+                for component in &mut update.components {
+                    rewrite_component(component, rename);
+                }
+            }
+            A2UIMessageResponse::BeginRendering(rendering) => {
+                if let Some(renamed) = surface_renames
+                    .get(&rendering.surface_id)
+                    .and_then(|renames| renames.get(&rendering.root))
+                {
+                    rendering.root = renamed.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Record the component ids each surface declares, forgetting deleted surfaces
+pub fn record_declarations(messages: &[A2UIMessageResponse], surfaces: &mut SurfaceComponents) {
+    for message in messages {
+        match message {
+            A2UIMessageResponse::SurfaceUpdate(update) => {
+                let ids = surfaces.entry(update.surface_id.clone()).or_default();
+                ids.extend(update.components.iter().map(|component| component.id.clone()));
+            }
+            A2UIMessageResponse::DeleteSurface(delete) => {
+                surfaces.remove(&delete.surface_id);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `(message index, surface id, component id)` for every declared component
+fn declarations(messages: &[A2UIMessageResponse]) -> Vec<(usize, String, String)> {
+    messages
+        .iter()
+        .enumerate()
+        .filter_map(|(index, message)| match message {
+            A2UIMessageResponse::SurfaceUpdate(update) => Some((index, update)),
+            _ => None,
+        })
+        .flat_map(|(index, update)| {
+            update
+                .components
+                .iter()
+                .map(move |component| (index, update.surface_id.clone(), component.id.clone()))
+        })
+        .collect()
+}
+
+/// Apply `rename` to a component's own id and every id it references
+fn rewrite_component(component: &mut UIComponent, rename: impl Fn(&mut String)) {
+    rename(&mut component.id);
+
+    let rename_children = |children: &mut Children| {
+        for id in children.explicit_list.iter_mut().flatten() {
+            rename(id);
+        }
+        if let Some(template) = children.template.as_mut() {
+            rename(&mut template.component_id);
+        }
+    };
+
+    match &mut component.component {
+        UIComponentType::Button { child, .. } => rename(child),
+        UIComponentType::Row { children, .. }
+        | UIComponentType::Column { children, .. }
+        | UIComponentType::List { children, .. } => rename_children(children),
+        UIComponentType::Card { child, children } => {
+            if let Some(child) = child {
+                rename(child);
+            }
+            if let Some(children) = children {
+                rename_children(children);
+            }
+        }
+        UIComponentType::Tabs { tab_items, .. } => {
+            for tab in tab_items {
+                rename(&mut tab.child);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(value: serde_json::Value) -> Vec<A2UIMessageResponse> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn two_surfaces() -> Vec<A2UIMessageResponse> {
+        messages(serde_json::json!([
+            {"beginRendering": {"surfaceId": "main", "root": "root"}},
+            {"surfaceUpdate": {"surfaceId": "main", "components": [
+                {"id": "root", "component": {"Column": {"children": {"explicitList": ["title", "card"]}}}},
+                {"id": "title", "component": {"Text": {"text": {"literalString": "Main"}}}},
+                {"id": "card", "component": {"Card": {"child": "body"}}},
+                {"id": "body", "component": {"Text": {"text": {"literalString": "Body"}}}}
+            ]}},
+            {"beginRendering": {"surfaceId": "sidebar", "root": "root"}},
+            {"surfaceUpdate": {"surfaceId": "sidebar", "components": [
+                {"id": "root", "component": {"Row": {"children": {"explicitList": ["title"]}}}},
+                {"id": "title", "component": {"Text": {"text": {"literalString": "Sidebar"}}}}
+            ]}}
+        ]))
+    }
+
+    #[test]
+    fn test_duplicate_ids_are_detected() {
+        let duplicates = find_duplicates(&two_surfaces(), &SurfaceComponents::new());
+        assert_eq!(duplicates.keys().collect::<Vec<_>>(), vec!["root", "title"]);
+        assert_eq!(duplicates["title"], vec!["main", "sidebar"]);
+        assert_eq!(
+            describe_duplicates(&duplicates),
+            "root (main, sidebar), title (main, sidebar)"
+        );
+
+        // An id owned by another surface collides; redeclaring on the same surface is an update
+        let single = messages(serde_json::json!([
+            {"surfaceUpdate": {"surfaceId": "main", "components": [
+                {"id": "status", "component": {"Text": {"text": {"literalString": "Ready"}}}}
+            ]}}
+        ]));
+        let owned_by_main = SurfaceComponents::from([("main".to_string(), HashSet::from(["status".to_string()]))]);
+        assert!(find_duplicates(&single, &owned_by_main).is_empty());
+        let owned_by_other = SurfaceComponents::from([("other".to_string(), HashSet::from(["status".to_string()]))]);
+        assert_eq!(
+            find_duplicates(&single, &owned_by_other)["status"],
+            vec!["main", "other"]
+        );
+    }
+
+    #[test]
+    fn test_namespacing_rewrites_declarations_and_references() {
+        let mut response = two_surfaces();
+        namespace_duplicates(&mut response, &SurfaceComponents::new());
+
+        assert!(find_duplicates(&response, &SurfaceComponents::new()).is_empty());
+        let json = serde_json::to_value(&response).unwrap();
+
+        assert_eq!(json[0]["beginRendering"]["root"], "main-1-root");
+        let main = &json[1]["surfaceUpdate"]["components"];
+        assert_eq!(main[0]["id"], "main-1-root");
+        assert_eq!(
+            main[0]["component"]["Column"]["children"]["explicitList"],
+            serde_json::json!(["main-1-title", "card"])
+        );
+        assert_eq!(main[1]["id"], "main-1-title");
+        // Non-colliding ids are left alone
+        assert_eq!(main[2]["component"]["Card"]["child"], "body");
+
+        assert_eq!(json[2]["beginRendering"]["root"], "sidebar-3-root");
+        let sidebar = &json[3]["surfaceUpdate"]["components"];
+        assert_eq!(sidebar[0]["id"], "sidebar-3-root");
+        assert_eq!(
+            sidebar[0]["component"]["Row"]["children"]["explicitList"],
+            serde_json::json!(["sidebar-3-title"])
+        );
+        assert_eq!(sidebar[1]["id"], "sidebar-3-title");
+    }
+
+    #[test]
+    fn test_redeclaring_on_the_same_surface_is_not_namespaced() {
+        let update = |surface: &str, text: &str| {
+            serde_json::json!({"surfaceUpdate": {"surfaceId": surface, "components": [
+                {"id": "title", "component": {"Text": {"text": {"literalString": text}}}}
+            ]}})
+        };
+
+        let mut same_surface = messages(serde_json::json!([update("main", "Loading"), update("main", "Done")]));
+        assert!(find_duplicates(&same_surface, &SurfaceComponents::new()).is_empty());
+        namespace_duplicates(&mut same_surface, &SurfaceComponents::new());
+        let json = serde_json::to_value(&same_surface).unwrap();
+        assert_eq!(json[0]["surfaceUpdate"]["components"][0]["id"], "title");
+        assert_eq!(json[1]["surfaceUpdate"]["components"][0]["id"], "title");
+
+        // Once another surface collides, the surface's updates keep sharing one name
+        let mut across = messages(serde_json::json!([
+            update("main", "Loading"),
+            update("sidebar", "Menu"),
+            update("main", "Done")
+        ]));
+        namespace_duplicates(&mut across, &SurfaceComponents::new());
+        let json = serde_json::to_value(&across).unwrap();
+        assert_eq!(json[0]["surfaceUpdate"]["components"][0]["id"], "main-0-title");
+        assert_eq!(json[1]["surfaceUpdate"]["components"][0]["id"], "sidebar-1-title");
+        assert_eq!(json[2]["surfaceUpdate"]["components"][0]["id"], "main-0-title");
+    }
+}
@@ -0,0 +1,163 @@
+//! Conversation state machine for A2UI sessions
+//!
+//! A turn moves its session through explicit events rather than ad-hoc assignments:
+//! a message starts response generation, tool calls loop through `ToolCalling`, and a UI
+//! reply is validated before the turn completes (or generation resumes after a failed
+//! validation). An event that doesn't apply in the current state leaves it unchanged, and
+//! each applied transition is kept in a short audit log on the session.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Transitions kept in a session's audit log
+pub const MAX_RECORDED_TRANSITIONS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConversationState {
+    Initial,
+    ToolCalling,
+    ResponseGeneration,
+    Validation,
+    Complete,
+}
+
+/// Something that happened during a turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConversationEvent {
+    /// A user message arrived; allowed in any state, abandoning an unfinished turn
+    MessageReceived,
+    /// The model asked for tool calls
+    ToolCallsRequested,
+    /// Tool results were sent back to the model
+    ToolResultsReturned,
+    /// The model gave a text reply that needs no validation
+    ResponseCompleted,
+    /// A UI reply (from the model or a template) is ready to be validated
+    ReplyReceived,
+    ValidationPassed,
+    ValidationFailed,
+}
+
+impl ConversationEvent {
+    pub const ALL: [ConversationEvent; 7] = [
+        ConversationEvent::MessageReceived,
+        ConversationEvent::ToolCallsRequested,
+        ConversationEvent::ToolResultsReturned,
+        ConversationEvent::ResponseCompleted,
+        ConversationEvent::ReplyReceived,
+        ConversationEvent::ValidationPassed,
+        ConversationEvent::ValidationFailed,
+    ];
+}
+
+/// An applied transition, for the session's audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateTransition {
+    pub from: ConversationState,
+    pub to: ConversationState,
+    pub event: ConversationEvent,
+    pub at: DateTime<Utc>,
+}
+
+/// The state `event` leads to from `current`, or `None` if it doesn't apply there
+pub fn transition(current: ConversationState, event: ConversationEvent) -> Option<ConversationState> {
+    use ConversationEvent as Event;
+    use ConversationState as State;
+
+    match (current, event) {
+        (_, Event::MessageReceived) => Some(State::ResponseGeneration),
+        (State::ResponseGeneration, Event::ToolCallsRequested) => Some(State::ToolCalling),
+        (State::ToolCalling, Event::ToolResultsReturned) => Some(State::ResponseGeneration),
+        (State::ResponseGeneration, Event::ResponseCompleted) => Some(State::Complete),
+        (State::ResponseGeneration, Event::ReplyReceived) => Some(State::Validation),
+        (State::Validation, Event::ValidationPassed) => Some(State::Complete),
+        (State::Validation, Event::ValidationFailed) => Some(State::ResponseGeneration),
+        _ => None,
+    }
+}
+
+impl ConversationState {
+    /// Events that would move a session out of this state
+    pub fn allowed_events(self) -> Vec<ConversationEvent> {
+        ConversationEvent::ALL
+            .into_iter()
+            .filter(|event| transition(self, *event).is_some())
+            .collect()
+    }
+}
+
+/// Apply `event` to `state`, logging the transition; returns whether it applied
+pub fn apply_event(
+    state: &mut ConversationState,
+    transitions: &mut Vec<StateTransition>,
+    event: ConversationEvent,
+) -> bool {
+    let Some(next) = transition(*state, event) else {
+        return false;
+    };
+
+    transitions.push(StateTransition {
+        from: *state,
+        to: next,
+        event,
+        at: Utc::now(),
+    });
+    if transitions.len() > MAX_RECORDED_TRANSITIONS {
+        transitions.drain(..transitions.len() - MAX_RECORDED_TRANSITIONS);
+    }
+    *state = next;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ConversationEvent as Event;
+    use ConversationState as State;
+
+    #[test]
+    fn test_turn_walks_through_defined_transitions() {
+        let mut state = State::Initial;
+        let mut transitions = Vec::new();
+        let turn = [
+            (Event::MessageReceived, State::ResponseGeneration),
+            (Event::ToolCallsRequested, State::ToolCalling),
+            (Event::ToolResultsReturned, State::ResponseGeneration),
+            (Event::ReplyReceived, State::Validation),
+            (Event::ValidationFailed, State::ResponseGeneration),
+            (Event::ReplyReceived, State::Validation),
+            (Event::ValidationPassed, State::Complete),
+        ];
+        for (event, expected) in turn {
+            assert!(apply_event(&mut state, &mut transitions, event));
+            assert_eq!(state, expected, "after {:?}", event);
+        }
+
+        assert_eq!(transitions.len(), turn.len());
+        assert_eq!(transitions[0].from, State::Initial);
+        assert_eq!(transitions[1].event, Event::ToolCallsRequested);
+        assert_eq!(transitions.last().unwrap().to, State::Complete);
+        assert_eq!(State::Complete.allowed_events(), vec![Event::MessageReceived]);
+
+        // A text reply completes without validation
+        assert_eq!(
+            transition(State::ResponseGeneration, Event::ResponseCompleted),
+            Some(State::Complete)
+        );
+    }
+
+    #[test]
+    fn test_invalid_event_is_a_no_op() {
+        let mut state = State::Initial;
+        let mut transitions = Vec::new();
+
+        assert!(!apply_event(&mut state, &mut transitions, Event::ValidationPassed));
+        assert!(!apply_event(&mut state, &mut transitions, Event::ToolResultsReturned));
+
+        assert_eq!(state, State::Initial);
+        assert!(transitions.is_empty());
+        assert_eq!(transition(State::Complete, Event::ToolCallsRequested), None);
+        assert!(!State::Initial.allowed_events().contains(&Event::ValidationPassed));
+    }
+}
@@ -1,7 +1,13 @@
 pub mod agent;
+pub mod component_ids;
+pub mod conversation;
+pub mod output_style;
 pub mod plugin_generator;
 pub mod provider;
+pub mod references;
 pub mod schema;
+pub mod sse;
+pub mod text;
 
 // Re-export main types for convenience
 // pub use agent::*;
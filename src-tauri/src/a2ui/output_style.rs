@@ -0,0 +1,361 @@
+//! Output language and style checks for the copy of generated Text components
+//!
+//! Nothing constrains the `literalString`s a model writes into Text components, so copy
+//! can come back in the wrong language or with markdown the renderer shows verbatim. With
+//! an output style configured, every literal is checked after generation and mismatches are
+//! reported as warnings; in rewrite mode markdown is stripped and wrong-language copy gets
+//! one follow-up rewrite. Languages are told apart by script, so ones sharing a script
+//! (English and French, say) are not distinguished.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::agent::A2UIMessageResponse;
+use super::schema::UIComponentType;
+use super::text;
+use crate::logging::env_flag;
+
+/// Fewer letters than this are too little to tell a text's script
+const MIN_LETTERS: usize = 3;
+
+/// What to do with copy that doesn't match the configured style
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StyleEnforcement {
+    /// Report mismatches as warnings only
+    #[default]
+    Warn,
+    /// Strip markdown and ask the provider to rewrite wrong-language copy, then report what's left
+    Rewrite,
+}
+
+impl StyleEnforcement {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "warn" | "" => Some(Self::Warn),
+            "rewrite" => Some(Self::Rewrite),
+            _ => None,
+        }
+    }
+}
+
+/// Language and style the copy of Text components should follow
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutputStyle {
+    /// Language code such as `en`, `zh-CN` or `ja`; `None` skips the language check
+    pub language: Option<String>,
+    /// Copy must be plain text, without markdown syntax
+    pub plain_text: bool,
+    pub enforcement: StyleEnforcement,
+}
+
+/// A Text component whose copy doesn't match the configured style
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StyleWarning {
+    pub surface_id: String,
+    pub component_id: String,
+    pub issue: StyleIssue,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StyleIssue {
+    /// Written in `detected` script rather than one used by the `expected` language
+    WrongLanguage {
+        expected: String,
+        detected: Script,
+    },
+    Markdown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Script {
+    Latin,
+    Han,
+    Kana,
+    Hangul,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+    Thai,
+}
+
+impl OutputStyle {
+    /// Read the style from `A2UI_OUTPUT_LANGUAGE`, `A2UI_PLAIN_TEXT_COPY` and
+    /// `A2UI_OUTPUT_STYLE` (`warn` or `rewrite`)
+    pub fn from_env() -> Self {
+        Self {
+            language: std::env::var("A2UI_OUTPUT_LANGUAGE")
+                .ok()
+                .map(|language| language.trim().to_string())
+                .filter(|language| !language.is_empty()),
+            plain_text: env_flag("A2UI_PLAIN_TEXT_COPY").unwrap_or(false),
+            enforcement: std::env::var("A2UI_OUTPUT_STYLE")
+                .ok()
+                .and_then(|value| StyleEnforcement::parse(&value))
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.language.is_some() || self.plain_text
+    }
+
+    /// Style mismatches in the literal copy of every Text component
+    pub fn check(&self, messages: &[A2UIMessageResponse]) -> Vec<StyleWarning> {
+        let mut warnings = Vec::new();
+        for (surface_id, component_id, copy) in literal_copy(messages) {
+            let mut warn = |issue| {
+                warnings.push(StyleWarning {
+                    surface_id: surface_id.to_string(),
+                    component_id: component_id.to_string(),
+                    issue,
+                })
+            };
+            if let Some(language) = &self.language {
+                if let Some(detected) =
+                    dominant_script(copy).filter(|script| !expected_scripts(language).contains(script))
+                {
+                    warn(StyleIssue::WrongLanguage {
+                        expected: language.clone(),
+                        detected,
+                    });
+                }
+            }
+            if self.plain_text && has_markdown(copy) {
+                warn(StyleIssue::Markdown);
+            }
+        }
+        warnings
+    }
+
+    /// Prompt asking for the wrong-language copy among `warnings` to be rewritten, as a JSON
+    /// object keyed by `rewrite_key`; `None` when there's nothing to rewrite
+    pub fn rewrite_prompt(&self, messages: &[A2UIMessageResponse], warnings: &[StyleWarning]) -> Option<String> {
+        let language = self.language.as_ref()?;
+        let copy: HashMap<String, &str> = literal_copy(messages)
+            .filter(|(surface_id, component_id, _)| {
+                warnings.iter().any(|warning| {
+                    matches!(warning.issue, StyleIssue::WrongLanguage { .. })
+                        && warning.surface_id == *surface_id
+                        && warning.component_id == *component_id
+                })
+            })
+            .map(|(surface_id, component_id, copy)| (rewrite_key(surface_id, component_id), copy))
+            .collect();
+        if copy.is_empty() {
+            return None;
+        }
+
+        Some(format!(
+            "Rewrite each user interface string below in the language with code '{}', keeping its meaning, \
+             tone and roughly its length. Reply with only a JSON object mapping each key to its rewritten string.\n\n{}",
+            language,
+            serde_json::to_string_pretty(&copy).ok()?
+        ))
+    }
+
+    /// Strip markdown from the literal copy of every Text component when plain text is required
+    pub fn strip_markdown(&self, messages: &mut [A2UIMessageResponse]) {
+        if !self.plain_text {
+            return;
+        }
+        for_each_literal(messages, |_, _, copy| {
+            if has_markdown(copy) {
+                *copy = text::strip_markdown(copy);
+            }
+        });
+    }
+}
+
+/// Key identifying a Text component in a rewrite prompt and its reply
+fn rewrite_key(surface_id: &str, component_id: &str) -> String {
+    format!("{}/{}", surface_id, component_id)
+}
+
+/// Apply the rewrites in a reply to `rewrite_prompt`; returns how many were applied,
+/// or `None` when the reply holds no JSON object
+pub fn apply_rewrites(messages: &mut [A2UIMessageResponse], reply: &str) -> Option<usize> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    let rewrites: HashMap<String, String> = serde_json::from_str(reply.get(start..=end)?).ok()?;
+
+    let mut applied = 0;
+    for_each_literal(messages, |surface_id, component_id, copy| {
+        if let Some(rewritten) = rewrites.get(&rewrite_key(surface_id, component_id)) {
+            *copy = rewritten.clone();
+            applied += 1;
+        }
+    });
+    Some(applied)
+}
+
+/// `(surface id, component id, literal)` of every Text component with literal copy
+fn literal_copy(messages: &[A2UIMessageResponse]) -> impl Iterator<Item = (&str, &str, &str)> {
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            A2UIMessageResponse::SurfaceUpdate(update) => Some(update),
+            _ => None,
+        })
+        .flat_map(|update| {
+            update
+                .components
+                .iter()
+                .filter_map(|component| match &component.component {
+                    UIComponentType::Text { text, .. } => text
+                        .literal_string
+                        .as_deref()
+                        .map(|copy| (update.surface_id.as_str(), component.id.as_str(), copy)),
+                    _ => None,
+                })
+        })
+}
+
+fn for_each_literal(messages: &mut [A2UIMessageResponse], mut f: impl FnMut(&str, &str, &mut String)) {
+    for message in messages {
+        let A2UIMessageResponse::SurfaceUpdate(update) = message else {
+            continue;
+        };
+        for component in &mut update.components {
+            if let UIComponentType::Text { text, .. } = &mut component.component {
+                if let Some(copy) = text.literal_string.as_mut() {
+                    f(&update.surface_id, &component.id, copy);
+                }
+            }
+        }
+    }
+}
+
+/// Scripts the language with code `language` is written in; Latin for any language not listed
+fn expected_scripts(language: &str) -> &'static [Script] {
+    let primary = language.split(['-', '_']).next().unwrap_or_default().to_lowercase();
+    match primary.as_str() {
+        "zh" => &[Script::Han],
+        "ja" => &[Script::Kana, Script::Han],
+        "ko" => &[Script::Hangul],
+        "ru" | "uk" | "be" | "bg" | "sr" | "mk" | "kk" | "mn" => &[Script::Cyrillic],
+        "el" => &[Script::Greek],
+        "ar" | "fa" | "ur" => &[Script::Arabic],
+        "he" | "yi" => &[Script::Hebrew],
+        "hi" | "mr" | "ne" => &[Script::Devanagari],
+        "th" => &[Script::Thai],
+        _ => &[Script::Latin],
+    }
+}
+
+fn script_of(c: char) -> Option<Script> {
+    let script = match c {
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' => Script::Latin,
+        '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' => Script::Han,
+        '\u{3040}'..='\u{30FF}' => Script::Kana,
+        '\u{AC00}'..='\u{D7AF}' | '\u{1100}'..='\u{11FF}' => Script::Hangul,
+        '\u{0400}'..='\u{04FF}' => Script::Cyrillic,
+        '\u{0370}'..='\u{03FF}' => Script::Greek,
+        '\u{0600}'..='\u{06FF}' => Script::Arabic,
+        '\u{0590}'..='\u{05FF}' => Script::Hebrew,
+        '\u{0900}'..='\u{097F}' => Script::Devanagari,
+        '\u{0E00}'..='\u{0E7F}' => Script::Thai,
+        _ => return None,
+    };
+    Some(script)
+}
+
+/// The script most of `copy`'s letters are in, if it has enough letters to tell
+///
+/// Japanese mixes kana with Han characters, so any kana makes Han text count as kana.
+fn dominant_script(copy: &str) -> Option<Script> {
+    let mut counts: HashMap<Script, usize> = HashMap::new();
+    for script in copy.chars().filter_map(script_of) {
+        *counts.entry(script).or_default() += 1;
+    }
+    if counts.values().sum::<usize>() < MIN_LETTERS {
+        return None;
+    }
+    if let Some(han) = counts
+        .get(&Script::Han)
+        .copied()
+        .filter(|_| counts.contains_key(&Script::Kana))
+    {
+        counts.remove(&Script::Han);
+        *counts.entry(Script::Kana).or_default() += han;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(script, _)| script)
+}
+
+/// Whether `copy` contains markdown emphasis, code, link, heading or list syntax
+fn has_markdown(copy: &str) -> bool {
+    ["**", "__", "`", "]("].iter().any(|marker| copy.contains(marker))
+        || copy.lines().map(str::trim_start).any(|line| {
+            line.starts_with("# ")
+                || line.starts_with("## ")
+                || line.starts_with("### ")
+                || line.starts_with("- ")
+                || line.starts_with("* ")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn messages() -> Vec<A2UIMessageResponse> {
+        serde_json::from_value(json!([
+            {"surfaceUpdate": {"surfaceId": "main", "components": [
+                {"id": "title", "component": {"Text": {"text": {"literalString": "**Wetter** heute"}}}},
+                {"id": "body", "component": {"Text": {"text": {"literalString": "今日は晴れです"}}}},
+                {"id": "bound", "component": {"Text": {"text": {"path": "/summary"}}}},
+                {"id": "ok", "component": {"Text": {"text": {"literalString": "OK"}}}}
+            ]}}
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_copy_is_checked_and_rewritten() {
+        assert_eq!(dominant_script("今日は晴れです"), Some(Script::Kana));
+        assert_eq!(dominant_script("今天是晴天"), Some(Script::Han));
+        assert_eq!(dominant_script("OK"), None, "too short to tell");
+
+        let style = OutputStyle {
+            language: Some("de-DE".to_string()),
+            plain_text: true,
+            enforcement: StyleEnforcement::Rewrite,
+        };
+        let mut messages = messages();
+        let warnings = style.check(&messages);
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|warning| (warning.component_id.as_str(), &warning.issue))
+                .collect::<Vec<_>>(),
+            vec![
+                ("title", &StyleIssue::Markdown),
+                (
+                    "body",
+                    &StyleIssue::WrongLanguage {
+                        expected: "de-DE".to_string(),
+                        detected: Script::Kana
+                    }
+                ),
+            ]
+        );
+
+        let prompt = style.rewrite_prompt(&messages, &warnings).unwrap();
+        assert!(prompt.contains("\"main/body\": \"今日は晴れです\""));
+        assert!(!prompt.contains("main/title"));
+
+        style.strip_markdown(&mut messages);
+        let reply = "Sure:\n```json\n{\"main/body\": \"Heute ist es sonnig\"}\n```";
+        assert_eq!(apply_rewrites(&mut messages, reply), Some(1));
+        assert!(style.check(&messages).is_empty());
+        assert_eq!(apply_rewrites(&mut messages, "no json here"), None);
+    }
+}
@@ -1,7 +1,11 @@
+use crate::storage::Storage;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 
+/// Storage key prefix of generated plugins, kept for the preview and regenerate flows
+const GENERATED_PLUGINS_PREFIX: &str = "plugins/generated";
+
 /// Request structure for generating a Fleet Chat plugin
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PluginGenerationRequest {
@@ -46,6 +50,27 @@ pub struct PluginGenerationResponse {
     pub warnings: Option<Vec<String>>,
 }
 
+fn generated_plugin_key(plugin_id: &str) -> String {
+    format!("{}/{}.json", GENERATED_PLUGINS_PREFIX, plugin_id)
+}
+
+/// Persist a generated plugin, explanation included, under its plugin id
+pub fn save_generated_plugin(storage: &dyn Storage, plugin: &PluginGenerationResponse) -> Result<(), String> {
+    let content = serde_json::to_vec(plugin).map_err(|e| e.to_string())?;
+    storage
+        .write(&generated_plugin_key(&plugin.plugin_id), &content)
+        .map_err(|e| e.to_string())
+}
+
+/// A previously generated plugin, or `None` if it was never saved or can't be read
+pub fn load_generated_plugin(storage: &dyn Storage, plugin_id: &str) -> Option<PluginGenerationResponse> {
+    storage
+        .read(&generated_plugin_key(plugin_id))
+        .ok()
+        .flatten()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+}
+
 /// Plugin manifest structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PluginManifest {
@@ -2,7 +2,16 @@ use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::time::Duration;
+use tauri_plugin_log::log::warn;
 use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+use crate::logging::ai_debug;
+use crate::provider_headers::provider_client;
+use crate::provider_trace::PROVIDER_TRACE;
+use crate::rate_limit::{parse_retry_after_header, retry_after_from_message};
+use crate::rig_agent::TokenUsage;
 
 #[derive(Debug, Error)]
 pub enum ProviderError {
@@ -14,10 +23,61 @@ pub enum ProviderError {
     JsonError(#[from] serde_json::Error),
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after_ms: Option<u64>,
+    },
+    #[error("Provider did not respond within {0:?}")]
+    Timeout(Duration),
+    #[error("Provider request was cancelled")]
+    Cancelled,
+}
+
+impl ProviderError {
+    /// Build the error for a non-success provider response, keeping any retry hint on 429
+    pub async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status();
+        let retry_after_header = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let error_text = response.text().await.unwrap_or_default();
+        Self::from_parts(status, retry_after_header, error_text)
+    }
+
+    fn from_parts(status: reqwest::StatusCode, retry_after_header: Option<String>, error_text: String) -> Self {
+        let message = format!("API call failed with status {}: {}", status, error_text);
+
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after_ms = retry_after_header
+                .as_deref()
+                .and_then(parse_retry_after_header)
+                .or_else(|| retry_after_from_message(&error_text));
+            return ProviderError::RateLimited {
+                message,
+                retry_after_ms,
+            };
+        }
+
+        ProviderError::ApiError(message)
+    }
+
+    /// How long the provider asked to wait before retrying, if it said
+    pub fn retry_after_ms(&self) -> Option<u64> {
+        match self {
+            ProviderError::RateLimited { retry_after_ms, .. } => *retry_after_ms,
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ChatRequest {
+    /// Instructions kept apart from the conversation, sent the way each provider expects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
     pub messages: Vec<ChatMessage>,
     pub temperature: f32,
     pub max_tokens: i32,
@@ -28,6 +88,47 @@ pub struct ChatRequest {
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
+    /// Function calls the assistant made in this turn
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// On a `tool` message, the id of the call it answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: "user".to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::assistant_tool_calls(content, Vec::new())
+    }
+
+    /// The assistant turn that requested `tool_calls`
+    pub fn assistant_tool_calls(content: impl Into<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content: content.into(),
+            tool_calls,
+            tool_call_id: None,
+        }
+    }
+
+    /// The JSON result of the tool call `call_id`
+    pub fn tool_result(call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: "tool".to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: Some(call_id.into()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -49,6 +150,9 @@ pub struct ToolParameters {
 pub struct ChatResponse {
     pub content: String,
     pub tool_calls: Option<Vec<ToolCall>>,
+    /// Token counts, when the provider reported them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,11 +162,76 @@ pub struct ToolCall {
     pub arguments: serde_json::Value,
 }
 
+/// Send a JSON request to a provider, tracing it when `FLEET_DEBUG_AI` is on
+///
+/// Non-success responses become a `ProviderError` after their body is traced. Successful
+/// responses are returned unread; callers trace their body with `read_traced_json`.
+async fn send_traced(
+    provider: &str,
+    url: &str,
+    request: reqwest::RequestBuilder,
+    body: &impl Serialize,
+) -> Result<reqwest::Response, ProviderError> {
+    let response = request.json(body).send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    if !PROVIDER_TRACE.is_enabled() {
+        return Err(ProviderError::from_response(response).await);
+    }
+
+    let retry_after_header = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    let error_text = response.text().await.unwrap_or_default();
+    PROVIDER_TRACE.record(provider, url, body, Some(status.as_u16()), Some(&error_text));
+    Err(ProviderError::from_parts(status, retry_after_header, error_text))
+}
+
+/// Read and parse a successful response body, tracing it alongside its request
+async fn read_traced_json<T: for<'de> Deserialize<'de>>(
+    provider: &str,
+    url: &str,
+    body: &impl Serialize,
+    response: reqwest::Response,
+) -> Result<T, ProviderError> {
+    let status = response.status().as_u16();
+    let text = response.text().await?;
+    PROVIDER_TRACE.record(provider, url, body, Some(status), Some(&text));
+    Ok(serde_json::from_str(&text)?)
+}
+
 #[async_trait]
 pub trait AIProvider: Send + Sync {
     async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, ProviderError>;
+
+    /// `chat_completion`, failing with `Timeout` after `timeout` and `Cancelled` once `cancel` fires.
+    ///
+    /// Either way the in-flight call is dropped, which aborts its HTTP request.
+    async fn chat_completion_bounded(
+        &self,
+        request: ChatRequest,
+        cancel: &CancellationToken,
+        timeout: Duration,
+    ) -> Result<ChatResponse, ProviderError> {
+        tokio::select! {
+            _ = cancel.cancelled() => Err(ProviderError::Cancelled),
+            response = tokio::time::timeout(timeout, self.chat_completion(request)) => {
+                response.unwrap_or(Err(ProviderError::Timeout(timeout)))
+            }
+        }
+    }
+
     fn provider_name(&self) -> &str;
     fn default_model(&self) -> &str;
+
+    /// The model requests are sent to, e.g. one chosen with `with_model`
+    fn model(&self) -> &str {
+        self.default_model()
+    }
 }
 
 // Gemini Provider Implementation
@@ -70,29 +239,37 @@ pub struct GeminiProvider {
     pub client: Client,
     pub api_key: String,
     pub model: String,
+    /// API root requests go to; `GEMINI_BASE_URL` when set
+    pub base_url: String,
 }
 
 impl GeminiProvider {
     pub fn new(api_key: String) -> Self {
-        Self {
-            client: Client::new(),
-            api_key,
-            model: "gemini-2.5-flash".to_string(),
-        }
+        Self::with_model(api_key, "gemini-2.5-flash".to_string())
     }
 
     pub fn with_model(api_key: String, model: String) -> Self {
         Self {
-            client: Client::new(),
+            client: provider_client("gemini"),
             api_key,
             model,
+            base_url: crate::rig_agent::AIProvider::Gemini.base_url(None),
         }
     }
+
+    fn generate_content_url(&self) -> String {
+        format!(
+            "{}/v1beta/models/{}:generateContent?key={}",
+            self.base_url, self.model, self.api_key
+        )
+    }
 }
 
 // Gemini API structures
 #[derive(Debug, Serialize)]
 struct GeminiRequest {
+    #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<GeminiContent>,
     contents: Vec<GeminiContent>,
     generation_config: Option<GeminiGenerationConfig>,
     tools: Option<Vec<GeminiTool>>,
@@ -101,13 +278,38 @@ struct GeminiRequest {
 #[derive(Debug, Serialize)]
 struct GeminiContent {
     parts: Vec<GeminiPart>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     role: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
 enum GeminiPart {
-    Text { text: String },
+    Text {
+        text: String,
+    },
+    FunctionCall {
+        #[serde(rename = "functionCall")]
+        function_call: GeminiFunctionCall,
+    },
+    FunctionResponse {
+        #[serde(rename = "functionResponse")]
+        function_response: GeminiFunctionResponse,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GeminiFunctionCall {
+    name: String,
+    #[serde(default)]
+    args: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiFunctionResponse {
+    name: String,
+    /// Must be a JSON object; other results are wrapped in `{"result": ...}`
+    response: serde_json::Value,
 }
 
 #[derive(Debug, Serialize)]
@@ -135,34 +337,116 @@ struct GeminiFunctionDeclaration {
 
 #[derive(Debug, Deserialize)]
 struct GeminiResponse {
+    #[serde(default)]
     candidates: Vec<GeminiCandidate>,
+    #[serde(default, rename = "usageMetadata")]
+    usage_metadata: Option<GeminiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiUsage {
+    #[serde(default)]
+    prompt_token_count: u32,
+    #[serde(default)]
+    candidates_token_count: u32,
+    total_token_count: Option<u32>,
+}
+
+impl From<&GeminiUsage> for TokenUsage {
+    fn from(usage: &GeminiUsage) -> Self {
+        TokenUsage {
+            prompt_tokens: usage.prompt_token_count,
+            completion_tokens: usage.candidates_token_count,
+            total_tokens: usage
+                .total_token_count
+                .unwrap_or(usage.prompt_token_count + usage.candidates_token_count),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct GeminiCandidate {
+    /// Missing when generation stopped early, e.g. on a malformed function call
+    #[serde(default)]
     content: GeminiResponseContent,
+    #[serde(rename = "finishReason")]
+    finish_reason: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 struct GeminiResponseContent {
+    #[serde(default)]
     parts: Vec<GeminiResponsePart>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(untagged)]
 enum GeminiResponsePart {
-    Text { text: String },
+    Text {
+        text: String,
+    },
+    FunctionCall {
+        #[serde(rename = "functionCall")]
+        function_call: GeminiFunctionCall,
+    },
+    /// Parts we don't use, such as inline data or executable code
+    Other(serde::de::IgnoredAny),
 }
 
-#[async_trait]
-impl AIProvider for GeminiProvider {
-    async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+/// Finish reason of a candidate whose function call Gemini couldn't produce
+const GEMINI_MALFORMED_FUNCTION_CALL: &str = "MALFORMED_FUNCTION_CALL";
+
+impl GeminiProvider {
+    fn build_request(request: ChatRequest) -> GeminiRequest {
         let mut contents = Vec::new();
+        // Gemini answers function calls by name, so remember which call each id was
+        let mut call_names = HashMap::new();
 
         for msg in request.messages {
+            if msg.role == "tool" {
+                let name = msg
+                    .tool_call_id
+                    .as_ref()
+                    .and_then(|id| call_names.get(id))
+                    .cloned()
+                    .unwrap_or_default();
+                let response = match serde_json::from_str(&msg.content) {
+                    Ok(serde_json::Value::Object(object)) => serde_json::Value::Object(object),
+                    Ok(other) => serde_json::json!({ "result": other }),
+                    Err(_) => serde_json::json!({ "result": msg.content }),
+                };
+                contents.push(GeminiContent {
+                    parts: vec![GeminiPart::FunctionResponse {
+                        function_response: GeminiFunctionResponse { name, response },
+                    }],
+                    role: Some("user".to_string()),
+                });
+                continue;
+            }
+
+            let mut parts = Vec::new();
+            if !msg.content.is_empty() || msg.tool_calls.is_empty() {
+                parts.push(GeminiPart::Text { text: msg.content });
+            }
+            for call in msg.tool_calls {
+                call_names.insert(call.id.clone(), call.name.clone());
+                parts.push(GeminiPart::FunctionCall {
+                    function_call: GeminiFunctionCall {
+                        name: call.name,
+                        args: call.arguments,
+                    },
+                });
+            }
+            // Gemini calls the assistant role "model"
+            let role = if msg.role == "assistant" {
+                "model".to_string()
+            } else {
+                msg.role
+            };
             contents.push(GeminiContent {
-                parts: vec![GeminiPart::Text { text: msg.content }],
-                role: Some(msg.role),
+                parts,
+                role: Some(role),
             });
         }
 
@@ -179,7 +463,11 @@ impl AIProvider for GeminiProvider {
             }]
         });
 
-        let gemini_request = GeminiRequest {
+        GeminiRequest {
+            system_instruction: request.system.map(|system| GeminiContent {
+                parts: vec![GeminiPart::Text { text: system }],
+                role: None,
+            }),
             contents,
             generation_config: Some(GeminiGenerationConfig {
                 temperature: request.temperature,
@@ -188,52 +476,81 @@ impl AIProvider for GeminiProvider {
                 top_p: 0.95,
             }),
             tools,
-        };
+        }
+    }
 
-        let url = format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
-            self.model, self.api_key
-        );
+    /// Concatenated text parts of the first candidate, if any
+    fn candidate_text(response: &GeminiResponse) -> Option<Vec<String>> {
+        let candidate = response.candidates.first()?;
+        Some(
+            candidate
+                .content
+                .parts
+                .iter()
+                .filter_map(|part| match part {
+                    GeminiResponsePart::Text { text } => Some(text.clone()),
+                    GeminiResponsePart::FunctionCall { .. } | GeminiResponsePart::Other(_) => None,
+                })
+                .collect(),
+        )
+    }
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .json(&gemini_request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(ProviderError::ApiError(format!(
-                "API call failed with status {}: {}",
-                status, error_text
-            )));
+    /// Function calls of the first candidate, given ids since Gemini matches results by name
+    fn candidate_tool_calls(response: &GeminiResponse) -> Vec<ToolCall> {
+        let Some(candidate) = response.candidates.first() else {
+            return Vec::new();
+        };
+        candidate
+            .content
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                GeminiResponsePart::FunctionCall { function_call } => Some(function_call),
+                GeminiResponsePart::Text { .. } | GeminiResponsePart::Other(_) => None,
+            })
+            .enumerate()
+            .map(|(index, call)| ToolCall {
+                id: format!("{}-{}", call.name, index),
+                name: call.name.clone(),
+                arguments: call.args.clone(),
+            })
+            .collect()
+    }
+
+    /// The text and function calls of the first candidate
+    fn chat_response(response: &GeminiResponse) -> Result<ChatResponse, ProviderError> {
+        let candidate = response
+            .candidates
+            .first()
+            .ok_or_else(|| ProviderError::InvalidResponse("No valid response from Gemini API".to_string()))?;
+        if candidate.finish_reason.as_deref() == Some(GEMINI_MALFORMED_FUNCTION_CALL) {
+            return Err(ProviderError::InvalidResponse(
+                "Gemini produced a malformed function call".to_string(),
+            ));
         }
 
-        let gemini_response: GeminiResponse = response.json().await?;
+        let content = Self::candidate_text(response).unwrap_or_default().join(" ");
+        let tool_calls = Self::candidate_tool_calls(response);
+        Ok(ChatResponse {
+            content,
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            usage: response.usage_metadata.as_ref().map(TokenUsage::from),
+        })
+    }
+}
 
-        if let Some(candidate) = gemini_response.candidates.first() {
-            let mut text_parts = Vec::new();
+#[async_trait]
+impl AIProvider for GeminiProvider {
+    async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+        let gemini_request = Self::build_request(request);
 
-            for part in &candidate.content.parts {
-                match part {
-                    GeminiResponsePart::Text { text } => {
-                        text_parts.push(text.clone());
-                    }
-                }
-            }
+        let url = self.generate_content_url();
 
-            return Ok(ChatResponse {
-                content: text_parts.join(" "),
-                tool_calls: None,
-            });
-        }
+        let request = self.client.post(&url).header("Content-Type", "application/json");
+        let response = send_traced("gemini", &url, request, &gemini_request).await?;
+        let gemini_response: GeminiResponse = read_traced_json("gemini", &url, &gemini_request, response).await?;
 
-        Err(ProviderError::InvalidResponse(
-            "No valid response from Gemini API".to_string(),
-        ))
+        Self::chat_response(&gemini_response)
     }
 
     fn provider_name(&self) -> &str {
@@ -243,6 +560,10 @@ impl AIProvider for GeminiProvider {
     fn default_model(&self) -> &str {
         "gemini-2.5-flash"
     }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
 }
 
 // OpenAI Provider Implementation
@@ -250,22 +571,21 @@ pub struct OpenAIProvider {
     pub client: Client,
     pub api_key: String,
     pub model: String,
+    /// API root requests go to; `OPENAI_BASE_URL` when set
+    pub base_url: String,
 }
 
 impl OpenAIProvider {
     pub fn new(api_key: String) -> Self {
-        Self {
-            client: Client::new(),
-            api_key,
-            model: "gpt-4".to_string(),
-        }
+        Self::with_model(api_key, "gpt-4".to_string())
     }
 
     pub fn with_model(api_key: String, model: String) -> Self {
         Self {
-            client: Client::new(),
+            client: provider_client("openai"),
             api_key,
             model,
+            base_url: crate::rig_agent::AIProvider::OpenAI.base_url(None),
         }
     }
 }
@@ -285,6 +605,10 @@ struct OpenAIRequest {
 struct OpenAIMessage {
     role: String,
     content: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAIToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -304,6 +628,9 @@ struct OpenAIFunction {
 #[derive(Debug, Deserialize)]
 struct OpenAIResponse {
     choices: Vec<OpenAIChoice>,
+    /// Reported with the same field names as `TokenUsage`
+    #[serde(default)]
+    usage: Option<TokenUsage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -318,7 +645,7 @@ struct OpenAIResponseMessage {
     tool_calls: Vec<OpenAIToolCall>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct OpenAIToolCall {
     id: String,
     #[serde(rename = "type")]
@@ -326,22 +653,45 @@ struct OpenAIToolCall {
     function: OpenAIFunctionCall,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct OpenAIFunctionCall {
     name: String,
     arguments: String,
 }
 
-#[async_trait]
-impl AIProvider for OpenAIProvider {
-    async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, ProviderError> {
-        let messages: Vec<OpenAIMessage> = request
-            .messages
+impl OpenAIProvider {
+    fn chat_completions_url(&self) -> String {
+        format!("{}/chat/completions", self.base_url)
+    }
+
+    fn build_request(&self, request: ChatRequest) -> OpenAIRequest {
+        let system = request.system.map(|content| OpenAIMessage {
+            role: "system".to_string(),
+            content,
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        });
+        let messages: Vec<OpenAIMessage> = system
             .into_iter()
-            .map(|msg| OpenAIMessage {
-                role: msg.role,
-                content: msg.content,
-            })
+            .chain(request.messages.into_iter().map(|msg| {
+                OpenAIMessage {
+                    role: msg.role,
+                    content: msg.content,
+                    tool_calls: msg
+                        .tool_calls
+                        .into_iter()
+                        .map(|call| OpenAIToolCall {
+                            id: call.id,
+                            tool_type: "function".to_string(),
+                            function: OpenAIFunctionCall {
+                                name: call.name,
+                                arguments: call.arguments.to_string(),
+                            },
+                        })
+                        .collect(),
+                    tool_call_id: msg.tool_call_id,
+                }
+            }))
             .collect();
 
         let tools = request.tools.map(|tools| {
@@ -358,35 +708,29 @@ impl AIProvider for OpenAIProvider {
                 .collect()
         });
 
-        let openai_request = OpenAIRequest {
+        OpenAIRequest {
             model: self.model.clone(),
             messages,
             temperature: request.temperature,
             max_tokens: request.max_tokens,
             tools,
-        };
+        }
+    }
+}
 
-        let url = "https://api.openai.com/v1/chat/completions";
+#[async_trait]
+impl AIProvider for OpenAIProvider {
+    async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+        let openai_request = self.build_request(request);
 
-        let response = self
+        let url = &self.chat_completions_url();
+        let request = self
             .client
             .post(url)
             .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&openai_request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(ProviderError::ApiError(format!(
-                "API call failed with status {}: {}",
-                status, error_text
-            )));
-        }
-
-        let openai_response: OpenAIResponse = response.json().await?;
+            .header("Authorization", format!("Bearer {}", self.api_key));
+        let response = send_traced("openai", url, request, &openai_request).await?;
+        let openai_response: OpenAIResponse = read_traced_json("openai", url, &openai_request, response).await?;
 
         if let Some(choice) = openai_response.choices.first() {
             let content = choice.message.content.clone().unwrap_or_default();
@@ -405,10 +749,8 @@ impl AIProvider for OpenAIProvider {
                                     arguments,
                                 }),
                                 Err(e) => {
-                                    eprintln!(
-                                        "Warning: Failed to parse tool call arguments for '{}': {}. Arguments: {}",
-                                        tc.function.name, e, tc.function.arguments
-                                    );
+                                    warn!("Failed to parse tool call arguments for '{}': {}", tc.function.name, e);
+                                    ai_debug!("Unparseable tool call arguments: {}", tc.function.arguments);
                                     // Return a tool call with empty object instead of dropping it
                                     Some(ToolCall {
                                         id: tc.id.clone(),
@@ -424,7 +766,11 @@ impl AIProvider for OpenAIProvider {
                 None
             };
 
-            return Ok(ChatResponse { content, tool_calls });
+            return Ok(ChatResponse {
+                content,
+                tool_calls,
+                usage: openai_response.usage,
+            });
         }
 
         Err(ProviderError::InvalidResponse(
@@ -439,12 +785,419 @@ impl AIProvider for OpenAIProvider {
     fn default_model(&self) -> &str {
         "gpt-4"
     }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+// Anthropic Provider Implementation
+pub struct AnthropicProvider {
+    pub client: Client,
+    pub api_key: String,
+    pub model: String,
+    /// API root requests go to; `ANTHROPIC_BASE_URL` when set
+    pub base_url: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(api_key: String) -> Self {
+        Self::with_model(api_key, "claude-3-5-sonnet-20241022".to_string())
+    }
+
+    pub fn with_model(api_key: String, model: String) -> Self {
+        Self {
+            client: provider_client("anthropic"),
+            api_key,
+            model,
+            base_url: crate::rig_agent::AIProvider::Anthropic.base_url(None),
+        }
+    }
+}
+
+// Anthropic API structures
+#[derive(Debug, Serialize)]
+struct AnthropicRequest {
+    model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage>,
+    temperature: f32,
+    max_tokens: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<AnthropicTool>>,
+}
+
+#[derive(Debug, Serialize)]
+struct AnthropicMessage {
+    role: String,
+    content: Vec<AnthropicContentBlock>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+    },
+    /// Blocks we don't use, such as thinking
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Serialize)]
+struct AnthropicTool {
+    name: String,
+    description: String,
+    input_schema: ToolParameters,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicContentBlock>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+impl AnthropicProvider {
+    fn messages_url(&self) -> String {
+        format!("{}/v1/messages", self.base_url)
+    }
+
+    const API_VERSION: &'static str = "2023-06-01";
+
+    fn build_request(&self, request: ChatRequest) -> AnthropicRequest {
+        let mut messages: Vec<AnthropicMessage> = Vec::new();
+        for msg in request.messages {
+            // Tool results go back in a user turn, and Claude expects turns to alternate
+            let (role, mut blocks) = match msg.role.as_str() {
+                "tool" => (
+                    "user",
+                    vec![AnthropicContentBlock::ToolResult {
+                        tool_use_id: msg.tool_call_id.unwrap_or_default(),
+                        content: msg.content,
+                    }],
+                ),
+                role => {
+                    // The API rejects empty text blocks
+                    let mut blocks = Vec::new();
+                    if !msg.content.is_empty() {
+                        blocks.push(AnthropicContentBlock::Text { text: msg.content });
+                    }
+                    blocks.extend(msg.tool_calls.into_iter().map(|call| AnthropicContentBlock::ToolUse {
+                        id: call.id,
+                        name: call.name,
+                        input: call.arguments,
+                    }));
+                    (if role == "assistant" { "assistant" } else { "user" }, blocks)
+                }
+            };
+            if blocks.is_empty() {
+                continue;
+            }
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.append(&mut blocks),
+                _ => messages.push(AnthropicMessage {
+                    role: role.to_string(),
+                    content: blocks,
+                }),
+            }
+        }
+
+        let tools = request.tools.map(|tools| {
+            tools
+                .into_iter()
+                .map(|tool| AnthropicTool {
+                    name: tool.name,
+                    description: tool.description,
+                    input_schema: tool.parameters,
+                })
+                .collect()
+        });
+
+        AnthropicRequest {
+            model: self.model.clone(),
+            system: request.system,
+            messages,
+            temperature: request.temperature,
+            max_tokens: request.max_tokens,
+            tools,
+        }
+    }
+
+    fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        self.client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", Self::API_VERSION)
+    }
+
+    /// The text and tool uses of a Messages API response
+    fn chat_response(response: AnthropicResponse) -> ChatResponse {
+        let mut text = Vec::new();
+        let mut tool_calls = Vec::new();
+        for block in response.content {
+            match block {
+                AnthropicContentBlock::Text { text: part } => text.push(part),
+                AnthropicContentBlock::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                    id,
+                    name,
+                    arguments: input,
+                }),
+                AnthropicContentBlock::ToolResult { .. } | AnthropicContentBlock::Other => {}
+            }
+        }
+
+        ChatResponse {
+            content: text.concat(),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            usage: response.usage.map(|usage| TokenUsage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+                total_tokens: usage.input_tokens + usage.output_tokens,
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl AIProvider for AnthropicProvider {
+    async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+        let anthropic_request = self.build_request(request);
+
+        let url = &self.messages_url();
+        let response = send_traced("anthropic", url, self.post(url), &anthropic_request).await?;
+        let anthropic_response: AnthropicResponse =
+            read_traced_json("anthropic", url, &anthropic_request, response).await?;
+
+        Ok(Self::chat_response(anthropic_response))
+    }
+
+    fn provider_name(&self) -> &str {
+        "Anthropic"
+    }
+
+    fn default_model(&self) -> &str {
+        "claude-3-5-sonnet-20241022"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+// Mock Provider Implementation (offline/dev use, selected with FLEET_AI_PROVIDER=mock)
+#[derive(Debug, Default)]
+pub struct MockProvider;
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Pull the user's request out of the agent prompt so template text doesn't skew matching
+    fn current_request(prompt: &str) -> &str {
+        prompt
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix("CURRENT REQUEST:"))
+            .map(str::trim)
+            .unwrap_or(prompt)
+    }
+
+    fn canned_response(query: &str) -> (String, serde_json::Value) {
+        let query = query.to_lowercase();
+        let matches = |keywords: &[&str]| keywords.iter().any(|keyword| query.contains(keyword));
+
+        if matches(&["contact", "联系人"]) {
+            (
+                "Here are your contacts.".to_string(),
+                serde_json::json!([
+                    {"beginRendering": {"surfaceId": "contacts", "root": "contacts-root", "styles": {"primaryColor": "#007BFF"}}},
+                    {"surfaceUpdate": {"surfaceId": "contacts", "components": [
+                        {"id": "contacts-root", "component": {"Column": {"children": {"explicitList": ["contacts-title", "contacts-list"]}}}},
+                        {"id": "contacts-title", "component": {"Text": {"usageHint": "h2", "text": {"literalString": "Contacts"}}}},
+                        {"id": "contacts-list", "component": {"List": {"children": {"template": {"componentId": "contact-card", "dataBinding": "/contacts"}}}}},
+                        {"id": "contact-card", "component": {"Card": {"children": {"explicitList": ["contact-name", "contact-email"]}}}},
+                        {"id": "contact-name", "component": {"Text": {"usageHint": "h3", "text": {"path": "/name"}}}},
+                        {"id": "contact-email", "component": {"Text": {"usageHint": "caption", "text": {"path": "/email"}}}}
+                    ]}},
+                    {"dataModelUpdate": {"surfaceId": "contacts", "patches": [
+                        {"path": "/contacts", "value": [
+                            {"name": "John Doe", "email": "john.doe@example.com"},
+                            {"name": "Jane Smith", "email": "jane.smith@example.com"}
+                        ]}
+                    ]}}
+                ]),
+            )
+        } else if matches(&["search", "find", "搜索", "查找"]) {
+            (
+                "Here is what I found.".to_string(),
+                serde_json::json!([
+                    {"beginRendering": {"surfaceId": "search", "root": "search-root"}},
+                    {"surfaceUpdate": {"surfaceId": "search", "components": [
+                        {"id": "search-root", "component": {"Column": {"children": {"explicitList": ["search-title", "search-results"]}}}},
+                        {"id": "search-title", "component": {"Text": {"usageHint": "h2", "text": {"literalString": "Search Results"}}}},
+                        {"id": "search-results", "component": {"List": {"children": {"explicitList": ["result-1", "result-2"]}}}},
+                        {"id": "result-1", "component": {"Text": {"text": {"literalString": "Getting started guide"}}}},
+                        {"id": "result-2", "component": {"Text": {"text": {"literalString": "Release notes"}}}}
+                    ]}}
+                ]),
+            )
+        } else if matches(&["form", "表单", "sign up", "register"]) {
+            (
+                "Please fill in the form below.".to_string(),
+                serde_json::json!([
+                    {"beginRendering": {"surfaceId": "form", "root": "form-root"}},
+                    {"surfaceUpdate": {"surfaceId": "form", "components": [
+                        {"id": "form-root", "component": {"Column": {"children": {"explicitList": ["form-name", "form-email", "form-submit"]}}}},
+                        {"id": "form-name", "component": {"TextField": {"label": {"literalString": "Name"}, "type": "text"}}},
+                        {"id": "form-email", "component": {"TextField": {"label": {"literalString": "Email"}, "type": "email"}}},
+                        {"id": "form-submit", "component": {"Button": {"child": "form-submit-label", "action": {"name": "submit_form", "context": []}}}},
+                        {"id": "form-submit-label", "component": {"Text": {"text": {"literalString": "Submit"}}}}
+                    ]}}
+                ]),
+            )
+        } else {
+            (
+                "Hello! This is a mock response; set an API key to use a real provider.".to_string(),
+                serde_json::json!([
+                    {"beginRendering": {"surfaceId": "main", "root": "greeting-card"}},
+                    {"surfaceUpdate": {"surfaceId": "main", "components": [
+                        {"id": "greeting-card", "component": {"Card": {"child": "greeting-text"}}},
+                        {"id": "greeting-text", "component": {"Text": {"usageHint": "body", "text": {"literalString": "Hello from the mock provider"}}}}
+                    ]}}
+                ]),
+            )
+        }
+    }
+}
+
+#[async_trait]
+impl AIProvider for MockProvider {
+    async fn chat_completion(&self, request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+        let prompt = request
+            .messages
+            .iter()
+            .rev()
+            .find(|message| message.role == "user")
+            .map(|message| message.content.as_str())
+            .unwrap_or_default();
+
+        let (text, messages) = Self::canned_response(Self::current_request(prompt));
+
+        // Text-mode instructions ask for a plain conversational reply
+        let text_mode = request
+            .system
+            .as_deref()
+            .is_some_and(|system| system.contains("without UI generation"));
+        let content = if text_mode {
+            text
+        } else {
+            format!(
+                "{}\n\nA2UI_MESSAGES: {}",
+                text,
+                serde_json::to_string_pretty(&messages)?
+            )
+        };
+
+        Ok(ChatResponse {
+            content,
+            tool_calls: None,
+            usage: None,
+        })
+    }
+
+    fn provider_name(&self) -> &str {
+        "Mock"
+    }
+
+    fn default_model(&self) -> &str {
+        "mock"
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_rate_limit_carries_retry_after() {
+        let response = reqwest::Response::from(
+            axum::http::Response::builder()
+                .status(429)
+                .header("Retry-After", "30")
+                .body("Too many requests")
+                .unwrap(),
+        );
+
+        let err = ProviderError::from_response(response).await;
+        assert!(matches!(err, ProviderError::RateLimited { .. }));
+        assert_eq!(err.retry_after_ms(), Some(30000));
+
+        let response = reqwest::Response::from(axum::http::Response::builder().status(500).body("boom").unwrap());
+        let err = ProviderError::from_response(response).await;
+        assert!(matches!(err, ProviderError::ApiError(message) if message.contains("boom")));
+    }
+
+    struct SlowProvider;
+
+    #[async_trait]
+    impl AIProvider for SlowProvider {
+        async fn chat_completion(&self, _request: ChatRequest) -> Result<ChatResponse, ProviderError> {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            Ok(ChatResponse {
+                content: "too late".to_string(),
+                tool_calls: None,
+                usage: None,
+            })
+        }
+
+        fn provider_name(&self) -> &str {
+            "Slow"
+        }
+
+        fn default_model(&self) -> &str {
+            "slow"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_provider_times_out() {
+        let request = ChatRequest {
+            system: None,
+            messages: Vec::new(),
+            temperature: 0.0,
+            max_tokens: 16,
+            tools: None,
+        };
+        let timeout = Duration::from_millis(20);
+
+        let started = std::time::Instant::now();
+        let result = SlowProvider
+            .chat_completion_bounded(request, &CancellationToken::new(), timeout)
+            .await;
+
+        assert!(matches!(result, Err(ProviderError::Timeout(after)) if after == timeout));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
     #[test]
     fn test_gemini_provider_creation() {
         let provider = GeminiProvider::new("test-api-key".to_string());
@@ -458,6 +1211,8 @@ mod tests {
         let provider = GeminiProvider::with_model("test-api-key".to_string(), "gemini-pro".to_string());
         assert_eq!(provider.provider_name(), "Gemini");
         assert_eq!(provider.model, "gemini-pro");
+        // Requests are recorded against the configured model, not the default
+        assert_eq!(provider.model(), "gemini-pro");
     }
 
     #[test]
@@ -468,6 +1223,58 @@ mod tests {
         assert_eq!(provider.model, "gpt-4");
     }
 
+    #[tokio::test]
+    async fn test_providers_send_to_their_configured_base_url() {
+        use std::sync::{Arc, Mutex};
+
+        // A gateway that answers both APIs and records the paths it is asked for
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let recorder = paths.clone();
+        let app = axum::Router::new().fallback(move |uri: axum::http::Uri| async move {
+            recorder.lock().unwrap().push(uri.path().to_string());
+            axum::Json(serde_json::json!({
+                "choices": [{ "message": { "content": "hi" } }],
+                "content": [{ "type": "text", "text": "hi" }],
+                "candidates": [{ "content": { "parts": [{ "text": "hi" }] } }]
+            }))
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let request = || ChatRequest {
+            system: None,
+            messages: vec![ChatMessage::user("Hello")],
+            temperature: 0.0,
+            max_tokens: 16,
+            tools: None,
+        };
+        let openai = OpenAIProvider {
+            base_url: format!("http://{}/openai/v1", addr),
+            ..OpenAIProvider::new("test-api-key".to_string())
+        };
+        let anthropic = AnthropicProvider {
+            base_url: format!("http://{}/anthropic", addr),
+            ..AnthropicProvider::new("test-api-key".to_string())
+        };
+        let gemini = GeminiProvider {
+            base_url: format!("http://{}/gemini", addr),
+            ..GeminiProvider::new("test-api-key".to_string())
+        };
+        assert_eq!(openai.chat_completion(request()).await.unwrap().content, "hi");
+        assert_eq!(anthropic.chat_completion(request()).await.unwrap().content, "hi");
+        assert_eq!(gemini.chat_completion(request()).await.unwrap().content, "hi");
+
+        assert_eq!(
+            *paths.lock().unwrap(),
+            vec![
+                "/openai/v1/chat/completions",
+                "/anthropic/v1/messages",
+                "/gemini/v1beta/models/gemini-2.5-flash:generateContent"
+            ]
+        );
+    }
+
     #[test]
     fn test_openai_provider_with_custom_model() {
         let provider = OpenAIProvider::with_model("test-api-key".to_string(), "gpt-3.5-turbo".to_string());
@@ -477,12 +1284,10 @@ mod tests {
 
     #[tokio::test]
     async fn test_chat_request_creation() {
-        let messages = vec![ChatMessage {
-            role: "user".to_string(),
-            content: "Hello, world!".to_string(),
-        }];
+        let messages = vec![ChatMessage::user("Hello, world!")];
 
         let request = ChatRequest {
+            system: None,
             messages,
             temperature: 0.7,
             max_tokens: 1024,
@@ -497,6 +1302,207 @@ mod tests {
         assert_eq!(request.messages[0].content, "Hello, world!");
     }
 
+    #[test]
+    fn test_system_is_sent_the_way_each_provider_expects() {
+        let request = ChatRequest {
+            system: Some("Answer in French.".to_string()),
+            messages: vec![ChatMessage::user("Hello")],
+            temperature: 0.7,
+            max_tokens: 1024,
+            tools: None,
+        };
+
+        let gemini = serde_json::to_value(GeminiProvider::build_request(request.clone())).unwrap();
+        assert_eq!(
+            gemini["systemInstruction"],
+            serde_json::json!({"parts": [{"text": "Answer in French."}]})
+        );
+        assert_eq!(gemini["contents"].as_array().unwrap().len(), 1);
+        assert_eq!(gemini["contents"][0]["role"], "user");
+
+        let openai = OpenAIProvider::new("test-api-key".to_string());
+        let openai = serde_json::to_value(openai.build_request(request.clone())).unwrap();
+        assert_eq!(
+            openai["messages"],
+            serde_json::json!([
+                {"role": "system", "content": "Answer in French."},
+                {"role": "user", "content": "Hello"}
+            ])
+        );
+
+        let without_system = ChatRequest {
+            system: None,
+            ..request
+        };
+        let gemini = serde_json::to_value(GeminiProvider::build_request(without_system)).unwrap();
+        assert!(gemini.get("systemInstruction").is_none());
+    }
+
+    #[test]
+    fn test_tool_round_trip_uses_each_providers_function_calling() {
+        let gemini_reply: GeminiResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{"content": {"role": "model", "parts": [
+                {"text": "Looking that up."},
+                {"functionCall": {"name": "get_contact_info", "args": {"name": "Jane"}}}
+            ]}}]
+        }))
+        .unwrap();
+        assert_eq!(
+            GeminiProvider::candidate_text(&gemini_reply),
+            Some(vec!["Looking that up.".to_string()])
+        );
+        let calls = GeminiProvider::candidate_tool_calls(&gemini_reply);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].name, "get_contact_info");
+        assert_eq!(calls[0].arguments["name"], "Jane");
+
+        let request = ChatRequest {
+            system: None,
+            messages: vec![
+                ChatMessage::user("Find Jane"),
+                ChatMessage::assistant_tool_calls("", calls.clone()),
+                ChatMessage::tool_result(calls[0].id.clone(), r#"{"contacts":[]}"#),
+            ],
+            temperature: 0.7,
+            max_tokens: 1024,
+            tools: None,
+        };
+
+        let gemini = serde_json::to_value(GeminiProvider::build_request(request.clone())).unwrap();
+        assert_eq!(gemini["contents"][1]["role"], "model");
+        assert_eq!(
+            gemini["contents"][1]["parts"],
+            serde_json::json!([{"functionCall": {"name": "get_contact_info", "args": {"name": "Jane"}}}])
+        );
+        assert_eq!(
+            gemini["contents"][2]["parts"][0]["functionResponse"],
+            serde_json::json!({"name": "get_contact_info", "response": {"contacts": []}})
+        );
+
+        let openai = OpenAIProvider::new("test-api-key".to_string());
+        let openai = serde_json::to_value(openai.build_request(request)).unwrap();
+        let call = &openai["messages"][1]["tool_calls"][0];
+        assert_eq!(call["id"], calls[0].id.as_str());
+        assert_eq!(call["type"], "function");
+        assert_eq!(call["function"]["arguments"], r#"{"name":"Jane"}"#);
+        assert_eq!(
+            openai["messages"][2],
+            serde_json::json!({"role": "tool", "content": r#"{"contacts":[]}"#, "tool_call_id": calls[0].id})
+        );
+    }
+
+    #[test]
+    fn test_gemini_function_calls_become_tool_calls() {
+        let reply: GeminiResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{"content": {"role": "model", "parts": [
+                {"functionCall": {"name": "get_contact_info", "args": {"name": "Jane"}}},
+                {"executableCode": {"language": "PYTHON", "code": "print(1)"}},
+                {"functionCall": {"name": "create_contact_list", "args": {}}}
+            ]}, "finishReason": "STOP"}],
+            "usageMetadata": {"promptTokenCount": 12, "candidatesTokenCount": 5}
+        }))
+        .unwrap();
+
+        let response = GeminiProvider::chat_response(&reply).unwrap();
+        assert_eq!(response.content, "");
+        let usage = response.usage.unwrap();
+        assert_eq!(
+            (usage.prompt_tokens, usage.completion_tokens, usage.total_tokens),
+            (12, 5, 17)
+        );
+        let calls = response.tool_calls.unwrap();
+        let names: Vec<&str> = calls.iter().map(|call| call.name.as_str()).collect();
+        assert_eq!(names, vec!["get_contact_info", "create_contact_list"]);
+        assert_eq!(calls[0].arguments, serde_json::json!({"name": "Jane"}));
+        assert_ne!(calls[0].id, calls[1].id);
+
+        let malformed: GeminiResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{"finishReason": "MALFORMED_FUNCTION_CALL"}]
+        }))
+        .unwrap();
+        assert!(matches!(
+            GeminiProvider::chat_response(&malformed),
+            Err(ProviderError::InvalidResponse(message)) if message.contains("malformed function call")
+        ));
+    }
+
+    #[test]
+    fn test_anthropic_tool_use_round_trip() {
+        let reply: AnthropicResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                {"type": "thinking", "thinking": "...", "signature": "sig"},
+                {"type": "text", "text": "Looking that up."},
+                {"type": "tool_use", "id": "toolu_1", "name": "get_contact_info", "input": {"name": "Jane"}}
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 40, "output_tokens": 9}
+        }))
+        .unwrap();
+        let response = AnthropicProvider::chat_response(reply);
+        assert_eq!(response.content, "Looking that up.");
+        assert_eq!(response.usage.as_ref().map(|usage| usage.total_tokens), Some(49));
+        let calls = response.tool_calls.unwrap();
+        assert_eq!(calls[0].id, "toolu_1");
+        assert_eq!(calls[0].arguments, serde_json::json!({"name": "Jane"}));
+
+        let request = ChatRequest {
+            system: Some("Answer in French.".to_string()),
+            messages: vec![
+                ChatMessage::user("Find Jane"),
+                ChatMessage::assistant_tool_calls("Looking that up.", calls),
+                ChatMessage::tool_result("toolu_1", r#"{"contacts":[]}"#),
+                ChatMessage::user("Now write your answer."),
+            ],
+            temperature: 0.7,
+            max_tokens: 1024,
+            tools: None,
+        };
+        let provider = AnthropicProvider::new("test-api-key".to_string());
+        let body = serde_json::to_value(provider.build_request(request)).unwrap();
+
+        assert_eq!(body["system"], "Answer in French.");
+        assert_eq!(
+            body["messages"][1]["content"][1],
+            serde_json::json!({"type": "tool_use", "id": "toolu_1", "name": "get_contact_info", "input": {"name": "Jane"}})
+        );
+        // The tool result and the follow-up share one user turn, the result first
+        assert_eq!(body["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            body["messages"][2],
+            serde_json::json!({"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "toolu_1", "content": r#"{"contacts":[]}"#},
+                {"type": "text", "text": "Now write your answer."}
+            ]})
+        );
+    }
+
+    #[test]
+    fn test_anthropic_skips_empty_text() {
+        let request = ChatRequest {
+            system: None,
+            messages: vec![
+                ChatMessage::user("Hi"),
+                ChatMessage::assistant(""),
+                ChatMessage::user("Still there?"),
+            ],
+            temperature: 0.7,
+            max_tokens: 1024,
+            tools: None,
+        };
+        let provider = AnthropicProvider::new("test-api-key".to_string());
+        let body = serde_json::to_value(provider.build_request(request)).unwrap();
+        assert_eq!(
+            body["messages"],
+            serde_json::json!([{"role": "user", "content": [
+                {"type": "text", "text": "Hi"},
+                {"type": "text", "text": "Still there?"}
+            ]}])
+        );
+    }
+
     #[test]
     fn test_tool_parameters_creation() {
         let mut properties = HashMap::new();
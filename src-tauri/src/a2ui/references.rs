@@ -0,0 +1,263 @@
+//! Reference integrity checks for agent output
+//!
+//! Schema validation only checks the shape of each component, so a model can emit a
+//! surface where `a`'s child is `b` and `b`'s child is `a`. A renderer walking that tree
+//! never terminates, so cycles are found here by a depth-first traversal of each
+//! surface, starting from its `beginRendering` roots.
+//!
+//! A `beginRendering` root that is never declared leaves the surface blank, so roots
+//! must be declared somewhere in the same response or by an earlier one.
+
+use std::collections::{HashMap, HashSet};
+
+use super::agent::A2UIMessageResponse;
+use super::component_ids::SurfaceComponents;
+use super::schema::{Children, UIComponent, UIComponentType};
+
+/// Ids of the components `component` renders as children
+pub fn component_references(component: &UIComponent) -> Vec<&str> {
+    fn children_ids(children: &Children) -> Vec<&str> {
+        children
+            .explicit_list
+            .iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(children.template.iter().map(|template| template.component_id.as_str()))
+            .collect()
+    }
+
+    match &component.component {
+        UIComponentType::Button { child, .. } => vec![child.as_str()],
+        UIComponentType::Row { children, .. }
+        | UIComponentType::Column { children, .. }
+        | UIComponentType::List { children, .. } => children_ids(children),
+        UIComponentType::Card { child, children } => child
+            .iter()
+            .map(String::as_str)
+            .chain(children.iter().flat_map(children_ids))
+            .collect(),
+        UIComponentType::Tabs { tab_items, .. } => tab_items.iter().map(|tab| tab.child.as_str()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// `beginRendering` roots, as `(surface_id, root)`, that no `surfaceUpdate` in `messages`
+/// declares on that surface and that `known` doesn't hold from an earlier response
+pub fn undefined_roots<'a>(messages: &'a [A2UIMessageResponse], known: &SurfaceComponents) -> Vec<(&'a str, &'a str)> {
+    let mut declared: HashSet<(&str, &str)> = HashSet::new();
+    for message in messages {
+        if let A2UIMessageResponse::SurfaceUpdate(update) = message {
+            for component in &update.components {
+                declared.insert((update.surface_id.as_str(), component.id.as_str()));
+            }
+        }
+    }
+
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            A2UIMessageResponse::BeginRendering(rendering) => {
+                Some((rendering.surface_id.as_str(), rendering.root.as_str()))
+            }
+            _ => None,
+        })
+        .filter(|(surface_id, root)| {
+            !declared.contains(&(*surface_id, *root)) && !known.get(*surface_id).is_some_and(|ids| ids.contains(*root))
+        })
+        .collect()
+}
+
+/// Move each `beginRendering` after the last `surfaceUpdate` for its surface, so
+/// renderers that act on messages one at a time have the root before rendering it
+///
+/// The order is otherwise kept.
+pub fn render_after_components(messages: Vec<A2UIMessageResponse>) -> Vec<A2UIMessageResponse> {
+    let mut last_update: HashMap<String, usize> = HashMap::new();
+    for (index, message) in messages.iter().enumerate() {
+        if let A2UIMessageResponse::SurfaceUpdate(update) = message {
+            last_update.insert(update.surface_id.clone(), index);
+        }
+    }
+
+    let mut ordered = Vec::with_capacity(messages.len());
+    let mut deferred: HashMap<String, Vec<A2UIMessageResponse>> = HashMap::new();
+    for (index, message) in messages.into_iter().enumerate() {
+        match &message {
+            A2UIMessageResponse::BeginRendering(rendering)
+                if last_update.get(&rendering.surface_id).is_some_and(|last| *last > index) =>
+            {
+                deferred.entry(rendering.surface_id.clone()).or_default().push(message);
+            }
+            A2UIMessageResponse::SurfaceUpdate(update) if last_update.get(&update.surface_id) == Some(&index) => {
+                let surface_id = update.surface_id.clone();
+                ordered.push(message);
+                ordered.extend(deferred.remove(&surface_id).unwrap_or_default());
+            }
+            _ => ordered.push(message),
+        }
+    }
+    ordered
+}
+
+/// The first reference cycle in `messages`, as the ids along it ending where it started
+///
+/// References to components not declared in the response are not followed.
+pub fn find_cycle(messages: &[A2UIMessageResponse]) -> Option<Vec<String>> {
+    let mut surfaces: Vec<&str> = Vec::new();
+    let mut roots: HashMap<&str, Vec<&str>> = HashMap::new();
+    let mut graphs: HashMap<&str, HashMap<&str, Vec<&str>>> = HashMap::new();
+    let mut declared: HashMap<&str, Vec<&str>> = HashMap::new();
+
+    for message in messages {
+        match message {
+            A2UIMessageResponse::BeginRendering(rendering) => {
+                let surface_id = rendering.surface_id.as_str();
+                if !surfaces.contains(&surface_id) {
+                    surfaces.push(surface_id);
+                }
+                roots.entry(surface_id).or_default().push(rendering.root.as_str());
+            }
+            A2UIMessageResponse::SurfaceUpdate(update) => {
+                let surface_id = update.surface_id.as_str();
+                if !surfaces.contains(&surface_id) {
+                    surfaces.push(surface_id);
+                }
+                for component in &update.components {
+                    declared.entry(surface_id).or_default().push(component.id.as_str());
+                    // A later declaration of the same id replaces the earlier one
+                    graphs
+                        .entry(surface_id)
+                        .or_default()
+                        .insert(component.id.as_str(), component_references(component));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    surfaces.into_iter().find_map(|surface_id| {
+        let graph = graphs.get(surface_id)?;
+        let mut finished = HashSet::new();
+        // Roots first so a reported cycle reads from the top of the tree, then anything
+        // unreachable from them, which a later update could still attach
+        let starts = roots.get(surface_id).into_iter().flatten();
+        let rest = declared.get(surface_id).into_iter().flatten();
+        starts
+            .chain(rest)
+            .find_map(|start| visit(graph, start, &mut Vec::new(), &mut finished))
+    })
+}
+
+/// Depth-first search from `id`; `path` holds the ids currently being visited
+fn visit<'a>(
+    graph: &HashMap<&'a str, Vec<&'a str>>,
+    id: &'a str,
+    path: &mut Vec<&'a str>,
+    finished: &mut HashSet<&'a str>,
+) -> Option<Vec<String>> {
+    if let Some(start) = path.iter().position(|visiting| *visiting == id) {
+        return Some(path[start..].iter().chain([&id]).map(|id| id.to_string()).collect());
+    }
+    if finished.contains(id) {
+        return None;
+    }
+    let references = graph.get(id)?;
+
+    path.push(id);
+    let cycle = references
+        .iter()
+        .find_map(|reference| visit(graph, reference, path, finished));
+    path.pop();
+    finished.insert(id);
+    cycle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(value: serde_json::Value) -> Vec<A2UIMessageResponse> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_acyclic_tree_has_no_cycle() {
+        let response = messages(serde_json::json!([
+            {"beginRendering": {"surfaceId": "main", "root": "root"}},
+            {"surfaceUpdate": {"surfaceId": "main", "components": [
+                {"id": "root", "component": {"Column": {"children": {"explicitList": ["title", "card", "missing"]}}}},
+                {"id": "title", "component": {"Text": {"text": {"literalString": "Main"}}}},
+                {"id": "card", "component": {"Card": {"children": {"explicitList": ["title", "body"]}}}},
+                {"id": "body", "component": {"Text": {"text": {"literalString": "Body"}}}}
+            ]}}
+        ]));
+
+        // `title` is shared by two parents, which is not a cycle
+        assert_eq!(find_cycle(&response), None);
+    }
+
+    #[test]
+    fn test_two_node_cycle_is_detected() {
+        let response = messages(serde_json::json!([
+            {"beginRendering": {"surfaceId": "main", "root": "root"}},
+            {"surfaceUpdate": {"surfaceId": "main", "components": [
+                {"id": "root", "component": {"Column": {"children": {"explicitList": ["a"]}}}},
+                {"id": "a", "component": {"Card": {"child": "b"}}},
+                {"id": "b", "component": {"Button": {"child": "a"}}}
+            ]}}
+        ]));
+
+        assert_eq!(
+            find_cycle(&response),
+            Some(vec!["a".to_string(), "b".to_string(), "a".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_roots_declared_later_in_the_batch_are_moved_after_their_components() {
+        let response = messages(serde_json::json!([
+            {"beginRendering": {"surfaceId": "main", "root": "root"}},
+            {"beginRendering": {"surfaceId": "side", "root": "note"}},
+            {"surfaceUpdate": {"surfaceId": "main", "components": [
+                {"id": "root", "component": {"Text": {"text": {"literalString": "Main"}}}}
+            ]}},
+            {"dataModelUpdate": {"surfaceId": "main", "patches": []}}
+        ]));
+        let known = SurfaceComponents::from([("side".to_string(), HashSet::from(["note".to_string()]))]);
+
+        assert!(undefined_roots(&response, &known).is_empty());
+
+        let kinds: Vec<String> = render_after_components(response)
+            .iter()
+            .map(|message| {
+                let json = serde_json::to_value(message).unwrap();
+                let kind = json.as_object().unwrap().keys().next().unwrap().clone();
+                format!("{}:{}", kind, json[&kind]["surfaceId"].as_str().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "beginRendering:side",
+                "surfaceUpdate:main",
+                "beginRendering:main",
+                "dataModelUpdate:main"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_roots_never_declared_are_reported() {
+        let response = messages(serde_json::json!([
+            {"beginRendering": {"surfaceId": "main", "root": "root"}},
+            {"surfaceUpdate": {"surfaceId": "side", "components": [
+                {"id": "root", "component": {"Text": {"text": {"literalString": "Wrong surface"}}}}
+            ]}}
+        ]));
+
+        assert_eq!(
+            undefined_roots(&response, &SurfaceComponents::new()),
+            vec![("main", "root")]
+        );
+    }
+}
@@ -3,9 +3,16 @@ use serde::{Deserialize, Serialize};
 // Include the A2UI schema as a static string
 pub const A2UI_SCHEMA_JSON: &str = include_str!("schema.json");
 
+/// The A2UI JSON schema that agent responses are validated against
+pub fn a2ui_schema() -> Result<serde_json::Value, serde_json::Error> {
+    serde_json::from_str(A2UI_SCHEMA_JSON)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Styles {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub font: Option<String>,
+    #[serde(rename = "primaryColor", skip_serializing_if = "Option::is_none")]
     pub primary_color: Option<String>,
 }
 
@@ -14,6 +21,7 @@ pub struct BeginRendering {
     #[serde(rename = "surfaceId")]
     pub surface_id: String,
     pub root: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub styles: Option<Styles>,
 }
 
@@ -37,86 +45,204 @@ pub struct DataPatch {
     pub value: serde_json::Value,
 }
 
+/// Legacy `dataModelUpdate` body that lists `contents` entries instead of `patches`
+#[derive(Debug, Clone, Deserialize)]
+pub struct LegacyDataModelUpdate {
+    #[serde(rename = "surfaceId")]
+    pub surface_id: String,
+    pub contents: Vec<DataEntry>,
+}
+
+/// A legacy data model entry: a key holding exactly one typed value or a nested map
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataEntry {
+    pub key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_string: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_number: Option<serde_json::Number>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_boolean: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_map: Option<Vec<DataEntry>>,
+}
+
+impl DataEntry {
+    /// The entry's value, with a nested map materialized as an object
+    ///
+    /// `parent` is the JSON Pointer of the enclosing map, used in error messages.
+    fn to_value(&self, parent: &str) -> Result<serde_json::Value, String> {
+        let path = format!("{}/{}", parent, pointer_token(&self.key));
+
+        let mut values = Vec::new();
+        if let Some(text) = &self.value_string {
+            values.push(("valueString", serde_json::Value::String(text.clone())));
+        }
+        if let Some(number) = &self.value_number {
+            values.push(("valueNumber", serde_json::Value::Number(number.clone())));
+        }
+        if let Some(flag) = self.value_boolean {
+            values.push(("valueBoolean", serde_json::Value::Bool(flag)));
+        }
+        if let Some(entries) = &self.value_map {
+            let map = entries
+                .iter()
+                .map(|entry| Ok((entry.key.clone(), entry.to_value(&path)?)))
+                .collect::<Result<_, String>>()?;
+            values.push(("valueMap", serde_json::Value::Object(map)));
+        }
+
+        match <[_; 1]>::try_from(values) {
+            Ok([(_, value)]) => Ok(value),
+            Err(values) => {
+                let found: Vec<&str> = values.iter().map(|(kind, _)| *kind).collect();
+                Err(format!(
+                    "Data entry {} must set exactly one of valueString, valueNumber, valueBoolean or valueMap (found: {})",
+                    path,
+                    if found.is_empty() { "none".to_string() } else { found.join(", ") }
+                ))
+            }
+        }
+    }
+
+    /// The patch setting this entry at the top level of the data model
+    fn to_patch(&self) -> Result<DataPatch, String> {
+        Ok(DataPatch {
+            path: format!("/{}", pointer_token(&self.key)),
+            value: self.to_value("")?,
+        })
+    }
+}
+
+/// Escape a key for use as a JSON Pointer (RFC 6901) reference token
+pub fn pointer_token(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+impl TryFrom<LegacyDataModelUpdate> for DataModelUpdate {
+    type Error = String;
+
+    /// One patch per top-level entry, after checking every entry holds exactly one value
+    fn try_from(legacy: LegacyDataModelUpdate) -> Result<Self, Self::Error> {
+        Ok(DataModelUpdate {
+            surface_id: legacy.surface_id,
+            patches: legacy
+                .contents
+                .iter()
+                .map(DataEntry::to_patch)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeleteSurface {
     #[serde(rename = "surfaceId")]
     pub surface_id: String,
 }
 
+/// A component wrapped in its type name, e.g. `{"Card": {"child": "body"}}`
 #[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "t", content = "c")]
 pub enum UIComponentType {
     #[serde(rename = "Text")]
     Text {
         #[serde(rename = "text")]
         text: TextValue,
-        #[serde(rename = "usageHint")]
+        #[serde(rename = "usageHint", skip_serializing_if = "Option::is_none")]
         usage_hint: Option<String>,
     },
+    #[serde(rename = "Image")]
+    Image {
+        url: TextValue,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fit: Option<String>,
+    },
     #[serde(rename = "Button")]
     Button {
         child: String,
-        #[serde(rename = "primary")]
+        #[serde(rename = "primary", skip_serializing_if = "Option::is_none")]
         primary: Option<bool>,
-        #[serde(rename = "secondary")]
+        #[serde(rename = "secondary", skip_serializing_if = "Option::is_none")]
         secondary: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         action: Option<Action>,
     },
     #[serde(rename = "Row")]
     Row {
+        #[serde(skip_serializing_if = "Option::is_none")]
         alignment: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         distribution: Option<String>,
         children: Children,
     },
     #[serde(rename = "Column")]
     Column {
+        #[serde(skip_serializing_if = "Option::is_none")]
         alignment: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         distribution: Option<String>,
         children: Children,
     },
+    /// Either an `explicitList` of item ids or a `template` repeated over a data binding
     #[serde(rename = "List")]
     List {
         children: Children,
+        #[serde(skip_serializing_if = "Option::is_none")]
         direction: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         alignment: Option<String>,
     },
+    /// A card holding a single `child`, or several via `children`
     #[serde(rename = "Card")]
-    Card { child: String },
+    Card {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        child: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        children: Option<Children>,
+    },
     #[serde(rename = "TextField")]
     TextField {
         label: TextValue,
+        #[serde(skip_serializing_if = "Option::is_none")]
         value: Option<TextValue>,
-        #[serde(rename = "type")]
+        #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
         field_type: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
         action: Option<Action>,
     },
     #[serde(rename = "Tabs")]
     Tabs {
         #[serde(rename = "tabItems")]
         tab_items: Vec<TabItem>,
-        #[serde(rename = "selectedTabBinding")]
+        #[serde(rename = "selectedTabBinding", skip_serializing_if = "Option::is_none")]
         selected_tab_binding: Option<String>,
     },
     #[serde(rename = "Icon")]
     Icon {
-        #[serde(rename = "iconType")]
+        #[serde(rename = "iconType", skip_serializing_if = "Option::is_none")]
         icon_type: Option<String>,
     },
     #[serde(rename = "Divider")]
-    Divider { orientation: Option<String> },
+    Divider {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        orientation: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UIComponent {
     pub id: String,
     pub component: UIComponentType,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub weight: Option<f64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TextValue {
-    #[serde(rename = "literalString")]
+    #[serde(rename = "literalString", skip_serializing_if = "Option::is_none")]
     pub literal_string: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub path: Option<String>,
 }
 
@@ -146,8 +272,9 @@ pub enum ActionValue {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Children {
-    #[serde(rename = "explicitList")]
+    #[serde(rename = "explicitList", skip_serializing_if = "Option::is_none")]
     pub explicit_list: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub template: Option<Template>,
 }
 
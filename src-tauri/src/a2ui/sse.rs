@@ -0,0 +1,189 @@
+//! Server-Sent Events parsing for provider streaming responses
+//!
+//! Network reads rarely line up with SSE event boundaries: a `data:` line (or even a
+//! multi-byte character) can be split across chunks. [`SseAssembler`] buffers raw bytes
+//! and only emits an event once its terminating blank line has arrived.
+
+use futures::{Stream, StreamExt};
+
+use super::provider::ProviderError;
+
+/// Sentinel payload OpenAI-compatible APIs send to mark the end of a stream
+const DONE_MARKER: &str = "[DONE]";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SseEvent {
+    /// The joined `data:` payload of a complete event
+    Data(String),
+    /// The provider signalled the end of the stream
+    Done,
+}
+
+/// Incrementally reassembles SSE events from arbitrarily split byte chunks
+#[derive(Debug, Default)]
+pub struct SseAssembler {
+    buffer: Vec<u8>,
+    data_lines: Vec<String>,
+}
+
+impl SseAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next network chunk, returning every event completed by it
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(event) = self.process_line(line.trim_end_matches(['\r', '\n'])) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Flush a trailing event that was not terminated by a blank line
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if !self.buffer.is_empty() {
+            let line = String::from_utf8_lossy(&std::mem::take(&mut self.buffer)).into_owned();
+            if let Some(event) = self.process_line(line.trim_end_matches('\r')) {
+                return Some(event);
+            }
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+
+        // Comment lines (commonly `: keep-alive`) carry no data
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        // Only the data field matters for completion deltas; event/id/retry are ignored
+        if field == "data" {
+            self.data_lines.push(value.to_string());
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        if self.data_lines.is_empty() {
+            return None;
+        }
+
+        let data = std::mem::take(&mut self.data_lines).join("\n");
+        if data.trim() == DONE_MARKER {
+            Some(SseEvent::Done)
+        } else {
+            Some(SseEvent::Data(data))
+        }
+    }
+}
+
+/// Turn a raw byte stream into a stream of SSE `data` payloads, ending at `[DONE]`
+pub fn sse_data_stream<S, B, E>(bytes: S) -> impl Stream<Item = Result<String, ProviderError>> + Send
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send,
+    E: Into<ProviderError> + Send,
+{
+    async_stream::stream! {
+        let mut assembler = SseAssembler::new();
+        futures::pin_mut!(bytes);
+
+        while let Some(chunk) = bytes.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e.into());
+                    return;
+                }
+            };
+
+            for event in assembler.push(chunk.as_ref()) {
+                match event {
+                    SseEvent::Data(data) => yield Ok(data),
+                    SseEvent::Done => return,
+                }
+            }
+        }
+
+        if let Some(SseEvent::Data(data)) = assembler.finish() {
+            yield Ok(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reassembles_events_split_across_chunks() {
+        let body = concat!(
+            ": keep-alive\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"lo, \"}}]}\r\n\r\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"wörld\"}}]}\n\n",
+            "data: [DONE]\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"ignored\"}}]}\n\n",
+        )
+        .as_bytes();
+
+        // Split at awkward offsets, including mid-JSON and inside the multi-byte "ö"
+        let mid_char = body.iter().position(|&b| b == 0xC3).unwrap() + 1;
+        let mut offsets: Vec<usize> = [3, 20, 41, 58, 97, 120, mid_char]
+            .into_iter()
+            .filter(|&i| i < body.len())
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for end in offsets.into_iter().chain(std::iter::once(body.len())) {
+            chunks.push(Ok::<_, ProviderError>(body[start..end].to_vec()));
+            start = end;
+        }
+
+        let payloads: Vec<String> = sse_data_stream(futures::stream::iter(chunks))
+            .map(|item| item.unwrap())
+            .collect()
+            .await;
+
+        let deltas: Vec<String> = payloads
+            .iter()
+            .map(|payload| {
+                let value: serde_json::Value = serde_json::from_str(payload).unwrap();
+                value["choices"][0]["delta"]["content"].as_str().unwrap().to_string()
+            })
+            .collect();
+
+        assert_eq!(deltas, vec!["Hel", "lo, ", "wörld"]);
+    }
+
+    #[test]
+    fn test_multiline_data_and_trailing_event() {
+        let mut assembler = SseAssembler::new();
+        assert!(assembler.push(b"event: message\ndata: first\nda").is_empty());
+        assert_eq!(
+            assembler.push(b"ta: second\n\ndata: tail"),
+            vec![SseEvent::Data("first\nsecond".to_string())]
+        );
+        assert_eq!(assembler.finish(), Some(SseEvent::Data("tail".to_string())));
+        assert_eq!(assembler.finish(), None);
+    }
+}
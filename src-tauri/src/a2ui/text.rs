@@ -0,0 +1,119 @@
+//! Post-processing for text-mode (non-UI) agent responses
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+/// Trim trailing whitespace and collapse runs of blank lines into a single blank line.
+///
+/// Content inside fenced code blocks is left untouched.
+pub fn normalize_whitespace(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    let mut previous_blank = true;
+
+    for line in text.lines() {
+        let is_fence = line.trim_start().starts_with("```");
+        if in_fence && !is_fence {
+            lines.push(line);
+            continue;
+        }
+        if is_fence {
+            in_fence = !in_fence;
+        }
+
+        let trimmed = line.trim_end();
+        let blank = trimmed.is_empty();
+        if blank && previous_blank {
+            continue;
+        }
+        lines.push(trimmed);
+        previous_blank = blank;
+    }
+
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+
+    lines.join("\n")
+}
+
+/// Render markdown as plain text, dropping emphasis, heading and link syntax
+pub fn strip_markdown(text: &str) -> String {
+    let mut out = String::new();
+    let mut lists: Vec<Option<u64>> = Vec::new();
+
+    let options = Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES | Options::ENABLE_TASKLISTS;
+    for event in Parser::new_ext(text, options) {
+        match event {
+            Event::Start(Tag::List(start)) => {
+                ensure_newline(&mut out);
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    out.push('\n');
+                }
+            }
+            Event::Start(Tag::Item) => {
+                ensure_newline(&mut out);
+                out.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        out.push_str(&format!("{}. ", number));
+                        *number += 1;
+                    }
+                    _ => out.push_str("- "),
+                }
+            }
+            Event::End(TagEnd::Item) | Event::End(TagEnd::TableHead) | Event::End(TagEnd::TableRow) => {
+                ensure_newline(&mut out)
+            }
+            Event::End(TagEnd::TableCell) => out.push('\t'),
+            Event::End(
+                TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::CodeBlock | TagEnd::BlockQuote(_) | TagEnd::Table,
+            )
+            | Event::Rule => out.push_str("\n\n"),
+            Event::Text(text) | Event::Code(text) | Event::InlineMath(text) | Event::DisplayMath(text) => {
+                out.push_str(&text)
+            }
+            Event::SoftBreak | Event::HardBreak => out.push('\n'),
+            Event::TaskListMarker(checked) => out.push_str(if checked { "[x] " } else { "[ ] " }),
+            _ => {}
+        }
+    }
+
+    normalize_whitespace(&out)
+}
+
+fn ensure_newline(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collapses_blank_lines() {
+        let text = "\n\nFirst line   \n\n\n\nSecond line\n\n\n```\ncode\n\n\nmore code\n```\n\n\n";
+        assert_eq!(
+            normalize_whitespace(text),
+            "First line\n\nSecond line\n\n```\ncode\n\n\nmore code\n```"
+        );
+    }
+
+    #[test]
+    fn test_strip_markdown_removes_markers() {
+        let text = "# Contacts\n\nHere is **John Doe** and *Jane*.\n\n\n\n- first\n- `second`\n\nSee [docs](https://example.com).";
+        let plain = strip_markdown(text);
+
+        assert_eq!(
+            plain,
+            "Contacts\n\nHere is John Doe and Jane.\n\n- first\n- second\n\nSee docs."
+        );
+        assert!(!plain.contains("**"));
+        assert!(!plain.contains('#'));
+    }
+}
@@ -0,0 +1,70 @@
+//! Application information for the About/diagnostics screen
+//!
+//! Reports what the frontend is running against: app version, build hash, platform,
+//! configured AI providers and which backend subsystems initialized successfully.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tauri::command;
+
+use crate::search::{application_index_populated, configured_ai_providers};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppInfo {
+    pub version: String,
+    /// Git commit the binary was built from, when built from a checkout
+    pub build_hash: Option<String>,
+    pub os: String,
+    pub arch: String,
+    pub ai_providers: Vec<String>,
+    /// Subsystem name to whether it initialized
+    pub subsystems: BTreeMap<String, bool>,
+}
+
+static SUBSYSTEMS: Lazy<Mutex<BTreeMap<String, bool>>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Record whether a subsystem initialized at startup
+pub fn record_initialized(subsystem: &str, initialized: bool) {
+    if let Ok(mut subsystems) = SUBSYSTEMS.lock() {
+        subsystems.insert(subsystem.to_string(), initialized);
+    }
+}
+
+pub fn app_info() -> AppInfo {
+    let mut subsystems = SUBSYSTEMS.lock().map(|s| s.clone()).unwrap_or_default();
+    subsystems.insert("app_cache".to_string(), application_index_populated());
+
+    AppInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        build_hash: option_env!("FLEET_BUILD_HASH").map(String::from),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        ai_providers: configured_ai_providers(),
+        subsystems,
+    }
+}
+
+/// Get the app version, build info, configured providers and subsystem status
+#[command]
+pub fn get_app_info() -> AppInfo {
+    app_info()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_app_info_reports_version_and_providers() {
+        record_initialized("rig_agent", false);
+
+        let info = app_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.ai_providers, configured_ai_providers());
+        assert_eq!(info.os, std::env::consts::OS);
+        assert_eq!(info.subsystems.get("rig_agent"), Some(&false));
+        assert!(info.subsystems.contains_key("app_cache"));
+    }
+}
@@ -0,0 +1,130 @@
+//! Application launch history for frecency ranking
+//!
+//! Counts how often each application is launched and when it was last opened, so app
+//! search can rank frequently and recently used apps above equally good name matches.
+//! History is persisted under the `app_launches.json` storage key, keyed by app path.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tauri::command;
+
+use crate::storage::{Storage, STORAGE};
+
+const STORAGE_KEY: &str = "app_launches.json";
+
+/// Days after which a launch counts half as much towards an app's frecency
+const RECENCY_HALF_LIFE_DAYS: f64 = 7.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LaunchStats {
+    pub count: u32,
+    pub last_launched: DateTime<Utc>,
+}
+
+pub struct LaunchHistory {
+    storage: Arc<dyn Storage>,
+    launches: HashMap<String, LaunchStats>,
+}
+
+impl LaunchHistory {
+    /// Load launch history from `storage`, starting empty if none has been saved yet
+    pub fn load(storage: Arc<dyn Storage>) -> Self {
+        let launches = storage
+            .read(STORAGE_KEY)
+            .ok()
+            .flatten()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Self { storage, launches }
+    }
+
+    /// Count a launch of the app at `path` and persist the history
+    pub fn record_launch(&mut self, path: &str) -> Result<(), String> {
+        self.record_launch_at(path, Utc::now())
+    }
+
+    fn record_launch_at(&mut self, path: &str, at: DateTime<Utc>) -> Result<(), String> {
+        let path = path.trim();
+        if path.is_empty() {
+            return Err("Application path cannot be empty".to_string());
+        }
+        self.launches
+            .entry(path.to_string())
+            .and_modify(|stats| {
+                stats.count += 1;
+                stats.last_launched = at;
+            })
+            .or_insert(LaunchStats {
+                count: 1,
+                last_launched: at,
+            });
+        self.save()
+    }
+
+    /// Frecency of every launched app at `now`: its launch count, halved for every
+    /// `RECENCY_HALF_LIFE_DAYS` since it was last opened
+    pub fn frecency_scores(&self, now: DateTime<Utc>) -> HashMap<String, f64> {
+        self.launches
+            .iter()
+            .map(|(path, stats)| {
+                let age_days = (now - stats.last_launched).num_seconds().max(0) as f64 / 86_400.0;
+                let decay = 0.5_f64.powf(age_days / RECENCY_HALF_LIFE_DAYS);
+                (path.clone(), stats.count as f64 * decay)
+            })
+            .collect()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let content = serde_json::to_vec_pretty(&self.launches).map_err(|e| e.to_string())?;
+        self.storage
+            .write(STORAGE_KEY, &content)
+            .map_err(|e| format!("Failed to save launch history: {}", e))
+    }
+}
+
+static LAUNCH_HISTORY: Lazy<Mutex<LaunchHistory>> = Lazy::new(|| Mutex::new(LaunchHistory::load(STORAGE.clone())));
+
+/// Current frecency of every launched app, for ranking search results
+pub fn frecency_scores() -> HashMap<String, f64> {
+    LAUNCH_HISTORY
+        .lock()
+        .map(|history| history.frecency_scores(Utc::now()))
+        .unwrap_or_default()
+}
+
+/// Record that the user launched the application at `path`
+#[command]
+pub fn record_launch(path: String) -> Result<(), String> {
+    LAUNCH_HISTORY.lock().map_err(|e| e.to_string())?.record_launch(&path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_launches_are_persisted_and_decay_with_age() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let now = Utc::now();
+        let mut history = LaunchHistory::load(Arc::clone(&storage));
+
+        history
+            .record_launch_at("/Applications/Chrome.app", now - chrono::Duration::days(14))
+            .unwrap();
+        history
+            .record_launch_at("/Applications/Chrome.app", now - chrono::Duration::days(14))
+            .unwrap();
+        history.record_launch_at("/Applications/Calendar.app", now).unwrap();
+        assert!(history.record_launch("  ").is_err());
+
+        let scores = LaunchHistory::load(storage).frecency_scores(now);
+        assert!((scores["/Applications/Calendar.app"] - 1.0).abs() < 1e-9);
+        // Two launches two half-lives ago weigh half as much as one launch today
+        assert!((scores["/Applications/Chrome.app"] - 0.5).abs() < 1e-9);
+        assert_eq!(scores.len(), 2);
+    }
+}
@@ -0,0 +1,414 @@
+/**
+ * Generation Attachments
+ *
+ * Reads files attached to an AI request and turns them into prompt context:
+ * text files are inlined into the prompt (truncated past a size limit), and
+ * images are sent as image parts when the target model accepts them.
+ */
+use base64::{engine::general_purpose, Engine as _};
+use rig::completion::message::{ImageDetail, ImageMediaType, MimeType, UserContent};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Maximum number of bytes of a text attachment inlined into the prompt
+pub const MAX_INLINE_TEXT_BYTES: usize = 32 * 1024;
+
+/// Images larger than this are not sent to the provider
+pub const MAX_IMAGE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// How an attachment should be included in the request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IncludeMode {
+    /// Images become image parts, everything else is inlined as text
+    #[default]
+    Auto,
+    Text,
+    Image,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub path: String,
+    #[serde(default)]
+    pub include_mode: IncludeMode,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageAttachment {
+    pub data: Vec<u8>,
+    pub media_type: ImageMediaType,
+}
+
+impl ImageAttachment {
+    /// Image part carrying the image inline, base64-encoded as OpenAI and Gemini require
+    pub fn into_content(self) -> UserContent {
+        UserContent::image_base64(
+            general_purpose::STANDARD.encode(self.data),
+            Some(self.media_type),
+            Some(ImageDetail::Auto),
+        )
+    }
+}
+
+/// Image part for an `http(s)` URL or a base64 `data:` URL
+///
+/// The media type comes from the data URL, or from a URL's file extension when it has one.
+pub fn image_url_content(image_url: &str) -> Result<UserContent, String> {
+    let image_url = image_url.trim();
+
+    if let Some(data_url) = image_url.strip_prefix("data:") {
+        let (mime_type, data) = data_url
+            .split_once(";base64,")
+            .ok_or_else(|| "Image data URLs must be base64-encoded".to_string())?;
+        let media_type = ImageMediaType::from_mime_type(mime_type)
+            .ok_or_else(|| format!("{} is not a supported image type", mime_type))?;
+        let size = general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| format!("Invalid base64 image data: {}", e))?
+            .len() as u64;
+        if size > MAX_IMAGE_BYTES {
+            return Err(format!(
+                "Image is too large ({} bytes, limit {})",
+                size, MAX_IMAGE_BYTES
+            ));
+        }
+        return Ok(UserContent::image_base64(
+            data,
+            Some(media_type),
+            Some(ImageDetail::Auto),
+        ));
+    }
+
+    if !image_url.starts_with("https://") && !image_url.starts_with("http://") {
+        return Err("Image must be an http(s) URL or a base64 data URL".to_string());
+    }
+    let path = image_url.split(['?', '#']).next().unwrap_or_default();
+    Ok(UserContent::image_url(
+        image_url,
+        image_media_type(Path::new(path)),
+        Some(ImageDetail::Auto),
+    ))
+}
+
+/// Image part carrying the image itself, for providers that can't fetch a URL (Gemini)
+///
+/// An `http(s)` URL is downloaded with `client` and its type sniffed from the data, falling
+/// back to the response's `Content-Type`. Data URLs are handled as by [`image_url_content`].
+pub async fn inline_image_url_content(client: &reqwest::Client, image_url: &str) -> Result<UserContent, String> {
+    let image_url = image_url.trim();
+    if !image_url.starts_with("https://") && !image_url.starts_with("http://") {
+        return image_url_content(image_url);
+    }
+
+    let mut response = client
+        .get(image_url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch image: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to fetch image: HTTP {}", response.status()));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(';').next().unwrap_or_default().trim().to_string());
+
+    let mut data = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("Failed to fetch image: {}", e))?
+    {
+        data.extend_from_slice(&chunk);
+        if data.len() as u64 > MAX_IMAGE_BYTES {
+            return Err(format!("Image is too large (limit {} bytes)", MAX_IMAGE_BYTES));
+        }
+    }
+
+    let media_type = sniff_image_type(&data)
+        .or_else(|| content_type.as_deref().and_then(ImageMediaType::from_mime_type))
+        .ok_or_else(|| format!("{} is not a supported image type", image_url))?;
+    Ok(ImageAttachment { data, media_type }.into_content())
+}
+
+/// Image type from the file signature at the start of `data`
+fn sniff_image_type(data: &[u8]) -> Option<ImageMediaType> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(ImageMediaType::PNG)
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some(ImageMediaType::JPEG)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(ImageMediaType::GIF)
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some(ImageMediaType::WEBP)
+    } else {
+        None
+    }
+}
+
+/// The prompt with text attachments inlined, plus any images to send alongside it
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedPrompt {
+    pub text: String,
+    pub images: Vec<ImageAttachment>,
+}
+
+/// Whether a model accepts image input
+pub fn supports_vision(model: &str) -> bool {
+    const VISION_MARKERS: &[&str] = &[
+        "gpt-4o",
+        "gpt-4.1",
+        "gpt-4-turbo",
+        "gpt-5",
+        "o1",
+        "o3",
+        "o4",
+        "claude",
+        "gemini",
+        "vision",
+    ];
+    let model = model.to_lowercase();
+    VISION_MARKERS.iter().any(|marker| model.contains(marker))
+}
+
+/// Read every attachment and merge it into the prompt
+pub fn resolve_attachments(prompt: &str, attachments: &[Attachment], vision: bool) -> Result<ResolvedPrompt, String> {
+    let mut resolved = ResolvedPrompt {
+        text: prompt.to_string(),
+        images: Vec::new(),
+    };
+
+    for attachment in attachments {
+        let path = Path::new(&attachment.path);
+        let image_type = image_media_type(path);
+
+        let as_image = match attachment.include_mode {
+            IncludeMode::Image => true,
+            IncludeMode::Text => false,
+            IncludeMode::Auto => image_type.is_some(),
+        };
+
+        if as_image {
+            let Some(media_type) = image_type else {
+                return Err(format!("{} is not a supported image type", attachment.path));
+            };
+            if !vision {
+                resolved.text.push_str(&format!(
+                    "\n\n[Image attachment {} omitted: the selected model does not accept images]",
+                    attachment.path
+                ));
+                continue;
+            }
+
+            let size = file_size(path, &attachment.path)?;
+            if size > MAX_IMAGE_BYTES {
+                return Err(format!(
+                    "{} is too large to attach ({} bytes, limit {})",
+                    attachment.path, size, MAX_IMAGE_BYTES
+                ));
+            }
+            let data = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", attachment.path, e))?;
+            resolved.images.push(ImageAttachment { data, media_type });
+            continue;
+        }
+
+        resolved
+            .text
+            .push_str(&format!("\n\n--- Attached file: {} ---\n", attachment.path));
+        match read_text(path, &attachment.path)? {
+            Some(text) => resolved.text.push_str(&text),
+            None => resolved.text.push_str("[Binary file omitted]"),
+        }
+        resolved.text.push_str("\n--- End of attached file ---");
+    }
+
+    Ok(resolved)
+}
+
+fn file_size(path: &Path, display: &str) -> Result<u64, String> {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .map_err(|e| format!("Failed to read {}: {}", display, e))
+}
+
+/// Read up to [`MAX_INLINE_TEXT_BYTES`] of a text file, or `None` if it looks binary
+fn read_text(path: &Path, display: &str) -> Result<Option<String>, String> {
+    let size = file_size(path, display)?;
+    let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", display, e))?;
+
+    let mut bytes = Vec::new();
+    file.take(MAX_INLINE_TEXT_BYTES as u64)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read {}: {}", display, e))?;
+
+    if bytes.contains(&0) {
+        return Ok(None);
+    }
+
+    let text = match std::str::from_utf8(&bytes) {
+        Ok(text) => text,
+        // The limit may cut a multi-byte character in half; keep the valid prefix
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+        Err(_) => return Ok(None),
+    };
+
+    let mut text = text.to_string();
+    let omitted = size.saturating_sub(text.len() as u64);
+    if omitted > 0 {
+        text.push_str(&format!("\n[... truncated {} bytes ...]", omitted));
+    }
+    Ok(Some(text))
+}
+
+fn image_media_type(path: &Path) -> Option<ImageMediaType> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "png" => Some(ImageMediaType::PNG),
+        "jpg" | "jpeg" => Some(ImageMediaType::JPEG),
+        "gif" => Some(ImageMediaType::GIF),
+        "webp" => Some(ImageMediaType::WEBP),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn temp_file(dir: &TempDir, name: &str, contents: &[u8]) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn attach(path: &Path) -> Attachment {
+        Attachment {
+            path: path.to_string_lossy().into_owned(),
+            include_mode: IncludeMode::Auto,
+        }
+    }
+
+    #[test]
+    fn test_text_attachment_is_inlined() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = temp_file(&dir, "notes.txt", b"fn main() {}\n");
+
+        let resolved = resolve_attachments("Explain this file", &[attach(&path)], false).unwrap();
+
+        assert!(resolved.text.starts_with("Explain this file\n\n--- Attached file: "));
+        assert!(resolved.text.contains("fn main() {}"));
+        assert!(!resolved.text.contains("truncated"));
+        assert!(resolved.images.is_empty());
+    }
+
+    #[test]
+    fn test_oversized_attachment_is_truncated_with_marker() {
+        let contents = "a".repeat(MAX_INLINE_TEXT_BYTES + 100);
+        let dir = tempfile::tempdir().unwrap();
+        let path = temp_file(&dir, "large.log", contents.as_bytes());
+
+        let resolved = resolve_attachments("Summarize", &[attach(&path)], false).unwrap();
+
+        assert!(resolved.text.contains("[... truncated 100 bytes ...]"));
+        assert!(!resolved.text.contains(&contents));
+    }
+
+    #[test]
+    fn test_binary_and_image_attachments() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = temp_file(&dir, "blob.bin", &[0x7f, 0x45, 0x00, 0x01]);
+        let image = temp_file(&dir, "shot.png", &[0x89, b'P', b'N', b'G']);
+        let attachments = [attach(&binary), attach(&image)];
+
+        let without_vision = resolve_attachments("Look", &attachments, false).unwrap();
+        assert!(without_vision.text.contains("[Binary file omitted]"));
+        assert!(without_vision.text.contains("does not accept images"));
+        assert!(without_vision.images.is_empty());
+
+        let with_vision = resolve_attachments("Look", &attachments, true).unwrap();
+
+        assert_eq!(with_vision.images.len(), 1);
+        assert_eq!(with_vision.images[0].media_type, ImageMediaType::PNG);
+    }
+
+    #[test]
+    fn test_image_urls_become_image_parts() {
+        use rig::completion::message::{DocumentSourceKind, Image};
+
+        let Ok(UserContent::Image(Image { data, media_type, .. })) =
+            image_url_content("data:image/png;base64,iVBORw0KGgo=")
+        else {
+            panic!("data URL was not turned into an image part");
+        };
+        assert_eq!(data, DocumentSourceKind::Base64("iVBORw0KGgo=".to_string()));
+        assert_eq!(media_type, Some(ImageMediaType::PNG));
+
+        let Ok(UserContent::Image(Image { data, media_type, .. })) =
+            image_url_content("https://example.com/cat.jpg?size=large")
+        else {
+            panic!("URL was not turned into an image part");
+        };
+        assert_eq!(
+            data,
+            DocumentSourceKind::Url("https://example.com/cat.jpg?size=large".to_string())
+        );
+        assert_eq!(media_type, Some(ImageMediaType::JPEG));
+
+        assert!(image_url_content("data:image/png,raw").is_err());
+        assert!(image_url_content("data:text/plain;base64,aGk=").is_err());
+        assert!(image_url_content("data:image/png;base64,not base64!").is_err());
+        assert!(image_url_content("file:///etc/passwd").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_image_urls_are_fetched_and_sniffed_for_inlining() {
+        use rig::completion::message::{DocumentSourceKind, Image};
+
+        const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let app = axum::Router::new()
+            // No extension and a generic content type: only the bytes say it's a PNG
+            .route(
+                "/avatar",
+                axum::routing::get(|| async { ([("content-type", "application/octet-stream")], PNG) }),
+            )
+            .route(
+                "/photo",
+                axum::routing::get(|| async { ([("content-type", "image/webp")], &b"not a known signature"[..]) }),
+            )
+            .route("/notes", axum::routing::get(|| async { "plain text" }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        let Ok(UserContent::Image(Image { data, media_type, .. })) =
+            inline_image_url_content(&client, &format!("http://{}/avatar", addr)).await
+        else {
+            panic!("URL was not inlined as an image part");
+        };
+        assert_eq!(data, DocumentSourceKind::Base64(general_purpose::STANDARD.encode(PNG)));
+        assert_eq!(media_type, Some(ImageMediaType::PNG));
+
+        let Ok(UserContent::Image(Image { media_type, .. })) =
+            inline_image_url_content(&client, &format!("http://{}/photo", addr)).await
+        else {
+            panic!("URL was not inlined as an image part");
+        };
+        assert_eq!(media_type, Some(ImageMediaType::WEBP));
+
+        assert!(inline_image_url_content(&client, &format!("http://{}/notes", addr))
+            .await
+            .is_err());
+        assert!(
+            inline_image_url_content(&client, &format!("http://{}/missing.png", addr))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -3,21 +3,27 @@
 //! This module sets up the Axum web server with all HTTP routes for the Fleet Chat backend.
 //! Routes are organized into separate modules for better maintainability.
 
-use crate::a2ui::agent::A2UIAgent;
-use crate::a2ui::provider::{AIProvider, GeminiProvider, OpenAIProvider};
-use crate::gemini_agent::GeminiAgent;
+use crate::a2ui::agent::{A2UIAgent, A2UIConfig};
+use crate::a2ui::provider::{AIProvider, AnthropicProvider, GeminiProvider, MockProvider, OpenAIProvider};
+use crate::app_info::record_initialized;
+use crate::conversation_export::agent_session_chunks;
+use crate::gemini_agent::{AgentError, AgentResponse, AgentStreamChunk, GeminiAgent};
 use crate::rig_agent::RigAgent;
-use crate::routes::{a2ui, ai};
+use crate::routes::{a2ui, ai, markdown_response, search};
+use crate::session_store::SessionStore;
+use crate::storage::STORAGE;
 use axum::{
     extract::{Path, State},
     http,
+    response::{sse::Event, IntoResponse, Response, Sse},
     routing::{delete, get, post},
     Json, Router,
 };
+use futures::StreamExt;
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use tauri_plugin_log::log::{error, warn};
 
 // ============================================================================
 // Application State
@@ -26,7 +32,7 @@ use std::sync::{Arc, Mutex};
 /// Combined application state for all route handlers
 #[derive(Clone)]
 pub struct AppState {
-    pub surfaces: Arc<Mutex<HashMap<String, a2ui::SurfaceState>>>,
+    pub surfaces: a2ui::SurfaceStore,
     pub agent: Option<GeminiAgent>,
     pub a2ui_agent: Option<Arc<A2UIAgent>>,
     pub rig_agent: Option<Arc<RigAgent>>,
@@ -39,6 +45,7 @@ impl From<&AppState> for a2ui::A2UIState {
             surfaces: state.surfaces.clone(),
             a2ui_agent: state.a2ui_agent.clone(),
             rig_agent: state.rig_agent.clone(),
+            plugin_storage: STORAGE.clone(),
         }
     }
 }
@@ -52,14 +59,28 @@ impl From<&AppState> for ai::AIState {
     }
 }
 
+/// State for search routes
+impl From<&AppState> for search::SearchState {
+    fn from(state: &AppState) -> Self {
+        search::SearchState {
+            rig_agent: state.rig_agent.clone(),
+        }
+    }
+}
+
 impl Default for AppState {
     fn default() -> Self {
-        Self {
-            surfaces: Arc::new(Mutex::new(HashMap::new())),
+        let state = Self {
+            surfaces: a2ui::SurfaceStore::from_env(),
             agent: Self::create_gemini_agent(),
             a2ui_agent: Self::create_a2ui_agent(),
             rig_agent: Self::create_rig_agent(),
-        }
+        };
+
+        record_initialized("gemini_agent", state.agent.is_some());
+        record_initialized("a2ui_agent", state.a2ui_agent.is_some());
+        record_initialized("rig_agent", state.rig_agent.is_some());
+        state
     }
 }
 
@@ -68,23 +89,83 @@ impl AppState {
         std::env::var("GEMINI_API_KEY")
             .ok()
             .and_then(|api_key| GeminiAgent::new(api_key).ok())
+            .map(|mut agent| {
+                let config = A2UIConfig::from_env();
+                agent.system_preamble = config.system_preamble;
+                agent.session_limit = config.session_limit;
+                agent.with_session_store(SessionStore::new(STORAGE.clone(), "sessions/gemini"))
+            })
     }
 
     fn create_a2ui_agent() -> Option<Arc<A2UIAgent>> {
-        // Try OpenAI first, then fall back to Gemini
+        let build = |provider: Arc<dyn AIProvider>| {
+            let agent = A2UIAgent::with_config(provider, A2UIConfig::from_env())
+                .ok()?
+                .with_session_store(SessionStore::new(STORAGE.clone(), "sessions/a2ui"));
+            Some(Arc::new(match Self::create_a2ui_fallback_provider() {
+                Some(fallback) => agent.with_fallback_provider(fallback),
+                None => agent,
+            }))
+        };
+
+        // Offline/dev mode: canned responses, no API key needed
+        if std::env::var("FLEET_AI_PROVIDER").is_ok_and(|provider| provider.eq_ignore_ascii_case("mock")) {
+            return build(Arc::new(MockProvider::new()));
+        }
+
+        // Try OpenAI first, then fall back to Gemini, then Anthropic
         if let Ok(api_key) = std::env::var("OPENAI_API_KEY") {
-            let provider = Arc::new(OpenAIProvider::new(api_key)) as Arc<dyn AIProvider>;
-            return A2UIAgent::new(provider).ok().map(Arc::new);
+            return build(Arc::new(OpenAIProvider::new(api_key)));
         }
 
         if let Ok(api_key) = std::env::var("GEMINI_API_KEY") {
-            let provider = Arc::new(GeminiProvider::new(api_key)) as Arc<dyn AIProvider>;
-            return A2UIAgent::new(provider).ok().map(Arc::new);
+            return build(Arc::new(GeminiProvider::new(api_key)));
+        }
+
+        if let Ok(api_key) = std::env::var("ANTHROPIC_API_KEY") {
+            return build(Arc::new(AnthropicProvider::new(api_key)));
         }
 
         None
     }
 
+    /// Provider named by `A2UI_FALLBACK_PROVIDER` (`openai`, `gemini` or `anthropic`, with an optional
+    /// `A2UI_FALLBACK_MODEL`), tried once when the primary one keeps producing invalid UI
+    fn create_a2ui_fallback_provider() -> Option<Arc<dyn AIProvider>> {
+        let provider = std::env::var("A2UI_FALLBACK_PROVIDER").ok()?;
+        let model = std::env::var("A2UI_FALLBACK_MODEL")
+            .ok()
+            .filter(|model| !model.trim().is_empty());
+
+        match provider.trim().to_lowercase().as_str() {
+            "openai" => {
+                let api_key = std::env::var("OPENAI_API_KEY").ok()?;
+                Some(match model {
+                    Some(model) => Arc::new(OpenAIProvider::with_model(api_key, model)),
+                    None => Arc::new(OpenAIProvider::new(api_key)),
+                })
+            }
+            "gemini" => {
+                let api_key = std::env::var("GEMINI_API_KEY").ok()?;
+                Some(match model {
+                    Some(model) => Arc::new(GeminiProvider::with_model(api_key, model)),
+                    None => Arc::new(GeminiProvider::new(api_key)),
+                })
+            }
+            "anthropic" => {
+                let api_key = std::env::var("ANTHROPIC_API_KEY").ok()?;
+                Some(match model {
+                    Some(model) => Arc::new(AnthropicProvider::with_model(api_key, model)),
+                    None => Arc::new(AnthropicProvider::new(api_key)),
+                })
+            }
+            other => {
+                warn!("Ignoring unknown A2UI_FALLBACK_PROVIDER '{}'", other);
+                None
+            }
+        }
+    }
+
     fn create_rig_agent() -> Option<Arc<RigAgent>> {
         RigAgent::new().ok().map(Arc::new)
     }
@@ -128,7 +209,7 @@ pub async fn create_agent_session(
             "status": "created",
             "timestamp": chrono::Utc::now()
         }))),
-        Err(_) => Err(http::StatusCode::INTERNAL_SERVER_ERROR),
+        Err(e) => Err(agent_error_status(&e)),
     }
 }
 
@@ -164,6 +245,110 @@ pub async fn list_agent_sessions(State(state): State<AppState>) -> Result<Json<V
     }
 }
 
+pub async fn fork_agent_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> Result<Json<Value>, http::StatusCode> {
+    let agent = state.agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+
+    match agent.fork_session(&session_id).await {
+        Ok(fork_id) => Ok(Json(json!({
+            "session_id": fork_id,
+            "forked_from": session_id,
+            "status": "created",
+            "timestamp": chrono::Utc::now()
+        }))),
+        Err(e) => Err(agent_error_status(&e)),
+    }
+}
+
+pub async fn export_agent_session_markdown(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+) -> Result<Response, http::StatusCode> {
+    let agent = state.agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+
+    match agent.get_session(&session_id).await {
+        Ok(session) => Ok(markdown_response(agent_session_chunks(session))),
+        Err(crate::gemini_agent::AgentError::SessionNotFound(_)) => Err(http::StatusCode::NOT_FOUND),
+        Err(_) => Err(http::StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SendAgentMessageRequest {
+    pub content: String,
+}
+
+fn agent_error_status(error: &AgentError) -> http::StatusCode {
+    match error {
+        AgentError::SessionNotFound(_) => http::StatusCode::NOT_FOUND,
+        AgentError::InvalidMessage(_) => http::StatusCode::BAD_REQUEST,
+        AgentError::SessionLimitExceeded(_) => http::StatusCode::SERVICE_UNAVAILABLE,
+        _ => http::StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Send a message to a session (created on demand) and return the agent's reply
+pub async fn send_agent_message(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+    Json(request): Json<SendAgentMessageRequest>,
+) -> Result<Json<AgentResponse>, http::StatusCode> {
+    let agent = state.agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+    if request.content.trim().is_empty() {
+        return Err(http::StatusCode::BAD_REQUEST);
+    }
+
+    match agent.send_message(&session_id, request.content).await {
+        Ok(response) => Ok(Json(response)),
+        Err(e) => {
+            error!("[send_agent_message] {}", e);
+            Err(agent_error_status(&e))
+        }
+    }
+}
+
+/// Streaming variant of [`send_agent_message`]
+///
+/// Emits a `chunk` event (`{ "text": ... }`) per generated piece of text, then a `done`
+/// event carrying the [`AgentResponse`], or an `error` event if generation fails.
+pub async fn send_agent_message_stream(
+    State(state): State<AppState>,
+    Path(session_id): Path<String>,
+    Json(request): Json<SendAgentMessageRequest>,
+) -> Result<Response, http::StatusCode> {
+    let agent = state.agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+    if request.content.trim().is_empty() {
+        return Err(http::StatusCode::BAD_REQUEST);
+    }
+
+    let stream = agent
+        .send_message_stream(&session_id, request.content)
+        .await
+        .map_err(|e| {
+            error!("[send_agent_message_stream] {}", e);
+            agent_error_status(&e)
+        })?;
+
+    let events = stream.map(|chunk| {
+        let event = match chunk {
+            Ok(AgentStreamChunk::Token(text)) => Event::default()
+                .event("chunk")
+                .data(json!({ "text": text }).to_string()),
+            Ok(AgentStreamChunk::Done(response)) => Event::default()
+                .event("done")
+                .data(serde_json::to_string(&response).unwrap_or_default()),
+            Err(e) => Event::default()
+                .event("error")
+                .data(json!({ "error": e.to_string() }).to_string()),
+        };
+        Ok::<_, std::convert::Infallible>(event)
+    });
+
+    Ok(Sse::new(events).into_response())
+}
+
 pub async fn delete_agent_session(
     State(state): State<AppState>,
     Path(session_id): Path<String>,
@@ -192,13 +377,55 @@ pub async fn delete_agent_session(
 /// - A2UI agent chat endpoints (streaming and non-streaming)
 /// - A2UI plugin generation endpoints
 /// - AI endpoints (Rig agent)
+/// - Streaming search endpoint
 /// - Legacy Gemini agent API endpoints
 pub fn create_axum_app() -> Router {
     let state = AppState::default();
+    spawn_session_sweep(&state);
+    create_router(state)
+}
+
+/// Seconds between sweeps for idle sessions
+const SESSION_SWEEP_INTERVAL_SECS: u64 = 60;
+
+/// Periodically expire idle agent sessions and prune the surfaces they owned, when an
+/// idle TTL is configured
+fn spawn_session_sweep(state: &AppState) {
+    let ttl_configured = state
+        .agent
+        .as_ref()
+        .is_some_and(|agent| agent.session_limit.idle_ttl_secs.is_some())
+        || state
+            .a2ui_agent
+            .as_ref()
+            .is_some_and(|agent| agent.config.session_limit.idle_ttl_secs.is_some());
+    if !ttl_configured {
+        return;
+    }
+
+    let gemini_agent = state.agent.clone();
+    let a2ui_state: a2ui::A2UIState = state.into();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(SESSION_SWEEP_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            if let Some(agent) = &gemini_agent {
+                agent.expire_idle_sessions().await;
+            }
+            if let Some(agent) = &a2ui_state.a2ui_agent {
+                if !agent.expire_idle_sessions().await.is_empty() {
+                    a2ui::prune_orphaned_surfaces(&a2ui_state).await;
+                }
+            }
+        }
+    });
+}
 
+fn create_router(state: AppState) -> Router {
     // Create route-specific states
     let a2ui_state: a2ui::A2UIState = (&state).into();
     let ai_state: ai::AIState = (&state).into();
+    let search_state: search::SearchState = (&state).into();
 
     Router::new()
         .without_v07_checks()
@@ -222,10 +449,123 @@ pub fn create_axum_app() -> Router {
         .route("/agent/session", post(create_agent_session))
         .route("/agent/session/{id}", get(get_agent_session))
         .route("/agent/session/{id}", delete(delete_agent_session))
+        .route("/agent/session/{id}/fork", post(fork_agent_session))
+        .route("/agent/session/{id}/message", post(send_agent_message))
+        .route("/agent/session/{id}/message/stream", post(send_agent_message_stream))
+        .route(
+            "/agent/session/{id}/export/markdown",
+            get(export_agent_session_markdown),
+        )
         .route("/agent/sessions", get(list_agent_sessions))
         // A2UI routes (mounted at /a2ui)
         .nest("/a2ui", a2ui::create_a2ui_router().with_state(a2ui_state))
         // AI routes (mounted at /ai)
         .nest("/ai", ai::create_ai_router().with_state(ai_state))
+        // Search routes (mounted at /search)
+        .nest("/search", search::create_search_router().with_state(search_state))
         .with_state(state)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tauri_axum::LocalRequest;
+    use std::collections::HashMap;
+
+    fn test_router() -> Router {
+        create_router(AppState {
+            surfaces: a2ui::SurfaceStore::default(),
+            agent: Some(GeminiAgent::new("test-api-key".to_string()).unwrap()),
+            a2ui_agent: None,
+            rig_agent: None,
+        })
+    }
+
+    fn post_message(uri: &str, content: &str) -> LocalRequest {
+        LocalRequest {
+            uri: uri.to_string(),
+            method: "POST".to_string(),
+            body: Some(json!({ "content": content }).to_string()),
+            headers: HashMap::from([("content-type".to_string(), "application/json".to_string())]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_message_returns_agent_response() {
+        let mut router = test_router();
+
+        let response = post_message("/agent/session/s1/message", "hello")
+            .send_to_router(&mut router)
+            .await;
+        assert_eq!(response.status_code, 200);
+        let reply: AgentResponse = serde_json::from_slice(&response.body).unwrap();
+        assert!(reply.content.contains("Fleet Assistant"));
+        assert_eq!(reply.conversation_state, "Complete");
+
+        let response = post_message("/agent/session/s1/message", "  ")
+            .send_to_router(&mut router)
+            .await;
+        assert_eq!(response.status_code, 400);
+    }
+
+    #[tokio::test]
+    async fn test_stream_message_emits_chunks_then_done() {
+        let mut router = test_router();
+
+        let response = post_message("/agent/session/s2/message/stream", "hello")
+            .send_to_router(&mut router)
+            .await;
+        assert_eq!(response.status_code, 200);
+
+        let body = String::from_utf8(response.body).unwrap();
+        let events: Vec<&str> = body.split("\n\n").filter(|event| !event.is_empty()).collect();
+        assert!(events.len() > 2);
+        assert!(events[..events.len() - 1]
+            .iter()
+            .all(|event| event.starts_with("event: chunk")));
+        let done = events.last().unwrap();
+        assert!(done.starts_with("event: done"));
+        assert!(done.contains(r#""conversation_state":"Complete""#));
+
+        // The streamed reply is recorded in the session like a regular message
+        let request = LocalRequest {
+            uri: "/agent/session/s2".to_string(),
+            method: "GET".to_string(),
+            body: None,
+            headers: HashMap::new(),
+        };
+        let response = request.send_to_router(&mut router).await;
+        let session: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(session["message_count"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_conversation_export_finds_the_agent_session() {
+        let mut router = test_router();
+        post_message("/agent/session/s3/message", "hello")
+            .send_to_router(&mut router)
+            .await;
+
+        let markdown = crate::conversation_export::export_markdown_through(&mut router, "s3")
+            .await
+            .unwrap();
+        assert!(markdown.starts_with("# Conversation s3\n"));
+        assert!(markdown.contains("## User · "));
+        assert!(markdown.contains("\n\nhello\n"));
+        assert!(markdown.contains("## Assistant · "));
+
+        let missing = crate::conversation_export::export_markdown_through(&mut router, "missing").await;
+        assert_eq!(missing.unwrap_err(), "Conversation missing not found");
+        let escaped = crate::conversation_export::export_markdown_through(&mut router, "../s3").await;
+        assert_eq!(escaped.unwrap_err(), "Conversation ../s3 not found");
+
+        // Ids outside the alphanumeric set are encoded rather than rejected
+        post_message("/agent/session/notes%20v1.2/message", "hi")
+            .send_to_router(&mut router)
+            .await;
+        let markdown = crate::conversation_export::export_markdown_through(&mut router, "notes v1.2")
+            .await
+            .unwrap();
+        assert!(markdown.starts_with("# Conversation notes v1.2\n"));
+    }
+}
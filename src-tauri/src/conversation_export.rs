@@ -0,0 +1,246 @@
+//! Markdown export of conversations
+//!
+//! Renders A2UI and Gemini agent sessions as a readable markdown document: one
+//! section per message with a role heading and timestamp. Message content is kept
+//! verbatim so code fences survive, and A2UI UI payloads are replaced by a short
+//! summary such as `[rendered UI: contact list]`.
+
+use axum::Router;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+use crate::a2ui::agent::{A2UIMessage, A2UISession};
+use crate::gemini_agent::{AgentSession, ChatMessage, MessageRole};
+use crate::tauri_axum::LocalRequest;
+
+/// Marker that introduces the A2UI message array in an assistant reply
+const A2UI_MARKER: &str = "A2UI_MESSAGES:";
+
+/// A message in the shape the exporter renders
+#[derive(Debug, Clone)]
+pub struct ExportMessage {
+    pub role: String,
+    pub timestamp: DateTime<Utc>,
+    pub content: String,
+}
+
+impl From<&A2UIMessage> for ExportMessage {
+    fn from(message: &A2UIMessage) -> Self {
+        ExportMessage {
+            role: message.role.clone(),
+            timestamp: message.timestamp,
+            content: summarize_ui(&message.content),
+        }
+    }
+}
+
+impl From<&ChatMessage> for ExportMessage {
+    fn from(message: &ChatMessage) -> Self {
+        let role = match message.role {
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+            MessageRole::System => "system",
+        };
+        ExportMessage {
+            role: role.to_string(),
+            timestamp: message.timestamp,
+            content: message.content.clone(),
+        }
+    }
+}
+
+/// Render a conversation as markdown chunks: a header, then one chunk per message
+///
+/// Concatenating the chunks gives the full document; routes stream them one by one.
+/// Each message is only rendered when its chunk is pulled.
+pub fn markdown_chunks(
+    session_id: &str,
+    created_at: DateTime<Utc>,
+    messages: impl ExactSizeIterator<Item = ExportMessage>,
+) -> impl Iterator<Item = String> {
+    let header = format!(
+        "# Conversation {}\n\n_Started {} · {} messages_\n",
+        session_id,
+        created_at.format("%Y-%m-%d %H:%M UTC"),
+        messages.len()
+    );
+    std::iter::once(header).chain(messages.map(|message| message_chunk(&message)))
+}
+
+fn message_chunk(message: &ExportMessage) -> String {
+    let mut content = message.content.trim_end().to_string();
+    if content.is_empty() {
+        content = "_(empty message)_".to_string();
+    }
+    format!(
+        "\n## {} · {}\n\n{}\n",
+        role_heading(&message.role),
+        message.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+        content
+    )
+}
+
+pub fn a2ui_session_chunks(session: A2UISession) -> impl Iterator<Item = String> + Send {
+    let messages = session
+        .messages
+        .into_iter()
+        .map(|message| ExportMessage::from(&message));
+    markdown_chunks(&session.id, session.created_at, messages)
+}
+
+pub fn agent_session_chunks(session: AgentSession) -> impl Iterator<Item = String> + Send {
+    let messages = session
+        .messages
+        .into_iter()
+        .map(|message| ExportMessage::from(&message));
+    markdown_chunks(&session.id, session.created_at, messages)
+}
+
+/// The markdown export of `session_id` from whichever agent has it, A2UI sessions first
+///
+/// Goes through the export routes, which is how the app reaches the agents' session stores.
+pub async fn export_markdown_through(router: &mut Router, session_id: &str) -> Result<String, String> {
+    if session_id.is_empty() {
+        return Err("Invalid session id: empty".to_string());
+    }
+
+    let segment = encode_path_segment(session_id);
+    for uri in [
+        format!("/a2ui/agent/session/{}/export/markdown", segment),
+        format!("/agent/session/{}/export/markdown", segment),
+    ] {
+        let request = LocalRequest {
+            uri,
+            method: "GET".to_string(),
+            body: None,
+            headers: HashMap::new(),
+        };
+        let response = request.send_to_router(router).await;
+        match response.status_code {
+            200 => return String::from_utf8(response.body).map_err(|e| e.to_string()),
+            // Not in this agent's sessions, or the agent isn't configured
+            404 | 503 => continue,
+            status => {
+                return Err(format!(
+                    "Exporting conversation {} failed with status {}",
+                    session_id, status
+                ))
+            }
+        }
+    }
+    Err(format!("Conversation {} not found", session_id))
+}
+
+/// Percent-encode everything but unreserved characters so any session id stays one path segment
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn role_heading(role: &str) -> String {
+    let mut chars = role.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => "Unknown".to_string(),
+    }
+}
+
+/// Replace the A2UI message array in a reply with a one-line summary of what it rendered
+fn summarize_ui(content: &str) -> String {
+    let Some(start) = content.find(A2UI_MARKER) else {
+        return content.to_string();
+    };
+
+    let text = content[..start].trim_end();
+    let payload = &content[start + A2UI_MARKER.len()..];
+    let summary = match ui_surface_name(payload) {
+        Some(name) => format!("[rendered UI: {}]", name),
+        None => "[rendered UI]".to_string(),
+    };
+
+    if text.is_empty() {
+        summary
+    } else {
+        format!("{}\n\n{}", text, summary)
+    }
+}
+
+/// Human-readable name of the first rendered surface, e.g. `contact_list` -> `contact list`
+fn ui_surface_name(payload: &str) -> Option<String> {
+    let json_start = payload.find('[')?;
+    let mut stream = serde_json::Deserializer::from_str(&payload[json_start..]).into_iter::<serde_json::Value>();
+    let messages = stream.next()?.ok()?;
+
+    messages.as_array()?.iter().find_map(|message| {
+        let surface_id = message
+            .get("beginRendering")
+            .or_else(|| message.get("surfaceUpdate"))?
+            .get("surfaceId")?
+            .as_str()?;
+        Some(surface_id.replace(['_', '-'], " "))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn message(role: &str, minute: u32, content: &str) -> ExportMessage {
+        ExportMessage::from(&A2UIMessage {
+            id: format!("msg-{}", minute),
+            role: role.to_string(),
+            content: content.to_string(),
+            timestamp: Utc.with_ymd_and_hms(2024, 5, 1, 9, minute, 0).unwrap(),
+            metadata: None,
+        })
+    }
+
+    #[test]
+    fn test_multi_turn_session_renders_markdown() {
+        let messages = vec![
+            message("user", 0, "How do I reverse a vec in Rust?"),
+            message("assistant", 1, "Use `reverse`:\n\n```rust\nlet mut v = vec![1, 2, 3];\nv.reverse();\n```"),
+            message("user", 2, "Show my contacts"),
+            message(
+                "assistant",
+                3,
+                "Here are your contacts.\nA2UI_MESSAGES: [{\"beginRendering\": {\"surfaceId\": \"contact_list\", \"root\": \"root\"}}]",
+            ),
+        ];
+        let created_at = Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+
+        let chunks: Vec<String> = markdown_chunks("session-1", created_at, messages.into_iter()).collect();
+        assert_eq!(chunks.len(), 5);
+
+        let markdown = chunks.concat();
+        assert!(markdown.starts_with("# Conversation session-1\n"));
+        assert!(markdown.contains("4 messages"));
+        assert!(markdown.contains("## User · 2024-05-01 09:00:00 UTC\n\nHow do I reverse a vec in Rust?"));
+        assert!(markdown.contains("## Assistant · 2024-05-01 09:01:00 UTC"));
+        assert!(markdown.contains("```rust\nlet mut v = vec![1, 2, 3];\nv.reverse();\n```"));
+        assert!(markdown.contains("Here are your contacts.\n\n[rendered UI: contact list]"));
+        assert!(!markdown.contains("beginRendering"));
+    }
+
+    #[test]
+    fn test_messages_are_rendered_as_chunks_are_pulled() {
+        let rendered = std::cell::Cell::new(0);
+        let messages = (0..1000).map(|minute| {
+            rendered.set(rendered.get() + 1);
+            message("user", minute % 60, "hello")
+        });
+        let created_at = Utc.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+
+        let mut chunks = markdown_chunks("long", created_at, messages);
+        assert!(chunks.next().unwrap().contains("1000 messages"));
+        assert_eq!(rendered.get(), 0);
+        chunks.next().unwrap();
+        assert_eq!(rendered.get(), 1);
+    }
+}
@@ -0,0 +1,122 @@
+//! Last-error registry for diagnostics
+//!
+//! Background work (stream tasks, plugin setup, search insights) has no caller to return
+//! errors to, so failures are recorded here per subsystem and surfaced through the
+//! `get_last_errors` / `clear_errors` commands for the diagnostics panel.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tauri::command;
+use tauri_plugin_log::log::error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    Ai,
+    A2ui,
+    Search,
+    Cache,
+    Plugins,
+}
+
+impl Subsystem {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Subsystem::Ai => "ai",
+            Subsystem::A2ui => "a2ui",
+            Subsystem::Search => "search",
+            Subsystem::Cache => "cache",
+            Subsystem::Plugins => "plugins",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Most recent error per subsystem
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    last_errors: Mutex<HashMap<String, ErrorRecord>>,
+}
+
+impl Diagnostics {
+    pub fn record(&self, subsystem: Subsystem, message: impl Into<String>) {
+        let record = ErrorRecord {
+            message: message.into(),
+            timestamp: Utc::now(),
+        };
+        error!("[{}] {}", subsystem.as_str(), record.message);
+
+        if let Ok(mut last_errors) = self.last_errors.lock() {
+            last_errors.insert(subsystem.as_str().to_string(), record);
+        }
+    }
+
+    pub fn last_errors(&self) -> HashMap<String, ErrorRecord> {
+        self.last_errors
+            .lock()
+            .map(|last_errors| last_errors.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut last_errors) = self.last_errors.lock() {
+            last_errors.clear();
+        }
+    }
+}
+
+/// Global diagnostics registry
+pub static DIAGNOSTICS: Lazy<Diagnostics> = Lazy::new(Diagnostics::default);
+
+/// Record the latest error for a subsystem in the global registry
+pub fn record_error(subsystem: Subsystem, message: impl Into<String>) {
+    DIAGNOSTICS.record(subsystem, message);
+}
+
+/// Get the most recent error recorded by each subsystem
+#[command]
+pub fn get_last_errors() -> HashMap<String, ErrorRecord> {
+    DIAGNOSTICS.last_errors()
+}
+
+/// Clear all recorded errors
+#[command]
+pub fn clear_errors() {
+    DIAGNOSTICS.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_background_failure_is_recorded_and_cleared() {
+        let diagnostics = Arc::new(Diagnostics::default());
+
+        let background = Arc::clone(&diagnostics);
+        tokio::spawn(async move {
+            background.record(Subsystem::Cache, "Failed to refresh applications: timed out");
+        })
+        .await
+        .unwrap();
+
+        let errors = diagnostics.last_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors["cache"].message, "Failed to refresh applications: timed out");
+
+        diagnostics.record(Subsystem::Cache, "second failure");
+        assert_eq!(diagnostics.last_errors()["cache"].message, "second failure");
+
+        diagnostics.clear();
+        assert!(diagnostics.last_errors().is_empty());
+    }
+}
@@ -1,11 +1,22 @@
 use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+use crate::a2ui::conversation::{apply_event, ConversationEvent, ConversationState, StateTransition};
+use crate::a2ui::sse::sse_data_stream;
+use crate::provider_headers::provider_client;
+use crate::provider_trace::PROVIDER_TRACE;
+use crate::recent_requests::{RequestSource, RECENT_REQUESTS};
+use crate::rig_agent::TokenUsage;
+use crate::session_limit::SessionLimit;
+use crate::session_store::SessionStore;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub id: String,
@@ -37,6 +48,15 @@ pub struct AgentResponse {
     pub conversation_state: String,
 }
 
+/// An item of a streamed reply: text as it is generated, then the recorded response
+#[derive(Debug, Clone)]
+pub enum AgentStreamChunk {
+    Token(String),
+    Done(AgentResponse),
+}
+
+pub type AgentStream = Pin<Box<dyn Stream<Item = Result<AgentStreamChunk, AgentError>> + Send>>;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentSession {
     pub id: String,
@@ -53,15 +73,16 @@ pub struct SessionContext {
     pub current_task: Option<String>,
     pub entities: HashMap<String, String>,
     pub conversation_state: ConversationState,
+    /// Recent conversation state transitions, oldest first
+    #[serde(default)]
+    pub transitions: Vec<StateTransition>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ConversationState {
-    Greeting,
-    TaskUnderstanding,
-    TaskExecution,
-    Clarification,
-    Completion,
+impl SessionContext {
+    /// Move the conversation state on `event`, recording the transition; returns whether it applied
+    pub fn apply_event(&mut self, event: ConversationEvent) -> bool {
+        apply_event(&mut self.conversation_state, &mut self.transitions, event)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -81,12 +102,71 @@ pub struct AgentPersona {
     pub interaction_style: String,
 }
 
+/// Trailing marker the model uses to name a suggested UI type, e.g. `<<ui:contact_list>>`
+/// Model the Gemini API is called with
+const GEMINI_MODEL: &str = "gemini-2.5-flash";
+
+const UI_MARKER_OPEN: &str = "<<ui:";
+const UI_MARKER_CLOSE: &str = ">>";
+
+const UI_MARKER_INSTRUCTION: &str = "回复的最后一行必须是界面类型标记：<<ui:contact_list>>、<<ui:search>>、<<ui:form>>、<<ui:list>> 或 <<ui:card>>；不需要界面时写 <<ui:none>>。";
+
+/// Split a trailing UI marker off a reply, returning the reply text and the marked type
+fn split_ui_marker(content: &str) -> (&str, Option<String>) {
+    let trimmed = content.trim_end();
+    let marker = trimmed.strip_suffix(UI_MARKER_CLOSE).and_then(|body| {
+        body.rfind(UI_MARKER_OPEN)
+            .map(|start| (start, &body[start + UI_MARKER_OPEN.len()..]))
+    });
+
+    match marker {
+        Some((start, ui_type))
+            if !ui_type.trim().is_empty() && ui_type.trim().chars().all(|c| c.is_ascii_alphanumeric() || c == '_') =>
+        {
+            (trimmed[..start].trim_end(), Some(ui_type.trim().to_lowercase()))
+        }
+        _ => (content, None),
+    }
+}
+
+/// Token usage from a Gemini `usageMetadata` object, if it reports any
+fn usage_metadata(metadata: &serde_json::Value) -> Option<TokenUsage> {
+    let count = |field: &str| metadata[field].as_u64().map(|count| count as u32);
+    let prompt_tokens = count("promptTokenCount")?;
+    let completion_tokens = count("candidatesTokenCount").unwrap_or(0);
+    Some(TokenUsage {
+        prompt_tokens,
+        completion_tokens,
+        total_tokens: count("totalTokenCount").unwrap_or(prompt_tokens + completion_tokens),
+    })
+}
+
+/// Length of the streamed reply that is safe to show: everything before a (possibly partial) marker
+fn visible_prefix_len(content: &str) -> usize {
+    if let Some(start) = content.find(UI_MARKER_OPEN) {
+        return start;
+    }
+    // A marker may be split across chunks; hold back any tail that could start one
+    (1..UI_MARKER_OPEN.len())
+        .rev()
+        .find(|&len| content.ends_with(&UI_MARKER_OPEN[..len]))
+        .map_or(content.len(), |len| content.len() - len)
+}
+
 #[derive(Debug, Clone)]
 pub struct GeminiAgent {
     pub client: Client,
     pub api_key: String,
     pub sessions: Arc<RwLock<HashMap<String, AgentSession>>>,
     pub default_settings: AgentSettings,
+    /// Deployment-specific guidance prepended to every prompt
+    pub system_preamble: Option<String>,
+    /// Cap on live sessions and what happens when it is reached
+    pub session_limit: SessionLimit,
+    /// Where sessions are saved on every change; `None` keeps them in memory only
+    pub session_store: Option<SessionStore>,
+    /// API root requests go to; `GEMINI_BASE_URL` when set
+    pub base_url: String,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -103,14 +183,16 @@ pub enum AgentError {
     SerializationError(#[from] serde_json::Error),
     #[error("HTTP client error: {0}")]
     HttpError(#[from] reqwest::Error),
+    #[error("Session limit of {0} reached")]
+    SessionLimitExceeded(usize),
 }
 
 impl GeminiAgent {
     pub fn new(api_key: String) -> Result<Self, AgentError> {
-        let client = Client::new();
+        let client = provider_client("gemini");
 
         let default_settings = AgentSettings {
-            model_name: "gemini-2.5-flash".to_string(),
+            model_name: GEMINI_MODEL.to_string(),
             temperature: 0.7,
             max_tokens: 2048,
             system_prompt: "你是一个智能助手，能够理解用户需求并提供建议。你可以帮助用户分析需求并建议合适的解决方案。当用户需要界面时，你可以建议使用哪种类型的UI组件。".to_string(),
@@ -132,9 +214,44 @@ impl GeminiAgent {
             api_key,
             sessions: Arc::new(RwLock::new(HashMap::new())),
             default_settings,
+            system_preamble: None,
+            session_limit: SessionLimit::default(),
+            session_store: None,
+            base_url: crate::rig_agent::AIProvider::Gemini.base_url(None),
         })
     }
 
+    fn generate_content_url(&self, stream: bool) -> String {
+        if stream {
+            format!(
+                "{}/v1beta/models/{}:streamGenerateContent?alt=sse&key={}",
+                self.base_url, GEMINI_MODEL, self.api_key
+            )
+        } else {
+            format!(
+                "{}/v1beta/models/{}:generateContent?key={}",
+                self.base_url, GEMINI_MODEL, self.api_key
+            )
+        }
+    }
+
+    /// Load the sessions saved in `store`, within the session limit, and save every
+    /// session change to it
+    pub fn with_session_store(mut self, store: SessionStore) -> Self {
+        let mut sessions = store.load_all();
+        self.session_limit
+            .trim(&mut sessions, |session: &AgentSession| session.updated_at, Utc::now());
+        self.sessions = Arc::new(RwLock::new(sessions));
+        self.session_store = Some(store);
+        self
+    }
+
+    fn persist(&self, session: &AgentSession) {
+        if let Some(store) = &self.session_store {
+            store.save(&session.id, session);
+        }
+    }
+
     async fn create_session_with_id(
         &self,
         session_id: &str,
@@ -152,12 +269,17 @@ impl GeminiAgent {
                 user_intent: None,
                 current_task: None,
                 entities: HashMap::new(),
-                conversation_state: ConversationState::Greeting,
+                conversation_state: ConversationState::Initial,
+                transitions: Vec::new(),
             },
             settings,
         };
 
         let mut sessions = self.sessions.write().await;
+        if !sessions.contains_key(session_id) {
+            self.make_room(&mut sessions)?;
+        }
+        self.persist(&session);
         sessions.insert(session_id.to_string(), session);
         Ok(())
     }
@@ -175,12 +297,15 @@ impl GeminiAgent {
                 user_intent: None,
                 current_task: None,
                 entities: HashMap::new(),
-                conversation_state: ConversationState::Greeting,
+                conversation_state: ConversationState::Initial,
+                transitions: Vec::new(),
             },
             settings: custom_settings.unwrap_or(self.default_settings.clone()),
         };
 
         let mut sessions = self.sessions.write().await;
+        self.make_room(&mut sessions)?;
+        self.persist(&session);
         sessions.insert(session_id.clone(), session);
 
         Ok(session_id)
@@ -195,6 +320,68 @@ impl GeminiAgent {
     }
 
     pub async fn send_message(&self, session_id: &str, content: String) -> Result<AgentResponse, AgentError> {
+        let session = self.record_user_message(session_id, content).await?;
+
+        // Generate response using Gemini
+        let response_content = self.generate_gemini_response(&session).await?;
+
+        self.record_assistant_message(session_id, response_content).await
+    }
+
+    /// Like [`send_message`](Self::send_message), but yields the reply as it is generated
+    ///
+    /// The stream ends with [`AgentStreamChunk::Done`] once the full reply has been
+    /// recorded in the session; a failed generation leaves only the user message behind.
+    pub async fn send_message_stream(&self, session_id: &str, content: String) -> Result<AgentStream, AgentError> {
+        let session = self.record_user_message(session_id, content).await?;
+        let prompt = self.build_prompt(&session);
+
+        let mut deltas: Pin<Box<dyn Stream<Item = Result<String, AgentError>> + Send>> = if self.uses_live_api() {
+            self.call_gemini_api_stream(&prompt).await?
+        } else {
+            let response = self.mock_gemini_call(&prompt).await?;
+            let pieces: Vec<Result<String, AgentError>> = response
+                .split_inclusive(['，', '。', '！', '？', ' '])
+                .map(|piece| Ok(piece.to_string()))
+                .collect();
+            Box::pin(futures::stream::iter(pieces))
+        };
+
+        let agent = self.clone();
+        let session_id = session_id.to_string();
+        Ok(Box::pin(async_stream::stream! {
+            let mut response_content = String::new();
+            // Text from a possible UI marker onwards is held back until the reply is complete
+            let mut emitted = 0;
+            while let Some(delta) = deltas.next().await {
+                match delta {
+                    Ok(text) => {
+                        response_content.push_str(&text);
+                        let visible = visible_prefix_len(&response_content);
+                        if visible > emitted {
+                            yield Ok(AgentStreamChunk::Token(response_content[emitted..visible].to_string()));
+                            emitted = visible;
+                        }
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+
+            let response = agent.record_assistant_message(&session_id, response_content).await;
+            if let Ok(response) = &response {
+                if response.content.len() > emitted {
+                    yield Ok(AgentStreamChunk::Token(response.content[emitted..].to_string()));
+                }
+            }
+            yield response.map(AgentStreamChunk::Done);
+        }))
+    }
+
+    /// Append a user message and update the conversation context, returning the updated session
+    async fn record_user_message(&self, session_id: &str, content: String) -> Result<AgentSession, AgentError> {
         // Auto-create session if it doesn't exist
         if !self.sessions.read().await.contains_key(session_id) {
             self.create_session_with_id(session_id, None).await?;
@@ -216,29 +403,39 @@ impl GeminiAgent {
         session.messages.push(user_message);
         session.updated_at = Utc::now();
 
-        // Update conversation context
+        session.context.apply_event(ConversationEvent::MessageReceived);
+
+        // Note what the user is after, for the prompt
         let content_lower = content.to_lowercase();
-        if content_lower.contains("你好") || content_lower.contains("hello") {
-            session.context.conversation_state = ConversationState::Greeting;
-        } else if content_lower.contains("显示") || content_lower.contains("展示") || content_lower.contains("show")
-        {
-            session.context.conversation_state = ConversationState::TaskExecution;
+        if content_lower.contains("显示") || content_lower.contains("展示") || content_lower.contains("show") {
             session.context.user_intent = Some("display_information".to_string());
         } else if content_lower.contains("搜索") || content_lower.contains("查找") || content_lower.contains("search")
         {
-            session.context.conversation_state = ConversationState::TaskExecution;
             session.context.user_intent = Some("search".to_string());
-        } else if content_lower.contains("?") || content_lower.contains("？") {
-            session.context.conversation_state = ConversationState::TaskUnderstanding;
-        } else {
-            session.context.conversation_state = ConversationState::TaskExecution;
         }
 
-        // Generate response using Gemini
-        let response_content = self.generate_gemini_response(&session).await?;
+        self.persist(session);
+        Ok(session.clone())
+    }
 
-        // Analyze content to suggest UI type
-        let suggested_ui_type = self.analyze_ui_suggestion(&response_content);
+    /// Append the assistant's reply to the session and describe it as an [`AgentResponse`]
+    async fn record_assistant_message(
+        &self,
+        session_id: &str,
+        response_content: String,
+    ) -> Result<AgentResponse, AgentError> {
+        // Prefer the model's own UI marker; fall back to keyword analysis without one
+        let (text, marker) = split_ui_marker(&response_content);
+        let suggested_ui_type = match marker {
+            Some(ui_type) => (ui_type != "none").then_some(ui_type),
+            None => self.analyze_ui_suggestion(text),
+        };
+        let response_content = text.to_string();
+
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| AgentError::SessionNotFound(session_id.to_string()))?;
 
         let assistant_message = ChatMessage {
             id: Uuid::new_v4().to_string(),
@@ -254,6 +451,8 @@ impl GeminiAgent {
 
         session.messages.push(assistant_message);
         session.updated_at = Utc::now();
+        session.context.apply_event(ConversationEvent::ResponseCompleted);
+        self.persist(session);
 
         Ok(AgentResponse {
             message_id: Uuid::new_v4().to_string(),
@@ -264,6 +463,19 @@ impl GeminiAgent {
     }
 
     async fn generate_gemini_response(&self, session: &AgentSession) -> Result<String, AgentError> {
+        let prompt = self.build_prompt(session);
+
+        // Call Gemini API or fallback to mock for testing
+        let response = if self.uses_live_api() {
+            self.call_gemini_api(&prompt).await?
+        } else {
+            self.mock_gemini_call(&prompt).await?
+        };
+
+        Ok(response)
+    }
+
+    fn build_prompt(&self, session: &AgentSession) -> String {
         let conversation_history: Vec<String> = session
             .messages
             .iter()
@@ -280,27 +492,48 @@ impl GeminiAgent {
             })
             .collect();
 
-        let prompt = format!(
-            "{}\n\n系统设定: {}\n\n对话历史:\n{}\n\n请根据用户的最新消息，提供一个有帮助的回复。如果用户需要查看信息，请建议合适的展示方式。",
+        let mut prompt = String::new();
+        if let Some(preamble) = &self.system_preamble {
+            prompt.push_str(preamble.trim());
+            prompt.push_str("\n\n");
+        }
+        prompt.push_str(&format!(
+            "{}\n\n系统设定: {}\n\n对话历史:\n{}\n\n请根据用户的最新消息，提供一个有帮助的回复。如果用户需要查看信息，请建议合适的展示方式。\n\n{}",
             session.settings.system_prompt,
             session.settings.persona.description,
-            conversation_history.join("\n")
-        );
-
-        // Call Gemini API or fallback to mock for testing
-        let response = if !self.api_key.is_empty() && self.api_key != "test-api-key" {
-            self.call_gemini_api(&prompt).await?
-        } else {
-            self.mock_gemini_call(&prompt).await?
-        };
+            conversation_history.join("\n"),
+            UI_MARKER_INSTRUCTION
+        ));
+        prompt
+    }
 
-        Ok(response)
+    /// Whether requests go to the real Gemini API rather than the canned mock replies
+    fn uses_live_api(&self) -> bool {
+        !self.api_key.is_empty() && self.api_key != "test-api-key"
     }
 
+    /// Call the Gemini API, recording the request in the recent requests feed
     async fn call_gemini_api(&self, prompt: &str) -> Result<String, AgentError> {
+        let request = RECENT_REQUESTS.start(RequestSource::Gemini, "gemini", Some(GEMINI_MODEL), prompt);
+        match self.generate_content(prompt).await {
+            Ok((text, usage)) => {
+                RECENT_REQUESTS.finish(request, usage, None);
+                Ok(text)
+            }
+            Err(e) => {
+                RECENT_REQUESTS.finish(request, None, Some(e.to_string()));
+                Err(e)
+            }
+        }
+    }
+
+    /// The reply text and token usage of a `generateContent` call
+    async fn generate_content(&self, prompt: &str) -> Result<(String, Option<TokenUsage>), AgentError> {
         #[derive(Deserialize)]
         struct GeminiResponse {
             candidates: Vec<Candidate>,
+            #[serde(default, rename = "usageMetadata")]
+            usage_metadata: serde_json::Value,
         }
 
         #[derive(Deserialize)]
@@ -318,47 +551,111 @@ impl GeminiAgent {
             text: String,
         }
 
-        let request_body = serde_json::json!({
-            "contents": [{
-                "parts": [{
-                    "text": prompt
-                }]
-            }],
-            "generationConfig": {
-                "temperature": 0.7,
-                "maxOutputTokens": 2048,
-                "topK": 40,
-                "topP": 0.95
-            }
-        });
-
-        let url = format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
-            "gemini-2.5-flash", self.api_key
-        );
+        let url = self.generate_content_url(false);
+        let body = Self::request_body(prompt);
 
-        let response = self.client.post(&url).json(&request_body).send().await?;
+        let response = self.client.post(&url).json(&body).send().await?;
+        let status = response.status();
 
-        if !response.status().is_success() {
-            let status = response.status();
+        if !status.is_success() {
             let error_text = response.text().await.unwrap_or_default();
+            PROVIDER_TRACE.record("gemini", &url, &body, Some(status.as_u16()), Some(&error_text));
             return Err(AgentError::GeminiError(format!(
                 "API call failed with status {}: {}",
                 status, error_text
             )));
         }
 
-        let gemini_response: GeminiResponse = response.json().await?;
+        let text = response.text().await?;
+        PROVIDER_TRACE.record("gemini", &url, &body, Some(status.as_u16()), Some(&text));
+        let gemini_response: GeminiResponse = serde_json::from_str(&text)?;
+        let usage = usage_metadata(&gemini_response.usage_metadata);
 
         if let Some(candidate) = gemini_response.candidates.first() {
             if let Some(part) = candidate.content.parts.first() {
-                return Ok(part.text.clone());
+                return Ok((part.text.clone(), usage));
             }
         }
 
         Err(AgentError::GeminiError("No valid response from Gemini API".to_string()))
     }
 
+    /// Stream a reply from the Gemini API, recorded in the recent requests feed once it ends
+    async fn call_gemini_api_stream(
+        &self,
+        prompt: &str,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AgentError>> + Send>>, AgentError> {
+        let mut request =
+            RECENT_REQUESTS.guard(RECENT_REQUESTS.start(RequestSource::Gemini, "gemini", Some(GEMINI_MODEL), prompt));
+        let url = self.generate_content_url(true);
+        let body = Self::request_body(prompt);
+
+        let response = match self.client.post(&url).json(&body).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                request.finish(None, Some(e.to_string()));
+                return Err(e.into());
+            }
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            PROVIDER_TRACE.record("gemini", &url, &body, Some(status.as_u16()), Some(&error_text));
+            let error = AgentError::GeminiError(format!("API call failed with status {}: {}", status, error_text));
+            request.finish(None, Some(error.to_string()));
+            return Err(error);
+        }
+        PROVIDER_TRACE.record("gemini", &url, &body, Some(status.as_u16()), None);
+
+        // Each SSE event is a partial GenerateContentResponse carrying the next text parts;
+        // the last one carries the usage for the whole reply
+        let mut events = Box::pin(sse_data_stream(response.bytes_stream()));
+        Ok(Box::pin(async_stream::stream! {
+            let mut usage = None;
+            while let Some(event) = events.next().await {
+                let chunk = event
+                    .map_err(|e| AgentError::GeminiError(e.to_string()))
+                    .and_then(|data| serde_json::from_str::<serde_json::Value>(&data).map_err(AgentError::from));
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        request.finish(None, Some(e.to_string()));
+                        yield Err(e);
+                        return;
+                    }
+                };
+                usage = usage_metadata(&chunk["usageMetadata"]).or(usage);
+                let text: String = chunk["candidates"][0]["content"]["parts"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|part| part["text"].as_str())
+                    .collect();
+                if !text.is_empty() {
+                    yield Ok(text);
+                }
+            }
+            request.finish(usage, None);
+        }))
+    }
+
+    fn request_body(prompt: &str) -> serde_json::Value {
+        serde_json::json!({
+            "contents": [{
+                "parts": [{
+                    "text": prompt
+                }]
+            }],
+            "generationConfig": {
+                "temperature": 0.7,
+                "maxOutputTokens": 2048,
+                "topK": 40,
+                "topP": 0.95
+            }
+        })
+    }
+
     async fn mock_gemini_call(&self, prompt: &str) -> Result<String, AgentError> {
         // Mock implementation for testing
         let prompt_lower = prompt.to_lowercase();
@@ -397,9 +694,53 @@ impl GeminiAgent {
         sessions
             .remove(session_id)
             .ok_or_else(|| AgentError::SessionNotFound(session_id.to_string()))?;
+        if let Some(store) = &self.session_store {
+            store.delete(session_id);
+        }
+        Ok(())
+    }
+
+    /// Copy an existing session (messages, context and settings) into a new, independent session
+    pub async fn fork_session(&self, session_id: &str) -> Result<String, AgentError> {
+        let mut sessions = self.sessions.write().await;
+        let source = sessions
+            .get(session_id)
+            .ok_or_else(|| AgentError::SessionNotFound(session_id.to_string()))?;
+
+        let fork_id = Uuid::new_v4().to_string();
+        let now = Utc::now();
+        let fork = AgentSession {
+            id: fork_id.clone(),
+            created_at: now,
+            updated_at: now,
+            ..source.clone()
+        };
+
+        self.make_room(&mut sessions)?;
+        self.persist(&fork);
+        sessions.insert(fork_id.clone(), fork);
+        Ok(fork_id)
+    }
+
+    /// Make room for one more session after expiring idle ones
+    ///
+    /// Only the in-memory sessions are dropped; their saved history stays in the store.
+    fn make_room(&self, sessions: &mut HashMap<String, AgentSession>) -> Result<(), AgentError> {
+        self.session_limit
+            .expire_idle(sessions, |session| session.updated_at, Utc::now());
+        self.session_limit
+            .make_room(sessions, |session| session.updated_at)
+            .map_err(AgentError::SessionLimitExceeded)?;
         Ok(())
     }
 
+    /// Drop sessions idle for longer than the configured TTL from memory, returning their ids
+    pub async fn expire_idle_sessions(&self) -> Vec<String> {
+        let mut sessions = self.sessions.write().await;
+        self.session_limit
+            .expire_idle(&mut sessions, |session| session.updated_at, Utc::now())
+    }
+
     pub async fn list_sessions(&self) -> Result<Vec<String>, AgentError> {
         let sessions = self.sessions.read().await;
         Ok(sessions.keys().cloned().collect())
@@ -425,6 +766,134 @@ mod tests {
         assert!(!response.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_fork_session_is_independent() {
+        let agent = GeminiAgent::new("test-api-key".to_string()).unwrap();
+        let session_id = agent.create_session(None).await.unwrap();
+        agent.send_message(&session_id, "hello".to_string()).await.unwrap();
+
+        let fork_id = agent.fork_session(&session_id).await.unwrap();
+        assert_ne!(fork_id, session_id);
+        assert_eq!(agent.get_session(&fork_id).await.unwrap().messages.len(), 2);
+
+        agent
+            .send_message(&fork_id, "search contacts".to_string())
+            .await
+            .unwrap();
+
+        let original = agent.get_session(&session_id).await.unwrap();
+        let fork = agent.get_session(&fork_id).await.unwrap();
+        assert_eq!(fork.context.conversation_state, ConversationState::Complete);
+        let events: Vec<_> = fork
+            .context
+            .transitions
+            .iter()
+            .map(|transition| transition.event)
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                ConversationEvent::MessageReceived,
+                ConversationEvent::ResponseCompleted,
+                ConversationEvent::MessageReceived,
+                ConversationEvent::ResponseCompleted
+            ]
+        );
+        assert_eq!(original.messages.len(), 2);
+        assert_eq!(fork.messages.len(), 4);
+        assert_eq!(original.messages[0].content, fork.messages[0].content);
+        assert!(original.context.user_intent.is_none());
+        assert_eq!(fork.context.user_intent.as_deref(), Some("search"));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_reloaded_from_the_store() {
+        let storage: Arc<dyn crate::storage::Storage> = Arc::new(crate::storage::MemoryStorage::new());
+        let store = SessionStore::new(Arc::clone(&storage), "sessions/gemini");
+
+        let agent = GeminiAgent::new("test-api-key".to_string())
+            .unwrap()
+            .with_session_store(store.clone());
+        let kept = agent.create_session(None).await.unwrap();
+        agent.send_message(&kept, "search contacts".to_string()).await.unwrap();
+        let deleted = agent.create_session(None).await.unwrap();
+        agent.delete_session(&deleted).await.unwrap();
+        store.flush();
+
+        let restarted = GeminiAgent::new("test-api-key".to_string())
+            .unwrap()
+            .with_session_store(store);
+        assert_eq!(restarted.list_sessions().await.unwrap(), vec![kept.clone()]);
+        let session = restarted.get_session(&kept).await.unwrap();
+        assert_eq!(session.messages.len(), 2);
+        assert_eq!(session.messages[0].content, "search contacts");
+        assert_eq!(session.context.user_intent.as_deref(), Some("search"));
+    }
+
+    #[test]
+    fn test_usage_is_read_from_usage_metadata() {
+        let usage = usage_metadata(&serde_json::json!({
+            "promptTokenCount": 12,
+            "candidatesTokenCount": 30,
+            "totalTokenCount": 45
+        }))
+        .unwrap();
+        assert_eq!(
+            (usage.prompt_tokens, usage.completion_tokens, usage.total_tokens),
+            (12, 30, 45)
+        );
+
+        let usage = usage_metadata(&serde_json::json!({"promptTokenCount": 12})).unwrap();
+        assert_eq!(usage.total_tokens, 12);
+        assert!(usage_metadata(&serde_json::Value::Null).is_none());
+    }
+
+    #[test]
+    fn test_ui_marker_is_parsed_and_stripped() {
+        let (text, ui_type) = split_ui_marker("Here are your contacts.\n<<ui:contact_list>>\n");
+        assert_eq!(text, "Here are your contacts.");
+        assert_eq!(ui_type.as_deref(), Some("contact_list"));
+
+        let (text, ui_type) = split_ui_marker("Voici vos fiches.\n<<ui:card>>");
+        assert_eq!(text, "Voici vos fiches.");
+        assert_eq!(ui_type.as_deref(), Some("card"));
+
+        // Markers must be well-formed and trailing
+        assert_eq!(split_ui_marker("Use a << b for shifts").1, None);
+        assert_eq!(split_ui_marker("<<ui:contact list>>").1, None);
+
+        assert_eq!(visible_prefix_len("Done.\n<<ui:fo"), "Done.\n".len());
+        assert_eq!(visible_prefix_len("Done.\n<"), "Done.\n".len());
+        assert_eq!(visible_prefix_len("Done."), "Done.".len());
+    }
+
+    #[tokio::test]
+    async fn test_suggested_ui_type_prefers_marker_over_keywords() {
+        let agent = GeminiAgent::new("test-api-key".to_string()).unwrap();
+        agent.create_session_with_id("s", None).await.unwrap();
+
+        // The marker wins even when keywords point elsewhere
+        let response = agent
+            .record_assistant_message("s", "Search finished; here is a contact.\n<<ui:form>>".to_string())
+            .await
+            .unwrap();
+        assert_eq!(response.suggested_ui_type.as_deref(), Some("form"));
+        assert_eq!(response.content, "Search finished; here is a contact.");
+
+        let response = agent
+            .record_assistant_message("s", "Your contacts are ready.\n<<ui:none>>".to_string())
+            .await
+            .unwrap();
+        assert_eq!(response.suggested_ui_type, None);
+
+        // Without a marker the keyword heuristic still applies
+        let response = agent
+            .record_assistant_message("s", "我可以为你创建一个联系人列表界面。".to_string())
+            .await
+            .unwrap();
+        assert_eq!(response.suggested_ui_type.as_deref(), Some("contact_list"));
+    }
+
     #[tokio::test]
     async fn test_contact_ui_generation() {
         let agent = GeminiAgent::new("test-api-key".to_string()).unwrap();
@@ -1,10 +1,31 @@
 mod a2ui;
+mod app_info;
+mod app_launches;
+mod attachments;
 mod axum_app;
+mod conversation_export;
+mod diagnostics;
 mod gemini_agent;
+mod insights_privacy;
+mod logging;
+mod model_cache;
+mod model_capabilities;
+mod open_target;
+mod plugin_dry_run;
 mod plugins;
+mod provider_headers;
+mod provider_trace;
+mod rate_limit;
+mod recent_requests;
+mod response_cache;
 mod rig_agent;
 mod routes;
 mod search;
+mod search_scopes;
+mod session_limit;
+mod session_store;
+mod storage;
+mod stream_checkpoint;
 mod tauri_axum;
 use axum::Router;
 use axum_app::create_axum_app;
@@ -31,6 +52,14 @@ async fn local_app_request(state: State<'_, AppState>, local_request: LocalReque
     Ok(response)
 }
 
+/// Export a conversation from the A2UI or Gemini agent as a markdown document
+#[tauri::command]
+async fn export_conversation_markdown(state: State<'_, AppState>, session_id: String) -> Result<String, String> {
+    // Export on a clone so other requests aren't blocked while the agents render the session
+    let mut router = state.router.lock().await.clone();
+    conversation_export::export_markdown_through(&mut router, &session_id).await
+}
+
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
@@ -54,6 +83,7 @@ pub fn run() {
     }
 
     builder
+        .plugin(tauri_plugin_log::Builder::new().level(logging::log_level()).build())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_http::init())
@@ -75,13 +105,17 @@ pub fn run() {
                     })
                     .build(app)?;
             }
+            model_cache::warm_on_startup();
             // Note: Window is now configured via tauri.conf.json (windows array)
             // No need to manually create window here, as it causes duplicate window error
             // Initialize plugin system
             match plugins::init_plugin_system(app) {
                 Ok(_) => Ok(()),
                 Err(e) => {
-                    eprintln!("Error setting up plugin system: {}", e);
+                    diagnostics::record_error(
+                        diagnostics::Subsystem::Plugins,
+                        format!("Error setting up plugin system: {}", e),
+                    );
                     Err(e)
                 }
             }
@@ -89,19 +123,41 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             greet,
             local_app_request,
+            export_conversation_markdown,
             search_applications,
             search_files,
+            search::search_files_stream,
+            search::cancel_file_search,
             unified_search,
             generate_search_insights,
             get_available_ai_providers,
+            search::get_supported_ai_providers,
+            search::get_provider_default,
             ask_ai_provider,
             get_all_applications,
             get_application_icon,
+            search::extract_icons,
             get_frontmost_application,
             get_running_applications,
             get_default_application,
             search_app_suggestions,
             search_file_suggestions,
+            search::get_ai_insights_enabled,
+            search::set_ai_insights_enabled,
+            insights_privacy::get_insights_privacy,
+            insights_privacy::set_insights_privacy,
+            search::suggest_query_correction,
+            search::get_query_correction_enabled,
+            search::set_query_correction_enabled,
+            search::refresh_application_cache,
+            search::cancel_application_cache_refresh,
+            search::copy_result,
+            open_target::open_target,
+            search_scopes::list_search_scopes,
+            search_scopes::set_search_scope,
+            app_launches::record_launch,
+            model_cache::warm_model_cache,
+            model_cache::refresh_models_with_diff,
             // Plugin system commands
             plugins::load_plugin,
             plugins::unload_plugin,
@@ -110,8 +166,22 @@ pub fn run() {
             plugins::get_plugin_commands,
             plugins::reload_plugin,
             plugins::read_extension_manifest,
-            plugins::get_user_extensions_dir
+            plugins::get_user_extensions_dir,
+            plugin_dry_run::dry_run_plugin,
+            // Diagnostics commands
+            app_info::get_app_info,
+            diagnostics::get_last_errors,
+            diagnostics::clear_errors,
+            provider_trace::get_provider_trace,
+            provider_trace::clear_provider_trace,
+            recent_requests::get_recent_requests,
+            recent_requests::clear_recent_requests
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                session_store::flush_all();
+            }
+        });
 }
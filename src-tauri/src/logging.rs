@@ -0,0 +1,147 @@
+//! Logging controls for the AI subsystems
+//!
+//! Verbose AI logging (prompts, streamed chunks, provider/key details) is disabled by
+//! default and only turned on when `FLEET_DEBUG_AI=1` is set. Any log line that may carry
+//! prompt or response content must go through [`ai_debug!`] so it never reaches the logs
+//! unless explicitly enabled.
+
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tauri_plugin_log::log::LevelFilter;
+
+/// Environment variable that opts into verbose AI logging
+pub const DEBUG_AI_ENV: &str = "FLEET_DEBUG_AI";
+
+static AI_DEBUG: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(env_flag(DEBUG_AI_ENV).unwrap_or(false)));
+
+/// An on/off environment variable: `1`, `true`, `yes` or `on` turn it on; `None` when unset
+pub fn env_flag(name: &str) -> Option<bool> {
+    std::env::var(name).ok().map(|value| parse_flag(&value))
+}
+
+fn parse_flag(value: &str) -> bool {
+    matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes" | "on")
+}
+
+/// Whether verbose, content-bearing AI logging is enabled
+pub fn ai_debug_enabled() -> bool {
+    AI_DEBUG.load(Ordering::Relaxed)
+}
+
+/// Log level for the application logger
+///
+/// Debug output is only emitted when AI debugging is enabled; release builds default to warnings.
+pub fn log_level() -> LevelFilter {
+    if ai_debug_enabled() {
+        LevelFilter::Debug
+    } else if cfg!(debug_assertions) {
+        LevelFilter::Info
+    } else {
+        LevelFilter::Warn
+    }
+}
+
+/// Debug-level log that is only emitted when `FLEET_DEBUG_AI` is enabled.
+///
+/// Use this for anything that may include prompts, responses, or credentials.
+macro_rules! ai_debug {
+    ($($arg:tt)+) => {
+        if $crate::logging::ai_debug_enabled() {
+            tauri_plugin_log::log::debug!($($arg)+);
+        }
+    };
+}
+
+pub(crate) use ai_debug;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rig_agent::{AIOptions, AIProvider, RigAgent};
+    use std::sync::Mutex;
+    use tauri_plugin_log::log::{self, Log, Metadata, Record};
+
+    static CAPTURED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct CaptureLogger;
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            CAPTURED.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CaptureLogger = CaptureLogger;
+
+    /// Turns AI debugging on or off until dropped, then restores the previous setting
+    ///
+    /// The trace and request feed read the flag once, so they are set up first and stay as
+    /// configured while other tests run.
+    struct DebugOverride(bool);
+
+    impl DebugOverride {
+        fn set(enabled: bool) -> Self {
+            Lazy::force(&crate::provider_trace::PROVIDER_TRACE);
+            Lazy::force(&crate::recent_requests::RECENT_REQUESTS);
+            DebugOverride(AI_DEBUG.swap(enabled, Ordering::Relaxed))
+        }
+    }
+
+    impl Drop for DebugOverride {
+        fn drop(&mut self) {
+            AI_DEBUG.store(self.0, Ordering::Relaxed);
+        }
+    }
+
+    fn captured_lines_containing(needle: &str) -> usize {
+        CAPTURED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.contains(needle))
+            .count()
+    }
+
+    fn log_generation(prompt: &str) {
+        let options = AIOptions {
+            prompt: prompt.to_string(),
+            ..Default::default()
+        };
+        RigAgent::log_stream_start(AIProvider::Ollama, "llama3.2", &options);
+    }
+
+    #[test]
+    fn test_generation_logs_no_content_unless_debug_enabled() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(LevelFilter::Trace);
+
+        {
+            let _debug = DebugOverride::set(false);
+            log_generation("secret-prompt-debug-off");
+        }
+        assert_eq!(captured_lines_containing("secret-prompt-debug-off"), 0);
+
+        {
+            let _debug = DebugOverride::set(true);
+            log_generation("secret-prompt-debug-on");
+        }
+        assert!(captured_lines_containing("secret-prompt-debug-on") > 0);
+    }
+
+    #[test]
+    fn test_env_flags_accept_common_spellings() {
+        for value in ["1", "true", "TRUE", " yes ", "On"] {
+            assert!(parse_flag(value), "{:?} should turn a flag on", value);
+        }
+        for value in ["", "0", "false", "off", "no", "enabled"] {
+            assert!(!parse_flag(value), "{:?} should leave a flag off", value);
+        }
+        assert_eq!(env_flag("FLEET_TEST_FLAG_THAT_IS_NEVER_SET"), None);
+    }
+}
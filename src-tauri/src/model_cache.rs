@@ -0,0 +1,403 @@
+//! Persistent cache of provider model lists
+//!
+//! Model lists are served stale-while-revalidate: a cached list is returned at once,
+//! and if it is older than the TTL a background fetch replaces it. Lists are persisted
+//! under the `model_cache.json` storage key so a cold start can show models instantly.
+//! A refresh can also report which models were added or removed since the cached list,
+//! so a frontend can ask for a replacement when the selected model disappears.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::command;
+use tauri_plugin_log::log::{info, warn};
+
+use crate::model_capabilities::model_capabilities;
+use crate::rig_agent::{AIProvider, ModelInfo, RigAgent, RigAgentError};
+use crate::storage::{Storage, STORAGE};
+
+const STORAGE_KEY: &str = "model_cache.json";
+const DEFAULT_TTL_SECS: u64 = 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedModels {
+    pub fetched_at: DateTime<Utc>,
+    pub models: Vec<ModelInfo>,
+}
+
+/// How a provider's freshly fetched model list differs from the one cached before it, by model id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelListDiff {
+    pub provider: String,
+    /// New models, in the order of the fresh list
+    pub added: Vec<String>,
+    /// Models the provider no longer lists, in the order of the cached list
+    pub removed: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+impl ModelListDiff {
+    /// Compared by id alone, so a provider listing the same models in another order, or one
+    /// model twice, changes nothing
+    pub fn between(provider: &str, previous: &[ModelInfo], current: &[ModelInfo]) -> Self {
+        let previous_ids: HashSet<&str> = previous.iter().map(|model| model.id.as_str()).collect();
+        let current_ids: HashSet<&str> = current.iter().map(|model| model.id.as_str()).collect();
+        let (unchanged, added) = unique_ids(current).partition(|id| previous_ids.contains(id.as_str()));
+        let removed = unique_ids(previous)
+            .filter(|id| !current_ids.contains(id.as_str()))
+            .collect();
+
+        Self {
+            provider: provider.to_string(),
+            added,
+            removed,
+            unchanged,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Model ids in list order, each only the first time it appears
+fn unique_ids(models: &[ModelInfo]) -> impl Iterator<Item = String> + '_ {
+    let mut seen = HashSet::new();
+    models
+        .iter()
+        .filter(move |model| seen.insert(model.id.as_str()))
+        .map(|model| model.id.clone())
+}
+
+pub struct ModelListCache {
+    storage: Arc<dyn Storage>,
+    ttl: Duration,
+    lists: Mutex<HashMap<String, CachedModels>>,
+    /// Providers with a background refresh in flight
+    refreshing: Mutex<HashSet<String>>,
+}
+
+impl ModelListCache {
+    /// Load persisted lists from `storage`, starting empty if none have been saved yet
+    ///
+    /// Capabilities are looked up again rather than trusted from storage, so lists saved
+    /// by an older version pick up the current capabilities table.
+    pub fn load(storage: Arc<dyn Storage>, ttl: Duration) -> Self {
+        let mut lists: HashMap<String, CachedModels> = storage
+            .read(STORAGE_KEY)
+            .ok()
+            .flatten()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        for model in lists.values_mut().flat_map(|cached| cached.models.iter_mut()) {
+            model.capabilities = model_capabilities(&model.id);
+        }
+        Self {
+            storage,
+            ttl,
+            lists: Mutex::new(lists),
+            refreshing: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// TTL from `AI_MODEL_CACHE_TTL_SECS` (default one hour)
+    fn ttl_from_env() -> Duration {
+        let secs = std::env::var("AI_MODEL_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(DEFAULT_TTL_SECS);
+        Duration::from_secs(secs)
+    }
+
+    pub fn get(&self, provider: &str) -> Option<CachedModels> {
+        self.lists().get(provider).cloned()
+    }
+
+    fn is_stale(&self, cached: &CachedModels) -> bool {
+        let age = Utc::now().signed_duration_since(cached.fetched_at);
+        age.to_std().map_or(false, |age| age >= self.ttl)
+    }
+
+    /// Replace a provider's list and persist all lists
+    pub fn store(&self, provider: &str, models: Vec<ModelInfo>) {
+        let snapshot = {
+            let mut lists = self.lists();
+            lists.insert(
+                provider.to_string(),
+                CachedModels {
+                    fetched_at: Utc::now(),
+                    models,
+                },
+            );
+            serde_json::to_vec_pretty(&*lists)
+        };
+
+        let persisted = snapshot
+            .map_err(|e| e.to_string())
+            .and_then(|content| self.storage.write(STORAGE_KEY, &content).map_err(|e| e.to_string()));
+        if let Err(e) = persisted {
+            warn!("Failed to persist model list cache: {}", e);
+        }
+    }
+
+    /// Fetch a provider's list now and cache it
+    pub async fn refresh<F, Fut>(&self, provider: &str, fetch: F) -> Result<Vec<ModelInfo>, RigAgentError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<ModelInfo>, RigAgentError>>,
+    {
+        let models = fetch().await?;
+        self.store(provider, models.clone());
+        Ok(models)
+    }
+
+    /// Fetch a provider's list now and cache it, reporting how it differs from the cached one
+    ///
+    /// With nothing cached before, every fetched model counts as added.
+    pub async fn refresh_with_diff<F, Fut>(&self, provider: &str, fetch: F) -> Result<ModelListDiff, RigAgentError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<ModelInfo>, RigAgentError>>,
+    {
+        let previous = self.get(provider).map(|cached| cached.models).unwrap_or_default();
+        let models = self.refresh(provider, fetch).await?;
+        let diff = ModelListDiff::between(provider, &previous, &models);
+        if !diff.is_empty() {
+            info!(
+                "Model list for {} changed: {} added, {} removed",
+                provider,
+                diff.added.len(),
+                diff.removed.len()
+            );
+        }
+        Ok(diff)
+    }
+
+    /// Cached list for `provider`, refreshed in the background when stale
+    ///
+    /// Only a provider with nothing cached waits for `fetch`.
+    pub async fn get_or_refresh<F, Fut>(
+        self: &Arc<Self>,
+        provider: &str,
+        fetch: F,
+    ) -> Result<Vec<ModelInfo>, RigAgentError>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Vec<ModelInfo>, RigAgentError>> + Send,
+    {
+        let Some(cached) = self.get(provider) else {
+            return self.refresh(provider, fetch).await;
+        };
+
+        if self.is_stale(&cached) && self.refreshing().insert(provider.to_string()) {
+            let cache = Arc::clone(self);
+            let provider = provider.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = cache.refresh(&provider, fetch).await {
+                    warn!("Background model list refresh for {} failed: {}", provider, e);
+                }
+                cache.refreshing().remove(&provider);
+            });
+        }
+
+        Ok(cached.models)
+    }
+
+    fn lists(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedModels>> {
+        self.lists.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn refreshing(&self) -> std::sync::MutexGuard<'_, HashSet<String>> {
+        self.refreshing.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Model lists shared by every `RigAgent`
+pub static MODEL_CACHE: Lazy<Arc<ModelListCache>> =
+    Lazy::new(|| Arc::new(ModelListCache::load(STORAGE.clone(), ModelListCache::ttl_from_env())));
+
+/// Fetch and cache model lists for the default provider, or every configured one
+///
+/// Returns the number of models cached per provider; providers that fail are logged and skipped.
+pub async fn warm(all_providers: bool) -> HashMap<String, usize> {
+    let mut warmed = HashMap::new();
+    for agent in RigAgent::configured(all_providers) {
+        let provider = agent.provider_key();
+        match agent.refresh_models(None).await {
+            Ok(models) => {
+                info!("Cached {} models for {}", models.len(), provider);
+                warmed.insert(provider, models.len());
+            }
+            Err(e) => warn!("Model list warm-up for {} failed: {}", provider, e),
+        }
+    }
+    warmed
+}
+
+/// Startup warm-up selected by `AI_WARM_MODEL_CACHE`: `default`, `all`, or unset to skip
+pub fn warm_on_startup() {
+    let all_providers = match std::env::var("AI_WARM_MODEL_CACHE")
+        .map(|value| value.trim().to_lowercase())
+        .as_deref()
+    {
+        Ok("default") | Ok("1") | Ok("true") => false,
+        Ok("all") => true,
+        _ => return,
+    };
+    tauri::async_runtime::spawn(warm(all_providers));
+}
+
+/// Fetch and persist model lists now, e.g. before opening a model picker
+#[command]
+pub async fn warm_model_cache(all_providers: Option<bool>) -> Result<HashMap<String, usize>, String> {
+    Ok(warm(all_providers.unwrap_or(false)).await)
+}
+
+/// Fetch the model list for `provider` (or the default provider) now and report what changed
+/// since the cached list, so a frontend can replace a selected model that was removed
+#[command]
+pub async fn refresh_models_with_diff(provider: Option<String>) -> Result<ModelListDiff, String> {
+    let agent = match provider.as_deref() {
+        Some(name) => {
+            let provider = AIProvider::parse(name).ok_or_else(|| format!("Unknown AI provider: {}", name))?;
+            RigAgent::with_provider(provider)
+        }
+        None => RigAgent::new(),
+    }
+    .map_err(|e| e.to_string())?;
+    agent.refresh_models_with_diff().await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn model(id: &str) -> ModelInfo {
+        ModelInfo::new(id, id, String::new(), 128000)
+    }
+
+    fn persisted(storage: &MemoryStorage, fetched_at: DateTime<Utc>, ids: &[&str]) {
+        let lists = HashMap::from([(
+            "openai".to_string(),
+            CachedModels {
+                fetched_at,
+                models: ids.iter().map(|id| model(id)).collect(),
+            },
+        )]);
+        storage
+            .write(STORAGE_KEY, &serde_json::to_vec(&lists).unwrap())
+            .unwrap();
+    }
+
+    fn ids(models: &[ModelInfo]) -> Vec<&str> {
+        models.iter().map(|model| model.id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_persisted_list_is_served_without_fetching() {
+        let storage = Arc::new(MemoryStorage::new());
+        persisted(&storage, Utc::now(), &["gpt-4o", "gpt-4o-mini"]);
+
+        let cache = Arc::new(ModelListCache::load(storage, Duration::from_secs(3600)));
+        let models = cache
+            .get_or_refresh("openai", || async { panic!("fresh cache must not fetch") })
+            .await
+            .unwrap();
+        assert_eq!(ids(&models), vec!["gpt-4o", "gpt-4o-mini"]);
+    }
+
+    #[test]
+    fn test_lists_saved_without_capabilities_get_them_on_load() {
+        let storage = Arc::new(MemoryStorage::new());
+        storage
+            .write(
+                STORAGE_KEY,
+                br#"{"openai": {"fetched_at": "2024-01-01T00:00:00Z", "models": [
+                    {"id": "gpt-4o", "name": "GPT-4o", "description": "", "context_length": 128000}
+                ]}}"#,
+            )
+            .unwrap();
+
+        let cache = ModelListCache::load(storage, Duration::from_secs(3600));
+        let models = cache.get("openai").unwrap().models;
+        assert!(models[0].capabilities.supports_vision);
+        assert_eq!(models[0].capabilities.max_output_tokens, Some(16384));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_reports_added_and_removed_models() {
+        let storage = Arc::new(MemoryStorage::new());
+        persisted(&storage, Utc::now(), &["gpt-4", "gpt-4o", "gpt-3.5-turbo"]);
+        let cache = ModelListCache::load(storage, Duration::from_secs(3600));
+
+        let diff = cache
+            .refresh_with_diff("openai", || async {
+                Ok(vec![model("o3"), model("gpt-4o"), model("gpt-4.1"), model("gpt-4")])
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(diff.provider, "openai");
+        assert_eq!(diff.added, vec!["o3", "gpt-4.1"]);
+        assert_eq!(diff.removed, vec!["gpt-3.5-turbo"]);
+        assert_eq!(diff.unchanged, vec!["gpt-4o", "gpt-4"]);
+        assert_eq!(
+            ids(&cache.get("openai").unwrap().models),
+            vec!["o3", "gpt-4o", "gpt-4.1", "gpt-4"]
+        );
+
+        let first_fetch = cache
+            .refresh_with_diff("gemini", || async { Ok(vec![model("gemini-2.5-flash")]) })
+            .await
+            .unwrap();
+        assert_eq!(first_fetch.added, vec!["gemini-2.5-flash"]);
+        assert!(first_fetch.removed.is_empty() && first_fetch.unchanged.is_empty());
+    }
+
+    #[test]
+    fn test_reordered_models_are_unchanged() {
+        let previous = vec![model("gpt-4"), model("gpt-4o"), model("o3")];
+        let current = vec![model("o3"), model("gpt-4"), model("gpt-4o"), model("o3")];
+
+        let diff = ModelListDiff::between("openai", &previous, &current);
+        assert!(diff.is_empty());
+        assert_eq!(diff.unchanged, vec!["o3", "gpt-4", "gpt-4o"]);
+    }
+
+    #[tokio::test]
+    async fn test_stale_list_is_refreshed_in_background() {
+        let storage = Arc::new(MemoryStorage::new());
+        persisted(&storage, Utc::now() - chrono::Duration::days(2), &["gpt-4"]);
+
+        let cache = Arc::new(ModelListCache::load(storage.clone(), Duration::from_secs(3600)));
+        let (fetched_tx, fetched_rx) = tokio::sync::oneshot::channel();
+        let models = cache
+            .get_or_refresh("openai", || async move {
+                let _ = fetched_tx.send(());
+                Ok(vec![model("gpt-4o")])
+            })
+            .await
+            .unwrap();
+
+        // The stale list is returned immediately
+        assert_eq!(ids(&models), vec!["gpt-4"]);
+
+        fetched_rx.await.unwrap();
+        for _ in 0..100 {
+            if !cache.refreshing().contains("openai") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(ids(&cache.get("openai").unwrap().models), vec!["gpt-4o"]);
+
+        // The refreshed list survives a restart
+        let reloaded = ModelListCache::load(storage, Duration::from_secs(3600));
+        assert_eq!(ids(&reloaded.get("openai").unwrap().models), vec!["gpt-4o"]);
+    }
+}
@@ -0,0 +1,139 @@
+//! What each known model can do
+//!
+//! Provider model lists don't say whether a model accepts images or tool calls, so the
+//! capabilities come from a maintained table matched on model id prefixes. Ids are
+//! matched without an OpenRouter-style `vendor/` prefix, so `openai/gpt-4o` reads the
+//! `gpt-4o` entry. Models not in the table fall back to a name-based vision check.
+
+use serde::{Deserialize, Serialize};
+
+use crate::attachments::supports_vision;
+
+/// Features the UI enables or disables per model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelCapabilities {
+    /// Accepts image input
+    pub supports_vision: bool,
+    /// Accepts tool definitions and emits tool calls
+    pub supports_tools: bool,
+    /// Can stream its response
+    pub supports_streaming: bool,
+    /// Most tokens the model generates in one response, when known
+    pub max_output_tokens: Option<usize>,
+}
+
+impl Default for ModelCapabilities {
+    fn default() -> Self {
+        Self {
+            supports_vision: false,
+            supports_tools: true,
+            supports_streaming: true,
+            max_output_tokens: None,
+        }
+    }
+}
+
+const fn capabilities(vision: bool, tools: bool, max_output_tokens: usize) -> ModelCapabilities {
+    ModelCapabilities {
+        supports_vision: vision,
+        supports_tools: tools,
+        supports_streaming: true,
+        max_output_tokens: Some(max_output_tokens),
+    }
+}
+
+/// Capabilities by model id prefix; more specific prefixes come first
+const MODEL_CAPABILITIES: &[(&str, ModelCapabilities)] = &[
+    // OpenAI
+    ("gpt-4o-mini", capabilities(true, true, 16384)),
+    ("gpt-4o", capabilities(true, true, 16384)),
+    ("chatgpt-4o", capabilities(true, false, 16384)),
+    ("gpt-4.1", capabilities(true, true, 32768)),
+    ("gpt-4-turbo", capabilities(true, true, 4096)),
+    ("gpt-4", capabilities(false, true, 8192)),
+    ("gpt-3.5-turbo", capabilities(false, true, 4096)),
+    ("gpt-5", capabilities(true, true, 128000)),
+    ("o1-mini", capabilities(false, false, 65536)),
+    ("o1-preview", capabilities(false, false, 32768)),
+    ("o1", capabilities(true, true, 100000)),
+    ("o3-mini", capabilities(false, true, 100000)),
+    ("o3", capabilities(true, true, 100000)),
+    ("o4-mini", capabilities(true, true, 100000)),
+    // Anthropic
+    ("claude-3-5-sonnet", capabilities(true, true, 8192)),
+    ("claude-3.5-sonnet", capabilities(true, true, 8192)),
+    ("claude-3-5-haiku", capabilities(true, true, 8192)),
+    ("claude-3.5-haiku", capabilities(true, true, 8192)),
+    ("claude-3-7-sonnet", capabilities(true, true, 64000)),
+    ("claude-3.7-sonnet", capabilities(true, true, 64000)),
+    ("claude-3", capabilities(true, true, 4096)),
+    ("claude-sonnet-4", capabilities(true, true, 64000)),
+    ("claude-opus-4", capabilities(true, true, 32000)),
+    // Gemini
+    ("gemini-2.5", capabilities(true, true, 65536)),
+    ("gemini-2.0", capabilities(true, true, 8192)),
+    ("gemini-1.5", capabilities(true, true, 8192)),
+    // DeepSeek
+    ("deepseek-chat", capabilities(false, true, 8192)),
+    ("deepseek-coder", capabilities(false, true, 8192)),
+    ("deepseek-reasoner", capabilities(false, false, 65536)),
+    ("deepseek-r1", capabilities(false, false, 65536)),
+    // Open models, as served by Ollama or OpenRouter
+    ("llama-3.2-11b-vision", capabilities(true, false, 8192)),
+    ("llama3.2-vision", capabilities(true, false, 8192)),
+    ("llama-3.3", capabilities(false, true, 8192)),
+    ("llama3.3", capabilities(false, true, 8192)),
+    ("llama-3.2", capabilities(false, true, 8192)),
+    ("llama3.2", capabilities(false, true, 8192)),
+    ("llava", capabilities(true, false, 4096)),
+];
+
+/// Capabilities of `model`, from the table or inferred from its name
+pub fn model_capabilities(model: &str) -> ModelCapabilities {
+    let model = model.to_lowercase();
+    let id = model.rsplit('/').next().unwrap_or(&model);
+    MODEL_CAPABILITIES
+        .iter()
+        .find(|(prefix, _)| id.starts_with(prefix))
+        .map(|(_, capabilities)| *capabilities)
+        .unwrap_or_else(|| ModelCapabilities {
+            supports_vision: supports_vision(id),
+            ..Default::default()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_takes_precedence_over_name_heuristics() {
+        let mini = model_capabilities("gpt-4o-mini-2024-07-18");
+        assert!(mini.supports_vision);
+        assert_eq!(mini.max_output_tokens, Some(16384));
+
+        // "o1" would pass the name-based vision check, but o1-mini is text only
+        let o1_mini = model_capabilities("o1-mini");
+        assert!(!o1_mini.supports_vision);
+        assert!(!o1_mini.supports_tools);
+
+        assert!(!model_capabilities("gpt-4").supports_vision);
+        assert!(!model_capabilities("deepseek-chat").supports_vision);
+    }
+
+    #[test]
+    fn test_vendor_prefix_and_unknown_models() {
+        assert_eq!(
+            model_capabilities("anthropic/claude-3.5-sonnet"),
+            model_capabilities("claude-3-5-sonnet-20241022")
+        );
+        assert!(model_capabilities("meta-llama/llama-3.2-11b-vision-instruct").supports_vision);
+
+        let unknown = model_capabilities("acme/some-vision-model");
+        assert!(unknown.supports_vision);
+        assert!(unknown.supports_streaming);
+        assert_eq!(unknown.max_output_tokens, None);
+        assert!(!model_capabilities("mistral-large").supports_vision);
+    }
+}
@@ -0,0 +1,279 @@
+//! Open search and agent results
+//!
+//! A single entry point for "open this" actions from the frontend: URLs open in the
+//! default browser, files in their default application, and applications are launched.
+//! Targets are validated before anything is opened; executables are only ever run as
+//! applications, never handed to the file opener.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tauri::{command, AppHandle, Runtime, Url};
+use tauri_plugin_opener::OpenerExt;
+use thiserror::Error;
+
+use crate::search::is_application_path;
+
+/// URL schemes `OpenTarget::Url` may open; local files go through `OpenTarget::File`
+const ALLOWED_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum OpenTarget {
+    Url(String),
+    File(String),
+    App(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OpenError {
+    #[error("Invalid URL '{url}': {reason}")]
+    InvalidUrl { url: String, reason: String },
+    #[error("URLs with the '{scheme}' scheme cannot be opened")]
+    UnsupportedScheme { scheme: String },
+    #[error("No such file: {path}")]
+    NotFound { path: String },
+    #[error("Not an application: {path}")]
+    NotAnApplication { path: String },
+    #[error("{path} is an application and cannot be opened as a file")]
+    IsApplication { path: String },
+    #[error("Failed to open {target}: {reason}")]
+    OpenFailed { target: String, reason: String },
+}
+
+/// Where validated targets are handed off, so dispatch can be tested without a desktop
+pub trait Launcher {
+    fn open_url(&self, url: &str) -> Result<(), String>;
+    fn open_path(&self, path: &str) -> Result<(), String>;
+    fn launch_app(&self, path: &str) -> Result<(), String>;
+}
+
+impl<R: Runtime> Launcher for tauri_plugin_opener::Opener<R> {
+    fn open_url(&self, url: &str) -> Result<(), String> {
+        tauri_plugin_opener::Opener::open_url(self, url, None::<&str>).map_err(|e| e.to_string())
+    }
+
+    fn open_path(&self, path: &str) -> Result<(), String> {
+        tauri_plugin_opener::Opener::open_path(self, path, None::<&str>).map_err(|e| e.to_string())
+    }
+
+    /// xdg-open opens an executable as a document rather than running it, so on Linux
+    /// applications are started directly; elsewhere the opener launches bundles itself
+    fn launch_app(&self, path: &str) -> Result<(), String> {
+        #[cfg(target_os = "linux")]
+        {
+            launch_command(path).spawn().map(|_| ()).map_err(|e| e.to_string())
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            Launcher::open_path(self, path)
+        }
+    }
+}
+
+/// The command starting the application at `path`: `gio launch` for a `.desktop` entry,
+/// otherwise the executable itself
+#[cfg(target_os = "linux")]
+fn launch_command(path: &str) -> std::process::Command {
+    if is_desktop_entry(Path::new(path)) {
+        let mut command = std::process::Command::new("gio");
+        command.arg("launch").arg(path);
+        command
+    } else {
+        std::process::Command::new(path)
+    }
+}
+
+impl OpenTarget {
+    /// Check the target can be opened, returning the URL or path to hand to the launcher
+    pub fn validate(&self) -> Result<String, OpenError> {
+        match self {
+            OpenTarget::Url(url) => validate_url(url),
+            OpenTarget::File(path) => {
+                let path = path.trim();
+                if path.is_empty() || !Path::new(path).exists() {
+                    return Err(OpenError::NotFound { path: path.to_string() });
+                }
+                if is_application_path(Path::new(path)) {
+                    return Err(OpenError::IsApplication { path: path.to_string() });
+                }
+                Ok(path.to_string())
+            }
+            OpenTarget::App(path) => {
+                let path = path.trim();
+                if path.is_empty() || !Path::new(path).exists() {
+                    return Err(OpenError::NotFound { path: path.to_string() });
+                }
+                if !is_application_path(Path::new(path)) {
+                    return Err(OpenError::NotAnApplication { path: path.to_string() });
+                }
+                Ok(path.to_string())
+            }
+        }
+    }
+
+    /// Validate the target and open it with `launcher`
+    pub fn open_with(&self, launcher: &impl Launcher) -> Result<(), OpenError> {
+        let target = self.validate()?;
+        let opened = match self {
+            OpenTarget::Url(_) => launcher.open_url(&target),
+            OpenTarget::File(_) => launcher.open_path(&target),
+            OpenTarget::App(_) => launcher.launch_app(&target),
+        };
+        opened.map_err(|reason| OpenError::OpenFailed { target, reason })
+    }
+}
+
+fn validate_url(url: &str) -> Result<String, OpenError> {
+    let parsed = Url::parse(url.trim()).map_err(|e| OpenError::InvalidUrl {
+        url: url.to_string(),
+        reason: e.to_string(),
+    })?;
+    if !ALLOWED_URL_SCHEMES.contains(&parsed.scheme()) {
+        return Err(OpenError::UnsupportedScheme {
+            scheme: parsed.scheme().to_string(),
+        });
+    }
+    Ok(parsed.to_string())
+}
+
+fn is_desktop_entry(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "desktop")
+}
+
+/// Open a URL, file or application from a search or agent result
+#[command]
+pub fn open_target<R: Runtime>(app: AppHandle<R>, target: OpenTarget) -> Result<(), OpenError> {
+    target.open_with(app.opener())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records what it was asked to open instead of opening it
+    #[derive(Default)]
+    struct RecordingLauncher {
+        opened: Mutex<Vec<String>>,
+    }
+
+    impl Launcher for RecordingLauncher {
+        fn open_url(&self, url: &str) -> Result<(), String> {
+            self.opened.lock().unwrap().push(format!("url:{}", url));
+            Ok(())
+        }
+
+        fn open_path(&self, path: &str) -> Result<(), String> {
+            self.opened.lock().unwrap().push(format!("path:{}", path));
+            Ok(())
+        }
+
+        fn launch_app(&self, path: &str) -> Result<(), String> {
+            self.opened.lock().unwrap().push(format!("app:{}", path));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_each_target_kind_is_dispatched() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("Notes.app");
+        std::fs::create_dir_all(&bundle).unwrap();
+        let file = dir.path().join("report.pdf");
+        std::fs::write(&file, "pdf").unwrap();
+
+        let launcher = RecordingLauncher::default();
+        let file = file.to_string_lossy().to_string();
+        let bundle = bundle.to_string_lossy().to_string();
+        OpenTarget::Url("https://example.com/docs".to_string())
+            .open_with(&launcher)
+            .unwrap();
+        OpenTarget::File(file.clone()).open_with(&launcher).unwrap();
+        OpenTarget::App(bundle.clone()).open_with(&launcher).unwrap();
+        let not_an_app = OpenTarget::App(dir.path().to_string_lossy().to_string()).open_with(&launcher);
+
+        assert_eq!(
+            *launcher.opened.lock().unwrap(),
+            vec![
+                "url:https://example.com/docs".to_string(),
+                format!("path:{}", file),
+                format!("app:{}", bundle),
+            ]
+        );
+        assert!(matches!(not_an_app, Err(OpenError::NotAnApplication { .. })));
+    }
+
+    #[test]
+    fn test_malformed_targets_are_rejected() {
+        let launcher = RecordingLauncher::default();
+
+        let invalid = OpenTarget::Url("not a url".to_string()).open_with(&launcher);
+        assert!(matches!(invalid, Err(OpenError::InvalidUrl { .. })));
+        let scheme = OpenTarget::Url("javascript:alert(1)".to_string()).open_with(&launcher);
+        assert_eq!(
+            scheme,
+            Err(OpenError::UnsupportedScheme {
+                scheme: "javascript".to_string()
+            })
+        );
+        let missing = OpenTarget::File("/nonexistent/fleet-open-target.txt".to_string()).open_with(&launcher);
+        assert!(matches!(missing, Err(OpenError::NotFound { .. })));
+        assert!(launcher.opened.lock().unwrap().is_empty());
+
+        let target: OpenTarget = serde_json::from_str(r#"{"kind": "url", "value": "https://example.com"}"#).unwrap();
+        assert_eq!(target, OpenTarget::Url("https://example.com".to_string()));
+    }
+
+    #[test]
+    fn test_windows_programs_and_shortcuts_are_applications() {
+        let dir = tempfile::tempdir().unwrap();
+        let launcher = RecordingLauncher::default();
+        for name in ["Setup.EXE", "Editor.lnk", "build.cmd"] {
+            let program = dir.path().join(name);
+            std::fs::write(&program, "MZ").unwrap();
+            let program = program.to_string_lossy().to_string();
+
+            let as_file = OpenTarget::File(program.clone()).open_with(&launcher);
+            assert!(matches!(as_file, Err(OpenError::IsApplication { .. })), "{}", name);
+            OpenTarget::App(program).open_with(&launcher).unwrap();
+        }
+        assert_eq!(launcher.opened.lock().unwrap().len(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_executables_are_launched_not_opened_as_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let executable = dir.path().join("editor");
+        std::fs::write(&executable, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let entry = dir.path().join("org.example.Editor.desktop");
+        std::fs::write(&entry, "[Desktop Entry]\nExec=editor\n").unwrap();
+        let executable = executable.to_string_lossy().to_string();
+        let entry = entry.to_string_lossy().to_string();
+
+        let launcher = RecordingLauncher::default();
+        let as_file = OpenTarget::File(executable.clone()).open_with(&launcher);
+        let entry_as_file = OpenTarget::File(entry.clone()).open_with(&launcher);
+        OpenTarget::App(executable.clone()).open_with(&launcher).unwrap();
+        OpenTarget::App(entry.clone()).open_with(&launcher).unwrap();
+
+        assert!(matches!(as_file, Err(OpenError::IsApplication { .. })));
+        assert!(matches!(entry_as_file, Err(OpenError::IsApplication { .. })));
+        assert_eq!(
+            *launcher.opened.lock().unwrap(),
+            vec![format!("app:{}", executable), format!("app:{}", entry)]
+        );
+
+        #[cfg(target_os = "linux")]
+        {
+            let command = launch_command(&entry);
+            assert_eq!(command.get_program(), "gio");
+            assert_eq!(command.get_args().collect::<Vec<_>>(), vec!["launch", entry.as_str()]);
+            assert_eq!(launch_command(&executable).get_program(), executable.as_str());
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// Mock of the Fleet Chat plugin API used by `dry_run_plugin`.
+//
+// Every API call is recorded in `globalThis.__dryRun.calls` and every rendered
+// component in `globalThis.__dryRun.components`. Nothing touches the host.
+
+const dryRun = globalThis.__dryRun;
+
+function record(name) {
+  dryRun.calls.push(name);
+}
+
+function recorded(name, result) {
+  return (...args) => {
+    record(name);
+    return typeof result === 'function' ? result(...args) : result;
+  };
+}
+
+function recordedAsync(name, result) {
+  const call = recorded(name, result);
+  return async (...args) => call(...args);
+}
+
+function component(name, children = []) {
+  const render = (props) => ({ type: name, props });
+  render.displayName = name;
+  for (const child of children) {
+    render[child] = component(`${name}.${child}`);
+  }
+  return render;
+}
+
+// Components
+export const List = component('List', ['Item', 'Section', 'EmptyView', 'Dropdown']);
+export const Grid = component('Grid', ['Item', 'Section', 'EmptyView', 'Dropdown']);
+export const Detail = component('Detail', ['Metadata']);
+Detail.Metadata.Label = component('Detail.Metadata.Label');
+Detail.Metadata.Separator = component('Detail.Metadata.Separator');
+Detail.Metadata.Link = component('Detail.Metadata.Link');
+export const Form = component('Form', [
+  'TextField',
+  'TextArea',
+  'PasswordField',
+  'Checkbox',
+  'DatePicker',
+  'Dropdown',
+  'TagPicker',
+  'Separator',
+  'Description',
+]);
+Form.Dropdown.Item = component('Form.Dropdown.Item');
+export const ActionPanel = component('ActionPanel', ['Section', 'Submenu']);
+export const Action = component('Action', [
+  'CopyToClipboard',
+  'OpenInBrowser',
+  'Paste',
+  'Push',
+  'SubmitForm',
+  'ShowInFinder',
+  'Open',
+]);
+
+// Feedback
+export const showToast = recordedAsync('showToast', () => ({ hide: async () => {} }));
+export const showHUD = recordedAsync('showHUD');
+export const confirmAlert = recordedAsync('confirmAlert', true);
+export const Toast = { Style: { Success: 'success', Failure: 'failure', Animated: 'animated' } };
+
+// Navigation and system
+export const push = recorded('push');
+export const pop = recorded('pop');
+export const useNavigation = () => ({ push, pop });
+export const open = recordedAsync('open');
+export const closeMainWindow = recordedAsync('closeMainWindow');
+export const getPreferenceValues = recorded('getPreferenceValues', () => ({}));
+export const environment = { commandName: dryRun.command, extensionName: 'dry-run', isDevelopment: true };
+
+// Storage
+export const Clipboard = {
+  copy: recordedAsync('Clipboard.copy'),
+  paste: recordedAsync('Clipboard.paste'),
+  readText: recordedAsync('Clipboard.readText', ''),
+};
+export const LocalStorage = {
+  getItem: recordedAsync('LocalStorage.getItem'),
+  setItem: recordedAsync('LocalStorage.setItem'),
+  removeItem: recordedAsync('LocalStorage.removeItem'),
+  allItems: recordedAsync('LocalStorage.allItems', () => ({})),
+  clear: recordedAsync('LocalStorage.clear'),
+};
+export class Cache {
+  get = recorded('Cache.get');
+  set = recorded('Cache.set');
+  has = recorded('Cache.has', false);
+  remove = recorded('Cache.remove', false);
+  clear = recorded('Cache.clear');
+}
+
+// Icons and colors resolve to their own names
+const names = new Proxy({}, { get: (_, name) => String(name) });
+export const Icon = names;
+export const Color = names;
+export const Image = { Mask: names };
+
+// Hooks render once: state keeps its initial value and effects run after render
+export function useState(initial) {
+  const value = typeof initial === 'function' ? initial() : initial;
+  return [value, recorded('setState')];
+}
+export function useEffect(effect) {
+  dryRun.effects.push(effect);
+}
+export const useLayoutEffect = useEffect;
+export const useMemo = (factory) => factory();
+export const useCallback = (callback) => callback;
+export const useRef = (current) => ({ current });
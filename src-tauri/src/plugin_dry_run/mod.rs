@@ -0,0 +1,260 @@
+//! Headless dry run of generated plugin code
+//!
+//! The TS/JSX source is transpiled with oxc and evaluated in a QuickJS runtime that
+//! has no filesystem or network access. Plugin API imports resolve to a recording
+//! mock (`mock_api.js`), so the report shows whether the command ran, what it threw,
+//! which APIs it called and which components it rendered.
+
+use oxc::allocator::Allocator;
+use oxc::codegen::Codegen;
+use oxc::parser::Parser;
+use oxc::semantic::SemanticBuilder;
+use oxc::span::SourceType;
+use oxc::transformer::{JsxRuntime, TransformOptions, Transformer};
+use rquickjs::loader::{BuiltinLoader, BuiltinResolver};
+use rquickjs::{CatchResultExt, CaughtError, Context, Module, Object, Runtime};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tauri::command;
+
+const MOCK_API: &str = include_str!("mock_api.js");
+
+/// Module specifiers served by the mock API
+const MOCK_MODULES: &[&str] = &[
+    "@fleet-chat/raycast-api",
+    "@fleet-chat/core-api",
+    "@raycast/api",
+    "react",
+    "lit/decorators.js",
+];
+
+const PLUGIN_MODULE: &str = "plugin";
+const RUN_TIMEOUT: Duration = Duration::from_secs(5);
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+/// JSX compiles to calls of these globals, which record each rendered component
+const PRELUDE: &str = r#"
+globalThis.__fleetFragment = (props) => props.children;
+globalThis.__fleetJsx = (type, props, ...children) => {
+  props = { ...props, children };
+  if (typeof type === 'function' && !type.displayName) {
+    return type(props);
+  }
+  const name = typeof type === 'string' ? type : type.displayName;
+  globalThis.__dryRun.components.push(name);
+  return { type: name, props };
+};
+"#;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DryRunReport {
+    pub command: String,
+    /// Whether the command and its effects finished without throwing
+    pub ran: bool,
+    pub error: Option<String>,
+    /// Mock API calls in the order they were made, e.g. `showToast`
+    pub api_calls: Vec<String>,
+    /// Top-level component returned by the command, e.g. `List`
+    pub rendered: Option<String>,
+    /// Every component rendered, in render order
+    pub components: Vec<String>,
+}
+
+/// Transpile and run a generated plugin command against the mock API
+#[command]
+pub async fn dry_run_plugin(source: String, command: String) -> Result<DryRunReport, String> {
+    tokio::task::spawn_blocking(move || dry_run(&source, &command))
+        .await
+        .map_err(|e| format!("Dry run panicked: {}", e))?
+}
+
+/// Run `command` from `source`; the command is a named export, falling back to the default export
+pub fn dry_run(source: &str, command: &str) -> Result<DryRunReport, String> {
+    let mut report = DryRunReport {
+        command: command.to_string(),
+        ran: false,
+        error: None,
+        api_calls: Vec::new(),
+        rendered: None,
+        components: Vec::new(),
+    };
+
+    let code = match transpile(source) {
+        Ok(code) => code,
+        Err(e) => {
+            report.error = Some(e);
+            return Ok(report);
+        }
+    };
+
+    let runtime = Runtime::new().map_err(|e| format!("Failed to create JS runtime: {}", e))?;
+    runtime.set_memory_limit(MEMORY_LIMIT);
+    let deadline = Instant::now() + RUN_TIMEOUT;
+    runtime.set_interrupt_handler(Some(Box::new(move || Instant::now() > deadline)));
+
+    let resolver = MOCK_MODULES
+        .iter()
+        .fold(BuiltinResolver::default(), |resolver, name| resolver.with_module(*name))
+        .with_module(PLUGIN_MODULE);
+    let loader = MOCK_MODULES
+        .iter()
+        .fold(BuiltinLoader::default(), |loader, name| {
+            loader.with_module(*name, MOCK_API)
+        })
+        .with_module(PLUGIN_MODULE, code);
+    runtime.set_loader(resolver, loader);
+
+    let context = Context::full(&runtime).map_err(|e| format!("Failed to create JS context: {}", e))?;
+    context.with(|ctx| {
+        let state = Object::new(ctx.clone()).map_err(|e| e.to_string())?;
+        for key in ["calls", "components", "effects"] {
+            state
+                .set(key, rquickjs::Array::new(ctx.clone()).map_err(|e| e.to_string())?)
+                .map_err(|e| e.to_string())?;
+        }
+        state.set("command", command).map_err(|e| e.to_string())?;
+        ctx.globals()
+            .set("__dryRun", state.clone())
+            .map_err(|e| e.to_string())?;
+        ctx.eval::<(), _>(PRELUDE).map_err(|e| e.to_string())?;
+
+        let outcome = Module::evaluate(ctx.clone(), "dry-run", driver_source(command))
+            .and_then(|promise| promise.finish::<()>())
+            .catch(&ctx);
+        // Drain work left behind by fire-and-forget async calls
+        while ctx.execute_pending_job() {}
+
+        match outcome {
+            Ok(()) => report.ran = true,
+            Err(_) if Instant::now() > deadline => {
+                report.error = Some(format!("Timed out after {}s", RUN_TIMEOUT.as_secs()))
+            }
+            Err(e) => report.error = Some(describe_error(e)),
+        }
+        report.api_calls = state.get("calls").unwrap_or_default();
+        report.components = state.get("components").unwrap_or_default();
+        report.rendered = state.get("rendered").ok().flatten();
+        Ok(report)
+    })
+}
+
+/// Module that imports the plugin, renders the command and then runs its effects
+fn driver_source(command: &str) -> String {
+    let name = serde_json::to_string(command).unwrap_or_else(|_| "\"\"".to_string());
+    format!(
+        r#"import * as plugin from '{module}';
+const command = typeof plugin[{name}] === 'function' ? plugin[{name}] : plugin.default;
+if (typeof command !== 'function') {{
+  throw new Error('Plugin has no default export or export named ' + {name});
+}}
+const output = await command({{ arguments: {{}} }});
+globalThis.__dryRun.rendered = output && typeof output === 'object' ? output.type : undefined;
+const effects = globalThis.__dryRun.effects;
+while (effects.length > 0) {{
+  const cleanup = await effects.shift()();
+  if (typeof cleanup === 'function') cleanup();
+}}
+"#,
+        module = PLUGIN_MODULE,
+        name = name
+    )
+}
+
+/// Strip TypeScript and compile JSX to `__fleetJsx` calls
+fn transpile(source: &str) -> Result<String, String> {
+    let allocator = Allocator::default();
+    let path = Path::new("command.tsx");
+    let source_type = SourceType::tsx();
+
+    let parsed = Parser::new(&allocator, source, source_type).parse();
+    if let Some(error) = parsed.errors.first() {
+        return Err(format!("Syntax error: {}", error));
+    }
+    let mut program = parsed.program;
+
+    let scoping = SemanticBuilder::new().build(&program).semantic.into_scoping();
+    let mut options = TransformOptions::default();
+    options.jsx.runtime = JsxRuntime::Classic;
+    options.jsx.pragma = Some("__fleetJsx".to_string());
+    options.jsx.pragma_frag = Some("__fleetFragment".to_string());
+    let transformed = Transformer::new(&allocator, path, &options).build_with_scoping(scoping, &mut program);
+    if let Some(error) = transformed.errors.first() {
+        return Err(format!("Transform error: {}", error));
+    }
+
+    Ok(Codegen::new().build(&program).code)
+}
+
+fn describe_error(error: CaughtError<'_>) -> String {
+    match error {
+        CaughtError::Exception(exception) => match exception.message() {
+            Some(message) => format!("Error: {}", message),
+            None => "Error: uncaught exception".to_string(),
+        },
+        CaughtError::Value(value) => format!(
+            "Thrown value: {}",
+            value
+                .as_string()
+                .and_then(|s| s.to_string().ok())
+                .unwrap_or_else(|| format!("{:?}", value.type_of()))
+        ),
+        CaughtError::Error(rquickjs::Error::WouldBlock) => "Command awaited a promise that never settled".to_string(),
+        CaughtError::Error(e) => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::a2ui::plugin_generator::{generate_plugin_code, PluginCommand, PluginManifest};
+
+    fn generated_list_plugin() -> String {
+        let manifest = PluginManifest {
+            name: "Snippets".to_string(),
+            version: "1.0.0".to_string(),
+            description: "Browse saved snippets".to_string(),
+            author: "fleet".to_string(),
+            icon: "📋".to_string(),
+            commands: vec![PluginCommand {
+                name: "index".to_string(),
+                title: "Browse Snippets".to_string(),
+                description: "Browse saved snippets".to_string(),
+                mode: "view".to_string(),
+            }],
+            categories: None,
+            preferences: None,
+        };
+        generate_plugin_code(&manifest, "list", &[], false).unwrap()
+    }
+
+    #[test]
+    fn test_generated_list_plugin_renders_list() {
+        let report = dry_run(&generated_list_plugin(), "index").unwrap();
+
+        assert!(report.ran, "dry run failed: {:?}", report.error);
+        assert_eq!(report.error, None);
+        assert_eq!(report.rendered.as_deref(), Some("List"));
+        assert!(report.components.iter().any(|name| name == "List"));
+        // The load effect reads storage after the first render
+        assert!(report.api_calls.iter().any(|call| call == "LocalStorage.getItem"));
+    }
+
+    #[test]
+    fn test_throwing_command_is_reported_as_failed() {
+        let source = r#"
+import { showToast } from '@fleet-chat/raycast-api';
+
+export default async function Command(): Promise<void> {
+  await showToast({ title: 'Starting' });
+  throw new Error('boom');
+}
+"#;
+        let report = dry_run(source, "index").unwrap();
+
+        assert!(!report.ran);
+        assert_eq!(report.error.as_deref(), Some("Error: boom"));
+        assert_eq!(report.api_calls, vec!["showToast"]);
+        assert_eq!(report.rendered, None);
+    }
+}
@@ -6,9 +6,18 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
-use tauri::{command, AppHandle, Manager, State};
+use tauri::{command, Manager, State};
 use tokio::sync::Mutex;
 
+use crate::a2ui::agent::A2UIMessageResponse;
+use crate::a2ui::schema::{BeginRendering, SurfaceUpdate, TextValue, UIComponent, UIComponentType};
+
+/// Declared mode of commands that run in the background instead of rendering a view
+pub const NO_VIEW_MODE: &str = "no-view";
+
+/// Manifest files looked for in a plugin directory, in order
+const MANIFEST_FILES: &[&str] = &["package.json", "plugin.json", "manifest.json"];
+
 // Plugin state structure
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct PluginInfo {
@@ -21,15 +30,108 @@ pub struct PluginInfo {
     pub commands: Vec<PluginCommand>,
 }
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PluginCommand {
     pub name: String,
     pub title: String,
+    #[serde(default)]
     pub description: Option<String>,
+    #[serde(default = "default_command_mode")]
     pub mode: String,
+    #[serde(default)]
     pub keywords: Vec<String>,
 }
 
+fn default_command_mode() -> String {
+    "view".to_string()
+}
+
+impl PluginCommand {
+    /// Whether the command runs in the background and reports through a toast or HUD
+    pub fn is_no_view(&self) -> bool {
+        self.mode == NO_VIEW_MODE
+    }
+}
+
+/// Outcome of a plugin command, telling the frontend how to present it
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CommandResult {
+    /// A view command's UI, as A2UI messages for its surface
+    View { messages: Vec<A2UIMessageResponse> },
+    /// A no-view command's feedback once it has finished in the background
+    NoView { feedback: CommandFeedback },
+}
+
+/// How a no-view command reports back to the user
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CommandFeedback {
+    Toast {
+        title: String,
+        message: Option<String>,
+        style: ToastStyle,
+    },
+    Hud {
+        message: String,
+    },
+    Notification {
+        title: String,
+        body: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToastStyle {
+    Success,
+    Failure,
+    Animated,
+}
+
+impl CommandResult {
+    /// Result of a command that ran successfully, shaped by its declared mode
+    pub fn completed(command: &PluginCommand) -> Self {
+        let message = format!("Command {} executed successfully", command.name);
+
+        if command.is_no_view() {
+            return Self::NoView {
+                feedback: CommandFeedback::Toast {
+                    title: command.title.clone(),
+                    message: Some(message),
+                    style: ToastStyle::Success,
+                },
+            };
+        }
+
+        let surface_id = command.name.clone();
+        let root = format!("{}-root", command.name);
+        Self::View {
+            messages: vec![
+                A2UIMessageResponse::BeginRendering(BeginRendering {
+                    surface_id: surface_id.clone(),
+                    root: root.clone(),
+                    styles: None,
+                }),
+                A2UIMessageResponse::SurfaceUpdate(SurfaceUpdate {
+                    surface_id,
+                    components: vec![UIComponent {
+                        id: root,
+                        component: UIComponentType::Text {
+                            text: TextValue {
+                                literal_string: Some(message),
+                                path: None,
+                            },
+                            usage_hint: None,
+                        },
+                        weight: None,
+                    }],
+                }),
+            ],
+        }
+    }
+}
+
 // Plugin manager state
 pub struct PluginManagerState {
     plugins: Arc<Mutex<HashMap<String, PluginInfo>>>,
@@ -45,53 +147,51 @@ impl Default for PluginManagerState {
     }
 }
 
-// Plugin management commands
-#[command]
-pub async fn load_plugin(
-    app: AppHandle,
-    state: State<'_, PluginManagerState>,
-    plugin_path: String,
-) -> Result<String, String> {
-    let plugin_id = extract_plugin_id(&plugin_path)?;
-
-    // Initialize extension manager if not already done
-    let mut extension_manager = state.extension_manager.lock().await;
-    if extension_manager.is_none() {
-        let manager = crate::plugins::extension_manager::ExtensionManager::new(app);
-        *extension_manager = Some(manager);
+impl PluginManagerState {
+    /// Load the plugin at `plugin_path`, registering the commands its manifest declares
+    ///
+    /// Takes the extension manager lock before the plugins lock, like every other path
+    /// that holds both.
+    pub async fn load(&self, plugin_path: &str) -> Result<String, String> {
+        // Initialize extension manager if not already done
+        let mut extension_manager = self.extension_manager.lock().await;
+        let manager = extension_manager.get_or_insert_with(crate::plugins::extension_manager::ExtensionManager::new);
+
+        let plugin_info = manager
+            .load_extension(plugin_path)
+            .await
+            .map_err(|e| format!("Failed to load plugin: {}", e))?;
+
+        let plugin_id = plugin_info.id.clone();
+        self.plugins.lock().await.insert(plugin_id.clone(), plugin_info);
+        Ok(plugin_id)
     }
 
-    // Load the plugin
-    if let Some(ref manager) = *extension_manager {
+    /// Run a command of a loaded plugin
+    pub async fn execute(
+        &self,
+        plugin_id: &str,
+        command_name: &str,
+        context: Option<serde_json::Value>,
+    ) -> Result<CommandResult, String> {
+        let extension_manager = self.extension_manager.lock().await;
+        let manager = extension_manager.as_ref().ok_or("Extension manager not initialized")?;
         manager
-            .load_extension(&plugin_path)
+            .execute_command(plugin_id, command_name, context)
             .await
-            .map_err(|e| format!("Failed to load plugin: {}", e))?;
+            .map_err(|e| e.to_string())
     }
+}
 
-    // Update plugin state
-    let mut plugins = state.plugins.lock().await;
-    let plugin_info = PluginInfo {
-        id: plugin_id.clone(),
-        name: format!("Plugin {}", plugin_id),
-        version: "1.0.0".to_string(),
-        description: "A Fleet Chat plugin".to_string(),
-        author: "Unknown".to_string(),
-        status: "loaded".to_string(),
-        commands: vec![],
-    };
-    plugins.insert(plugin_id.clone(), plugin_info);
-
-    Ok(plugin_id)
+// Plugin management commands
+#[command]
+pub async fn load_plugin(state: State<'_, PluginManagerState>, plugin_path: String) -> Result<String, String> {
+    state.load(&plugin_path).await
 }
 
 #[command]
 pub async fn unload_plugin(state: State<'_, PluginManagerState>, plugin_id: String) -> Result<(), String> {
-    // Remove from plugin state
-    let mut plugins = state.plugins.lock().await;
-    plugins.remove(&plugin_id);
-
-    // Unload from extension manager
+    // Unload from extension manager, then remove from plugin state (the lock order `load` uses)
     let extension_manager = state.extension_manager.lock().await;
     if let Some(ref manager) = *extension_manager {
         manager
@@ -99,29 +199,19 @@ pub async fn unload_plugin(state: State<'_, PluginManagerState>, plugin_id: Stri
             .await
             .map_err(|e| format!("Failed to unload plugin: {}", e))?;
     }
+    state.plugins.lock().await.remove(&plugin_id);
 
     Ok(())
 }
 
 #[command]
 pub async fn execute_plugin_command(
-    _app: AppHandle,
     state: State<'_, PluginManagerState>,
     plugin_id: String,
     command_name: String,
     context: Option<serde_json::Value>,
-) -> Result<serde_json::Value, String> {
-    let extension_manager = state.extension_manager.lock().await;
-
-    if let Some(ref manager) = *extension_manager {
-        let result = manager
-            .execute_command(&plugin_id, &command_name, context)
-            .await
-            .map_err(|e| e.to_string())?;
-        Ok(result)
-    } else {
-        Err("Extension manager not initialized".to_string())
-    }
+) -> Result<CommandResult, String> {
+    state.execute(&plugin_id, &command_name, context).await
 }
 
 #[command]
@@ -168,10 +258,7 @@ pub async fn read_extension_manifest(path: String) -> Result<String, String> {
 
 #[command]
 pub async fn get_user_extensions_dir() -> Result<String, String> {
-    let mut path = dirs::home_dir()
-        .ok_or("Could not find home directory")?
-        .join(".fleet-chat");
-    path.push("extensions");
+    let path = crate::storage::data_dir().join("extensions");
 
     // Create directory if it doesn't exist
     std::fs::create_dir_all(&path).map_err(|e| format!("Failed to create extensions directory: {}", e))?;
@@ -191,39 +278,91 @@ fn extract_plugin_id(plugin_path: &str) -> Result<String, String> {
     Ok(plugin_id)
 }
 
+/// The manifest fields read when registering a plugin
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+struct ExtensionManifest {
+    name: Option<String>,
+    title: Option<String>,
+    version: Option<String>,
+    description: Option<String>,
+    /// A name, or an object with a `name`
+    author: serde_json::Value,
+    commands: Vec<PluginCommand>,
+}
+
+/// Describe the plugin at `plugin_path` from its manifest; a plugin without one is
+/// registered with no declared commands
+fn read_plugin_info(plugin_path: &str) -> Result<PluginInfo, String> {
+    let plugin_id = extract_plugin_id(plugin_path)?;
+    let manifest = match MANIFEST_FILES
+        .iter()
+        .map(|file| PathBuf::from(plugin_path).join(file))
+        .find(|path| path.is_file())
+    {
+        Some(path) => {
+            let content =
+                std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            serde_json::from_str(&content).map_err(|e| format!("Invalid manifest {}: {}", path.display(), e))?
+        }
+        None => ExtensionManifest::default(),
+    };
+
+    let author = manifest
+        .author
+        .as_str()
+        .or_else(|| manifest.author.get("name").and_then(|name| name.as_str()))
+        .unwrap_or("Unknown")
+        .to_string();
+    Ok(PluginInfo {
+        name: manifest
+            .title
+            .or(manifest.name)
+            .unwrap_or_else(|| format!("Plugin {}", plugin_id)),
+        version: manifest.version.unwrap_or_else(|| "1.0.0".to_string()),
+        description: manifest
+            .description
+            .unwrap_or_else(|| "A Fleet Chat plugin".to_string()),
+        author,
+        status: "loaded".to_string(),
+        commands: manifest.commands,
+        id: plugin_id,
+    })
+}
+
 // Extension Manager implementation
 pub mod extension_manager {
     use super::*;
-    use tauri::AppHandle;
 
+    #[derive(Default)]
     pub struct ExtensionManager {
-        app: AppHandle,
         plugins: Arc<Mutex<HashMap<String, PluginInfo>>>,
     }
 
     impl ExtensionManager {
-        pub fn new(app: AppHandle) -> Self {
-            Self {
-                app,
-                plugins: Arc::new(Mutex::new(HashMap::new())),
-            }
+        pub fn new() -> Self {
+            Self::default()
         }
 
-        pub async fn load_extension(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-            let plugin_id = extract_plugin_id(path)?;
+        /// Register the plugin at `path` and the commands its manifest declares
+        pub async fn load_extension(&self, path: &str) -> Result<PluginInfo, Box<dyn std::error::Error>> {
+            let plugin_info = read_plugin_info(path)?;
 
-            // In a real implementation, this would:
-            // 1. Load the plugin manifest
-            // 2. Create a Web Worker or isolate
-            // 3. Load the plugin code
-            // 4. Register commands
+            // In a real implementation, this would also:
+            // 1. Create a Web Worker or isolate
+            // 2. Load the plugin code
 
-            println!("Loading extension: {}", plugin_id);
-            Ok(())
+            println!("Loading extension: {}", plugin_info.id);
+            self.plugins
+                .lock()
+                .await
+                .insert(plugin_info.id.clone(), plugin_info.clone());
+            Ok(plugin_info)
         }
 
         pub async fn unload_extension(&self, plugin_id: &str) -> Result<(), Box<dyn std::error::Error>> {
             println!("Unloading extension: {}", plugin_id);
+            self.plugins.lock().await.remove(plugin_id);
             Ok(())
         }
 
@@ -232,14 +371,14 @@ pub mod extension_manager {
             plugin_id: &str,
             command_name: &str,
             _context: Option<serde_json::Value>,
-        ) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+        ) -> Result<CommandResult, Box<dyn std::error::Error>> {
             println!("Executing command: {} from plugin: {}", command_name, plugin_id);
 
-            // Mock response for now
-            Ok(serde_json::json!({
-                "type": "success",
-                "message": format!("Command {} executed successfully", command_name)
-            }))
+            let plugins = self.plugins.lock().await;
+            let command = declared_command(&plugins, plugin_id, command_name)?;
+
+            // Mock execution for now; the result still follows the declared mode
+            Ok(CommandResult::completed(&command))
         }
 
         pub async fn get_all_commands(&self) -> Result<Vec<(String, PluginCommand)>, Box<dyn std::error::Error>> {
@@ -262,6 +401,23 @@ pub mod extension_manager {
     }
 }
 
+/// The command as declared in its plugin's manifest
+fn declared_command(
+    plugins: &HashMap<String, PluginInfo>,
+    plugin_id: &str,
+    command_name: &str,
+) -> Result<PluginCommand, String> {
+    let plugin = plugins
+        .get(plugin_id)
+        .ok_or_else(|| format!("Plugin not loaded: {}", plugin_id))?;
+    plugin
+        .commands
+        .iter()
+        .find(|command| command.name == command_name)
+        .cloned()
+        .ok_or_else(|| format!("Plugin {} has no command {}", plugin_id, command_name))
+}
+
 // Initialize plugin system
 pub fn init_plugin_system(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
     let plugin_state = PluginManagerState::default();
@@ -269,3 +425,115 @@ pub fn init_plugin_system(app: &mut tauri::App) -> Result<(), Box<dyn std::error
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plugin_with(commands: Vec<(&str, &str)>) -> HashMap<String, PluginInfo> {
+        let plugin = PluginInfo {
+            id: "clipboard".to_string(),
+            name: "Clipboard".to_string(),
+            version: "1.0.0".to_string(),
+            description: "Clipboard tools".to_string(),
+            author: "Fleet".to_string(),
+            status: "loaded".to_string(),
+            commands: commands
+                .into_iter()
+                .map(|(name, mode)| PluginCommand {
+                    name: name.to_string(),
+                    title: format!("Run {}", name),
+                    description: None,
+                    mode: mode.to_string(),
+                    keywords: vec![],
+                })
+                .collect(),
+        };
+        HashMap::from([(plugin.id.clone(), plugin)])
+    }
+
+    #[test]
+    fn test_no_view_command_yields_toast() {
+        let plugins = plugin_with(vec![("clear-history", NO_VIEW_MODE)]);
+        let command = declared_command(&plugins, "clipboard", "clear-history").unwrap();
+
+        let result = CommandResult::completed(&command);
+        match &result {
+            CommandResult::NoView {
+                feedback: CommandFeedback::Toast { title, style, .. },
+            } => {
+                assert_eq!(title, "Run clear-history");
+                assert_eq!(*style, ToastStyle::Success);
+            }
+            other => panic!("expected a no-view toast, got {:?}", other),
+        }
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["kind"], "no_view");
+        assert_eq!(json["feedback"]["type"], "toast");
+    }
+
+    #[test]
+    fn test_view_command_yields_a2ui_messages() {
+        let plugins = plugin_with(vec![("history", "view")]);
+
+        let command = declared_command(&plugins, "clipboard", "history").unwrap();
+        let CommandResult::View { messages } = CommandResult::completed(&command) else {
+            panic!("expected a view for {}", command.name);
+        };
+        let json = serde_json::to_value(&messages).unwrap();
+        assert_eq!(json[0]["beginRendering"]["root"], "history-root");
+        assert_eq!(json[1]["surfaceUpdate"]["components"][0]["id"], "history-root");
+
+        assert_eq!(
+            declared_command(&plugins, "clipboard", "undeclared").unwrap_err(),
+            "Plugin clipboard has no command undeclared"
+        );
+        assert_eq!(
+            declared_command(&plugins, "calendar", "history").unwrap_err(),
+            "Plugin not loaded: calendar"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_loaded_manifest_decides_the_command_result() {
+        let dir = tempfile::tempdir().unwrap();
+        let plugin_path = dir.path().join("clipboard");
+        std::fs::create_dir(&plugin_path).unwrap();
+        std::fs::write(
+            plugin_path.join("package.json"),
+            r#"{
+                "name": "clipboard",
+                "title": "Clipboard",
+                "author": {"name": "Fleet"},
+                "commands": [
+                    {"name": "clear-history", "title": "Clear History", "mode": "no-view"},
+                    {"name": "history", "title": "History"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let state = PluginManagerState::default();
+        let plugin_id = state.load(plugin_path.to_str().unwrap()).await.unwrap();
+        assert_eq!(plugin_id, "clipboard");
+        let plugin = state.plugins.lock().await["clipboard"].clone();
+        assert_eq!((plugin.name.as_str(), plugin.author.as_str()), ("Clipboard", "Fleet"));
+
+        let result = state.execute("clipboard", "clear-history", None).await.unwrap();
+        assert!(matches!(
+            result,
+            CommandResult::NoView {
+                feedback: CommandFeedback::Toast { ref title, .. }
+            } if title == "Clear History"
+        ));
+        let result = state.execute("clipboard", "history", None).await.unwrap();
+        assert!(matches!(result, CommandResult::View { .. }));
+
+        assert!(state.execute("clipboard", "missing", None).await.is_err());
+        assert!(PluginManagerState::default()
+            .execute("clipboard", "history", None)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Extra HTTP headers sent with every request to an AI provider
+//!
+//! Corporate gateways and observability tools want their own headers on provider
+//! traffic, e.g. `X-Org-Id` or `Helicone-Auth`. They are configured in
+//! `AI_PROVIDER_HEADERS` as a JSON object keyed by provider id, where `*` applies to
+//! every provider: `{"*": {"X-Org-Id": "acme"}, "openai": {"Helicone-Auth": "Bearer …"}}`.
+//! OpenRouter also gets `HTTP-Referer` and `X-Title` app attribution unless overridden.
+
+use once_cell::sync::Lazy;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, ClientBuilder};
+use std::collections::{BTreeMap, HashMap};
+use tauri_plugin_log::log::warn;
+
+/// Key whose headers are sent to every provider
+const ALL_PROVIDERS: &str = "*";
+
+/// Headers OpenRouter uses to attribute requests to the app
+const OPENROUTER_ATTRIBUTION: &[(&str, &str)] =
+    &[("HTTP-Referer", env!("CARGO_PKG_REPOSITORY")), ("X-Title", "FleetAI")];
+
+static PROVIDER_HEADERS: Lazy<ProviderHeaders> = Lazy::new(ProviderHeaders::from_env);
+
+/// Extra headers per provider id, validated when parsed
+#[derive(Debug, Clone, Default)]
+pub struct ProviderHeaders {
+    headers: HashMap<String, HeaderMap>,
+}
+
+impl ProviderHeaders {
+    /// Parse `{"<provider id>" | "*": {"<name>": "<value>"}}`, failing on the first invalid header
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let config: HashMap<String, BTreeMap<String, String>> =
+            serde_json::from_str(json).map_err(|e| format!("Invalid provider headers: {}", e))?;
+
+        let mut headers = HashMap::new();
+        for (provider, entries) in config {
+            let mut map = HeaderMap::new();
+            for (name, value) in entries {
+                let header = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| format!("Invalid header name '{}' for {}: {}", name, provider, e))?;
+                let value = HeaderValue::from_str(&value)
+                    .map_err(|e| format!("Invalid value for header '{}' for {}: {}", name, provider, e))?;
+                map.insert(header, value);
+            }
+            headers.insert(provider.trim().to_lowercase(), map);
+        }
+        Ok(Self { headers })
+    }
+
+    /// Headers from `AI_PROVIDER_HEADERS`, or none if it is unset or invalid
+    pub fn from_env() -> Self {
+        let Ok(json) = std::env::var("AI_PROVIDER_HEADERS") else {
+            return Self::default();
+        };
+        Self::parse(&json).unwrap_or_else(|e| {
+            warn!("Ignoring AI_PROVIDER_HEADERS: {}", e);
+            Self::default()
+        })
+    }
+
+    /// Headers for `provider`: defaults, then `*`, then the provider's own, later ones winning
+    pub fn for_provider(&self, provider: &str) -> HeaderMap {
+        let provider = provider.to_lowercase();
+        let mut headers = HeaderMap::new();
+        if provider == "openrouter" {
+            for &(name, value) in OPENROUTER_ATTRIBUTION {
+                headers.insert(name, HeaderValue::from_static(value));
+            }
+        }
+        for key in [ALL_PROVIDERS, provider.as_str()] {
+            if let Some(extra) = self.headers.get(key) {
+                for (name, value) in extra {
+                    headers.insert(name.clone(), value.clone());
+                }
+            }
+        }
+        headers
+    }
+}
+
+/// The configured extra headers for `provider`
+pub fn provider_headers(provider: &str) -> HeaderMap {
+    PROVIDER_HEADERS.for_provider(provider)
+}
+
+/// A client builder that sends `provider`'s extra headers with every request
+pub fn provider_client_builder(provider: &str) -> ClientBuilder {
+    Client::builder().default_headers(provider_headers(provider))
+}
+
+/// A client that sends `provider`'s extra headers with every request
+pub fn provider_client(provider: &str) -> Client {
+    provider_client_builder(provider).build().unwrap_or_else(|e| {
+        warn!("Failed to create HTTP client for {}: {}", provider, e);
+        Client::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap as RequestHeaders;
+    use axum::routing::post;
+    use axum::Router;
+
+    #[test]
+    fn test_provider_headers_override_shared_and_default_ones() {
+        let config = ProviderHeaders::parse(
+            r#"{"*": {"X-Org-Id": "acme"}, "OpenRouter": {"X-Title": "Acme Chat"}, "openai": {"X-Org-Id": "acme-ai"}}"#,
+        )
+        .unwrap();
+
+        let openrouter = config.for_provider("openrouter");
+        assert_eq!(openrouter["x-org-id"], "acme");
+        assert_eq!(openrouter["x-title"], "Acme Chat");
+        assert_eq!(openrouter["http-referer"], env!("CARGO_PKG_REPOSITORY"));
+
+        let openai = config.for_provider("openai");
+        assert_eq!(openai["x-org-id"], "acme-ai");
+        assert!(!openai.contains_key("x-title"));
+
+        assert!(ProviderHeaders::parse(r#"{"openai": {"Bad Header": "x"}}"#)
+            .unwrap_err()
+            .contains("Invalid header name 'Bad Header'"));
+    }
+
+    #[tokio::test]
+    async fn test_configured_headers_are_sent_to_the_provider() {
+        use rig::client::CompletionClient;
+        use rig::completion::CompletionModel;
+        use std::sync::{Arc, Mutex};
+
+        // A mock OpenRouter that records the headers of the request it receives
+        let received = Arc::new(Mutex::new(None));
+        let recorder = received.clone();
+        let app = Router::new().route(
+            "/chat/completions",
+            post(move |headers: RequestHeaders| async move {
+                *recorder.lock().unwrap() = Some(headers);
+                "{}"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let config = ProviderHeaders::parse(r#"{"openrouter": {"Helicone-Auth": "Bearer test"}}"#).unwrap();
+        let http_client = Client::builder()
+            .default_headers(config.for_provider("openrouter"))
+            .build()
+            .unwrap();
+        let client = rig::providers::openrouter::Client::<Client>::builder()
+            .api_key("test-key")
+            .base_url(format!("http://{}", addr))
+            .http_client(http_client)
+            .build()
+            .unwrap();
+        // The mock's empty reply doesn't parse; only the request matters here
+        let _ = client
+            .completion_model("openrouter/auto")
+            .completion_request("Hello")
+            .send()
+            .await;
+
+        let headers = received.lock().unwrap().take().expect("provider was not called");
+        assert_eq!(headers["helicone-auth"], "Bearer test");
+        assert_eq!(headers["x-title"], "FleetAI");
+        assert_eq!(headers["authorization"], "Bearer test-key");
+    }
+}
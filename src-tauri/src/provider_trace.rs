@@ -0,0 +1,272 @@
+//! Redacted provider request/response capture for bug reports
+//!
+//! When `FLEET_DEBUG_AI` is on, the last few provider exchanges are kept in memory with
+//! their raw request JSON and response (or error) body, so a rejected request can be
+//! diagnosed from what was actually sent. API keys and sensitive fields are redacted
+//! before anything is stored, and nothing is captured or logged with debugging off.
+//! `AI_DEBUG_TRACE_SIZE` sets how many exchanges are kept and `AI_DEBUG_REDACT_FIELDS`
+//! adds comma-separated field names to redact on top of the defaults.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tauri::command;
+
+use crate::insights_privacy::{redact_secrets, REDACTED};
+use crate::logging::ai_debug_enabled;
+
+/// Exchanges kept when `AI_DEBUG_TRACE_SIZE` is unset
+const DEFAULT_TRACE_SIZE: usize = 20;
+
+/// Field names whose values are always redacted, compared case-insensitively
+const DEFAULT_SENSITIVE_FIELDS: &[&str] = &[
+    "api_key",
+    "apikey",
+    "key",
+    "x-api-key",
+    "x-goog-api-key",
+    "authorization",
+    "token",
+    "access_token",
+    "refresh_token",
+    "password",
+    "secret",
+];
+
+/// Credential query parameters, e.g. Gemini's `?key=`
+static SECRET_QUERY_PARAM: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)([?&](?:key|api_key|access_token|token)=)[^&#]*").expect("valid query pattern"));
+
+pub static PROVIDER_TRACE: Lazy<ProviderTrace> = Lazy::new(ProviderTrace::from_env);
+
+/// One provider call as it went over the wire, with secrets redacted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderExchange {
+    pub provider: String,
+    pub url: String,
+    pub request: Value,
+    pub status: Option<u16>,
+    /// Response or error body, as JSON when it parses; `None` for streamed responses
+    pub response: Option<Value>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Ring buffer of the most recent provider exchanges, only filled when enabled
+#[derive(Debug)]
+pub struct ProviderTrace {
+    enabled: bool,
+    capacity: usize,
+    sensitive_fields: Vec<String>,
+    exchanges: Mutex<VecDeque<ProviderExchange>>,
+}
+
+impl ProviderTrace {
+    pub fn new(enabled: bool, capacity: usize, extra_sensitive_fields: impl IntoIterator<Item = String>) -> Self {
+        let sensitive_fields = DEFAULT_SENSITIVE_FIELDS
+            .iter()
+            .map(|field| field.to_string())
+            .chain(extra_sensitive_fields)
+            .map(|field| field.trim().to_lowercase())
+            .filter(|field| !field.is_empty())
+            .collect();
+
+        Self {
+            enabled,
+            capacity,
+            sensitive_fields,
+            exchanges: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Enabled with `FLEET_DEBUG_AI`, sized by `AI_DEBUG_TRACE_SIZE`, extended by `AI_DEBUG_REDACT_FIELDS`
+    pub fn from_env() -> Self {
+        let capacity = std::env::var("AI_DEBUG_TRACE_SIZE")
+            .ok()
+            .and_then(|size| size.trim().parse().ok())
+            .unwrap_or(DEFAULT_TRACE_SIZE);
+        let extra_fields = std::env::var("AI_DEBUG_REDACT_FIELDS").unwrap_or_default();
+
+        Self::new(
+            ai_debug_enabled(),
+            capacity,
+            extra_fields.split(',').map(String::from).collect::<Vec<_>>(),
+        )
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record an exchange, redacted; does nothing (not even serialize) unless enabled
+    pub fn record(
+        &self,
+        provider: &str,
+        url: &str,
+        request: &impl Serialize,
+        status: Option<u16>,
+        response: Option<&str>,
+    ) {
+        if !self.enabled || self.capacity == 0 {
+            return;
+        }
+
+        let request = serde_json::to_value(request).unwrap_or(Value::Null);
+        let response = response.map(|body| match serde_json::from_str::<Value>(body) {
+            Ok(json) => self.redact_value(json),
+            Err(_) => Value::String(redact_secrets(body)),
+        });
+        let exchange = ProviderExchange {
+            provider: provider.to_string(),
+            url: redact_secrets(&SECRET_QUERY_PARAM.replace_all(url, format!("${{1}}{}", REDACTED))),
+            request: self.redact_value(request),
+            status,
+            response,
+            timestamp: Utc::now(),
+        };
+
+        if let Ok(mut exchanges) = self.exchanges.lock() {
+            while exchanges.len() >= self.capacity {
+                exchanges.pop_front();
+            }
+            exchanges.push_back(exchange);
+        }
+    }
+
+    /// Recorded exchanges, oldest first
+    pub fn recent(&self) -> Vec<ProviderExchange> {
+        self.exchanges
+            .lock()
+            .map(|exchanges| exchanges.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut exchanges) = self.exchanges.lock() {
+            exchanges.clear();
+        }
+    }
+
+    fn is_sensitive(&self, field: &str) -> bool {
+        let field = field.to_lowercase();
+        self.sensitive_fields.iter().any(|sensitive| *sensitive == field)
+    }
+
+    fn redact_value(&self, value: Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(field, value)| {
+                        let value = if self.is_sensitive(&field) {
+                            Value::String(REDACTED.to_string())
+                        } else {
+                            self.redact_value(value)
+                        };
+                        (field, value)
+                    })
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(items.into_iter().map(|item| self.redact_value(item)).collect()),
+            Value::String(text) => Value::String(redact_secrets(&text)),
+            other => other,
+        }
+    }
+}
+
+/// Get the recorded provider exchanges (empty unless `FLEET_DEBUG_AI` is on)
+#[command]
+pub fn get_provider_trace() -> Vec<ProviderExchange> {
+    PROVIDER_TRACE.recent()
+}
+
+/// Clear the recorded provider exchanges
+#[command]
+pub fn clear_provider_trace() {
+    PROVIDER_TRACE.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn gemini_request() -> Value {
+        json!({
+            "contents": [{"role": "user", "parts": [{"text": "my key is sk-live-1234567890abcdef"}]}],
+            "api_key": "AIzaSyA-1234567890abcdefghijk",
+            "user": {"email": "jane@example.com", "id": "u1"}
+        })
+    }
+
+    #[test]
+    fn test_debug_trace_captures_redacted_payloads() {
+        let trace = ProviderTrace::new(true, 2, vec!["Email".to_string()]);
+
+        trace.record(
+            "gemini",
+            "https://example.com/models/m:generateContent?key=AIzaSyA-1234567890abcdefghijk&alt=sse",
+            &gemini_request(),
+            Some(400),
+            Some(r#"{"error": {"message": "Invalid value", "token": "abc"}}"#),
+        );
+
+        let recent = trace.recent();
+        assert_eq!(recent.len(), 1);
+        let exchange = &recent[0];
+        assert_eq!(exchange.provider, "gemini");
+        assert_eq!(exchange.status, Some(400));
+        assert_eq!(
+            exchange.url,
+            "https://example.com/models/m:generateContent?key=[REDACTED]&alt=sse"
+        );
+        assert_eq!(exchange.request["api_key"], REDACTED);
+        assert_eq!(exchange.request["user"]["email"], REDACTED);
+        assert_eq!(exchange.request["user"]["id"], "u1");
+        assert_eq!(
+            exchange.request["contents"][0]["parts"][0]["text"],
+            "my key is [REDACTED]"
+        );
+        let response = exchange.response.as_ref().unwrap();
+        assert_eq!(response["error"]["message"], "Invalid value");
+        assert_eq!(response["error"]["token"], REDACTED);
+
+        let serialized = serde_json::to_string(&recent).unwrap();
+        assert!(!serialized.contains("AIzaSyA-1234567890abcdefghijk"));
+        assert!(!serialized.contains("sk-live-1234567890abcdef"));
+
+        trace.record(
+            "openai",
+            "https://example.com",
+            &json!({}),
+            Some(200),
+            Some("Bearer abc.def"),
+        );
+        trace.record("openai", "https://example.com", &json!({}), Some(200), None);
+        let recent = trace.recent();
+        assert_eq!(recent.len(), 2, "oldest exchange is evicted at capacity");
+        assert_eq!(recent[0].response, Some(json!("[REDACTED]")));
+        assert_eq!(recent[1].response, None);
+
+        trace.clear();
+        assert!(trace.recent().is_empty());
+    }
+
+    #[test]
+    fn test_nothing_is_captured_with_debug_off() {
+        let trace = ProviderTrace::new(false, 20, Vec::new());
+
+        trace.record(
+            "gemini",
+            "https://example.com?key=secret",
+            &gemini_request(),
+            Some(500),
+            Some("boom"),
+        );
+
+        assert!(!trace.is_enabled());
+        assert!(trace.recent().is_empty());
+    }
+}
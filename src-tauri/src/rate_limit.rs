@@ -0,0 +1,200 @@
+//! Retry-after parsing and backoff for provider rate-limit (429) and transient responses
+//!
+//! Providers say when to retry either through the `Retry-After` header (seconds or an
+//! HTTP date) or only in the error body ("Please try again in 20s", Gemini's
+//! `"retryDelay": "30s"`). Both are normalized to milliseconds for the frontend.
+//! [`RetryPolicy`] turns them, or exponential backoff when absent, into retry delays.
+
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+const DEFAULT_MAX_RETRIES: u32 = 2;
+
+/// How often and how long to wait before retrying a transient provider failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each later one
+    pub base_delay: Duration,
+    /// Longest delay to wait; a longer `Retry-After` gives up instead
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Default policy with `AI_MAX_RETRIES` applied
+    pub fn from_env() -> Self {
+        let max_retries = std::env::var("AI_MAX_RETRIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_RETRIES);
+        Self {
+            max_retries,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before retrying after failed attempt number `attempt` (0-based), or `None` to give up
+    pub fn delay(&self, attempt: u32, retry_after_ms: Option<u64>) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        match retry_after_ms {
+            Some(ms) => {
+                let delay = Duration::from_millis(ms);
+                (delay <= self.max_delay).then_some(delay)
+            }
+            None => {
+                let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(attempt));
+                Some(backoff.min(self.max_delay))
+            }
+        }
+    }
+}
+
+/// Whether an HTTP status is worth retrying: timeouts, rate limits and server errors
+pub fn is_transient_status(status: u16) -> bool {
+    matches!(status, 408 | 429 | 500..=599)
+}
+
+/// Parse a `Retry-After` header value into milliseconds
+pub fn parse_retry_after_header(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        // `f64` also parses "inf" and "NaN", which are no delay at all
+        return (seconds.is_finite() && seconds >= 0.0).then(|| (seconds * 1000.0).round() as u64);
+    }
+
+    let retry_at = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+    let delay = retry_at.signed_duration_since(Utc::now()).num_milliseconds();
+    Some(delay.max(0) as u64)
+}
+
+/// Look for a retry delay in a provider error message
+pub fn retry_after_from_message(message: &str) -> Option<u64> {
+    const MARKERS: &[&str] = &["try again in ", "retry after ", "retrydelay\": \"", "retry in "];
+
+    let message = message.to_lowercase();
+    MARKERS.iter().find_map(|marker| {
+        let start = message.find(marker)? + marker.len();
+        parse_duration(&message[start..])
+    })
+}
+
+/// Parse a leading duration like `30`, `1.5s`, `500ms`, `20 seconds` or `1m30s`
+///
+/// A number without a known unit is seconds and ends the duration; one with a unit may be
+/// followed by further components.
+fn parse_duration(text: &str) -> Option<u64> {
+    let mut rest = text;
+    let mut millis = None;
+    loop {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let Ok(value) = rest[..number_len].parse::<f64>() else {
+            break;
+        };
+        let after = rest[number_len..].trim_start();
+        let unit_len = after.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(after.len());
+        let scale = match &after[..unit_len] {
+            "ms" | "msec" | "msecs" | "millisecond" | "milliseconds" => Some(1.0),
+            "s" | "sec" | "secs" | "second" | "seconds" => Some(1000.0),
+            "m" | "min" | "mins" | "minute" | "minutes" => Some(60_000.0),
+            "h" | "hr" | "hrs" | "hour" | "hours" => Some(3_600_000.0),
+            _ => None,
+        };
+        *millis.get_or_insert(0.0) += value * scale.unwrap_or(1000.0);
+        if scale.is_none() {
+            break;
+        }
+        rest = after[unit_len..].trim_start();
+    }
+    millis
+        .filter(|millis: &f64| millis.is_finite())
+        .map(|millis| millis.round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after_header() {
+        assert_eq!(parse_retry_after_header("30"), Some(30_000));
+        assert_eq!(parse_retry_after_header(" 1.5 "), Some(1_500));
+        assert_eq!(parse_retry_after_header("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+        assert_eq!(parse_retry_after_header("soon"), None);
+        assert_eq!(parse_retry_after_header("inf"), None);
+        assert_eq!(parse_retry_after_header("NaN"), None);
+    }
+
+    #[test]
+    fn test_retry_after_from_message() {
+        assert_eq!(
+            retry_after_from_message("Rate limit reached for gpt-4o. Please try again in 20s."),
+            Some(20_000)
+        );
+        assert_eq!(retry_after_from_message("Please try again in 450ms"), Some(450));
+        assert_eq!(
+            retry_after_from_message(r#"{"error": {"details": [{"retryDelay": "30s"}]}}"#),
+            Some(30_000)
+        );
+        assert_eq!(retry_after_from_message("quota exceeded"), None);
+    }
+
+    #[test]
+    fn test_compound_durations() {
+        assert_eq!(parse_duration("1m30s"), Some(90_000));
+        assert_eq!(parse_duration("2 minutes 5 seconds."), Some(125_000));
+        assert_eq!(parse_duration("1h"), Some(3_600_000));
+        assert_eq!(parse_duration("1.5s, then again"), Some(1_500));
+        assert_eq!(parse_duration("20 or so"), Some(20_000));
+        assert_eq!(
+            retry_after_from_message("Rate limit reached. Please try again in 1m12.5s."),
+            Some(72_500)
+        );
+    }
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(5),
+        };
+
+        assert_eq!(policy.delay(0, None), Some(Duration::from_millis(500)));
+        assert_eq!(policy.delay(1, None), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(2, None), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay(3, None), None);
+        // Retry-After wins over backoff, but not past the cap
+        assert_eq!(policy.delay(0, Some(1_200)), Some(Duration::from_millis(1_200)));
+        assert_eq!(policy.delay(0, Some(60_000)), None);
+
+        let disabled = RetryPolicy {
+            max_retries: 0,
+            ..policy
+        };
+        assert_eq!(disabled.delay(0, None), None);
+    }
+
+    #[test]
+    fn test_is_transient_status() {
+        assert!(is_transient_status(429));
+        assert!(is_transient_status(503));
+        assert!(is_transient_status(408));
+        assert!(!is_transient_status(400));
+        assert!(!is_transient_status(401));
+    }
+}
@@ -0,0 +1,261 @@
+//! Activity feed of recent AI and A2UI requests for the debug console
+//!
+//! Every AI generation and A2UI provider call is recorded with its provider, model,
+//! latency, token usage and outcome, keeping the last `RECENT_REQUESTS_SIZE` (default 100)
+//! in memory. Prompts stay out of the feed unless `FLEET_DEBUG_AI` is on, and even then
+//! only a short preview with credentials redacted is kept.
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+use tauri::command;
+
+use crate::insights_privacy::redact_secrets;
+use crate::logging::ai_debug_enabled;
+use crate::rig_agent::TokenUsage;
+
+/// Requests kept when `RECENT_REQUESTS_SIZE` is unset
+const DEFAULT_RECENT_REQUESTS_SIZE: usize = 100;
+
+/// Characters of the prompt kept in a preview
+const PROMPT_PREVIEW_LENGTH: usize = 200;
+
+pub static RECENT_REQUESTS: Lazy<RecentRequests> = Lazy::new(RecentRequests::from_env);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestSource {
+    Ai,
+    A2ui,
+    Gemini,
+}
+
+/// A finished request as shown in the debug console
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestRecord {
+    pub source: RequestSource,
+    pub provider: String,
+    pub model: Option<String>,
+    /// When the request was sent
+    pub timestamp: DateTime<Utc>,
+    pub latency_ms: u64,
+    pub usage: Option<TokenUsage>,
+    pub success: bool,
+    pub error: Option<String>,
+    /// Start of the prompt, redacted; only kept with AI debugging on
+    pub prompt_preview: Option<String>,
+}
+
+/// A request in flight, turned into a `RequestRecord` by `RecentRequests::finish`
+#[derive(Debug)]
+pub struct PendingRequest {
+    source: RequestSource,
+    provider: String,
+    model: Option<String>,
+    timestamp: DateTime<Utc>,
+    started: Instant,
+    prompt_preview: Option<String>,
+}
+
+/// A request that is recorded when finished, or as failed if dropped unfinished
+///
+/// Streamed requests hold one so that a stream abandoned part way through still shows up.
+#[derive(Debug)]
+pub struct RequestGuard<'a> {
+    requests: &'a RecentRequests,
+    request: Option<PendingRequest>,
+}
+
+impl RequestGuard<'_> {
+    /// Record the request; only the first call has an effect
+    pub fn finish(&mut self, usage: Option<TokenUsage>, error: Option<String>) {
+        if let Some(request) = self.request.take() {
+            self.requests.finish(request, usage, error);
+        }
+    }
+}
+
+impl Drop for RequestGuard<'_> {
+    fn drop(&mut self) {
+        self.finish(None, Some("Stream ended before completing".to_string()));
+    }
+}
+
+/// Ring buffer of the most recent requests
+#[derive(Debug)]
+pub struct RecentRequests {
+    capacity: usize,
+    preview_prompts: bool,
+    records: Mutex<VecDeque<RequestRecord>>,
+}
+
+impl RecentRequests {
+    pub fn new(capacity: usize, preview_prompts: bool) -> Self {
+        Self {
+            capacity,
+            preview_prompts,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Sized by `RECENT_REQUESTS_SIZE`, with prompt previews when `FLEET_DEBUG_AI` is on
+    pub fn from_env() -> Self {
+        let capacity = std::env::var("RECENT_REQUESTS_SIZE")
+            .ok()
+            .and_then(|size| size.trim().parse().ok())
+            .unwrap_or(DEFAULT_RECENT_REQUESTS_SIZE);
+        Self::new(capacity, ai_debug_enabled())
+    }
+
+    /// Start timing a request; the prompt is only looked at when previews are on
+    pub fn start(&self, source: RequestSource, provider: &str, model: Option<&str>, prompt: &str) -> PendingRequest {
+        PendingRequest {
+            source,
+            provider: provider.to_string(),
+            model: model.map(String::from),
+            timestamp: Utc::now(),
+            started: Instant::now(),
+            prompt_preview: self.preview_prompts.then(|| prompt_preview(prompt)),
+        }
+    }
+
+    /// Wrap a request started with `start` so it is recorded even if never finished
+    pub fn guard(&self, request: PendingRequest) -> RequestGuard<'_> {
+        RequestGuard {
+            requests: self,
+            request: Some(request),
+        }
+    }
+
+    /// Record a request started with `start`; `error` is `None` when it succeeded
+    pub fn finish(&self, request: PendingRequest, usage: Option<TokenUsage>, error: Option<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let record = RequestRecord {
+            source: request.source,
+            provider: request.provider,
+            model: request.model,
+            timestamp: request.timestamp,
+            latency_ms: request.started.elapsed().as_millis() as u64,
+            usage,
+            success: error.is_none(),
+            error: error.map(|error| redact_secrets(&error)),
+            prompt_preview: request.prompt_preview,
+        };
+
+        if let Ok(mut records) = self.records.lock() {
+            while records.len() >= self.capacity {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+    }
+
+    /// Recorded requests, oldest first
+    pub fn recent(&self) -> Vec<RequestRecord> {
+        self.records
+            .lock()
+            .map(|records| records.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut records) = self.records.lock() {
+            records.clear();
+        }
+    }
+}
+
+/// First `PROMPT_PREVIEW_LENGTH` characters of `prompt`, redacted
+fn prompt_preview(prompt: &str) -> String {
+    let redacted = redact_secrets(prompt.trim());
+    match redacted.char_indices().nth(PROMPT_PREVIEW_LENGTH) {
+        Some((end, _)) => format!("{}…", &redacted[..end]),
+        None => redacted,
+    }
+}
+
+/// Get the recent AI and A2UI requests, oldest first
+#[command]
+pub fn get_recent_requests() -> Vec<RequestRecord> {
+    RECENT_REQUESTS.recent()
+}
+
+/// Clear the recent requests feed
+#[command]
+pub fn clear_recent_requests() {
+    RECENT_REQUESTS.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_requests_are_evicted_at_capacity() {
+        let requests = RecentRequests::new(2, false);
+
+        for model in ["first", "second", "third"] {
+            let request = requests.start(RequestSource::Ai, "openai", Some(model), "hello");
+            requests.finish(request, None, None);
+        }
+
+        let models: Vec<_> = requests
+            .recent()
+            .into_iter()
+            .filter_map(|record| record.model)
+            .collect();
+        assert_eq!(models, vec!["second", "third"]);
+
+        requests.clear();
+        assert!(requests.recent().is_empty());
+    }
+
+    #[test]
+    fn test_records_carry_outcome_usage_and_redacted_preview() {
+        let requests = RecentRequests::new(10, true);
+
+        let request = requests.start(
+            RequestSource::Ai,
+            "anthropic",
+            Some("claude-3-5-haiku-latest"),
+            &format!("my key is sk-live-1234567890abcdef {}", "x".repeat(300)),
+        );
+        let usage = TokenUsage {
+            prompt_tokens: 12,
+            completion_tokens: 30,
+            total_tokens: 42,
+        };
+        requests.finish(request, Some(usage), None);
+        let request = requests.start(RequestSource::A2ui, "Gemini", None, "show a card");
+        requests.finish(request, None, Some("Request failed: 500".to_string()));
+
+        let records = requests.recent();
+        let ok = &records[0];
+        assert_eq!(ok.source, RequestSource::Ai);
+        assert_eq!(ok.provider, "anthropic");
+        assert_eq!(ok.model.as_deref(), Some("claude-3-5-haiku-latest"));
+        assert!(ok.success);
+        assert_eq!(ok.usage.as_ref().map(|usage| usage.total_tokens), Some(42));
+        let preview = ok.prompt_preview.as_deref().unwrap();
+        assert!(preview.starts_with("my key is [REDACTED] xxx"));
+        assert!(preview.ends_with('…'));
+        assert_eq!(preview.chars().count(), PROMPT_PREVIEW_LENGTH + 1);
+
+        let failed = &records[1];
+        assert_eq!(failed.source, RequestSource::A2ui);
+        assert!(!failed.success);
+        assert_eq!(failed.error.as_deref(), Some("Request failed: 500"));
+        assert!(failed.timestamp >= ok.timestamp);
+
+        let without_previews = RecentRequests::new(10, false);
+        let request = without_previews.start(RequestSource::Ai, "openai", None, "secret plans");
+        without_previews.finish(request, None, None);
+        assert_eq!(without_previews.recent()[0].prompt_preview, None);
+    }
+}
@@ -0,0 +1,235 @@
+/**
+ * Response Cache
+ *
+ * In-memory cache for idempotent `RigAgent::generate` calls. Entries are keyed by a
+ * hash of the provider, model, normalized prompt and sampling options, expire after
+ * a TTL, and the oldest entry is evicted once the cache is full.
+ */
+use once_cell::sync::Lazy;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::rig_agent::{AIOptions, AIProvider, AIResponse};
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(10 * 60);
+pub const DEFAULT_MAX_ENTRIES: usize = 256;
+
+struct CacheEntry {
+    response: AIResponse,
+    inserted_at: Instant,
+}
+
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<u64, CacheEntry>>,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL, DEFAULT_MAX_ENTRIES)
+    }
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create a cache configured from the environment
+    ///
+    /// - `AI_CACHE_TTL_SECS`: seconds before an entry expires (default 600)
+    /// - `AI_CACHE_MAX_ENTRIES`: maximum number of cached responses (default 256)
+    pub fn from_env() -> Self {
+        let ttl = std::env::var("AI_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TTL);
+        let max_entries = std::env::var("AI_CACHE_MAX_ENTRIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_ENTRIES);
+        Self::new(ttl, max_entries)
+    }
+
+    pub fn get(&self, key: u64) -> Option<AIResponse> {
+        let mut entries = self.entries.lock().ok()?;
+        match entries.get(&key) {
+            Some(entry) if entry.inserted_at.elapsed() < self.ttl => Some(entry.response.clone()),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: u64, response: AIResponse) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        let ttl = self.ttl;
+        entries.retain(|_, entry| entry.inserted_at.elapsed() < ttl);
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted_at)
+                .map(|(key, _)| *key)
+            {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key,
+            CacheEntry {
+                response,
+                inserted_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Return the cached response for `key`, or run `generate` and cache its result
+    ///
+    /// Errors are never cached.
+    pub async fn get_or_try_insert_with<F, Fut, E>(&self, key: u64, generate: F) -> Result<AIResponse, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<AIResponse, E>>,
+    {
+        if let Some(response) = self.get(key) {
+            return Ok(response);
+        }
+
+        let response = generate().await?;
+        self.insert(key, response.clone());
+        Ok(response)
+    }
+}
+
+/// Cache shared by every `RigAgent`
+pub static RESPONSE_CACHE: Lazy<Arc<ResponseCache>> = Lazy::new(|| Arc::new(ResponseCache::from_env()));
+
+/// Hash the parts of a request that determine its response
+pub fn cache_key(provider: &AIProvider, model: &str, options: &AIOptions) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", provider).hash(&mut hasher);
+    model.hash(&mut hasher);
+    normalize_prompt(&options.prompt).hash(&mut hasher);
+    options.temperature.map(f32::to_bits).hash(&mut hasher);
+    options.max_tokens.hash(&mut hasher);
+    options.top_p.map(f32::to_bits).hash(&mut hasher);
+    options.frequency_penalty.map(f32::to_bits).hash(&mut hasher);
+    options.presence_penalty.map(f32::to_bits).hash(&mut hasher);
+    options.system_prompt.hash(&mut hasher);
+    options.base_url.hash(&mut hasher);
+    serde_json::to_string(&options.response_format)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Collapse whitespace so formatting-only differences share a cache entry
+fn normalize_prompt(prompt: &str) -> String {
+    prompt.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn options(prompt: &str, temperature: Option<f32>) -> AIOptions {
+        AIOptions {
+            prompt: prompt.to_string(),
+            temperature,
+            cacheable: true,
+            ..Default::default()
+        }
+    }
+
+    async fn cached_generate(cache: &ResponseCache, calls: &AtomicUsize, options: &AIOptions) -> AIResponse {
+        let key = cache_key(&AIProvider::OpenAI, "gpt-4o", options);
+        cache
+            .get_or_try_insert_with(key, || async {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                Ok::<_, ()>(AIResponse {
+                    text: format!("response {}", call),
+                    usage: None,
+                    model: Some("gpt-4o".to_string()),
+                    finish_reason: Some("stop".to_string()),
+                    reasoning: None,
+                })
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_identical_calls_hit_cache() {
+        let cache = ResponseCache::default();
+        let calls = AtomicUsize::new(0);
+
+        let first = cached_generate(&cache, &calls, &options("Explain this plugin", Some(0.7))).await;
+        let second = cached_generate(&cache, &calls, &options("  Explain   this plugin\n", Some(0.7))).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(first.text, second.text);
+    }
+
+    #[tokio::test]
+    async fn test_differing_options_miss_cache() {
+        let cache = ResponseCache::default();
+        let calls = AtomicUsize::new(0);
+
+        cached_generate(&cache, &calls, &options("Explain this plugin", Some(0.7))).await;
+        let other = cached_generate(&cache, &calls, &options("Explain this plugin", Some(0.2))).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(other.text, "response 2");
+        assert_ne!(
+            cache_key(&AIProvider::OpenAI, "gpt-4o", &options("a", None)),
+            cache_key(&AIProvider::Anthropic, "gpt-4o", &options("a", None))
+        );
+        let instructed = AIOptions {
+            system_prompt: Some("Answer in French.".to_string()),
+            ..options("a", None)
+        };
+        assert_ne!(
+            cache_key(&AIProvider::OpenAI, "gpt-4o", &options("a", None)),
+            cache_key(&AIProvider::OpenAI, "gpt-4o", &instructed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_evicted_entries() {
+        let calls = AtomicUsize::new(0);
+
+        let expiring = ResponseCache::new(Duration::ZERO, 8);
+        cached_generate(&expiring, &calls, &options("prompt", None)).await;
+        cached_generate(&expiring, &calls, &options("prompt", None)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let small = ResponseCache::new(DEFAULT_TTL, 2);
+        for prompt in ["one", "two", "three"] {
+            cached_generate(&small, &calls, &options(prompt, None)).await;
+        }
+        let cached = |prompt| small.get(cache_key(&AIProvider::OpenAI, "gpt-4o", &options(prompt, None)));
+        assert!(cached("one").is_none());
+        assert!(cached("two").is_some());
+        assert!(cached("three").is_some());
+    }
+}
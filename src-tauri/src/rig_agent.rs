@@ -6,20 +6,42 @@
  *
  * Refactored to use AgentBuilder::new() pattern uniformly across all providers
  */
+use futures::future::BoxFuture;
 use futures::stream::{Stream, StreamExt};
 use reqwest::Client;
 use rig::{
     agent::{AgentBuilder, MultiTurnStreamItem},
-    client::{CompletionClient, EmbeddingsClient, ProviderClient},
-    completion::{Chat, Message, Prompt, PromptError},
-    providers::{anthropic, deepseek, gemini, openai, openrouter},
-    streaming::{StreamedAssistantContent, StreamingPrompt},
+    client::{CompletionClient, EmbeddingsClient, Nothing},
+    completion::{
+        message::{ToolResultContent, UserContent},
+        AssistantContent, CompletionError, CompletionModel, Message, Prompt, PromptError, ToolDefinition,
+    },
+    providers::{anthropic, deepseek, gemini, ollama, openai, openrouter},
+    streaming::{StreamedAssistantContent, StreamingChat},
+    OneOrMany,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri_plugin_log::log::{error, warn};
 use thiserror::Error;
 
+use crate::attachments::{
+    image_url_content, inline_image_url_content, resolve_attachments, Attachment, ImageAttachment,
+};
+use crate::logging::ai_debug;
+use crate::model_cache::{ModelListCache, ModelListDiff, MODEL_CACHE};
+use crate::model_capabilities::{model_capabilities, ModelCapabilities};
+use crate::provider_headers::provider_client_builder;
+use crate::provider_trace::PROVIDER_TRACE;
+use crate::rate_limit::{is_transient_status, parse_retry_after_header, retry_after_from_message, RetryPolicy};
+use crate::recent_requests::{PendingRequest, RecentRequests, RequestSource, RECENT_REQUESTS};
+use crate::response_cache::{cache_key, ResponseCache, RESPONSE_CACHE};
+use crate::stream_checkpoint::{Checkpointer, STREAM_CHECKPOINTS};
+
 // Import the EmbeddingModel trait for use in the embeddings method
 use rig::embeddings::{EmbeddingError, EmbeddingModel};
 
@@ -37,6 +59,7 @@ enum ProviderCompletionModel {
     Gemini(rig::providers::gemini::completion::CompletionModel),
     DeepSeek(rig::providers::deepseek::CompletionModel),
     OpenRouter(rig::providers::openrouter::completion::CompletionModel),
+    Ollama(rig::providers::ollama::CompletionModel),
 }
 
 // ============================================================================
@@ -77,13 +100,380 @@ struct OpenRouterModel {
     description: Option<String>,
 }
 
-// Helper to create HTTP client with proper headers
-fn create_http_client() -> Result<Client, RigAgentError> {
-    Client::builder()
+const DEFAULT_HTTP_TIMEOUT_SECS: u64 = 30;
+
+/// DALL·E can take well over the default timeout to render an image
+const IMAGE_GENERATION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Longest a stream may go without a chunk; reasoning models can think for a while first
+const STREAM_STALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Timeout and retry settings for provider HTTP calls
+#[derive(Debug, Clone, Copy)]
+pub struct HttpConfig {
+    /// Whole-request timeout for direct HTTP calls (model lists, moderation)
+    pub timeout: Duration,
+    /// Retries for model fetching and for setting up a stream
+    pub retry: RetryPolicy,
+}
+
+impl HttpConfig {
+    /// Defaults with `AI_HTTP_TIMEOUT_SECS` and `AI_MAX_RETRIES` applied
+    pub fn from_env() -> Self {
+        let timeout_secs = env::var("AI_HTTP_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_HTTP_TIMEOUT_SECS);
+        Self {
+            timeout: Duration::from_secs(timeout_secs),
+            retry: RetryPolicy::from_env(),
+        }
+    }
+}
+
+// Helper to create HTTP client with the provider's configured extra headers
+fn create_http_client(provider: &AIProvider, timeout: Duration) -> Result<Client, RigAgentError> {
+    provider_client_builder(&provider.key())
+        .timeout(timeout)
         .build()
         .map_err(|e| RigAgentError::Other(format!("Failed to create HTTP client: {}", e)))
 }
 
+/// Send `request`, retrying timeouts, dropped connections and transient statuses (429, 5xx)
+///
+/// Waits for the response's `Retry-After` when it has one, otherwise backs off exponentially.
+/// The last response or error is returned once retries run out.
+async fn send_with_retry(request: reqwest::RequestBuilder, retry: &RetryPolicy) -> reqwest::Result<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        // Streaming bodies can't be replayed, so those get a single attempt
+        let Some(this_attempt) = request.try_clone() else {
+            return request.send().await;
+        };
+        let (outcome, retry_after_ms) = match this_attempt.send().await {
+            Ok(response) if is_transient_status(response.status().as_u16()) => {
+                let retry_after_ms = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_retry_after_header);
+                (Ok(response), retry_after_ms)
+            }
+            Err(e) if e.is_timeout() || e.is_connect() => (Err(e), None),
+            outcome => return outcome,
+        };
+
+        let Some(delay) = retry.delay(attempt, retry_after_ms) else {
+            return outcome;
+        };
+        match &outcome {
+            Ok(response) => warn!(
+                "{} returned {}, retrying in {:?}",
+                response.url(),
+                response.status(),
+                delay
+            ),
+            Err(e) => warn!("HTTP request failed ({}), retrying in {:?}", e, delay),
+        }
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Tokenizer for a model id, for OpenAI models and DeepSeek
+fn tokenizer_for_model(model: &str) -> Option<&'static tiktoken_rs::CoreBPE> {
+    // OpenRouter-style ids carry a vendor prefix, e.g. `openai/gpt-4o`
+    let model = model.rsplit('/').next().unwrap_or(model).to_lowercase();
+    if let Ok(bpe) = tiktoken_rs::bpe_for_model(&model) {
+        return Some(bpe);
+    }
+    // DeepSeek's tokenizer isn't published for tiktoken; cl100k is the closest BPE
+    model.starts_with("deepseek").then(tiktoken_rs::cl100k_base_singleton)
+}
+
+fn count_text_tokens(model: &str, text: &str) -> u32 {
+    match tokenizer_for_model(model) {
+        Some(bpe) => bpe.count_ordinary(text) as u32,
+        // Simple approximation: ~4 characters per token
+        None => (text.len() as f32 / 4.0).ceil() as u32,
+    }
+}
+
+/// Map a failed OpenAI REST response to the matching error variant
+async fn openai_error_response(response: reqwest::Response, context: &str) -> RigAgentError {
+    let status = response.status();
+    let retry_after_ms = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after_header);
+    let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+    let message = format!("{} returned {}: {}", context, status, error_text);
+    match status.as_u16() {
+        400 => RigAgentError::InvalidModel(message),
+        401 | 403 => RigAgentError::ApiKeyNotFound(message),
+        429 => RigAgentError::RateLimited {
+            retry_after_ms: retry_after_ms.or_else(|| retry_after_from_message(&message)),
+            message,
+        },
+        _ => RigAgentError::HttpError(message),
+    }
+}
+
+/// Take the single result of an OpenAI moderation response
+fn parse_moderation_response(body: &str) -> Result<ModerationResponse, RigAgentError> {
+    #[derive(Deserialize)]
+    struct ModerationResults {
+        results: Vec<ModerationResponse>,
+    }
+
+    let parsed: ModerationResults = serde_json::from_str(body)
+        .map_err(|e| RigAgentError::Other(format!("Failed to parse OpenAI moderation response: {}", e)))?;
+    parsed
+        .results
+        .into_iter()
+        .next()
+        .ok_or_else(|| RigAgentError::Other("OpenAI moderation returned no results".to_string()))
+}
+
+const IMAGE_MODEL: &str = "dall-e-3";
+const DALL_E_3_SIZES: &[&str] = &["1024x1024", "1792x1024", "1024x1792"];
+
+/// Validate an image request and build the OpenAI images API body for it
+fn image_generation_body(request: &ImageGenerationRequest) -> Result<serde_json::Value, RigAgentError> {
+    if request.prompt.trim().is_empty() {
+        return Err(RigAgentError::InvalidModel("Image prompt cannot be empty".to_string()));
+    }
+
+    // dall-e-3 only generates one image per request; a batch is refused rather than
+    // quietly sent to a different model
+    let n = request.n.unwrap_or(1);
+    if n != 1 {
+        return Err(RigAgentError::InvalidModel(format!(
+            "{} generates one image per request, got n = {}",
+            IMAGE_MODEL, n
+        )));
+    }
+
+    let size = request.size.as_deref().unwrap_or("1024x1024");
+    if !DALL_E_3_SIZES.contains(&size) {
+        return Err(RigAgentError::InvalidModel(format!(
+            "Unsupported image size {} for {} (allowed: {})",
+            size,
+            IMAGE_MODEL,
+            DALL_E_3_SIZES.join(", ")
+        )));
+    }
+
+    let mut body = serde_json::json!({
+        "model": IMAGE_MODEL,
+        "prompt": request.prompt,
+        "n": n,
+        "size": size,
+    });
+
+    if let Some(quality) = request.quality.as_deref() {
+        if !matches!(quality, "standard" | "hd") {
+            return Err(RigAgentError::InvalidModel(format!(
+                "Unsupported image quality {} for {}",
+                quality, IMAGE_MODEL
+            )));
+        }
+        body["quality"] = serde_json::json!(quality);
+    }
+
+    Ok(body)
+}
+
+/// Embedding model used when the caller does not name one; `None` if the provider has no embeddings API
+fn default_embedding_model(provider: &AIProvider) -> Option<&'static str> {
+    match provider {
+        AIProvider::OpenAI => Some(openai::TEXT_EMBEDDING_3_SMALL),
+        AIProvider::Gemini => Some(gemini::embedding::EMBEDDING_004),
+        AIProvider::Ollama => Some(ollama::NOMIC_EMBED_TEXT),
+        AIProvider::Anthropic | AIProvider::DeepSeek | AIProvider::OpenRouter => None,
+    }
+}
+
+/// Default address of a local Ollama server
+const DEFAULT_OLLAMA_HOST: &str = "http://localhost:11434";
+
+// Ollama needs no API key; the server address comes from OLLAMA_HOST
+fn ollama_client(base_url: Option<&str>) -> Result<ollama::Client, RigAgentError> {
+    let host = base_url_override(&AIProvider::Ollama, base_url).unwrap_or_else(|| DEFAULT_OLLAMA_HOST.to_string());
+    ollama::Client::<reqwest::Client>::builder()
+        .api_key(Nothing)
+        .base_url(&host)
+        .http_client(provider_http_client(&AIProvider::Ollama)?)
+        .build()
+        .map_err(|e| RigAgentError::Other(format!("Failed to create Ollama client for {}: {}", host, e)))
+}
+
+// rig clients are built from an HTTP client carrying the provider's extra headers,
+// since rig has no other way to add headers to its requests
+fn provider_http_client(provider: &AIProvider) -> Result<Client, RigAgentError> {
+    provider_client_builder(&provider.key())
+        .build()
+        .map_err(|e| client_error(provider, e))
+}
+
+fn provider_api_key(provider: &AIProvider) -> Result<String, RigAgentError> {
+    let key_env_var = provider.key_env_var().unwrap_or_default();
+    env::var(key_env_var).map_err(|_| RigAgentError::ApiKeyNotFound(key_env_var.to_string()))
+}
+
+/// API key for a request sent to `requested`, else to the provider's configured endpoint
+///
+/// The stored key only goes to the configured endpoint, so a request naming another
+/// host can't collect it; such requests are sent without a key.
+fn provider_api_key_for(provider: &AIProvider, requested: Option<&str>) -> Result<String, RigAgentError> {
+    if sends_elsewhere(provider, requested) {
+        return Ok(String::new());
+    }
+    provider_api_key(provider)
+}
+
+/// Whether `requested` is on a different origin than the provider's configured endpoint
+fn sends_elsewhere(provider: &AIProvider, requested: Option<&str>) -> bool {
+    let Some(requested) = requested.map(normalize_base_url).filter(|url| !url.is_empty()) else {
+        return false;
+    };
+    let origin = |url: &str| reqwest::Url::parse(url).ok().map(|url| url.origin());
+    match origin(&requested) {
+        Some(requested) => Some(requested) != origin(&provider.base_url(None)),
+        None => true,
+    }
+}
+
+fn client_error(provider: &AIProvider, error: impl std::fmt::Display) -> RigAgentError {
+    RigAgentError::Other(format!(
+        "Failed to create {} client: {}",
+        provider.display_name(),
+        error
+    ))
+}
+
+/// Base URL replacing the provider's default: the request's own, else the provider's env var
+fn base_url_override(provider: &AIProvider, requested: Option<&str>) -> Option<String> {
+    requested
+        .map(normalize_base_url)
+        .filter(|base_url| !base_url.is_empty())
+        .or_else(|| provider.api_base())
+}
+
+fn normalize_base_url(base_url: &str) -> String {
+    base_url.trim().trim_end_matches('/').to_string()
+}
+
+/// `request` authorized with `api_key`, or as is when there is no key to send
+fn with_bearer(request: reqwest::RequestBuilder, api_key: &str) -> reqwest::RequestBuilder {
+    if api_key.is_empty() {
+        request
+    } else {
+        request.bearer_auth(api_key)
+    }
+}
+
+fn openai_client(base_url: Option<&str>) -> Result<openai::Client, RigAgentError> {
+    let provider = AIProvider::OpenAI;
+    let mut builder = openai::Client::<reqwest::Client>::builder()
+        .api_key(provider_api_key_for(&provider, base_url)?)
+        .http_client(provider_http_client(&provider)?);
+    if let Some(base_url) = base_url_override(&provider, base_url) {
+        builder = builder.base_url(base_url);
+    }
+    builder.build().map_err(|e| client_error(&provider, e))
+}
+
+fn anthropic_client(base_url: Option<&str>) -> Result<anthropic::Client, RigAgentError> {
+    let provider = AIProvider::Anthropic;
+    let mut builder = anthropic::Client::<reqwest::Client>::builder()
+        .api_key(provider_api_key_for(&provider, base_url)?)
+        .http_client(provider_http_client(&provider)?);
+    if let Some(base_url) = base_url_override(&provider, base_url) {
+        builder = builder.base_url(base_url);
+    }
+    builder.build().map_err(|e| client_error(&provider, e))
+}
+
+fn gemini_client(base_url: Option<&str>) -> Result<gemini::Client, RigAgentError> {
+    let provider = AIProvider::Gemini;
+    let mut builder = gemini::Client::<reqwest::Client>::builder()
+        .api_key(provider_api_key_for(&provider, base_url)?)
+        .http_client(provider_http_client(&provider)?);
+    if let Some(base_url) = base_url_override(&provider, base_url) {
+        builder = builder.base_url(base_url);
+    }
+    builder.build().map_err(|e| client_error(&provider, e))
+}
+
+fn deepseek_client(base_url: Option<&str>) -> Result<deepseek::Client, RigAgentError> {
+    let provider = AIProvider::DeepSeek;
+    let mut builder = deepseek::Client::<reqwest::Client>::builder()
+        .api_key(provider_api_key_for(&provider, base_url)?)
+        .http_client(provider_http_client(&provider)?);
+    if let Some(base_url) = base_url_override(&provider, base_url) {
+        builder = builder.base_url(base_url);
+    }
+    builder.build().map_err(|e| client_error(&provider, e))
+}
+
+fn openrouter_client(base_url: Option<&str>) -> Result<openrouter::Client, RigAgentError> {
+    let provider = AIProvider::OpenRouter;
+    let mut builder = openrouter::Client::<reqwest::Client>::builder()
+        .api_key(provider_api_key_for(&provider, base_url)?)
+        .http_client(provider_http_client(&provider)?);
+    if let Some(base_url) = base_url_override(&provider, base_url) {
+        builder = builder.base_url(base_url);
+    }
+    builder.build().map_err(|e| client_error(&provider, e))
+}
+
+/// Completion model `model` of `provider`, served from `base_url` if given
+fn provider_completion_model(
+    provider: &AIProvider,
+    model: &str,
+    base_url: Option<&str>,
+) -> Result<ProviderCompletionModel, RigAgentError> {
+    let normalized = provider.normalize_model_id(model, base_url);
+    if normalized != model {
+        ai_debug!("[get_completion_model] Using model id '{}' for '{}'", normalized, model);
+    }
+    let model = normalized.as_str();
+    Ok(match provider {
+        AIProvider::OpenAI => ProviderCompletionModel::OpenAI(openai_client(base_url)?.completion_model(model)),
+        AIProvider::Anthropic => {
+            ProviderCompletionModel::Anthropic(anthropic_client(base_url)?.completion_model(model))
+        }
+        AIProvider::Gemini => ProviderCompletionModel::Gemini(gemini_client(base_url)?.completion_model(model)),
+        AIProvider::DeepSeek => {
+            ai_debug!("[get_completion_model] Creating DeepSeek client with model: {}", model);
+            ProviderCompletionModel::DeepSeek(deepseek_client(base_url)?.completion_model(model))
+        }
+        AIProvider::OpenRouter => {
+            ProviderCompletionModel::OpenRouter(openrouter_client(base_url)?.completion_model(model))
+        }
+        AIProvider::Ollama => ProviderCompletionModel::Ollama(ollama_client(base_url)?.completion_model(model)),
+    })
+}
+
+/// OpenRouter vendor prefix for a bare model id, from the model family's naming
+fn model_vendor(model: &str) -> Option<&'static str> {
+    let model = model.to_lowercase();
+    let starts_with_any = |prefixes: &[&str]| prefixes.iter().any(|prefix| model.starts_with(prefix));
+    if starts_with_any(&["gpt-", "chatgpt-", "o1", "o3", "o4", "text-embedding-"]) {
+        Some("openai")
+    } else if starts_with_any(&["claude-"]) {
+        Some("anthropic")
+    } else if starts_with_any(&["gemini-", "gemma-"]) {
+        Some("google")
+    } else if starts_with_any(&["deepseek-"]) {
+        Some("deepseek")
+    } else {
+        None
+    }
+}
+
 // ============================================================================
 // Rig Agent
 // ============================================================================
@@ -91,6 +481,9 @@ fn create_http_client() -> Result<Client, RigAgentError> {
 pub struct RigAgent {
     provider: AIProvider,
     default_model: String,
+    cache: Arc<ResponseCache>,
+    models: Arc<ModelListCache>,
+    http: HttpConfig,
 }
 
 impl RigAgent {
@@ -104,6 +497,9 @@ impl RigAgent {
         Ok(Self {
             provider,
             default_model,
+            cache: RESPONSE_CACHE.clone(),
+            models: MODEL_CACHE.clone(),
+            http: HttpConfig::from_env(),
         })
     }
 
@@ -116,54 +512,53 @@ impl RigAgent {
         Ok(Self {
             provider,
             default_model,
+            cache: RESPONSE_CACHE.clone(),
+            models: MODEL_CACHE.clone(),
+            http: HttpConfig::from_env(),
         })
     }
 
+    /// Agents for the default provider, or for every provider with credentials configured
+    pub fn configured(all_providers: bool) -> Vec<Self> {
+        if !all_providers {
+            return Self::new().into_iter().collect();
+        }
+        AIProvider::ALL
+            .into_iter()
+            .filter_map(|provider| Self::with_provider(provider).ok())
+            .collect()
+    }
+
+    /// Lowercase provider name, used as the model list cache key
+    pub fn provider_key(&self) -> String {
+        self.provider.key()
+    }
+
     fn verify_api_key(provider: &AIProvider) -> Result<(), RigAgentError> {
-        match provider {
-            AIProvider::OpenAI => {
-                env::var("OPENAI_API_KEY").map_err(|e| RigAgentError::ApiKeyNotFound(e.to_string()))?;
-            }
-            AIProvider::Anthropic => {
-                env::var("ANTHROPIC_API_KEY").map_err(|e| RigAgentError::ApiKeyNotFound(e.to_string()))?;
-            }
-            AIProvider::Gemini => {
-                env::var("GEMINI_API_KEY").map_err(|e| RigAgentError::ApiKeyNotFound(e.to_string()))?;
-            }
-            AIProvider::DeepSeek => {
-                env::var("DEEPSEEK_API_KEY").map_err(|e| RigAgentError::ApiKeyNotFound(e.to_string()))?;
-            }
-            AIProvider::OpenRouter => {
-                env::var("OPENROUTER_API_KEY").map_err(|e| RigAgentError::ApiKeyNotFound(e.to_string()))?;
-            }
-            AIProvider::Ollama => {
-                // Ollama doesn't need an API key
-            }
+        // Ollama doesn't need an API key
+        if let Some(key_env_var) = provider.key_env_var() {
+            env::var(key_env_var).map_err(|e| RigAgentError::ApiKeyNotFound(e.to_string()))?;
         }
         Ok(())
     }
 
     /// Resolve provider from request options, fallback to instance provider
     fn resolve_provider(&self, options: &AIOptions) -> AIProvider {
-        if let Some(provider_str) = &options.provider {
-            match provider_str.to_lowercase().as_str() {
-                "openai" => AIProvider::OpenAI,
-                "anthropic" | "claude" => AIProvider::Anthropic,
-                "gemini" | "google" => AIProvider::Gemini,
-                "deepseek" => AIProvider::DeepSeek,
-                "openrouter" => AIProvider::OpenRouter,
-                "ollama" => AIProvider::Ollama,
-                _ => {
-                    eprintln!(
-                        "[resolve_provider] Unknown provider '{}', using instance provider",
-                        provider_str
-                    );
-                    self.provider.clone()
-                }
-            }
-        } else {
-            self.provider.clone()
-        }
+        self.provider_override(options.provider.as_deref())
+    }
+
+    /// Provider named by a per-request override, fallback to instance provider
+    fn provider_override(&self, provider: Option<&str>) -> AIProvider {
+        let Some(provider_str) = provider else {
+            return self.provider;
+        };
+        AIProvider::parse(provider_str).unwrap_or_else(|| {
+            warn!(
+                "[resolve_provider] Unknown provider '{}', using instance provider",
+                provider_str
+            );
+            self.provider
+        })
     }
 
     fn resolve_model(&self, options: &AIOptions) -> (AIProvider, String) {
@@ -178,51 +573,13 @@ impl RigAgent {
         &self,
         provider: &AIProvider,
         model: &str,
+        base_url: Option<&str>,
     ) -> Result<ProviderCompletionModel, RigAgentError> {
-        match provider {
-            AIProvider::OpenAI => {
-                let client = openai::Client::from_env();
-                Ok(ProviderCompletionModel::OpenAI(client.completion_model(model)))
-            }
-            AIProvider::Anthropic => {
-                let client = anthropic::Client::from_env();
-                Ok(ProviderCompletionModel::Anthropic(client.completion_model(model)))
-            }
-            AIProvider::Gemini => {
-                let client = gemini::Client::from_env();
-                Ok(ProviderCompletionModel::Gemini(client.completion_model(model)))
-            }
-            AIProvider::DeepSeek => {
-                println!("[get_completion_model] Creating DeepSeek client with model: {}", model);
-                let api_key = env::var("DEEPSEEK_API_KEY").map_err(|e| {
-                    eprintln!("[get_completion_model] DEEPSEEK_API_KEY not found: {}", e);
-                    RigAgentError::ApiKeyNotFound(e.to_string())
-                })?;
-                println!(
-                    "[get_completion_model] DEEPSEEK_API_KEY found (length: {})",
-                    api_key.len()
-                );
-
-                let client = deepseek::Client::new(&api_key).map_err(|e| {
-                    eprintln!("[get_completion_model] Failed to create DeepSeek client: {}", e);
-                    RigAgentError::Other(format!("Failed to create DeepSeek client: {}", e))
-                })?;
-
-                println!("[get_completion_model] DeepSeek client created successfully, getting completion model");
-                let completion_model = client.completion_model(model);
-                println!("[get_completion_model] DeepSeek completion model created");
-                Ok(ProviderCompletionModel::DeepSeek(completion_model))
-            }
-            AIProvider::OpenRouter => {
-                let client = openrouter::Client::from_env();
-                Ok(ProviderCompletionModel::OpenRouter(client.completion_model(model)))
-            }
-            AIProvider::Ollama => Err(RigAgentError::NotSupported("Ollama not yet implemented".to_string())),
-        }
+        provider_completion_model(provider, model, base_url).inspect_err(|e| error!("[get_completion_model] {}", e))
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AIOptions {
     pub prompt: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -239,8 +596,192 @@ pub struct AIOptions {
     pub frequency_penalty: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub presence_penalty: Option<f32>,
+    /// System instruction given to the model ahead of the conversation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// API endpoint replacing the provider's, e.g. an OpenAI-compatible gateway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// Files whose contents are added to the prompt as context
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    /// Reuse a cached response for an identical earlier request (non-streaming only)
+    #[serde(default)]
+    pub cacheable: bool,
+    /// Client-chosen id for a streamed run; its output is checkpointed so the run can be
+    /// continued with `resume_generation` if the stream drops
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Shape `generate` asks the model's output to take; plain text when unset. Streaming,
+    /// chat and tool calls reject a structured format rather than ignore it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+}
+
+/// Reject a structured `response_format` in `call`, which only produces plain text
+fn plain_text_only(options: &AIOptions, call: &str) -> Result<(), RigAgentError> {
+    match &options.response_format {
+        None | Some(ResponseFormat::Text) => Ok(()),
+        Some(_) => Err(RigAgentError::NotSupported(format!(
+            "response_format is only applied by generate, not {}",
+            call
+        ))),
+    }
+}
+
+/// Output format requested from the provider
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "schema", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    /// Any JSON object
+    JsonObject,
+    /// JSON matching the given JSON Schema
+    JsonSchema(serde_json::Value),
+}
+
+/// Request parameters asking `provider` for output in `format`
+///
+/// `None` for plain text and for providers without a structured-output control (Anthropic,
+/// and Ollama, whose extra parameters rig sends as model options); those are instructed
+/// through the system prompt instead, see `structured_system_prompt`.
+fn response_format_params(provider: &AIProvider, format: &ResponseFormat) -> Option<serde_json::Value> {
+    let schema = match format {
+        ResponseFormat::Text => return None,
+        ResponseFormat::JsonObject => None,
+        ResponseFormat::JsonSchema(schema) => Some(schema),
+    };
+    match provider {
+        AIProvider::OpenAI => {
+            // The Responses API only takes schemas, so any object is asked for with an open one
+            let schema = schema
+                .cloned()
+                .unwrap_or_else(|| serde_json::json!({ "type": "object" }));
+            Some(serde_json::json!({
+                "text": {"format": {"type": "json_schema", "name": "response", "schema": schema, "strict": false}}
+            }))
+        }
+        AIProvider::Gemini => {
+            let mut config = serde_json::json!({ "responseMimeType": "application/json" });
+            if let Some(schema) = schema {
+                config["responseJsonSchema"] = schema.clone();
+            }
+            Some(serde_json::json!({ "generationConfig": config }))
+        }
+        AIProvider::OpenRouter => Some(match schema {
+            Some(schema) => serde_json::json!({
+                "response_format": {
+                    "type": "json_schema",
+                    "json_schema": {"name": "response", "schema": schema, "strict": false}
+                }
+            }),
+            None => serde_json::json!({ "response_format": {"type": "json_object"} }),
+        }),
+        // JSON mode takes no schema; it is described in the system prompt
+        AIProvider::DeepSeek => Some(serde_json::json!({ "response_format": {"type": "json_object"} })),
+        AIProvider::Anthropic | AIProvider::Ollama => None,
+    }
+}
+
+/// `system_prompt` plus an instruction to answer in JSON, for providers that can't be asked
+/// for `format` through request parameters or can't be given its schema that way
+///
+/// DeepSeek's JSON mode also requires the prompt itself to ask for JSON.
+fn structured_system_prompt(
+    provider: &AIProvider,
+    system_prompt: Option<&str>,
+    format: Option<&ResponseFormat>,
+) -> Option<String> {
+    let instruction = match format {
+        None | Some(ResponseFormat::Text) => None,
+        Some(format)
+            if response_format_params(provider, format).is_some() && !matches!(provider, AIProvider::DeepSeek) =>
+        {
+            None
+        }
+        Some(ResponseFormat::JsonSchema(schema)) => Some(format!(
+            "Respond with a single JSON value matching this JSON Schema and nothing else:\n{}",
+            schema
+        )),
+        Some(_) => Some("Respond with a single JSON object and nothing else.".to_string()),
+    };
+    match (system_prompt, instruction) {
+        (Some(system_prompt), Some(instruction)) => Some(format!("{}\n\n{}", system_prompt, instruction)),
+        (system_prompt, None) => system_prompt.map(String::from),
+        (None, instruction) => instruction,
+    }
+}
+
+/// Provider and model for a frequent, low-value background task such as search insights
+///
+/// The pair comes from the first level that sets either of them: the requested `provider`
+/// and `model`, then the task's own `AI_<TASK>_PROVIDER` and `AI_<TASK>_MODEL`, then
+/// `AI_BACKGROUND_PROVIDER` and `AI_BACKGROUND_MODEL`, so cheap tasks can be kept off the
+/// premium model used for chat. A model is never paired with another level's provider.
+/// `(None, None)` leaves the choice to the agent.
+pub fn background_model(
+    task: &str,
+    provider: Option<String>,
+    model: Option<String>,
+) -> (Option<String>, Option<String>) {
+    background_model_from(task, provider, model, env_setting)
+}
+
+/// Trimmed value of the environment variable `name`, if set and not blank
+pub fn env_setting(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// `background_model` reading its settings from `setting` instead of the environment
+pub fn background_model_from(
+    task: &str,
+    provider: Option<String>,
+    model: Option<String>,
+    setting: impl Fn(&str) -> Option<String>,
+) -> (Option<String>, Option<String>) {
+    if provider.is_some() || model.is_some() {
+        return (provider, model);
+    }
+    [format!("AI_{}", task), "AI_BACKGROUND".to_string()]
+        .iter()
+        .map(|prefix| {
+            (
+                setting(&format!("{}_PROVIDER", prefix)),
+                setting(&format!("{}_MODEL", prefix)),
+            )
+        })
+        .find(|(provider, model)| provider.is_some() || model.is_some())
+        .unwrap_or((None, None))
+}
+
+/// A tool the model may call during `generate_with_tools`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDef {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the tool's arguments
+    pub parameters: serde_json::Value,
+}
+
+impl From<&ToolDef> for ToolDefinition {
+    fn from(tool: &ToolDef) -> Self {
+        ToolDefinition {
+            name: tool.name.clone(),
+            description: tool.description.clone(),
+            parameters: tool.parameters.clone(),
+        }
+    }
 }
 
+/// Runs a tool call: receives the model's JSON arguments and returns the result text
+pub type ToolHandler = Arc<dyn Fn(serde_json::Value) -> BoxFuture<'static, Result<String, String>> + Send + Sync>;
+
+/// Maximum number of model round-trips in a single `generate_with_tools` call
+pub const MAX_TOOL_TURNS: usize = 8;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AIResponse {
     pub text: String,
@@ -250,6 +791,10 @@ pub struct AIResponse {
     pub model: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub finish_reason: Option<String>,
+    /// The model's reasoning, reported separately from `text` by reasoning models such
+    /// as OpenAI o1; DeepSeek's reasoner only reports it when streamed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -259,6 +804,150 @@ pub struct TokenUsage {
     pub total_tokens: u32,
 }
 
+impl From<rig::completion::Usage> for TokenUsage {
+    fn from(usage: rig::completion::Usage) -> Self {
+        TokenUsage {
+            prompt_tokens: usage.input_tokens as u32,
+            completion_tokens: usage.output_tokens as u32,
+            // Some providers only report the input and output counts
+            total_tokens: if usage.total_tokens > 0 {
+                usage.total_tokens as u32
+            } else {
+                (usage.input_tokens + usage.output_tokens) as u32
+            },
+        }
+    }
+}
+
+/// An item of `generate_stream` output
+#[derive(Debug, Clone)]
+pub enum StreamChunk {
+    Text(String),
+    /// Reasoning the model streams separately from its answer, e.g. DeepSeek's reasoner
+    Reasoning(String),
+    /// Generation finished; always the last item of a successful stream
+    Done(StreamCompletion),
+}
+
+/// Terminal metadata for a completed generation stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamCompletion {
+    pub id: String,
+    pub usage: Option<TokenUsage>,
+    pub finish_reason: Option<String>,
+}
+
+/// A stream of `generate_stream` output
+pub type ChunkStream = Pin<Box<dyn Stream<Item = Result<StreamChunk, RigAgentError>> + Send>>;
+
+/// An interrupted generation picked up from its checkpoint
+pub struct ResumedGeneration {
+    /// Text streamed before the interruption
+    pub partial: String,
+    /// Whether `stream` continues `partial`; when the provider can't continue a
+    /// response, `stream` is empty and only the partial text is returned
+    pub continued: bool,
+    /// Text following `partial`, ending with `StreamChunk::Done`
+    pub stream: ChunkStream,
+}
+
+/// Token usage as reported by the provider, or `None` if it reported nothing
+fn reported_usage(usage: rig::completion::Usage) -> Option<TokenUsage> {
+    // Providers that don't report usage leave every count at zero
+    (usage.total_tokens > 0 || usage.input_tokens > 0 || usage.output_tokens > 0).then(|| TokenUsage::from(usage))
+}
+
+/// A rig request as recorded in the provider trace
+///
+/// rig doesn't expose its HTTP exchange, so the request is described by the options it
+/// was built from and the response by the reply text or error.
+struct TracedRequest {
+    provider: String,
+    url: String,
+    request: serde_json::Value,
+}
+
+impl TracedRequest {
+    fn record(self, response: Option<&str>) {
+        PROVIDER_TRACE.record(&self.provider, &self.url, &self.request, None, response);
+    }
+}
+
+/// Record the outcome of a request started with `RigAgent::start_request` and pass it on
+fn finish_request(
+    request: PendingRequest,
+    trace: Option<TracedRequest>,
+    result: Result<AIResponse, RigAgentError>,
+) -> Result<AIResponse, RigAgentError> {
+    if let Some(trace) = trace {
+        match &result {
+            Ok(response) => trace.record(Some(&response.text)),
+            Err(e) => trace.record(Some(&e.to_string())),
+        }
+    }
+    match &result {
+        Ok(response) => RECENT_REQUESTS.finish(request, response.usage.clone(), None),
+        Err(e) => RECENT_REQUESTS.finish(request, None, Some(e.to_string())),
+    }
+    result
+}
+
+/// Pass `stream` through, recording it in `requests` once it completes, fails or is dropped
+///
+/// The usage comes from the final `StreamChunk::Done`.
+fn record_stream(requests: &'static RecentRequests, request: PendingRequest, mut stream: ChunkStream) -> ChunkStream {
+    Box::pin(async_stream::stream! {
+        let mut request = requests.guard(request);
+        while let Some(item) = stream.next().await {
+            match &item {
+                Ok(StreamChunk::Done(completion)) => request.finish(completion.usage.clone(), None),
+                Err(e) => request.finish(None, Some(e.to_string())),
+                Ok(_) => {}
+            }
+            yield item;
+        }
+    })
+}
+
+/// `length` when the output used up the `max_tokens` limit, otherwise `stop`
+///
+/// rig doesn't pass the provider's own finish reason through, so a truncated
+/// response is recognized by its output token count.
+fn finish_reason(usage: &rig::completion::Usage, max_tokens: Option<u64>) -> String {
+    match max_tokens {
+        Some(limit) if usage.output_tokens >= limit => "length".to_string(),
+        _ => "stop".to_string(),
+    }
+}
+
+/// Reasoning in the assistant turns of `messages`, or `None` if the model reported none
+fn message_reasoning(messages: &[Message]) -> Option<String> {
+    let reasoning: Vec<String> = messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::Assistant { content, .. } => Some(content.iter()),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|content| match content {
+            AssistantContent::Reasoning(reasoning) => Some(reasoning.reasoning.join("\n")),
+            _ => None,
+        })
+        .filter(|reasoning| !reasoning.is_empty())
+        .collect();
+    (!reasoning.is_empty()).then(|| reasoning.join("\n\n"))
+}
+
+impl StreamChunk {
+    fn done(usage: rig::completion::Usage, max_tokens: Option<u64>) -> Self {
+        StreamChunk::Done(StreamCompletion {
+            id: uuid::Uuid::new_v4().to_string(),
+            finish_reason: Some(finish_reason(&usage, max_tokens)),
+            usage: reported_usage(usage),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub role: String,
@@ -270,11 +959,20 @@ pub struct EmbeddingRequest {
     pub text: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub model: Option<String>,
+    /// Provider for this request, overriding the agent's provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModerationRequest {
     pub content: String,
+    /// Provider for this request, overriding the agent's provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// API endpoint replacing OpenAI's, e.g. an OpenAI-compatible gateway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -293,14 +991,24 @@ pub struct ImageGenerationRequest {
     pub quality: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub n: Option<u32>,
+    /// Provider for this request, overriding the agent's provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// API endpoint replacing OpenAI's, e.g. an OpenAI-compatible gateway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageAnalysisRequest {
+    /// `http(s)` URL of the image, or the image itself as a base64 `data:` URL
     pub image_url: String,
     pub prompt: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub model: Option<String>,
+    /// Provider for this request, overriding the agent's provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -316,37 +1024,229 @@ pub struct ModelInfo {
     pub name: String,
     pub description: String,
     pub context_length: usize,
+    #[serde(flatten)]
+    pub capabilities: ModelCapabilities,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum AIProvider {
-    OpenAI,
-    Anthropic,
-    Gemini,
-    Ollama,
-    DeepSeek,
-    OpenRouter,
-}
-
-impl AIProvider {
-    pub fn from_env() -> Self {
-        if env::var("OPENAI_API_KEY").is_ok() {
-            AIProvider::OpenAI
-        } else if env::var("ANTHROPIC_API_KEY").is_ok() {
-            AIProvider::Anthropic
-        } else if env::var("GEMINI_API_KEY").is_ok() {
-            AIProvider::Gemini
-        } else if env::var("DEEPSEEK_API_KEY").is_ok() {
-            AIProvider::DeepSeek
-        } else if env::var("OPENROUTER_API_KEY").is_ok() {
-            AIProvider::OpenRouter
-        } else {
-            // Default to OpenAI - will fail if no key
-            AIProvider::OpenAI
+impl ModelInfo {
+    /// A model whose capabilities are looked up from its id
+    pub fn new(
+        id: impl Into<String>,
+        name: impl Into<String>,
+        description: impl Into<String>,
+        context_length: usize,
+    ) -> Self {
+        let id = id.into();
+        Self {
+            capabilities: model_capabilities(&id),
+            id,
+            name: name.into(),
+            description: description.into(),
+            context_length,
         }
     }
+}
 
-    pub fn default_model(&self) -> String {
+/// A provider's configuration requirements and defaults, for the settings UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderDescriptor {
+    /// Lowercase id accepted in request `provider` fields
+    pub id: String,
+    pub display_name: String,
+    pub key_env_var: Option<String>,
+    pub requires_key: bool,
+    pub default_model: String,
+    pub default_base_url: String,
+    /// Environment variable overriding the base URL, for providers that read one
+    pub base_url_env_var: Option<String>,
+    pub capabilities: Vec<String>,
+}
+
+/// A provider's default model with the context window and capabilities a new chat budgets for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderDefault {
+    pub model: String,
+    pub context_length: usize,
+    pub capabilities: ModelCapabilities,
+}
+
+/// Descriptors for every provider the agent can talk to, in `AIProvider::ALL` order
+pub fn supported_providers() -> Vec<ProviderDescriptor> {
+    AIProvider::ALL.iter().map(AIProvider::descriptor).collect()
+}
+
+/// Context window assumed for a model no list reports one for, small enough not to
+/// overrun the window of whatever model it turns out to be
+const FALLBACK_CONTEXT_LENGTH: usize = 8192;
+
+/// `provider`'s default model, described without a network call
+///
+/// The context window is the one the provider reported in its cached model list, else
+/// the known model lists', else [`FALLBACK_CONTEXT_LENGTH`].
+pub fn provider_default(provider: &AIProvider) -> ProviderDefault {
+    let cached = MODEL_CACHE.get(&provider.key()).map(|cached| cached.models);
+    describe_provider_default(provider, cached.as_deref())
+}
+
+fn describe_provider_default(provider: &AIProvider, cached: Option<&[ModelInfo]>) -> ProviderDefault {
+    let model = provider.default_model();
+    let known = RigAgent::get_known_models(provider);
+    let context_length = cached
+        .unwrap_or_default()
+        .iter()
+        .chain(&known)
+        .find(|listed| listed.id == model)
+        .map(|listed| listed.context_length)
+        .unwrap_or_else(|| {
+            warn!(
+                "No context window known for {} model {}, assuming {} tokens",
+                provider.key(),
+                model,
+                FALLBACK_CONTEXT_LENGTH
+            );
+            FALLBACK_CONTEXT_LENGTH
+        });
+    ProviderDefault {
+        capabilities: model_capabilities(&model),
+        model,
+        context_length,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AIProvider {
+    OpenAI,
+    Anthropic,
+    Gemini,
+    Ollama,
+    DeepSeek,
+    OpenRouter,
+}
+
+impl AIProvider {
+    pub const ALL: [AIProvider; 6] = [
+        AIProvider::OpenAI,
+        AIProvider::Anthropic,
+        AIProvider::Gemini,
+        AIProvider::Ollama,
+        AIProvider::DeepSeek,
+        AIProvider::OpenRouter,
+    ];
+
+    /// The first provider, in `ALL` order, whose API key is set
+    pub fn from_env() -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|provider| provider.has_api_key())
+            // Default to OpenAI - will fail if no key
+            .unwrap_or(AIProvider::OpenAI)
+    }
+
+    /// Environment variable holding the provider's API key, `None` if it needs none
+    pub fn key_env_var(&self) -> Option<&'static str> {
+        match self {
+            AIProvider::OpenAI => Some("OPENAI_API_KEY"),
+            AIProvider::Anthropic => Some("ANTHROPIC_API_KEY"),
+            AIProvider::Gemini => Some("GEMINI_API_KEY"),
+            AIProvider::DeepSeek => Some("DEEPSEEK_API_KEY"),
+            AIProvider::OpenRouter => Some("OPENROUTER_API_KEY"),
+            AIProvider::Ollama => None,
+        }
+    }
+
+    /// Whether the provider needs an API key and it is set
+    pub fn has_api_key(&self) -> bool {
+        self.key_env_var().is_some_and(|var| env::var(var).is_ok())
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            AIProvider::OpenAI => "OpenAI",
+            AIProvider::Anthropic => "Anthropic",
+            AIProvider::Gemini => "Gemini",
+            AIProvider::Ollama => "Ollama",
+            AIProvider::DeepSeek => "DeepSeek",
+            AIProvider::OpenRouter => "OpenRouter",
+        }
+    }
+
+    /// API endpoint requests go to unless overridden
+    pub fn default_base_url(&self) -> String {
+        match self {
+            AIProvider::OpenAI => "https://api.openai.com/v1".to_string(),
+            AIProvider::Anthropic => "https://api.anthropic.com".to_string(),
+            AIProvider::Gemini => "https://generativelanguage.googleapis.com".to_string(),
+            AIProvider::Ollama => DEFAULT_OLLAMA_HOST.to_string(),
+            AIProvider::DeepSeek => "https://api.deepseek.com/v1".to_string(),
+            AIProvider::OpenRouter => "https://openrouter.ai/api/v1".to_string(),
+        }
+    }
+
+    /// Environment variable overriding the base URL, e.g. to point OpenAI at a LiteLLM gateway
+    pub fn base_url_env_var(&self) -> &'static str {
+        match self {
+            AIProvider::OpenAI => "OPENAI_BASE_URL",
+            AIProvider::Anthropic => "ANTHROPIC_BASE_URL",
+            AIProvider::Gemini => "GEMINI_BASE_URL",
+            AIProvider::Ollama => "OLLAMA_HOST",
+            AIProvider::DeepSeek => "DEEPSEEK_BASE_URL",
+            AIProvider::OpenRouter => "OPENROUTER_BASE_URL",
+        }
+    }
+
+    /// API endpoint in use: a request's own `base_url`, else the env override, else the default
+    pub fn base_url(&self, requested: Option<&str>) -> String {
+        base_url_override(self, requested).unwrap_or_else(|| self.default_base_url())
+    }
+
+    /// Features the provider supports, e.g. `embeddings` or `resume`
+    pub fn capabilities(&self) -> Vec<&'static str> {
+        let mut capabilities = vec!["chat", "streaming", "tools"];
+        if default_embedding_model(self).is_some() {
+            capabilities.push("embeddings");
+        }
+        if matches!(self, AIProvider::OpenAI) {
+            capabilities.extend(["moderation", "image_generation"]);
+        }
+        if self.supports_continuation() {
+            capabilities.push("resume");
+        }
+        capabilities
+    }
+
+    /// Everything a settings form needs to configure the provider
+    pub fn descriptor(&self) -> ProviderDescriptor {
+        ProviderDescriptor {
+            id: self.key(),
+            display_name: self.display_name().to_string(),
+            key_env_var: self.key_env_var().map(str::to_string),
+            requires_key: self.key_env_var().is_some(),
+            default_model: self.default_model(),
+            default_base_url: self.default_base_url(),
+            base_url_env_var: Some(self.base_url_env_var().to_string()),
+            capabilities: self.capabilities().into_iter().map(str::to_string).collect(),
+        }
+    }
+
+    /// Parse a provider name as accepted in request `provider` fields
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "openai" => Some(AIProvider::OpenAI),
+            "anthropic" | "claude" => Some(AIProvider::Anthropic),
+            "gemini" | "google" => Some(AIProvider::Gemini),
+            "deepseek" => Some(AIProvider::DeepSeek),
+            "openrouter" => Some(AIProvider::OpenRouter),
+            "ollama" => Some(AIProvider::Ollama),
+            _ => None,
+        }
+    }
+
+    /// Lowercase provider name
+    pub fn key(&self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+
+    pub fn default_model(&self) -> String {
         match self {
             AIProvider::OpenAI => "gpt-4o-mini".to_string(),
             AIProvider::Anthropic => "claude-3-5-sonnet-20241022".to_string(),
@@ -357,13 +1257,65 @@ impl AIProvider {
         }
     }
 
-    pub fn api_base(&self) -> Option<String> {
+    /// Vendor prefixes a model id may carry when it names one of this provider's own models,
+    /// e.g. `openai/gpt-4o` as written for OpenRouter
+    fn model_id_prefixes(&self) -> &'static [&'static str] {
         match self {
-            AIProvider::DeepSeek => Some("https://api.deepseek.com/v1".to_string()),
-            AIProvider::OpenRouter => Some("https://openrouter.ai/api/v1".to_string()),
-            _ => None,
+            AIProvider::OpenAI => &["openai"],
+            AIProvider::Anthropic => &["anthropic"],
+            AIProvider::Gemini => &["google", "gemini"],
+            AIProvider::DeepSeek => &["deepseek"],
+            AIProvider::Ollama => &["ollama"],
+            AIProvider::OpenRouter => &[],
+        }
+    }
+
+    /// Model id in the form the provider expects: direct providers drop their own vendor
+    /// prefix, OpenRouter gets one added to bare ids it can attribute. Anything else is
+    /// passed through unchanged.
+    ///
+    /// The prefix is only dropped on the provider's own endpoint. Behind another `base_url`
+    /// (a LiteLLM gateway, say) it may be part of the id, so the id is left as given.
+    pub fn normalize_model_id(&self, model: &str, base_url: Option<&str>) -> String {
+        let model = model.trim();
+        if matches!(self, AIProvider::OpenRouter) {
+            return match model_vendor(model) {
+                Some(vendor) if !model.contains('/') => format!("{}/{}", vendor, model),
+                _ => model.to_string(),
+            };
+        }
+
+        match model.split_once('/') {
+            Some((prefix, id))
+                if self.is_native_endpoint(base_url)
+                    && self.model_id_prefixes().contains(&prefix.to_lowercase().as_str()) =>
+            {
+                id.to_string()
+            }
+            _ => model.to_string(),
         }
     }
+
+    /// Whether requests with `base_url` go to the provider's own API rather than a compatible one
+    ///
+    /// Any Ollama host is an Ollama server, so only the hosted APIs' URLs are compared.
+    fn is_native_endpoint(&self, base_url: Option<&str>) -> bool {
+        matches!(self, AIProvider::Ollama) || self.base_url(base_url) == normalize_base_url(&self.default_base_url())
+    }
+
+    /// Whether the provider continues a trailing assistant message (prefill) rather than
+    /// answering it, which is what resuming an interrupted stream relies on
+    pub fn supports_continuation(&self) -> bool {
+        matches!(self, AIProvider::Anthropic)
+    }
+
+    /// Base URL set in `base_url_env_var`, `None` to use the default
+    pub fn api_base(&self) -> Option<String> {
+        env::var(self.base_url_env_var())
+            .ok()
+            .map(|base_url| normalize_base_url(&base_url))
+            .filter(|base_url| !base_url.is_empty())
+    }
 }
 
 #[derive(Debug, Error)]
@@ -378,8 +1330,23 @@ pub enum RigAgentError {
     NotSupported(String),
     #[error("Request failed: {0}")]
     RequestFailed(String),
+    #[error("Context too long: {0}")]
+    ContextTooLong(String),
+    #[error("Tool error: {0}")]
+    ToolError(String),
+    #[error("Invalid attachment: {0}")]
+    InvalidAttachment(String),
+    #[error("Invalid image: {0}")]
+    InvalidImage(String),
+    #[error("No checkpoint for generation: {0}")]
+    CheckpointNotFound(String),
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after_ms: Option<u64>,
+    },
     #[error("Prompt error: {0}")]
-    PromptError(#[from] PromptError),
+    PromptError(PromptError),
     #[error("Embedding error: {0}")]
     EmbeddingError(#[from] EmbeddingError),
     #[error("HTTP error: {0}")]
@@ -398,6 +1365,119 @@ impl From<env::VarError> for RigAgentError {
     }
 }
 
+/// Provider messages that indicate the prompt exceeded the model's context window
+const CONTEXT_LENGTH_MARKERS: &[&str] = &[
+    "context_length_exceeded",
+    "maximum context length",
+    "context window",
+    "prompt is too long",
+    "too many tokens",
+    "input is too long",
+];
+
+/// Provider messages that indicate a missing or rejected API key
+const AUTH_MARKERS: &[&str] = &[
+    "invalid_api_key",
+    "invalid api key",
+    "incorrect api key",
+    "api key not valid",
+    "authentication",
+    "unauthorized",
+];
+
+fn message_matches(message: &str, markers: &[&str]) -> bool {
+    let message = message.to_lowercase();
+    markers.iter().any(|marker| message.contains(marker))
+}
+
+impl From<PromptError> for RigAgentError {
+    fn from(err: PromptError) -> Self {
+        let message = err.to_string();
+
+        match err {
+            PromptError::CompletionError(CompletionError::HttpError(http_err)) => {
+                let status = match &http_err {
+                    rig::http_client::Error::InvalidStatusCode(status)
+                    | rig::http_client::Error::InvalidStatusCodeWithMessage(status, _) => Some(status.as_u16()),
+                    _ => None,
+                };
+
+                match status {
+                    Some(401) | Some(403) => RigAgentError::ApiKeyNotFound(message),
+                    Some(413) => RigAgentError::ContextTooLong(message),
+                    Some(429) => RigAgentError::RateLimited {
+                        retry_after_ms: retry_after_from_message(&message),
+                        message,
+                    },
+                    _ if message_matches(&message, CONTEXT_LENGTH_MARKERS) => RigAgentError::ContextTooLong(message),
+                    _ => RigAgentError::HttpError(message),
+                }
+            }
+            PromptError::CompletionError(CompletionError::JsonError(json_err)) => RigAgentError::JsonError(json_err),
+            PromptError::CompletionError(CompletionError::ProviderError(_))
+            | PromptError::CompletionError(CompletionError::ResponseError(_))
+                if message_matches(&message, CONTEXT_LENGTH_MARKERS) =>
+            {
+                RigAgentError::ContextTooLong(message)
+            }
+            PromptError::CompletionError(CompletionError::ProviderError(_))
+                if message_matches(&message, AUTH_MARKERS) =>
+            {
+                RigAgentError::ApiKeyNotFound(message)
+            }
+            PromptError::ToolError(_) | PromptError::ToolServerError(_) => RigAgentError::ToolError(message),
+            other => RigAgentError::PromptError(other),
+        }
+    }
+}
+
+/// A failed streaming attempt, and whether retrying it might succeed
+struct StreamFailure {
+    error: RigAgentError,
+    transient: bool,
+    retry_after_ms: Option<u64>,
+}
+
+impl StreamFailure {
+    /// Classify an error from rig's agent stream (its error type isn't exported, so the
+    /// underlying `CompletionError` is reached through `source()`)
+    fn from_stream_error<E: std::error::Error + 'static>(err: E) -> Self {
+        let message = err.to_string();
+        let completion = err.source().and_then(|source| source.downcast_ref::<CompletionError>());
+        let status = match completion {
+            Some(CompletionError::HttpError(
+                rig::http_client::Error::InvalidStatusCode(status)
+                | rig::http_client::Error::InvalidStatusCodeWithMessage(status, _),
+            )) => Some(status.as_u16()),
+            _ => None,
+        };
+        let transient = match completion {
+            Some(CompletionError::HttpError(
+                rig::http_client::Error::StreamEnded | rig::http_client::Error::Instance(_),
+            )) => true,
+            _ => status.is_some_and(is_transient_status),
+        };
+
+        Self {
+            retry_after_ms: (status == Some(429))
+                .then(|| retry_after_from_message(&message))
+                .flatten(),
+            error: RigAgentError::Other(message),
+            transient,
+        }
+    }
+}
+
+impl From<RigAgentError> for StreamFailure {
+    fn from(error: RigAgentError) -> Self {
+        Self {
+            error,
+            transient: false,
+            retry_after_ms: None,
+        }
+    }
+}
+
 // ========================================================================
 // Rig Agent
 // ============================================================================
@@ -407,372 +1487,649 @@ impl RigAgent {
     // Text Generation
     // ========================================================================
 
-    /// Generate text using AgentBuilder::new() pattern
+    /// Generate text, serving `cacheable` requests from the response cache when possible
     pub async fn generate(&self, options: AIOptions) -> Result<AIResponse, RigAgentError> {
+        // Attached files may change between calls, so those requests always hit the provider
+        if !options.cacheable || !options.attachments.is_empty() {
+            return self.generate_recorded(options).await;
+        }
+
+        let (provider, model) = self.resolve_model(&options);
+        let key = cache_key(&provider, &model, &options);
+        self.cache
+            .get_or_try_insert_with(key, || self.generate_recorded(options))
+            .await
+    }
+
+    /// `generate_uncached`, recorded in the recent requests feed
+    async fn generate_recorded(&self, options: AIOptions) -> Result<AIResponse, RigAgentError> {
+        let request = self.start_request(&options, &options.prompt);
+        let trace = self.trace_request(&options, None);
+        finish_request(request, trace, self.generate_uncached(options).await)
+    }
+
+    /// Start recording a request made with `options` in the recent requests feed
+    fn start_request(&self, options: &AIOptions, prompt: &str) -> PendingRequest {
+        let (provider, model) = self.resolve_model(options);
+        RECENT_REQUESTS.start(RequestSource::Ai, &provider.key(), Some(&model), prompt)
+    }
+
+    /// Describe a request made with `options` for the provider trace; `None` unless it is enabled
+    fn trace_request(&self, options: &AIOptions, messages: Option<&[ChatMessage]>) -> Option<TracedRequest> {
+        if !PROVIDER_TRACE.is_enabled() {
+            return None;
+        }
+        let (provider, model) = self.resolve_model(options);
+        let mut request = serde_json::json!({ "model": model, "options": options });
+        if let Some(messages) = messages {
+            request["messages"] = serde_json::to_value(messages).unwrap_or_default();
+        }
+        Some(TracedRequest {
+            provider: provider.key(),
+            url: provider.base_url(options.base_url.as_deref()),
+            request,
+        })
+    }
+
+    /// Generate text using AgentBuilder::new() pattern
+    async fn generate_uncached(&self, options: AIOptions) -> Result<AIResponse, RigAgentError> {
         let (provider, model) = self.resolve_model(&options);
         let temperature = options.temperature.map(|t| t as f64);
         let max_tokens = options.max_tokens.map(|t| t as u64);
+        let structured_prompt = structured_system_prompt(
+            &provider,
+            options.system_prompt.as_deref(),
+            options.response_format.as_ref(),
+        );
+        let system_prompt = structured_prompt.as_deref();
+        let format_params = options
+            .response_format
+            .as_ref()
+            .and_then(|format| response_format_params(&provider, format));
 
         // Get completion model for specified provider
-        let completion_model = self.get_completion_model(&provider, &model)?;
+        let completion_model = self.get_completion_model(&provider, &model, options.base_url.as_deref())?;
+        let prompt = Self::prompt_message(&options, &model)?;
+
+        // The prompt and the model's reply are recorded here, reasoning included
+        let mut history = Vec::new();
 
         // Build agent and call prompt
-        let text = match completion_model {
+        let response = match completion_model {
             ProviderCompletionModel::OpenAI(model) => {
                 let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
                     builder = builder.temperature(temp);
                 }
                 if let Some(tokens) = max_tokens {
                     builder = builder.max_tokens(tokens);
                 }
-                builder.build().prompt(&options.prompt).await?
+                if let Some(params) = format_params.clone() {
+                    builder = builder.additional_params(params);
+                }
+                builder
+                    .build()
+                    .prompt(prompt)
+                    .with_history(&mut history)
+                    .extended_details()
+                    .await?
             }
             ProviderCompletionModel::Anthropic(model) => {
                 // Anthropic requires max_tokens
                 let tokens = max_tokens.unwrap_or(4096);
                 let mut builder = AgentBuilder::new(model).max_tokens(tokens);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
                     builder = builder.temperature(temp);
                 }
-                builder.build().prompt(&options.prompt).await?
+                builder
+                    .build()
+                    .prompt(prompt)
+                    .with_history(&mut history)
+                    .extended_details()
+                    .await?
             }
             ProviderCompletionModel::Gemini(model) => {
                 let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
                     builder = builder.temperature(temp);
                 }
                 if let Some(tokens) = max_tokens {
                     builder = builder.max_tokens(tokens);
                 }
-                builder.build().prompt(&options.prompt).await?
+                if let Some(params) = format_params.clone() {
+                    builder = builder.additional_params(params);
+                }
+                builder
+                    .build()
+                    .prompt(prompt)
+                    .with_history(&mut history)
+                    .extended_details()
+                    .await?
             }
             ProviderCompletionModel::DeepSeek(model) => {
-                println!("[generate] Building DeepSeek agent for prompt generation");
+                ai_debug!("[generate] Building DeepSeek agent for prompt generation");
                 let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
-                    println!("[generate] Setting temperature: {}", temp);
+                    ai_debug!("[generate] Setting temperature: {}", temp);
                     builder = builder.temperature(temp);
                 }
                 if let Some(tokens) = max_tokens {
-                    println!("[generate] Setting max_tokens: {}", tokens);
+                    ai_debug!("[generate] Setting max_tokens: {}", tokens);
                     builder = builder.max_tokens(tokens);
                 }
-                builder.build().prompt(&options.prompt).await?
+                if let Some(params) = format_params.clone() {
+                    builder = builder.additional_params(params);
+                }
+                builder
+                    .build()
+                    .prompt(prompt)
+                    .with_history(&mut history)
+                    .extended_details()
+                    .await?
             }
             ProviderCompletionModel::OpenRouter(model) => {
                 let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
+                if let Some(temp) = temperature {
+                    builder = builder.temperature(temp);
+                }
+                if let Some(tokens) = max_tokens {
+                    builder = builder.max_tokens(tokens);
+                }
+                if let Some(params) = format_params.clone() {
+                    builder = builder.additional_params(params);
+                }
+                builder
+                    .build()
+                    .prompt(prompt)
+                    .with_history(&mut history)
+                    .extended_details()
+                    .await?
+            }
+            ProviderCompletionModel::Ollama(model) => {
+                let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
                     builder = builder.temperature(temp);
                 }
                 if let Some(tokens) = max_tokens {
                     builder = builder.max_tokens(tokens);
                 }
-                builder.build().prompt(&options.prompt).await?
+                builder
+                    .build()
+                    .prompt(prompt)
+                    .with_history(&mut history)
+                    .extended_details()
+                    .await?
             }
         };
 
         Ok(AIResponse {
-            text,
+            text: response.output,
             model: Some(model),
-            usage: None,
-            finish_reason: Some("stop".to_string()),
+            finish_reason: Some(finish_reason(&response.total_usage, max_tokens)),
+            usage: reported_usage(response.total_usage),
+            reasoning: message_reasoning(&history),
+        })
+    }
+
+    /// Build the user message for a request, inlining text attachments and adding
+    /// image attachments as image parts when the model supports them
+    fn prompt_message(options: &AIOptions, model: &str) -> Result<Message, RigAgentError> {
+        if options.attachments.is_empty() {
+            return Ok(Message::user(&options.prompt));
+        }
+
+        let resolved = resolve_attachments(
+            &options.prompt,
+            &options.attachments,
+            model_capabilities(model).supports_vision,
+        )
+        .map_err(RigAgentError::InvalidAttachment)?;
+        let mut content = vec![UserContent::text(resolved.text)];
+        content.extend(resolved.images.into_iter().map(ImageAttachment::into_content));
+
+        Ok(Message::User {
+            content: OneOrMany::many(content).map_err(|e| RigAgentError::Other(e.to_string()))?,
         })
     }
 
     /// Stream text generation using rig's built-in streaming support
-    /// Returns a stream of text chunks
-    pub fn generate_stream(
-        &self,
-        options: AIOptions,
-    ) -> Pin<Box<dyn Stream<Item = Result<String, RigAgentError>> + Send>> {
-        use tokio::sync::mpsc;
-        use tokio_stream::wrappers::ReceiverStream;
+    /// Returns a stream of text chunks, ending with `StreamChunk::Done` when generation completes
+    ///
+    /// With `options.request_id` set, the text produced so far is checkpointed so an
+    /// interrupted run can be continued with `resume_generation`. Dropping the stream
+    /// cancels the provider request. The stream is recorded in the recent requests feed.
+    pub fn generate_stream(&self, options: AIOptions) -> ChunkStream {
+        let request = self.start_request(&options, &options.prompt);
+        if let Some(trace) = self.trace_request(&options, None) {
+            trace.record(None);
+        }
+        record_stream(&RECENT_REQUESTS, request, self.generate_stream_unrecorded(options))
+    }
 
+    fn generate_stream_unrecorded(&self, options: AIOptions) -> ChunkStream {
+        if let Err(e) = plain_text_only(&options, "generate_stream") {
+            return Box::pin(futures::stream::once(async move { Err(e) }));
+        }
         let (provider, model) = self.resolve_model(&options);
-        let prompt = options.prompt.clone();
+        let prompt = match Self::prompt_message(&options, &model) {
+            Ok(prompt) => prompt,
+            Err(e) => return Box::pin(futures::stream::once(async move { Err(e) })),
+        };
         let temperature = options.temperature.map(|t| t as f64);
         let max_tokens = options.max_tokens.map(|t| t as u64);
+        let system_prompt = options.system_prompt.clone();
+        let base_url = options.base_url.clone();
+        Self::log_stream_start(provider, &model, &options);
+
+        let checkpointer = options.request_id.clone().map(|request_id| {
+            Checkpointer::new(STREAM_CHECKPOINTS.clone(), request_id, options.clone(), String::new())
+        });
+        Self::run_stream(
+            move |tx| {
+                Self::stream_attempt(
+                    provider,
+                    model.clone(),
+                    prompt.clone(),
+                    Vec::new(),
+                    system_prompt.clone(),
+                    base_url.clone(),
+                    temperature,
+                    max_tokens,
+                    tx,
+                )
+            },
+            self.http.retry,
+            STREAM_STALL_TIMEOUT.max(self.http.timeout),
+            checkpointer,
+        )
+    }
+
+    /// Log the request a stream is about to make; only with AI debugging on, as it holds the prompt
+    pub(crate) fn log_stream_start(provider: AIProvider, model: &str, options: &AIOptions) {
+        ai_debug!("[generate_stream] ========== START ==========");
+        ai_debug!("[generate_stream] provider: {:?}", provider);
+        ai_debug!("[generate_stream] model: {}", model);
+        ai_debug!("[generate_stream] prompt: {}", options.prompt);
+        ai_debug!("[generate_stream] temperature: {:?}", options.temperature);
+        ai_debug!("[generate_stream] max_tokens: {:?}", options.max_tokens);
+        ai_debug!(
+            "[generate_stream] system_prompt: {:?} chars",
+            options.system_prompt.as_ref().map(|system| system.chars().count())
+        );
+        ai_debug!("[generate_stream] =============================");
+    }
+
+    /// Continue a stream interrupted mid-generation from its checkpoint
+    ///
+    /// The original prompt is replayed with the partial output as the assistant's turn,
+    /// so a provider that supports continuation picks up where the text stopped. Other
+    /// providers get `continued: false` with just the buffered partial text.
+    pub fn resume_generation(&self, request_id: &str) -> Result<ResumedGeneration, RigAgentError> {
+        let checkpoint = STREAM_CHECKPOINTS
+            .get(request_id)
+            .ok_or_else(|| RigAgentError::CheckpointNotFound(request_id.to_string()))?;
+        let (provider, model) = self.resolve_model(&checkpoint.options);
+
+        if !provider.supports_continuation() {
+            return Ok(ResumedGeneration {
+                partial: checkpoint.text,
+                continued: false,
+                stream: Box::pin(futures::stream::empty()),
+            });
+        }
+
+        // The continuation starts after the trimmed text, including any whitespace it needs
+        let partial = checkpoint.text.trim_end().to_string();
+        let (prompt, history) = Self::continuation_messages(&checkpoint.options, &model, &partial)?;
+        let temperature = checkpoint.options.temperature.map(|t| t as f64);
+        let max_tokens = checkpoint.options.max_tokens.map(|t| t as u64);
+        let system_prompt = checkpoint.options.system_prompt.clone();
+        let base_url = checkpoint.options.base_url.clone();
+        ai_debug!(
+            "[resume_generation] resuming {} after {} chars",
+            request_id,
+            partial.len()
+        );
+
+        let request = self.start_request(&checkpoint.options, &checkpoint.options.prompt);
+        if let Some(trace) = self.trace_request(&checkpoint.options, None) {
+            trace.record(None);
+        }
+        let checkpointer = Checkpointer::new(
+            STREAM_CHECKPOINTS.clone(),
+            request_id.to_string(),
+            checkpoint.options,
+            partial.clone(),
+        );
+        let stream = Self::run_stream(
+            move |tx| {
+                Self::stream_attempt(
+                    provider,
+                    model.clone(),
+                    prompt.clone(),
+                    history.clone(),
+                    system_prompt.clone(),
+                    base_url.clone(),
+                    temperature,
+                    max_tokens,
+                    tx,
+                )
+            },
+            self.http.retry,
+            STREAM_STALL_TIMEOUT.max(self.http.timeout),
+            Some(checkpointer),
+        );
+        Ok(ResumedGeneration {
+            partial,
+            continued: true,
+            stream: record_stream(&RECENT_REQUESTS, request, stream),
+        })
+    }
+
+    /// The original prompt as history, followed by the partial output as the assistant's turn
+    ///
+    /// `partial` must not end in whitespace, which providers reject in a prefilled turn.
+    fn continuation_messages(
+        options: &AIOptions,
+        model: &str,
+        partial: &str,
+    ) -> Result<(Message, Vec<Message>), RigAgentError> {
+        let prompt = Self::prompt_message(options, model)?;
+        Ok((Message::assistant(partial), vec![prompt]))
+    }
 
-        eprintln!("[generate_stream] ========== START ==========");
-        eprintln!("[generate_stream] provider: {:?}", provider);
-        eprintln!("[generate_stream] model: {}", model);
-        eprintln!("[generate_stream] prompt: {}", prompt);
-        eprintln!("[generate_stream] temperature: {:?}", temperature);
-        eprintln!("[generate_stream] max_tokens: {:?}", max_tokens);
-        eprintln!("[generate_stream] =============================");
+    /// Forward the chunks of streaming attempts made by `attempt` to the returned stream
+    ///
+    /// Setting the stream up is retried while it fails transiently; once text has been
+    /// forwarded a retry would repeat it, so later errors are passed through. Forwarded
+    /// text is recorded by `checkpointer`, which is cleared when the stream completes.
+    /// Dropping the returned stream aborts the attempt in flight, and so does going
+    /// `stall_timeout` without a chunk, which fails the attempt like a dropped connection.
+    fn run_stream<F, Fut>(
+        attempt: F,
+        retry: RetryPolicy,
+        stall_timeout: Duration,
+        mut checkpointer: Option<Checkpointer>,
+    ) -> ChunkStream
+    where
+        F: Fn(tokio::sync::mpsc::Sender<Result<StreamChunk, StreamFailure>>) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        use tokio::sync::mpsc;
+        use tokio_stream::wrappers::ReceiverStream;
 
         // Create a channel for sending chunks
         let (tx, rx) = mpsc::channel(100);
 
-        // Spawn a task to handle streaming
         tokio::spawn(async move {
-            let result: Result<(), RigAgentError> = async move {
-                // Get completion model for current provider
-                let completion_model = match provider {
-                    AIProvider::OpenAI => {
-                        let client = openai::Client::from_env();
-                        ProviderCompletionModel::OpenAI(client.completion_model(&model))
-                    }
-                    AIProvider::Anthropic => {
-                        let client = anthropic::Client::from_env();
-                        ProviderCompletionModel::Anthropic(client.completion_model(&model))
-                    }
-                    AIProvider::Gemini => {
-                        let client = gemini::Client::from_env();
-                        ProviderCompletionModel::Gemini(client.completion_model(&model))
-                    }
-                    AIProvider::DeepSeek => {
-                        println!("[generate_stream] Creating DeepSeek client with model: {}", model);
-                        let api_key = match env::var("DEEPSEEK_API_KEY") {
-                            Ok(key) => {
-                                println!("[generate_stream] DEEPSEEK_API_KEY found (length: {})", key.len());
-                                key
-                            }
-                            Err(e) => {
-                                eprintln!("[generate_stream] DEEPSEEK_API_KEY not found: {}", e);
-                                let _ = tx.send(Err(RigAgentError::ApiKeyNotFound(e.to_string()))).await;
-                                return Ok(());
-                            }
-                        };
-
-                        let client = match deepseek::Client::new(&api_key) {
-                            Ok(client) => {
-                                println!("[generate_stream] DeepSeek client created successfully");
-                                client
-                            }
-                            Err(e) => {
-                                eprintln!("[generate_stream] Failed to create DeepSeek client: {}", e);
-                                let _ = tx
-                                    .send(Err(RigAgentError::Other(format!(
-                                        "Failed to create DeepSeek client: {}",
-                                        e
-                                    ))))
-                                    .await;
-                                return Ok(());
-                            }
-                        };
+            let mut attempt_number = 0;
+            'attempts: loop {
+                let (attempt_tx, mut attempt_rx) = mpsc::channel(100);
+                let run = tokio::spawn(attempt(attempt_tx));
 
-                        let completion_model = client.completion_model(&model);
-                        println!("[generate_stream] DeepSeek completion model created");
-                        ProviderCompletionModel::DeepSeek(completion_model)
-                    }
-                    AIProvider::OpenRouter => {
-                        let client = openrouter::Client::from_env();
-                        ProviderCompletionModel::OpenRouter(client.completion_model(&model))
-                    }
-                    AIProvider::Ollama => {
-                        let _ = tx
-                            .send(Err(RigAgentError::NotSupported(
-                                "Ollama not yet implemented".to_string(),
-                            )))
-                            .await;
-                        return Ok(());
-                    }
-                };
-
-                // Build agent and stream
-                match completion_model {
-                    ProviderCompletionModel::OpenAI(model) => {
-                        let mut builder = AgentBuilder::new(model);
-                        if let Some(temp) = temperature {
-                            builder = builder.temperature(temp);
-                        }
-                        if let Some(tokens) = max_tokens {
-                            builder = builder.max_tokens(tokens);
-                        }
-                        let agent = std::sync::Arc::new(builder.build());
-
-                        let mut stream = agent.stream_prompt(&prompt).await;
-                        while let Some(item) = stream.next().await {
-                            match item {
-                                Ok(chunk) => match chunk {
-                                    MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(text)) => {
-                                        let _ = tx.send(Ok(text.text)).await;
-                                    }
-                                    MultiTurnStreamItem::FinalResponse(_) => {
-                                        break;
-                                    }
-                                    _ => {}
-                                },
-                                Err(e) => {
-                                    let _ = tx.send(Err(RigAgentError::Other(e.to_string()))).await;
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    ProviderCompletionModel::Anthropic(model) => {
-                        let tokens = max_tokens.unwrap_or(4096);
-                        let mut builder = AgentBuilder::new(model).max_tokens(tokens);
-                        if let Some(temp) = temperature {
-                            builder = builder.temperature(temp);
-                        }
-                        let agent = std::sync::Arc::new(builder.build());
-
-                        let mut stream = agent.stream_prompt(&prompt).await;
-                        while let Some(item) = stream.next().await {
-                            match item {
-                                Ok(chunk) => match chunk {
-                                    MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(text)) => {
-                                        let _ = tx.send(Ok(text.text)).await;
-                                    }
-                                    MultiTurnStreamItem::FinalResponse(_) => {
-                                        break;
-                                    }
-                                    _ => {}
-                                },
-                                Err(e) => {
-                                    let _ = tx.send(Err(RigAgentError::Other(e.to_string()))).await;
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    ProviderCompletionModel::Gemini(model) => {
-                        let mut builder = AgentBuilder::new(model);
-                        if let Some(temp) = temperature {
-                            builder = builder.temperature(temp);
-                        }
-                        if let Some(tokens) = max_tokens {
-                            builder = builder.max_tokens(tokens);
-                        }
-                        let agent = std::sync::Arc::new(builder.build());
-
-                        let mut stream = agent.stream_prompt(&prompt).await;
-                        while let Some(item) = stream.next().await {
-                            match item {
-                                Ok(chunk) => match chunk {
-                                    MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(text)) => {
-                                        let _ = tx.send(Ok(text.text)).await;
-                                    }
-                                    MultiTurnStreamItem::FinalResponse(_) => {
-                                        break;
-                                    }
-                                    _ => {}
-                                },
-                                Err(e) => {
-                                    let _ = tx.send(Err(RigAgentError::Other(e.to_string()))).await;
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    ProviderCompletionModel::DeepSeek(model) => {
-                        println!("[generate_stream] Building DeepSeek agent");
-                        let mut builder = AgentBuilder::new(model);
-                        if let Some(temp) = temperature {
-                            println!("[generate_stream] Setting temperature: {}", temp);
-                            builder = builder.temperature(temp);
+                let mut started = false;
+                let mut retry_after = None;
+                loop {
+                    // Dropping the returned stream cancels the provider request right away
+                    // rather than once the next chunk fails to send
+                    let item = tokio::select! {
+                        item = attempt_rx.recv() => match item {
+                            Some(item) => item,
+                            None => break,
+                        },
+                        _ = tx.closed() => {
+                            ai_debug!("[generate_stream] consumer dropped, cancelling generation");
+                            run.abort();
+                            break 'attempts;
                         }
-                        if let Some(tokens) = max_tokens {
-                            println!("[generate_stream] Setting max_tokens: {}", tokens);
-                            builder = builder.max_tokens(tokens);
+                        _ = tokio::time::sleep(stall_timeout) => {
+                            run.abort();
+                            Err(StreamFailure {
+                                error: RigAgentError::HttpError(format!(
+                                    "No response from the provider for {}s",
+                                    stall_timeout.as_secs()
+                                )),
+                                transient: true,
+                                retry_after_ms: None,
+                            })
                         }
-                        let agent = std::sync::Arc::new(builder.build());
-                        println!("[generate_stream] DeepSeek agent built, calling stream_prompt");
-
-                        let mut stream = agent.stream_prompt(&prompt).await;
-                        println!("[generate_stream] DeepSeek stream created, starting to consume");
-                        let mut chunk_count = 0;
-
-                        while let Some(item) = stream.next().await {
-                            chunk_count += 1;
-                            println!(
-                                "[generate_stream] DeepSeek chunk #{}, item type: {:?}",
-                                chunk_count,
-                                std::mem::discriminant(&item)
-                            );
-
-                            match item {
-                                Ok(chunk) => match chunk {
-                                    MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(text)) => {
-                                        println!("[generate_stream] DeepSeek text chunk: '{}'", text.text);
-                                        if tx.send(Ok(text.text)).await.is_err() {
-                                            eprintln!("[generate_stream] Failed to send chunk, channel closed");
-                                            break;
-                                        }
-                                    }
-                                    MultiTurnStreamItem::FinalResponse(_) => {
-                                        println!("[generate_stream] DeepSeek FinalResponse received");
-                                        break;
+                    };
+                    let item = match item {
+                        Ok(chunk) => {
+                            started = true;
+                            match (&chunk, checkpointer.as_mut()) {
+                                (StreamChunk::Text(text), Some(checkpointer)) => checkpointer.push(text),
+                                (StreamChunk::Done(_), _) => {
+                                    if let Some(checkpointer) = checkpointer.take() {
+                                        checkpointer.finish();
                                     }
-                                    _ => {
-                                        println!("[generate_stream] DeepSeek ignoring non-text chunk");
-                                    }
-                                },
-                                Err(e) => {
-                                    eprintln!("[generate_stream] DeepSeek stream error: {:?}", e);
-                                    let _ = tx.send(Err(RigAgentError::Other(e.to_string()))).await;
-                                    break;
                                 }
+                                _ => {}
                             }
+                            Ok(chunk)
                         }
-                        println!("[generate_stream] DeepSeek stream ended, total chunks: {}", chunk_count);
-                    }
-                    ProviderCompletionModel::OpenRouter(model) => {
-                        let mut builder = AgentBuilder::new(model);
-                        if let Some(temp) = temperature {
-                            builder = builder.temperature(temp);
-                        }
-                        if let Some(tokens) = max_tokens {
-                            builder = builder.max_tokens(tokens);
-                        }
-                        let agent = std::sync::Arc::new(builder.build());
-
-                        let mut stream = agent.stream_prompt(&prompt).await;
-                        while let Some(item) = stream.next().await {
-                            match item {
-                                Ok(chunk) => match chunk {
-                                    MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(text)) => {
-                                        let _ = tx.send(Ok(text.text)).await;
-                                    }
-                                    MultiTurnStreamItem::FinalResponse(_) => {
-                                        break;
-                                    }
-                                    _ => {}
-                                },
-                                Err(e) => {
-                                    let _ = tx.send(Err(RigAgentError::Other(e.to_string()))).await;
+                        Err(failure) if !started && failure.transient => {
+                            match retry.delay(attempt_number, failure.retry_after_ms) {
+                                Some(delay) => {
+                                    retry_after = Some((delay, failure.error));
                                     break;
                                 }
+                                None => Err(failure.error),
                             }
                         }
+                        Err(failure) => Err(failure.error),
+                    };
+                    if tx.send(item).await.is_err() {
+                        run.abort();
+                        break 'attempts;
                     }
                 }
-                Ok(())
+
+                let Some((delay, error)) = retry_after else {
+                    break;
+                };
+                run.abort();
+                warn!(
+                    "[generate_stream] attempt {} failed ({}), retrying in {:?}",
+                    attempt_number + 1,
+                    error,
+                    delay
+                );
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = tx.closed() => break,
+                }
+                attempt_number += 1;
             }
-            .await;
 
-            let _ = result;
+            // The stream failed, ended early or was dropped: keep what it produced
+            if let Some(mut checkpointer) = checkpointer {
+                checkpointer.save();
+            }
         });
 
         Box::pin(ReceiverStream::new(rx))
     }
 
-    // ========================================================================
-    // Chat
-    // ========================================================================
+    /// Run one streaming attempt, sending text chunks and a final `Done` (or an error) to `tx`
+    async fn stream_attempt(
+        provider: AIProvider,
+        model: String,
+        prompt: Message,
+        history: Vec<Message>,
+        system_prompt: Option<String>,
+        base_url: Option<String>,
+        temperature: Option<f64>,
+        max_tokens: Option<u64>,
+        tx: tokio::sync::mpsc::Sender<Result<StreamChunk, StreamFailure>>,
+    ) {
+        // Get completion model for current provider
+        let completion_model = match provider_completion_model(&provider, &model, base_url.as_deref()) {
+            Ok(completion_model) => completion_model,
+            Err(e) => {
+                error!("[generate_stream] {}", e);
+                let _ = tx.send(Err(e.into())).await;
+                return;
+            }
+        };
 
-    /// Chat using AgentBuilder::new() pattern
-    pub async fn chat(
-        &self,
-        messages: Vec<ChatMessage>,
-        options: Option<AIOptions>,
-    ) -> Result<AIResponse, RigAgentError> {
-        let default_options = options.unwrap_or_else(|| AIOptions {
-            prompt: String::new(),
-            provider: None,
-            model: None,
-            temperature: None,
-            max_tokens: None,
-            top_p: None,
-            frequency_penalty: None,
-            presence_penalty: None,
-        });
+        let system_prompt = system_prompt.as_deref();
+
+        // Build agent and stream
+        match completion_model {
+            ProviderCompletionModel::OpenAI(model) => {
+                let agent = Self::stream_agent(AgentBuilder::new(model), system_prompt, temperature, max_tokens);
+                Self::stream_model(agent, prompt, history, &tx).await;
+            }
+            ProviderCompletionModel::Anthropic(model) => {
+                // Anthropic requires max_tokens
+                let agent = Self::stream_agent(
+                    AgentBuilder::new(model),
+                    system_prompt,
+                    temperature,
+                    Some(max_tokens.unwrap_or(4096)),
+                );
+                Self::stream_model(agent, prompt, history, &tx).await;
+            }
+            ProviderCompletionModel::Gemini(model) => {
+                let agent = Self::stream_agent(AgentBuilder::new(model), system_prompt, temperature, max_tokens);
+                Self::stream_model(agent, prompt, history, &tx).await;
+            }
+            ProviderCompletionModel::DeepSeek(model) => {
+                ai_debug!("[generate_stream] Building DeepSeek agent");
+                let agent = Self::stream_agent(AgentBuilder::new(model), system_prompt, temperature, max_tokens);
+                Self::stream_model(agent, prompt, history, &tx).await;
+            }
+            ProviderCompletionModel::OpenRouter(model) => {
+                let agent = Self::stream_agent(AgentBuilder::new(model), system_prompt, temperature, max_tokens);
+                Self::stream_model(agent, prompt, history, &tx).await;
+            }
+            ProviderCompletionModel::Ollama(model) => {
+                let agent = Self::stream_agent(AgentBuilder::new(model), system_prompt, temperature, max_tokens);
+                Self::stream_model(agent, prompt, history, &tx).await;
+            }
+        }
+    }
+
+    fn stream_agent<M: CompletionModel>(
+        mut builder: AgentBuilder<M>,
+        system_prompt: Option<&str>,
+        temperature: Option<f64>,
+        max_tokens: Option<u64>,
+    ) -> rig::agent::Agent<M> {
+        if let Some(preamble) = system_prompt {
+            builder = builder.preamble(preamble);
+        }
+        if let Some(temp) = temperature {
+            builder = builder.temperature(temp);
+        }
+        if let Some(tokens) = max_tokens {
+            builder = builder.max_tokens(tokens);
+        }
+        builder.build()
+    }
+
+    /// Stream `prompt` (after `history`) through `agent`, forwarding text and the final usage to `tx`
+    async fn stream_model<M>(
+        agent: rig::agent::Agent<M>,
+        prompt: Message,
+        history: Vec<Message>,
+        tx: &tokio::sync::mpsc::Sender<Result<StreamChunk, StreamFailure>>,
+    ) where
+        M: CompletionModel + 'static,
+        M::StreamingResponse: rig::completion::GetTokenUsage + Send,
+    {
+        let max_tokens = agent.max_tokens;
+        let mut stream = agent.stream_chat(prompt, history).await;
+        let mut chunk_count = 0;
+        // Providers that stream reasoning deltas repeat them as one block when it ends
+        let mut reasoning_streamed = false;
+        while let Some(item) = stream.next().await {
+            chunk_count += 1;
+            let chunk = match item {
+                Ok(MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Text(text))) => {
+                    StreamChunk::Text(text.text)
+                }
+                Ok(MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::ReasoningDelta {
+                    reasoning,
+                    ..
+                })) => {
+                    reasoning_streamed = true;
+                    StreamChunk::Reasoning(reasoning)
+                }
+                Ok(MultiTurnStreamItem::StreamAssistantItem(StreamedAssistantContent::Reasoning(reasoning))) => {
+                    if std::mem::take(&mut reasoning_streamed) {
+                        continue;
+                    }
+                    StreamChunk::Reasoning(reasoning.reasoning.join("\n"))
+                }
+                Ok(MultiTurnStreamItem::FinalResponse(response)) => {
+                    let _ = tx.send(Ok(StreamChunk::done(response.usage(), max_tokens))).await;
+                    break;
+                }
+                Ok(_) => continue,
+                Err(e) => {
+                    error!("[generate_stream] stream error: {:?}", e);
+                    let _ = tx.send(Err(StreamFailure::from_stream_error(e))).await;
+                    break;
+                }
+            };
+            if tx.send(Ok(chunk)).await.is_err() {
+                warn!("[generate_stream] Failed to send chunk, channel closed");
+                break;
+            }
+        }
+        ai_debug!("[generate_stream] stream ended after {} items", chunk_count);
+    }
+
+    // ========================================================================
+    // Chat
+    // ========================================================================
+
+    /// Chat using AgentBuilder::new() pattern
+    pub async fn chat(
+        &self,
+        messages: Vec<ChatMessage>,
+        options: Option<AIOptions>,
+    ) -> Result<AIResponse, RigAgentError> {
+        let options = options.unwrap_or_default();
+        let last_message = messages
+            .last()
+            .map(|message| message.content.as_str())
+            .unwrap_or_default();
+        let request = self.start_request(&options, last_message);
+        let trace = self.trace_request(&options, Some(&messages));
+        finish_request(request, trace, self.chat_unrecorded(messages, options).await)
+    }
+
+    async fn chat_unrecorded(
+        &self,
+        messages: Vec<ChatMessage>,
+        default_options: AIOptions,
+    ) -> Result<AIResponse, RigAgentError> {
+        plain_text_only(&default_options, "chat")?;
         let (provider, model) = self.resolve_model(&default_options);
         let temperature = default_options.temperature.map(|t| t as f64);
         let max_tokens = default_options.max_tokens.map(|t| t as u64);
+        let system_prompt = default_options.system_prompt.as_deref();
 
         // Convert ChatMessage to rig's Message format
         let rig_messages: Vec<Message> = messages
@@ -786,67 +2143,208 @@ impl RigAgent {
 
         // Get the last message as the prompt, and the rest as chat history
         let prompt_msg = rig_messages.last().cloned().unwrap_or_else(|| Message::user(""));
-        let chat_history = if rig_messages.len() > 1 {
+        let mut chat_history = if rig_messages.len() > 1 {
             rig_messages[..rig_messages.len() - 1].to_vec()
         } else {
             vec![]
         };
 
+        // Turns after the prior history are the ones this call adds
+        let new_turns = chat_history.len();
+
         // Get completion model for specified provider
-        let completion_model = self.get_completion_model(&provider, &model)?;
+        let completion_model = self.get_completion_model(&provider, &model, default_options.base_url.as_deref())?;
 
         // Build agent and call chat
-        let text = match completion_model {
+        let response = match completion_model {
             ProviderCompletionModel::OpenAI(model) => {
                 let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
                     builder = builder.temperature(temp);
                 }
                 if let Some(tokens) = max_tokens {
                     builder = builder.max_tokens(tokens);
                 }
-                builder.build().chat(prompt_msg, chat_history).await?
+                builder
+                    .build()
+                    .prompt(prompt_msg)
+                    .with_history(&mut chat_history)
+                    .extended_details()
+                    .await?
             }
             ProviderCompletionModel::Anthropic(model) => {
                 let tokens = max_tokens.unwrap_or(4096);
                 let mut builder = AgentBuilder::new(model).max_tokens(tokens);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
                     builder = builder.temperature(temp);
                 }
-                builder.build().chat(prompt_msg, chat_history).await?
+                builder
+                    .build()
+                    .prompt(prompt_msg)
+                    .with_history(&mut chat_history)
+                    .extended_details()
+                    .await?
             }
             ProviderCompletionModel::Gemini(model) => {
                 let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
                     builder = builder.temperature(temp);
                 }
                 if let Some(tokens) = max_tokens {
                     builder = builder.max_tokens(tokens);
                 }
-                builder.build().chat(prompt_msg, chat_history).await?
+                builder
+                    .build()
+                    .prompt(prompt_msg)
+                    .with_history(&mut chat_history)
+                    .extended_details()
+                    .await?
             }
             ProviderCompletionModel::DeepSeek(model) => {
-                println!("[chat] Building DeepSeek agent for chat");
+                ai_debug!("[chat] Building DeepSeek agent for chat");
                 let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
-                    println!("[chat] Setting temperature: {}", temp);
+                    ai_debug!("[chat] Setting temperature: {}", temp);
                     builder = builder.temperature(temp);
                 }
                 if let Some(tokens) = max_tokens {
-                    println!("[chat] Setting max_tokens: {}", tokens);
+                    ai_debug!("[chat] Setting max_tokens: {}", tokens);
                     builder = builder.max_tokens(tokens);
                 }
-                builder.build().chat(prompt_msg, chat_history).await?
+                builder
+                    .build()
+                    .prompt(prompt_msg)
+                    .with_history(&mut chat_history)
+                    .extended_details()
+                    .await?
             }
             ProviderCompletionModel::OpenRouter(model) => {
                 let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
+                if let Some(temp) = temperature {
+                    builder = builder.temperature(temp);
+                }
+                if let Some(tokens) = max_tokens {
+                    builder = builder.max_tokens(tokens);
+                }
+                builder
+                    .build()
+                    .prompt(prompt_msg)
+                    .with_history(&mut chat_history)
+                    .extended_details()
+                    .await?
+            }
+            ProviderCompletionModel::Ollama(model) => {
+                let mut builder = AgentBuilder::new(model);
+                if let Some(preamble) = system_prompt {
+                    builder = builder.preamble(preamble);
+                }
                 if let Some(temp) = temperature {
                     builder = builder.temperature(temp);
                 }
                 if let Some(tokens) = max_tokens {
                     builder = builder.max_tokens(tokens);
                 }
-                builder.build().chat(prompt_msg, chat_history).await?
+                builder
+                    .build()
+                    .prompt(prompt_msg)
+                    .with_history(&mut chat_history)
+                    .extended_details()
+                    .await?
+            }
+        };
+
+        Ok(AIResponse {
+            text: response.output,
+            model: Some(model),
+            finish_reason: Some(finish_reason(&response.total_usage, max_tokens)),
+            usage: reported_usage(response.total_usage),
+            reasoning: message_reasoning(&chat_history[new_turns..]),
+        })
+    }
+
+    // ========================================================================
+    // Tool Calling
+    // ========================================================================
+
+    /// Generate text while letting the model call the given tools
+    ///
+    /// Each tool call is routed to the handler registered under the tool's name and the
+    /// result is sent back to the model, until it answers without calling a tool or
+    /// [`MAX_TOOL_TURNS`] round-trips have been made.
+    pub async fn generate_with_tools(
+        &self,
+        options: AIOptions,
+        tools: Vec<ToolDef>,
+        handlers: HashMap<String, ToolHandler>,
+    ) -> Result<AIResponse, RigAgentError> {
+        let request = self.start_request(&options, &options.prompt);
+        let trace = self.trace_request(&options, None);
+        finish_request(
+            request,
+            trace,
+            self.generate_with_tools_unrecorded(options, tools, handlers).await,
+        )
+    }
+
+    async fn generate_with_tools_unrecorded(
+        &self,
+        options: AIOptions,
+        tools: Vec<ToolDef>,
+        handlers: HashMap<String, ToolHandler>,
+    ) -> Result<AIResponse, RigAgentError> {
+        plain_text_only(&options, "generate_with_tools")?;
+        let (provider, model) = self.resolve_model(&options);
+        let temperature = options.temperature.map(|t| t as f64);
+        let max_tokens = options.max_tokens.map(|t| t as u64);
+        let system_prompt = options.system_prompt.as_deref();
+
+        let completion_model = self.get_completion_model(&provider, &model, options.base_url.as_deref())?;
+        let prompt = Self::prompt_message(&options, &model)?;
+
+        let text = match completion_model {
+            ProviderCompletionModel::OpenAI(model) => {
+                run_tool_loop(model, prompt, system_prompt, &tools, &handlers, temperature, max_tokens).await?
+            }
+            ProviderCompletionModel::Anthropic(model) => {
+                // Anthropic requires max_tokens
+                let tokens = max_tokens.unwrap_or(4096);
+                run_tool_loop(
+                    model,
+                    prompt,
+                    system_prompt,
+                    &tools,
+                    &handlers,
+                    temperature,
+                    Some(tokens),
+                )
+                .await?
+            }
+            ProviderCompletionModel::Gemini(model) => {
+                run_tool_loop(model, prompt, system_prompt, &tools, &handlers, temperature, max_tokens).await?
+            }
+            ProviderCompletionModel::DeepSeek(model) => {
+                run_tool_loop(model, prompt, system_prompt, &tools, &handlers, temperature, max_tokens).await?
+            }
+            ProviderCompletionModel::OpenRouter(model) => {
+                run_tool_loop(model, prompt, system_prompt, &tools, &handlers, temperature, max_tokens).await?
+            }
+            ProviderCompletionModel::Ollama(model) => {
+                run_tool_loop(model, prompt, system_prompt, &tools, &handlers, temperature, max_tokens).await?
             }
         };
 
@@ -855,6 +2353,7 @@ impl RigAgent {
             model: Some(model),
             usage: None,
             finish_reason: Some("stop".to_string()),
+            reasoning: None,
         })
     }
 
@@ -862,46 +2361,129 @@ impl RigAgent {
     // Embeddings
     // ========================================================================
 
-    pub async fn embed(&self, text: String, model: Option<String>) -> Result<Vec<f32>, RigAgentError> {
-        match self.provider {
+    /// Embed `text` with the provider's embedding model, or its default when `model` is `None`
+    pub async fn embed(&self, request: EmbeddingRequest) -> Result<Vec<f32>, RigAgentError> {
+        let provider = self.provider_override(request.provider.as_deref());
+        let not_supported =
+            || RigAgentError::NotSupported(format!("{:?} does not provide an embeddings API", provider));
+        let default_model = default_embedding_model(&provider).ok_or_else(not_supported)?;
+        let model_name = request.model.unwrap_or_else(|| default_model.to_string());
+        let text = request.text;
+
+        let embedding = match provider {
             AIProvider::OpenAI => {
-                let _ = env::var("OPENAI_API_KEY")
-                    .map_err(|_| RigAgentError::ApiKeyNotFound("OPENAI_API_KEY".to_string()))?;
-                let client = openai::Client::from_env();
-                let model_name = model.unwrap_or_else(|| "text-embedding-3-small".to_string());
-                let embedding_model = client.embedding_model(&model_name);
-                let embedding = embedding_model.embed_text(&text).await?;
-                // Convert Vec<f64> to Vec<f32>
-                let vec_f32: Vec<f32> = embedding.vec.into_iter().map(|v| v as f32).collect();
-                Ok(vec_f32)
+                openai_client(None)?
+                    .embedding_model(&model_name)
+                    .embed_text(&text)
+                    .await?
             }
-            _ => Err(RigAgentError::NotSupported(
-                "Embeddings only supported for OpenAI".to_string(),
-            )),
-        }
+            AIProvider::Gemini => {
+                gemini_client(None)?
+                    .embedding_model(&model_name)
+                    .embed_text(&text)
+                    .await?
+            }
+            AIProvider::Ollama => {
+                ollama_client(None)?
+                    .embedding_model(&model_name)
+                    .embed_text(&text)
+                    .await?
+            }
+            AIProvider::Anthropic | AIProvider::DeepSeek | AIProvider::OpenRouter => return Err(not_supported()),
+        };
+
+        // Convert Vec<f64> to Vec<f32>
+        Ok(embedding.vec.into_iter().map(|v| v as f32).collect())
     }
 
     // ========================================================================
     // Moderation
     // ========================================================================
 
-    pub async fn moderate(&self, _content: String) -> Result<ModerationResponse, RigAgentError> {
-        // For now, return a placeholder response
-        Ok(ModerationResponse {
-            flagged: false,
-            categories: std::collections::HashMap::new(),
-            category_scores: std::collections::HashMap::new(),
-        })
+    /// Classify `content` with OpenAI's moderation endpoint (OpenAI only)
+    pub async fn moderate(&self, request: ModerationRequest) -> Result<ModerationResponse, RigAgentError> {
+        if !matches!(self.provider_override(request.provider.as_deref()), AIProvider::OpenAI) {
+            return Err(RigAgentError::NotSupported(
+                "Moderation is only supported for OpenAI".to_string(),
+            ));
+        }
+
+        let api_key = provider_api_key_for(&AIProvider::OpenAI, request.base_url.as_deref())?;
+        let url = format!(
+            "{}/moderations",
+            AIProvider::OpenAI.base_url(request.base_url.as_deref())
+        );
+        let response = with_bearer(
+            create_http_client(&AIProvider::OpenAI, self.http.timeout)?.post(url),
+            &api_key,
+        )
+        .json(&serde_json::json!({ "input": request.content }))
+        .send()
+        .await
+        .map_err(|e| RigAgentError::HttpError(format!("OpenAI moderation request failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(openai_error_response(response, "OpenAI moderation").await);
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| RigAgentError::HttpError(format!("Failed to read OpenAI moderation response: {}", e)))?;
+        parse_moderation_response(&body)
     }
 
     // ========================================================================
     // Image Generation
     // ========================================================================
 
-    pub async fn generate_image(&self, _request: ImageGenerationRequest) -> Result<Vec<String>, RigAgentError> {
-        Err(RigAgentError::NotSupported(
-            "Image generation not yet implemented".to_string(),
-        ))
+    /// Generate an image with DALL·E 3 and return its URL (OpenAI only)
+    ///
+    /// DALL·E 3 generates one image per request, so `n` other than 1 is rejected.
+    pub async fn generate_image(&self, request: ImageGenerationRequest) -> Result<Vec<String>, RigAgentError> {
+        if !matches!(self.provider_override(request.provider.as_deref()), AIProvider::OpenAI) {
+            return Err(RigAgentError::NotSupported(
+                "Image generation is only supported for OpenAI".to_string(),
+            ));
+        }
+
+        let body = image_generation_body(&request)?;
+        let api_key = provider_api_key_for(&AIProvider::OpenAI, request.base_url.as_deref())?;
+
+        #[derive(Deserialize)]
+        struct ImagesResponse {
+            data: Vec<GeneratedImage>,
+        }
+
+        #[derive(Deserialize)]
+        struct GeneratedImage {
+            url: Option<String>,
+        }
+
+        let url = format!(
+            "{}/images/generations",
+            AIProvider::OpenAI.base_url(request.base_url.as_deref())
+        );
+        let client = create_http_client(&AIProvider::OpenAI, IMAGE_GENERATION_TIMEOUT.max(self.http.timeout))?;
+        let response = with_bearer(client.post(url), &api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| RigAgentError::HttpError(format!("OpenAI image request failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(openai_error_response(response, "OpenAI image generation").await);
+        }
+
+        let images: ImagesResponse = response
+            .json()
+            .await
+            .map_err(|e| RigAgentError::Other(format!("Failed to parse OpenAI image response: {}", e)))?;
+        let urls: Vec<String> = images.data.into_iter().filter_map(|image| image.url).collect();
+        if urls.is_empty() {
+            return Err(RigAgentError::Other("OpenAI returned no image URLs".to_string()));
+        }
+        Ok(urls)
     }
 
     // ========================================================================
@@ -909,51 +2491,109 @@ impl RigAgent {
     // ========================================================================
 
     pub async fn analyze_image(&self, request: ImageAnalysisRequest) -> Result<String, RigAgentError> {
-        let prompt = format!("{} Image URL: {}", request.prompt, request.image_url);
-        let model = request.model.as_ref().map(|m| m.as_str()).unwrap_or("gpt-4o");
+        let provider = self.provider_override(request.provider.as_deref());
+        if !matches!(provider, AIProvider::OpenAI | AIProvider::Gemini) {
+            return Err(RigAgentError::NotSupported(
+                "Image analysis not supported for this provider".to_string(),
+            ));
+        }
+        let prompt = self.image_message(&request, provider).await?;
 
-        match self.provider {
+        match provider {
             AIProvider::OpenAI => {
-                let _ = env::var("OPENAI_API_KEY")
-                    .map_err(|_| RigAgentError::ApiKeyNotFound("OPENAI_API_KEY".to_string()))?;
-                let client = openai::Client::from_env();
-                let agent = client.agent(model).build();
-                let response = agent.prompt(&prompt).await?;
+                let model = request.model.as_ref().map(|m| m.as_str()).unwrap_or("gpt-4o");
+                let agent = openai_client(None)?.agent(model).build();
+                let response = agent.prompt(prompt).await?;
                 Ok(response)
             }
-            AIProvider::Gemini => {
-                let _ = env::var("GEMINI_API_KEY")
-                    .map_err(|_| RigAgentError::ApiKeyNotFound("GEMINI_API_KEY".to_string()))?;
-                let client = gemini::Client::from_env();
+            _ => {
+                let client = gemini_client(None)?;
                 let model = request
                     .model
                     .as_ref()
                     .map(|m| m.as_str())
                     .unwrap_or("gemini-2.0-flash-exp");
                 let agent = client.agent(model).build();
-                let response = agent.prompt(&prompt).await?;
+                let response = agent.prompt(prompt).await?;
                 Ok(response)
             }
-            _ => Err(RigAgentError::NotSupported(
-                "Image analysis not supported for this provider".to_string(),
-            )),
         }
     }
 
+    /// The analysis prompt with the image attached as an image part, so the model sees it
+    ///
+    /// Gemini only accepts image URLs it hosts itself, so for it the image is downloaded
+    /// and sent inline.
+    async fn image_message(
+        &self,
+        request: &ImageAnalysisRequest,
+        provider: AIProvider,
+    ) -> Result<Message, RigAgentError> {
+        let image = if matches!(provider, AIProvider::Gemini) {
+            let client = Client::builder()
+                .timeout(self.http.timeout)
+                .build()
+                .map_err(|e| RigAgentError::Other(format!("Failed to create HTTP client: {}", e)))?;
+            inline_image_url_content(&client, &request.image_url).await
+        } else {
+            image_url_content(&request.image_url)
+        }
+        .map_err(RigAgentError::InvalidImage)?;
+        Ok(Message::User {
+            content: OneOrMany::many(vec![UserContent::text(&request.prompt), image])
+                .map_err(|e| RigAgentError::Other(e.to_string()))?,
+        })
+    }
+
     // ========================================================================
     // Token Counting
     // ========================================================================
 
-    pub async fn count_tokens(&self, text: String, _model: Option<String>) -> Result<u32, RigAgentError> {
-        // Simple approximation: ~4 characters per token
-        let approx_tokens = (text.len() as f32 / 4.0).ceil() as u32;
-        Ok(approx_tokens)
+    /// Count tokens with the model's tokenizer, or approximate when it has none we know
+    ///
+    /// `model` defaults to the agent's default model.
+    pub async fn count_tokens(&self, text: String, model: Option<String>) -> Result<u32, RigAgentError> {
+        let model = model.unwrap_or_else(|| self.default_model.clone());
+        Ok(count_text_tokens(&model, &text))
     }
 
     // ========================================================================
     // Model Information
     // ========================================================================
 
+    /// Available models for `provider`, or for the agent's provider when `None`
+    ///
+    /// Lists are cached and persisted; a stale list is returned immediately while a fresh
+    /// one is fetched in the background. `force` skips the cache and fetches now.
+    pub async fn get_models(&self, provider: Option<&str>, force: bool) -> Result<Vec<ModelInfo>, RigAgentError> {
+        if force {
+            return self.refresh_models(provider).await;
+        }
+        let provider = self.provider_override(provider);
+        let http = self.http;
+        self.models
+            .get_or_refresh(&provider.key(), move || Self::fetch_models(provider, http))
+            .await
+    }
+
+    /// Fetch the model list for `provider` (or the agent's provider) now, replacing the cached copy
+    pub async fn refresh_models(&self, provider: Option<&str>) -> Result<Vec<ModelInfo>, RigAgentError> {
+        let provider = self.provider_override(provider);
+        let http = self.http;
+        self.models
+            .refresh(&provider.key(), || Self::fetch_models(provider, http))
+            .await
+    }
+
+    /// Fetch the agent's provider's model list now, reporting what changed since the cached copy
+    pub async fn refresh_models_with_diff(&self) -> Result<ModelListDiff, RigAgentError> {
+        let provider = self.provider;
+        let http = self.http;
+        self.models
+            .refresh_with_diff(&provider.key(), || Self::fetch_models(provider, http))
+            .await
+    }
+
     /// Fetch available models from the provider's API
     ///
     /// This function makes actual API calls to fetch the model list:
@@ -962,17 +2602,20 @@ impl RigAgent {
     /// - OpenRouter: https://openrouter.ai/api/v1/models
     /// - Anthropic, Gemini: Return known model lists (no public API)
     /// - Ollama: Return known models (would require local API access)
-    pub async fn get_models(&self) -> Result<Vec<ModelInfo>, RigAgentError> {
-        let client = create_http_client()?;
+    ///
+    /// A base URL set in the provider's environment variable replaces the endpoints above.
+    /// Timeouts and transient failures are retried according to `http.retry`.
+    async fn fetch_models(provider: AIProvider, http: HttpConfig) -> Result<Vec<ModelInfo>, RigAgentError> {
+        let client = create_http_client(&provider, http.timeout)?;
 
-        match self.provider {
+        match provider {
             AIProvider::OpenAI => {
                 let api_key = env::var("OPENAI_API_KEY").map_err(|e| RigAgentError::ApiKeyNotFound(e.to_string()))?;
 
-                let response = client
-                    .get("https://api.openai.com/v1/models")
-                    .header("Authorization", format!("Bearer {}", api_key))
-                    .send()
+                let request = client
+                    .get(format!("{}/models", provider.base_url(None)))
+                    .header("Authorization", format!("Bearer {}", api_key));
+                let response = send_with_retry(request, &http.retry)
                     .await
                     .map_err(|e| RigAgentError::HttpError(format!("OpenAI API request failed: {}", e)))?;
 
@@ -1000,12 +2643,7 @@ impl RigAgent {
                     })
                     .map(|m| {
                         let (name, description, context_length) = Self::describe_openai_model(&m.id);
-                        ModelInfo {
-                            id: m.id.clone(),
-                            name,
-                            description,
-                            context_length,
-                        }
+                        ModelInfo::new(m.id, name, description, context_length)
                     })
                     .collect();
 
@@ -1027,10 +2665,10 @@ impl RigAgent {
             AIProvider::DeepSeek => {
                 let api_key = env::var("DEEPSEEK_API_KEY").map_err(|e| RigAgentError::ApiKeyNotFound(e.to_string()))?;
 
-                let response = client
-                    .get("https://api.deepseek.com/v1/models")
-                    .header("Authorization", format!("Bearer {}", api_key))
-                    .send()
+                let request = client
+                    .get(format!("{}/models", provider.base_url(None)))
+                    .header("Authorization", format!("Bearer {}", api_key));
+                let response = send_with_retry(request, &http.retry)
                     .await
                     .map_err(|e| RigAgentError::HttpError(format!("DeepSeek API request failed: {}", e)))?;
 
@@ -1053,12 +2691,7 @@ impl RigAgent {
                     .into_iter()
                     .map(|m| {
                         let (name, description, context_length) = Self::describe_deepseek_model(&m.id);
-                        ModelInfo {
-                            id: m.id.clone(),
-                            name,
-                            description,
-                            context_length,
-                        }
+                        ModelInfo::new(m.id, name, description, context_length)
                     })
                     .collect();
 
@@ -1072,10 +2705,10 @@ impl RigAgent {
                 let api_key =
                     env::var("OPENROUTER_API_KEY").map_err(|e| RigAgentError::ApiKeyNotFound(e.to_string()))?;
 
-                let response = client
-                    .get("https://openrouter.ai/api/v1/models")
-                    .header("Authorization", format!("Bearer {}", api_key))
-                    .send()
+                let request = client
+                    .get(format!("{}/models", provider.base_url(None)))
+                    .header("Authorization", format!("Bearer {}", api_key));
+                let response = send_with_retry(request, &http.retry)
                     .await
                     .map_err(|e| RigAgentError::HttpError(format!("OpenRouter API request failed: {}", e)))?;
 
@@ -1096,14 +2729,18 @@ impl RigAgent {
                 let models: Vec<ModelInfo> = models_response
                     .data
                     .into_iter()
-                    .map(|m| ModelInfo {
-                        id: m.id.clone(),
-                        name: m.name.unwrap_or_else(|| m.id.clone()),
-                        description: m.description.unwrap_or_else(|| {
+                    .map(|m| {
+                        let name = m.name.unwrap_or_else(|| m.id.clone());
+                        let description = m.description.unwrap_or_else(|| {
                             let provider = m.id.split('/').next().unwrap_or("openrouter");
                             format!("Model via {}", provider)
-                        }),
-                        context_length: m.context_length.unwrap_or(128000),
+                        });
+                        ModelInfo::new(
+                            m.id,
+                            name,
+                            description,
+                            m.context_length.unwrap_or(FALLBACK_CONTEXT_LENGTH),
+                        )
                     })
                     .collect();
 
@@ -1179,121 +2816,1328 @@ impl RigAgent {
     }
 
     // Fallback known model lists
+    fn get_known_models(provider: &AIProvider) -> Vec<ModelInfo> {
+        match provider {
+            AIProvider::OpenAI => Self::get_known_openai_models(),
+            AIProvider::Anthropic => Self::get_known_anthropic_models(),
+            AIProvider::Gemini => Self::get_known_gemini_models(),
+            AIProvider::Ollama => Self::get_known_ollama_models(),
+            AIProvider::DeepSeek => Self::get_known_deepseek_models(),
+            AIProvider::OpenRouter => Self::get_known_openrouter_models(),
+        }
+    }
+
     fn get_known_openai_models() -> Vec<ModelInfo> {
         vec![
-            ModelInfo {
-                id: "gpt-4o".to_string(),
-                name: "GPT-4 Omni".to_string(),
-                description: "OpenAI's most advanced multimodal model".to_string(),
-                context_length: 128000,
-            },
-            ModelInfo {
-                id: "gpt-4o-mini".to_string(),
-                name: "GPT-4 Omni Mini".to_string(),
-                description: "Faster, cheaper version of GPT-4o".to_string(),
-                context_length: 128000,
-            },
-            ModelInfo {
-                id: "gpt-4-turbo".to_string(),
-                name: "GPT-4 Turbo".to_string(),
-                description: "High-intelligence model with vision capabilities".to_string(),
-                context_length: 128000,
-            },
-            ModelInfo {
-                id: "gpt-3.5-turbo".to_string(),
-                name: "GPT-3.5 Turbo".to_string(),
-                description: "Fast, efficient model for most tasks".to_string(),
-                context_length: 16385,
-            },
+            ModelInfo::new(
+                "gpt-4o",
+                "GPT-4 Omni",
+                "OpenAI's most advanced multimodal model",
+                128000,
+            ),
+            ModelInfo::new(
+                "gpt-4o-mini",
+                "GPT-4 Omni Mini",
+                "Faster, cheaper version of GPT-4o",
+                128000,
+            ),
+            ModelInfo::new(
+                "gpt-4-turbo",
+                "GPT-4 Turbo",
+                "High-intelligence model with vision capabilities",
+                128000,
+            ),
+            ModelInfo::new(
+                "gpt-3.5-turbo",
+                "GPT-3.5 Turbo",
+                "Fast, efficient model for most tasks",
+                16385,
+            ),
         ]
     }
 
     fn get_known_anthropic_models() -> Vec<ModelInfo> {
         vec![
-            ModelInfo {
-                id: "claude-3-5-sonnet-20241022".to_string(),
-                name: "Claude 3.5 Sonnet".to_string(),
-                description: "Most intelligent model for complex tasks".to_string(),
-                context_length: 200000,
-            },
-            ModelInfo {
-                id: "claude-3-5-haiku-20241022".to_string(),
-                name: "Claude 3.5 Haiku".to_string(),
-                description: "Fastest model for simple tasks".to_string(),
-                context_length: 200000,
-            },
-            ModelInfo {
-                id: "claude-3-opus-20240229".to_string(),
-                name: "Claude 3 Opus".to_string(),
-                description: "Powerful model for nuanced tasks".to_string(),
-                context_length: 200000,
-            },
+            ModelInfo::new(
+                "claude-3-5-sonnet-20241022",
+                "Claude 3.5 Sonnet",
+                "Most intelligent model for complex tasks",
+                200000,
+            ),
+            ModelInfo::new(
+                "claude-3-5-haiku-20241022",
+                "Claude 3.5 Haiku",
+                "Fastest model for simple tasks",
+                200000,
+            ),
+            ModelInfo::new(
+                "claude-3-opus-20240229",
+                "Claude 3 Opus",
+                "Powerful model for nuanced tasks",
+                200000,
+            ),
         ]
     }
 
     fn get_known_gemini_models() -> Vec<ModelInfo> {
         vec![
-            ModelInfo {
-                id: "gemini-2.0-flash-exp".to_string(),
-                name: "Gemini 2.0 Flash".to_string(),
-                description: "Google's latest experimental flash model".to_string(),
-                context_length: 1000000,
-            },
-            ModelInfo {
-                id: "gemini-1.5-pro".to_string(),
-                name: "Gemini 1.5 Pro".to_string(),
-                description: "Google's advanced model with long context".to_string(),
-                context_length: 2000000,
-            },
-            ModelInfo {
-                id: "gemini-1.5-flash".to_string(),
-                name: "Gemini 1.5 Flash".to_string(),
-                description: "Google's fast, efficient model".to_string(),
-                context_length: 1000000,
-            },
+            ModelInfo::new(
+                "gemini-2.0-flash-exp",
+                "Gemini 2.0 Flash",
+                "Google's latest experimental flash model",
+                1000000,
+            ),
+            ModelInfo::new(
+                "gemini-1.5-pro",
+                "Gemini 1.5 Pro",
+                "Google's advanced model with long context",
+                2000000,
+            ),
+            ModelInfo::new(
+                "gemini-1.5-flash",
+                "Gemini 1.5 Flash",
+                "Google's fast, efficient model",
+                1000000,
+            ),
         ]
     }
 
     fn get_known_deepseek_models() -> Vec<ModelInfo> {
         vec![
-            ModelInfo {
-                id: "deepseek-chat".to_string(),
-                name: "DeepSeek Chat".to_string(),
-                description: "DeepSeek's advanced chat model".to_string(),
-                context_length: 128000,
-            },
-            ModelInfo {
-                id: "deepseek-coder".to_string(),
-                name: "DeepSeek Coder".to_string(),
-                description: "DeepSeek's code-specialized model".to_string(),
-                context_length: 128000,
-            },
+            ModelInfo::new(
+                "deepseek-chat",
+                "DeepSeek Chat",
+                "DeepSeek's advanced chat model",
+                128000,
+            ),
+            ModelInfo::new(
+                "deepseek-coder",
+                "DeepSeek Coder",
+                "DeepSeek's code-specialized model",
+                128000,
+            ),
         ]
     }
 
     fn get_known_openrouter_models() -> Vec<ModelInfo> {
         vec![
-            ModelInfo {
-                id: "meta-llama/llama-3.3-70b-instruct".to_string(),
-                name: "Llama 3.3 70B".to_string(),
-                description: "Meta's large language model via OpenRouter".to_string(),
-                context_length: 128000,
-            },
-            ModelInfo {
-                id: "anthropic/claude-3.5-sonnet".to_string(),
-                name: "Claude 3.5 Sonnet".to_string(),
-                description: "Anthropic's Claude via OpenRouter".to_string(),
-                context_length: 200000,
-            },
+            ModelInfo::new(
+                "meta-llama/llama-3.3-70b-instruct",
+                "Llama 3.3 70B",
+                "Meta's large language model via OpenRouter",
+                128000,
+            ),
+            ModelInfo::new(
+                "anthropic/claude-3.5-sonnet",
+                "Claude 3.5 Sonnet",
+                "Anthropic's Claude via OpenRouter",
+                200000,
+            ),
         ]
     }
 
     fn get_known_ollama_models() -> Vec<ModelInfo> {
-        vec![ModelInfo {
-            id: "llama3.2".to_string(),
-            name: "Llama 3.2".to_string(),
-            description: "Meta's open source model".to_string(),
-            context_length: 128000,
-        }]
+        vec![ModelInfo::new(
+            "llama3.2",
+            "Llama 3.2",
+            "Meta's open source model",
+            128000,
+        )]
+    }
+}
+
+/// Drive the tool-calling conversation with a completion model and return the final answer
+async fn run_tool_loop<M: CompletionModel>(
+    model: M,
+    prompt: Message,
+    system_prompt: Option<&str>,
+    tools: &[ToolDef],
+    handlers: &HashMap<String, ToolHandler>,
+    temperature: Option<f64>,
+    max_tokens: Option<u64>,
+) -> Result<String, RigAgentError> {
+    let definitions: Vec<ToolDefinition> = tools.iter().map(ToolDefinition::from).collect();
+    let mut history = Vec::new();
+    let mut prompt = prompt;
+
+    for turn in 0..MAX_TOOL_TURNS {
+        let mut request = model
+            .completion_request(prompt.clone())
+            .messages(history.clone())
+            .tools(definitions.clone())
+            .temperature_opt(temperature)
+            .max_tokens_opt(max_tokens);
+        if let Some(preamble) = system_prompt {
+            request = request.preamble(preamble.to_string());
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| RigAgentError::from(PromptError::CompletionError(e)))?;
+
+        let calls: Vec<_> = response
+            .choice
+            .iter()
+            .filter_map(|content| match content {
+                AssistantContent::ToolCall(call) => Some(call.clone()),
+                _ => None,
+            })
+            .collect();
+
+        if calls.is_empty() {
+            return Ok(response
+                .choice
+                .iter()
+                .filter_map(|content| match content {
+                    AssistantContent::Text(text) => Some(text.text.as_str()),
+                    _ => None,
+                })
+                .collect());
+        }
+
+        let mut results = Vec::with_capacity(calls.len());
+        for call in calls {
+            ai_debug!("[tools] Turn {}: calling {}", turn + 1, call.function.name);
+            // Failures are reported back to the model so it can recover or explain
+            let output = match handlers.get(&call.function.name) {
+                Some(handler) => handler(call.function.arguments.clone())
+                    .await
+                    .unwrap_or_else(|e| format!("Error: {}", e)),
+                None => format!("Error: unknown tool {}", call.function.name),
+            };
+            let content = OneOrMany::one(ToolResultContent::text(output));
+            results.push(match call.call_id {
+                Some(call_id) => UserContent::tool_result_with_call_id(call.id, call_id, content),
+                None => UserContent::tool_result(call.id, content),
+            });
+        }
+
+        history.push(prompt);
+        history.push(Message::Assistant {
+            id: None,
+            content: response.choice,
+        });
+        prompt = Message::User {
+            content: OneOrMany::many(results).map_err(|e| RigAgentError::Other(e.to_string()))?,
+        };
+    }
+
+    Err(RigAgentError::ToolError(format!(
+        "No final answer after {} tool-calling turns",
+        MAX_TOOL_TURNS
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use rig::tool::ToolSetError;
+
+    fn completion_error(err: CompletionError) -> RigAgentError {
+        PromptError::CompletionError(err).into()
+    }
+
+    #[test]
+    fn test_response_format_maps_to_provider_controls() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"city": {"type": "string"}},
+            "required": ["city"]
+        });
+        let format = ResponseFormat::JsonSchema(schema.clone());
+
+        // rig parses these into its own request types and panics on a mismatch
+        let openai = response_format_params(&AIProvider::OpenAI, &format).unwrap();
+        let openai: rig::providers::openai::responses_api::AdditionalParameters =
+            serde_json::from_value(openai).unwrap();
+        assert!(serde_json::to_string(&openai.text)
+            .unwrap()
+            .contains(r#""type":"json_schema""#));
+        let gemini = response_format_params(&AIProvider::Gemini, &format).unwrap();
+        let gemini: rig::providers::gemini::completion::gemini_api_types::AdditionalParameters =
+            serde_json::from_value(gemini).unwrap();
+        let config = gemini.generation_config.unwrap();
+        assert_eq!(config.response_mime_type.as_deref(), Some("application/json"));
+        assert_eq!(config.response_json_schema, Some(schema.clone()));
+        assert!(response_format_params(&AIProvider::OpenAI, &ResponseFormat::JsonObject).is_some());
+        assert!(response_format_params(&AIProvider::OpenAI, &ResponseFormat::Text).is_none());
+
+        // Providers without a native control are instructed, keeping the caller's system prompt
+        assert!(response_format_params(&AIProvider::Anthropic, &format).is_none());
+        let instructed = structured_system_prompt(&AIProvider::Anthropic, Some("Be brief."), Some(&format)).unwrap();
+        assert!(instructed.starts_with("Be brief.\n\nRespond with a single JSON value"));
+        assert!(instructed.contains(r#""required":["city"]"#));
+        let deepseek = structured_system_prompt(&AIProvider::DeepSeek, None, Some(&ResponseFormat::JsonObject));
+        assert_eq!(
+            deepseek.as_deref(),
+            Some("Respond with a single JSON object and nothing else.")
+        );
+        assert_eq!(
+            structured_system_prompt(&AIProvider::OpenAI, Some("Be brief."), Some(&format)).as_deref(),
+            Some("Be brief.")
+        );
+        assert_eq!(
+            structured_system_prompt(&AIProvider::Gemini, Some("Be brief."), Some(&ResponseFormat::Text)).as_deref(),
+            Some("Be brief.")
+        );
+    }
+
+    #[test]
+    fn test_context_length_errors_map_to_context_too_long() {
+        let err = completion_error(CompletionError::ProviderError(
+            "This model's maximum context length is 8192 tokens".to_string(),
+        ));
+        assert!(matches!(&err, RigAgentError::ContextTooLong(message) if message.contains("8192 tokens")));
+
+        let err = completion_error(CompletionError::HttpError(rig::http_client::Error::InvalidStatusCode(
+            StatusCode::PAYLOAD_TOO_LARGE,
+        )));
+        assert!(matches!(err, RigAgentError::ContextTooLong(_)));
+    }
+
+    #[test]
+    fn test_rate_limit_errors_carry_retry_after() {
+        let err = completion_error(CompletionError::HttpError(
+            rig::http_client::Error::InvalidStatusCodeWithMessage(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Rate limit reached. Please try again in 30s.".to_string(),
+            ),
+        ));
+        assert!(matches!(
+            err,
+            RigAgentError::RateLimited {
+                retry_after_ms: Some(30000),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_auth_errors_map_to_api_key_not_found() {
+        let err = completion_error(CompletionError::HttpError(
+            rig::http_client::Error::InvalidStatusCodeWithMessage(StatusCode::UNAUTHORIZED, "bad key".to_string()),
+        ));
+        assert!(matches!(&err, RigAgentError::ApiKeyNotFound(message) if message.contains("bad key")));
+
+        let err = completion_error(CompletionError::ProviderError("Incorrect API key provided".to_string()));
+        assert!(matches!(err, RigAgentError::ApiKeyNotFound(_)));
+    }
+
+    #[test]
+    fn test_network_errors_map_to_http_error() {
+        let err = completion_error(CompletionError::HttpError(rig::http_client::Error::StreamEnded));
+        assert!(matches!(err, RigAgentError::HttpError(_)));
+
+        let err = completion_error(CompletionError::HttpError(rig::http_client::Error::InvalidStatusCode(
+            StatusCode::BAD_GATEWAY,
+        )));
+        assert!(matches!(err, RigAgentError::HttpError(_)));
+    }
+
+    #[test]
+    fn test_token_counts_use_model_tokenizer() {
+        assert_eq!(count_text_tokens("gpt-4o", "hello world"), 2);
+        assert_eq!(count_text_tokens("openai/gpt-4o-mini", "hello world"), 2);
+        assert_eq!(count_text_tokens("deepseek-chat", "hello world"), 2);
+
+        // Non-English text is far from four bytes per token
+        assert_eq!(count_text_tokens("gpt-4o", "你好，世界"), 3);
+
+        // Models without a known tokenizer fall back to the approximation
+        assert_eq!(count_text_tokens("claude-3-5-sonnet-20241022", "hello world"), 3);
+    }
+
+    #[test]
+    fn test_finish_reason_reports_length_at_token_limit() {
+        let mut usage = rig::completion::Usage::new();
+        usage.output_tokens = 200;
+
+        assert_eq!(finish_reason(&usage, Some(200)), "length");
+        assert_eq!(finish_reason(&usage, Some(1024)), "stop");
+        assert_eq!(finish_reason(&usage, None), "stop");
+
+        let StreamChunk::Done(completion) = StreamChunk::done(usage, Some(200)) else {
+            panic!("expected a done chunk");
+        };
+        assert_eq!(completion.finish_reason.as_deref(), Some("length"));
+        assert_eq!(completion.usage.map(|usage| usage.completion_tokens), Some(200));
+    }
+
+    #[test]
+    fn test_parse_provider_names() {
+        assert!(matches!(AIProvider::parse("Claude"), Some(AIProvider::Anthropic)));
+        assert!(matches!(AIProvider::parse("google"), Some(AIProvider::Gemini)));
+        assert!(matches!(AIProvider::parse(" ollama "), Some(AIProvider::Ollama)));
+        assert!(AIProvider::parse("mistral").is_none());
+        assert_eq!(AIProvider::OpenRouter.key(), "openrouter");
+    }
+
+    #[test]
+    fn test_supported_providers_match_completion_models() {
+        // Exhaustive, so a provider added to `get_completion_model` fails to compile until listed here
+        fn provider_id(model: &ProviderCompletionModel) -> &'static str {
+            match model {
+                ProviderCompletionModel::OpenAI(_) => "openai",
+                ProviderCompletionModel::Anthropic(_) => "anthropic",
+                ProviderCompletionModel::Gemini(_) => "gemini",
+                ProviderCompletionModel::Ollama(_) => "ollama",
+                ProviderCompletionModel::DeepSeek(_) => "deepseek",
+                ProviderCompletionModel::OpenRouter(_) => "openrouter",
+            }
+        }
+
+        let descriptors = supported_providers();
+        let ids: Vec<&str> = descriptors.iter().map(|descriptor| descriptor.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            ["openai", "anthropic", "gemini", "ollama", "deepseek", "openrouter"]
+        );
+
+        for descriptor in &descriptors {
+            let provider = AIProvider::parse(&descriptor.id).unwrap();
+            assert_eq!(provider.key(), descriptor.id);
+            assert_eq!(descriptor.requires_key, descriptor.key_env_var.is_some());
+            assert!(!descriptor.default_base_url.is_empty());
+            assert!(descriptor.capabilities.iter().any(|capability| capability == "chat"));
+        }
+
+        // Ollama needs no API key, so its completion model can be built in tests
+        let agent = RigAgent::with_provider(AIProvider::Ollama).unwrap();
+        let model = agent
+            .get_completion_model(&AIProvider::Ollama, &AIProvider::Ollama.default_model(), None)
+            .unwrap();
+        assert_eq!(provider_id(&model), "ollama");
+        assert!(!descriptors[3].requires_key);
+    }
+
+    #[test]
+    fn test_provider_defaults_describe_the_default_model() {
+        for provider in AIProvider::ALL {
+            let default = describe_provider_default(&provider, None);
+            assert_eq!(default.model, provider.default_model());
+            assert!(
+                default.context_length >= 8192,
+                "{:?}: {}",
+                provider,
+                default.context_length
+            );
+            assert!(default.capabilities.supports_streaming);
+        }
+
+        let openai = describe_provider_default(&AIProvider::OpenAI, None);
+        assert_eq!(openai.context_length, 128000);
+        assert!(openai.capabilities.supports_vision);
+        assert_eq!(
+            describe_provider_default(&AIProvider::Gemini, None).context_length,
+            1000000
+        );
+    }
+
+    #[test]
+    fn test_unlisted_default_model_uses_the_reported_or_a_small_context() {
+        let openrouter = describe_provider_default(&AIProvider::OpenRouter, None);
+        assert_eq!(openrouter.model, "openrouter/auto");
+        assert_eq!(openrouter.context_length, FALLBACK_CONTEXT_LENGTH);
+
+        let reported = [ModelInfo::new("openrouter/auto", "Auto Router", String::new(), 2000000)];
+        let openrouter = describe_provider_default(&AIProvider::OpenRouter, Some(&reported));
+        assert_eq!(openrouter.context_length, 2000000);
+    }
+
+    #[test]
+    fn test_model_ids_are_normalized_per_provider() {
+        assert_eq!(AIProvider::OpenAI.normalize_model_id("openai/gpt-4o", None), "gpt-4o");
+        assert_eq!(AIProvider::OpenAI.normalize_model_id("gpt-4o", None), "gpt-4o");
+        assert_eq!(
+            AIProvider::Gemini.normalize_model_id("google/gemini-2.0-flash", None),
+            "gemini-2.0-flash"
+        );
+        assert_eq!(
+            AIProvider::OpenRouter.normalize_model_id("gpt-4o", None),
+            "openai/gpt-4o"
+        );
+        assert_eq!(
+            AIProvider::OpenRouter.normalize_model_id("claude-3-5-sonnet", None),
+            "anthropic/claude-3-5-sonnet"
+        );
+        assert_eq!(
+            AIProvider::OpenRouter.normalize_model_id("openai/gpt-4o", None),
+            "openai/gpt-4o"
+        );
+
+        // Behind a gateway the prefix may be part of the id
+        assert_eq!(
+            AIProvider::OpenAI.normalize_model_id("openai/gpt-4o", Some("http://localhost:4000/v1")),
+            "openai/gpt-4o"
+        );
+        assert_eq!(
+            AIProvider::OpenAI.normalize_model_id("openai/gpt-4o", Some("https://api.openai.com/v1/")),
+            "gpt-4o"
+        );
+
+        // Other vendors' prefixes and unknown formats pass through unchanged
+        assert_eq!(
+            AIProvider::OpenAI.normalize_model_id("mistral/large", None),
+            "mistral/large"
+        );
+        assert_eq!(
+            AIProvider::OpenRouter.normalize_model_id("openrouter/auto", None),
+            "openrouter/auto"
+        );
+        assert_eq!(
+            AIProvider::OpenRouter.normalize_model_id("mystery-model", None),
+            "mystery-model"
+        );
+
+        let agent = RigAgent::with_provider(AIProvider::Ollama).unwrap();
+        let model = agent
+            .get_completion_model(&AIProvider::Ollama, "ollama/llama3.2", None)
+            .unwrap();
+        assert!(matches!(model, ProviderCompletionModel::Ollama(model) if model.model == "llama3.2"));
+    }
+
+    #[tokio::test]
+    async fn test_structured_output_is_rejected_outside_generate() {
+        let agent = RigAgent::with_provider(AIProvider::Ollama).unwrap();
+        let options = AIOptions {
+            prompt: "List three colors".to_string(),
+            response_format: Some(ResponseFormat::JsonObject),
+            ..Default::default()
+        };
+        let rejected = |result: Result<(), RigAgentError>| matches!(result, Err(RigAgentError::NotSupported(message)) if message.contains("response_format"));
+
+        let chat = agent
+            .chat(
+                vec![ChatMessage {
+                    role: "user".to_string(),
+                    content: "List three colors".to_string(),
+                }],
+                Some(options.clone()),
+            )
+            .await;
+        assert!(rejected(chat.map(|_| ())));
+        let tools = agent
+            .generate_with_tools(options.clone(), Vec::new(), HashMap::new())
+            .await;
+        assert!(rejected(tools.map(|_| ())));
+        let first = agent.generate_stream(options).next().await.unwrap();
+        assert!(rejected(first.map(|_| ())));
+    }
+
+    #[tokio::test]
+    async fn test_provider_override_applies_to_every_capability() {
+        // Ollama needs no API key, so the agent can be built in tests
+        let agent = RigAgent::with_provider(AIProvider::Ollama).unwrap();
+
+        let embed = agent
+            .embed(EmbeddingRequest {
+                text: "hello".to_string(),
+                model: None,
+                provider: Some("deepseek".to_string()),
+            })
+            .await;
+        assert!(matches!(embed, Err(RigAgentError::NotSupported(message)) if message.contains("DeepSeek")));
+
+        let moderation = agent
+            .moderate(ModerationRequest {
+                content: "hello".to_string(),
+                provider: Some("gemini".to_string()),
+                base_url: None,
+            })
+            .await;
+        assert!(matches!(moderation, Err(RigAgentError::NotSupported(_))));
+
+        let analysis = agent
+            .analyze_image(ImageAnalysisRequest {
+                image_url: "https://example.com/cat.png".to_string(),
+                prompt: "Describe".to_string(),
+                model: None,
+                provider: Some("anthropic".to_string()),
+            })
+            .await;
+        assert!(matches!(analysis, Err(RigAgentError::NotSupported(_))));
+
+        assert!(matches!(agent.provider_override(Some("openai")), AIProvider::OpenAI));
+        assert!(matches!(agent.provider_override(Some("unknown")), AIProvider::Ollama));
+        assert!(matches!(agent.provider_override(None), AIProvider::Ollama));
+    }
+
+    #[tokio::test]
+    async fn test_request_base_url_replaces_the_provider_endpoint() {
+        use std::sync::Mutex;
+
+        // A mock Ollama gateway that records the paths it is asked for
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let recorder = paths.clone();
+        let app = axum::Router::new().fallback(move |uri: axum::http::Uri| async move {
+            recorder.lock().unwrap().push(uri.path().to_string());
+            "{}"
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let agent = RigAgent::with_provider(AIProvider::Ollama).unwrap();
+        // The mock's empty reply doesn't parse; only where the request went matters here
+        let _ = agent
+            .generate(AIOptions {
+                prompt: "Hello".to_string(),
+                base_url: Some(format!("http://{}/gateway/", addr)),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(
+            paths.lock().unwrap().first().map(String::as_str),
+            Some("/gateway/api/chat")
+        );
+
+        assert_eq!(
+            AIProvider::OpenAI.base_url(Some(" http://localhost:4000/v1/ ")),
+            "http://localhost:4000/v1"
+        );
+        assert_eq!(
+            AIProvider::OpenAI.descriptor().base_url_env_var.as_deref(),
+            Some("OPENAI_BASE_URL")
+        );
+    }
+
+    #[test]
+    fn test_stored_key_only_goes_to_the_configured_endpoint() {
+        let provider = AIProvider::Anthropic;
+        assert!(!sends_elsewhere(&provider, None));
+        assert!(!sends_elsewhere(&provider, Some(" ")));
+        assert!(!sends_elsewhere(&provider, Some("https://api.anthropic.com/")));
+        assert!(sends_elsewhere(&provider, Some("https://gateway.example.com")));
+        assert!(sends_elsewhere(&provider, Some("http://api.anthropic.com")));
+        assert!(sends_elsewhere(&provider, Some("not a url")));
+
+        // A foreign host needs no stored key, and gets none
+        assert_eq!(
+            provider_api_key_for(&provider, Some("https://gateway.example.com")).unwrap(),
+            ""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_identical_cacheable_generations_reach_the_model_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // A mock Ollama model that counts the completions it is asked for
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route(
+            "/api/chat",
+            axum::routing::post(move || async move {
+                let hit = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                axum::Json(serde_json::json!({
+                    "model": "llama3.2",
+                    "created_at": "2024-05-01T09:00:00Z",
+                    "message": { "role": "assistant", "content": format!("reply {}", hit) },
+                    "done": true
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let agent = RigAgent {
+            cache: Arc::new(ResponseCache::new(Duration::from_secs(60), 8)),
+            ..RigAgent::with_provider(AIProvider::Ollama).unwrap()
+        };
+        let options = |prompt: &str, cacheable| AIOptions {
+            prompt: prompt.to_string(),
+            base_url: Some(format!("http://{}", addr)),
+            cacheable,
+            ..Default::default()
+        };
+
+        let first = agent.generate(options("Hello", true)).await.unwrap();
+        let second = agent.generate(options("Hello", true)).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert_eq!((first.text.as_str(), second.text.as_str()), ("reply 1", "reply 1"));
+
+        // Uncacheable and different requests still reach the model
+        agent.generate(options("Hello", false)).await.unwrap();
+        agent.generate(options("Goodbye", true)).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_forced_model_list_bypasses_cache() {
+        use crate::storage::MemoryStorage;
+
+        let mut agent = RigAgent::with_provider(AIProvider::Ollama).unwrap();
+        agent.models = Arc::new(ModelListCache::load(
+            Arc::new(MemoryStorage::new()),
+            Duration::from_secs(3600),
+        ));
+        let cached = ModelInfo::new("cached-model", "Cached", String::new(), 8192);
+        agent.models.store("ollama", vec![cached]);
+
+        let models = agent.get_models(None, false).await.unwrap();
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].id, "cached-model");
+
+        let models = agent.get_models(None, true).await.unwrap();
+        assert!(models.iter().all(|model| model.id != "cached-model"));
+        assert!(!models.is_empty());
+        // The fetched list replaces the cached one
+        assert_eq!(agent.models.get("ollama").unwrap().models.len(), models.len());
+    }
+
+    #[test]
+    fn test_default_embedding_models() {
+        assert_eq!(
+            default_embedding_model(&AIProvider::OpenAI),
+            Some("text-embedding-3-small")
+        );
+        assert_eq!(default_embedding_model(&AIProvider::Gemini), Some("text-embedding-004"));
+        assert_eq!(default_embedding_model(&AIProvider::Ollama), Some("nomic-embed-text"));
+        assert_eq!(default_embedding_model(&AIProvider::DeepSeek), None);
+    }
+
+    #[test]
+    fn test_parse_moderation_response() {
+        let body = r#"{
+            "id": "modr-123",
+            "model": "omni-moderation-latest",
+            "results": [{
+                "flagged": true,
+                "categories": { "harassment": true, "violence": false },
+                "category_scores": { "harassment": 0.91, "violence": 0.02 },
+                "category_applied_input_types": { "harassment": ["text"], "violence": ["text"] }
+            }]
+        }"#;
+
+        let moderation = parse_moderation_response(body).unwrap();
+        assert!(moderation.flagged);
+        assert_eq!(moderation.categories.get("harassment"), Some(&true));
+        assert_eq!(moderation.categories.get("violence"), Some(&false));
+        assert_eq!(moderation.category_scores.get("harassment"), Some(&0.91));
+
+        assert!(matches!(
+            parse_moderation_response(r#"{"results": []}"#),
+            Err(RigAgentError::Other(_))
+        ));
+    }
+
+    #[test]
+    fn test_image_generation_body_validates_request() {
+        let request = |size: Option<&str>, quality: Option<&str>, n: Option<u32>| ImageGenerationRequest {
+            prompt: "a lighthouse at dusk".to_string(),
+            size: size.map(String::from),
+            quality: quality.map(String::from),
+            n,
+            provider: None,
+            base_url: None,
+        };
+
+        let body = image_generation_body(&request(Some("1792x1024"), Some("hd"), None)).unwrap();
+        assert_eq!(body["model"], "dall-e-3");
+        assert_eq!(body["size"], "1792x1024");
+        assert_eq!(body["quality"], "hd");
+        assert_eq!(body["n"], 1);
+
+        let body = image_generation_body(&request(None, None, Some(1))).unwrap();
+        assert_eq!(body["size"], "1024x1024");
+        assert!(body.get("quality").is_none());
+
+        // A batch is refused instead of switching to another model
+        for invalid in [
+            request(Some("800x600"), None, None),
+            request(None, None, Some(4)),
+            request(Some("512x512"), None, Some(2)),
+            request(None, Some("ultra"), None),
+            request(None, None, Some(0)),
+        ] {
+            assert!(matches!(
+                image_generation_body(&invalid),
+                Err(RigAgentError::InvalidModel(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reported_usage() {
+        let usage = |input_tokens, output_tokens, total_tokens| rig::completion::Usage {
+            input_tokens,
+            output_tokens,
+            total_tokens,
+        };
+
+        let reported = reported_usage(usage(120, 30, 150)).unwrap();
+        assert_eq!(
+            (
+                reported.prompt_tokens,
+                reported.completion_tokens,
+                reported.total_tokens
+            ),
+            (120, 30, 150)
+        );
+        // A missing total is derived from the parts
+        assert_eq!(reported_usage(usage(40, 2, 0)).unwrap().total_tokens, 42);
+        assert!(reported_usage(usage(0, 0, 0)).is_none());
+    }
+
+    #[test]
+    fn test_tool_and_other_errors() {
+        let err: RigAgentError = PromptError::ToolError(ToolSetError::ToolNotFoundError("lookup".to_string())).into();
+        assert!(matches!(&err, RigAgentError::ToolError(message) if message.contains("lookup")));
+
+        let err = completion_error(CompletionError::ProviderError("model overloaded".to_string()));
+        assert!(matches!(
+            err,
+            RigAgentError::PromptError(PromptError::CompletionError(CompletionError::ProviderError(_)))
+        ));
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    struct NoStream;
+
+    impl rig::completion::GetTokenUsage for NoStream {
+        fn token_usage(&self) -> Option<rig::completion::Usage> {
+            None
+        }
+    }
+
+    type MockStreamItem = Result<rig::streaming::RawStreamingChoice<NoStream>, CompletionError>;
+    type MockCompletion = Arc<dyn Fn(rig::completion::CompletionRequest) -> AssistantContent + Send + Sync>;
+    type MockStream = Arc<dyn Fn(rig::completion::CompletionRequest) -> Vec<MockStreamItem> + Send + Sync>;
+
+    /// A model answering from a closure, either as one completion or as a stream of items
+    #[derive(Clone, Default)]
+    struct MockModel {
+        complete: Option<MockCompletion>,
+        stream: Option<MockStream>,
+    }
+
+    impl MockModel {
+        fn completing(
+            reply: impl Fn(rig::completion::CompletionRequest) -> AssistantContent + Send + Sync + 'static,
+        ) -> Self {
+            Self {
+                complete: Some(Arc::new(reply)),
+                stream: None,
+            }
+        }
+
+        fn streaming(
+            items: impl Fn(rig::completion::CompletionRequest) -> Vec<MockStreamItem> + Send + Sync + 'static,
+        ) -> Self {
+            Self {
+                complete: None,
+                stream: Some(Arc::new(items)),
+            }
+        }
+    }
+
+    impl CompletionModel for MockModel {
+        type Response = ();
+        type StreamingResponse = NoStream;
+        type Client = ();
+
+        fn make(_: &Self::Client, _: impl Into<String>) -> Self {
+            Self::default()
+        }
+
+        async fn completion(
+            &self,
+            request: rig::completion::CompletionRequest,
+        ) -> Result<rig::completion::CompletionResponse<()>, CompletionError> {
+            let complete = self
+                .complete
+                .as_ref()
+                .ok_or_else(|| CompletionError::ProviderError("only streaming is supported".to_string()))?;
+            Ok(rig::completion::CompletionResponse {
+                choice: OneOrMany::one(complete(request)),
+                usage: rig::completion::Usage::new(),
+                raw_response: (),
+            })
+        }
+
+        async fn stream(
+            &self,
+            request: rig::completion::CompletionRequest,
+        ) -> Result<rig::streaming::StreamingCompletionResponse<NoStream>, CompletionError> {
+            let stream = self
+                .stream
+                .as_ref()
+                .ok_or_else(|| CompletionError::ProviderError("streaming not supported".to_string()))?;
+            Ok(rig::streaming::StreamingCompletionResponse::stream(Box::pin(
+                futures::stream::iter(stream(request)),
+            )))
+        }
+    }
+
+    /// Calls `get_weather` first, then answers using the tool result
+    fn weather_model() -> MockModel {
+        MockModel::completing(|request| {
+            assert!(request.tools.iter().any(|tool| tool.name == "get_weather"));
+
+            let tool_output = match request.chat_history.last() {
+                Message::User { content } => content.iter().find_map(|content| match content {
+                    UserContent::ToolResult(result) => match result.content.first() {
+                        ToolResultContent::Text(text) => Some(text.text),
+                        _ => None,
+                    },
+                    _ => None,
+                }),
+                _ => None,
+            };
+            match tool_output {
+                Some(output) => AssistantContent::text(format!("It is {} in Paris.", output)),
+                None => AssistantContent::tool_call("call_1", "get_weather", serde_json::json!({ "city": "Paris" })),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_tool_loop_runs_handler_and_uses_result() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = calls.clone();
+        let handler: ToolHandler = Arc::new(move |args| {
+            handler_calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                assert_eq!(args["city"], "Paris");
+                Ok("18°C and sunny".to_string())
+            })
+        });
+        let tools = vec![ToolDef {
+            name: "get_weather".to_string(),
+            description: "Current weather for a city".to_string(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": { "city": { "type": "string" } }
+            }),
+        }];
+        let handlers = HashMap::from([("get_weather".to_string(), handler)]);
+
+        let text = run_tool_loop(
+            weather_model(),
+            Message::user("Weather in Paris?"),
+            None,
+            &tools,
+            &handlers,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(text, "It is 18°C and sunny in Paris.");
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_retries_transient_statuses() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route(
+            "/models",
+            axum::routing::get(move || {
+                let counter = counter.clone();
+                async move {
+                    match counter.fetch_add(1, Ordering::SeqCst) {
+                        0 => (StatusCode::SERVICE_UNAVAILABLE, [("retry-after", "0")], "busy"),
+                        1 => (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "0")], "slow down"),
+                        _ => (StatusCode::OK, [("retry-after", "0")], "ok"),
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/models", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = create_http_client(&AIProvider::OpenAI, Duration::from_secs(5)).unwrap();
+        let retry = RetryPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_secs(1),
+        };
+        let response = send_with_retry(client.get(&url), &retry).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        // Once retries run out the last transient response is returned
+        hits.store(0, Ordering::SeqCst);
+        let retry = RetryPolicy {
+            max_retries: 1,
+            ..retry
+        };
+        let response = send_with_retry(client.get(&url), &retry).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_stream_failures_classify_transient_errors() {
+        #[derive(Debug, Error)]
+        #[error("CompletionError: {0}")]
+        struct StreamError(#[from] CompletionError);
+
+        let unavailable = StreamFailure::from_stream_error(StreamError(CompletionError::HttpError(
+            rig::http_client::Error::InvalidStatusCode(StatusCode::BAD_GATEWAY),
+        )));
+        assert!(unavailable.transient);
+
+        let rate_limited = StreamFailure::from_stream_error(StreamError(CompletionError::HttpError(
+            rig::http_client::Error::InvalidStatusCodeWithMessage(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Please try again in 2s".to_string(),
+            ),
+        )));
+        assert!(rate_limited.transient);
+        assert_eq!(rate_limited.retry_after_ms, Some(2_000));
+
+        let bad_request = StreamFailure::from_stream_error(StreamError(CompletionError::HttpError(
+            rig::http_client::Error::InvalidStatusCode(StatusCode::BAD_REQUEST),
+        )));
+        assert!(!bad_request.transient);
+
+        let provider =
+            StreamFailure::from_stream_error(StreamError(CompletionError::ProviderError("invalid model".to_string())));
+        assert!(!provider.transient);
+    }
+
+    /// Streams "The quick brown " and then drops, or continues the sentence when the
+    /// conversation ends with that text prefilled as the assistant's turn
+    fn interrupted_model() -> MockModel {
+        MockModel::streaming(|request| {
+            use rig::streaming::RawStreamingChoice;
+
+            let prefill = match request.chat_history.last() {
+                Message::Assistant { content, .. } => match content.first() {
+                    AssistantContent::Text(text) => Some(text.text),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match prefill.as_deref() {
+                Some("The quick brown") => vec![
+                    Ok(RawStreamingChoice::Message(" fox jumps.".to_string())),
+                    Ok(RawStreamingChoice::FinalResponse(NoStream)),
+                ],
+                Some(other) => panic!("unexpected prefill: {:?}", other),
+                None => vec![
+                    Ok(RawStreamingChoice::Message("The quick ".to_string())),
+                    Ok(RawStreamingChoice::Message("brown ".to_string())),
+                    Err(CompletionError::HttpError(rig::http_client::Error::StreamEnded)),
+                ],
+            }
+        })
+    }
+
+    async fn collect_text(stream: ChunkStream) -> (String, Option<Result<StreamChunk, RigAgentError>>) {
+        let mut items: Vec<_> = stream.collect().await;
+        let last = items.pop();
+        let text = items
+            .into_iter()
+            .map(|item| match item {
+                Ok(StreamChunk::Text(text)) => text,
+                other => panic!("expected a text chunk, got {:?}", other),
+            })
+            .collect();
+        (text, last)
+    }
+
+    #[tokio::test]
+    async fn test_resumed_stream_extends_interrupted_text() {
+        use crate::stream_checkpoint::CheckpointStore;
+
+        let store = Arc::new(CheckpointStore::default());
+        let agent = AgentBuilder::new(interrupted_model()).build();
+        let retry = RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        };
+        let options = AIOptions {
+            prompt: "Finish the sentence".to_string(),
+            request_id: Some("run-1".to_string()),
+            ..Default::default()
+        };
+
+        let stream_agent = agent.clone();
+        let prompt = Message::user(&options.prompt);
+        let checkpointer = Checkpointer::new(store.clone(), "run-1".to_string(), options.clone(), String::new());
+        let interrupted = RigAgent::run_stream(
+            move |tx| {
+                let (agent, prompt) = (stream_agent.clone(), prompt.clone());
+                async move { RigAgent::stream_model(agent, prompt, Vec::new(), &tx).await }
+            },
+            retry,
+            STREAM_STALL_TIMEOUT,
+            Some(checkpointer),
+        );
+        let (text, last) = collect_text(interrupted).await;
+        assert_eq!(text, "The quick brown ");
+        assert!(
+            matches!(last, Some(Err(_))),
+            "stream should end in an error, got {:?}",
+            last
+        );
+
+        let checkpoint = store.get("run-1").unwrap();
+        assert_eq!(checkpoint.text, "The quick brown ");
+
+        let partial = checkpoint.text.trim_end().to_string();
+        let (prompt, history) = RigAgent::continuation_messages(&checkpoint.options, "test-model", &partial).unwrap();
+        let checkpointer = Checkpointer::new(store.clone(), "run-1".to_string(), checkpoint.options, partial.clone());
+        let resumed = RigAgent::run_stream(
+            move |tx| {
+                let (agent, prompt, history) = (agent.clone(), prompt.clone(), history.clone());
+                async move { RigAgent::stream_model(agent, prompt, history, &tx).await }
+            },
+            retry,
+            STREAM_STALL_TIMEOUT,
+            Some(checkpointer),
+        );
+        let (continuation, last) = collect_text(resumed).await;
+
+        assert!(matches!(last, Some(Ok(StreamChunk::Done(_)))));
+        assert!(!continuation.starts_with("The quick"));
+        assert_eq!(format!("{}{}", partial, continuation), "The quick brown fox jumps.");
+        // A completed stream leaves nothing to resume
+        assert!(store.get("run-1").is_none());
+    }
+
+    /// Streams its reasoning as deltas, repeats it as one block, then answers
+    fn reasoning_model() -> MockModel {
+        MockModel::streaming(|_| {
+            use rig::streaming::RawStreamingChoice;
+
+            vec![
+                Ok(RawStreamingChoice::ReasoningDelta {
+                    id: None,
+                    reasoning: "2 + 2 ".to_string(),
+                }),
+                Ok(RawStreamingChoice::ReasoningDelta {
+                    id: None,
+                    reasoning: "is 4.".to_string(),
+                }),
+                Ok(RawStreamingChoice::Reasoning {
+                    id: None,
+                    reasoning: "2 + 2 is 4.".to_string(),
+                    signature: None,
+                }),
+                Ok(RawStreamingChoice::Message("4".to_string())),
+                Ok(RawStreamingChoice::FinalResponse(NoStream)),
+            ]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_is_streamed_separately_from_text() {
+        let agent = AgentBuilder::new(reasoning_model()).build();
+        let stream = RigAgent::run_stream(
+            move |tx| {
+                let agent = agent.clone();
+                async move { RigAgent::stream_model(agent, Message::user("2 + 2?"), Vec::new(), &tx).await }
+            },
+            RetryPolicy::default(),
+            STREAM_STALL_TIMEOUT,
+            None,
+        );
+        let chunks: Vec<_> = stream.collect().await;
+
+        let reasoning: Vec<&str> = chunks
+            .iter()
+            .filter_map(|chunk| match chunk {
+                Ok(StreamChunk::Reasoning(reasoning)) => Some(reasoning.as_str()),
+                _ => None,
+            })
+            .collect();
+        // The repeated block is not sent again
+        assert_eq!(reasoning, vec!["2 + 2 ", "is 4."]);
+        assert!(matches!(&chunks[2], Ok(StreamChunk::Text(text)) if text == "4"));
+        assert!(matches!(chunks.last(), Some(Ok(StreamChunk::Done(_)))));
+    }
+
+    /// Streams back the system prompt it was given, and how many messages it received
+    fn preamble_echo_model() -> MockModel {
+        MockModel::streaming(|request| {
+            use rig::streaming::RawStreamingChoice;
+
+            let echo = format!(
+                "{} ({} messages)",
+                request.preamble.unwrap_or_default(),
+                request.chat_history.len()
+            );
+            vec![
+                Ok(RawStreamingChoice::Message(echo)),
+                Ok(RawStreamingChoice::FinalResponse(NoStream)),
+            ]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt_is_sent_as_preamble() {
+        let agent = RigAgent::stream_agent(
+            AgentBuilder::new(preamble_echo_model()),
+            Some("Answer in French."),
+            None,
+            None,
+        );
+        let stream = RigAgent::run_stream(
+            move |tx| {
+                let agent = agent.clone();
+                async move { RigAgent::stream_model(agent, Message::user("Hello"), Vec::new(), &tx).await }
+            },
+            RetryPolicy::default(),
+            STREAM_STALL_TIMEOUT,
+            None,
+        );
+        let chunks: Vec<_> = stream.collect().await;
+
+        // The instruction goes in the preamble, not in the conversation
+        assert!(matches!(&chunks[0], Ok(StreamChunk::Text(text)) if text == "Answer in French. (1 messages)"));
+    }
+
+    #[test]
+    fn test_message_reasoning_collects_assistant_reasoning() {
+        let reply = Message::Assistant {
+            id: None,
+            content: rig::OneOrMany::many(vec![
+                AssistantContent::Reasoning(rig::completion::message::Reasoning::new("2 + 2 is 4.")),
+                AssistantContent::text("4"),
+            ])
+            .unwrap(),
+        };
+        assert_eq!(
+            message_reasoning(&[Message::user("2 + 2?"), reply]).as_deref(),
+            Some("2 + 2 is 4.")
+        );
+        assert_eq!(message_reasoning(&[Message::assistant("4")]), None);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_stream_cancels_generation() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        /// Set when the attempt's future is dropped, i.e. its task was aborted
+        struct Cancelled(Arc<AtomicBool>);
+
+        impl Drop for Cancelled {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let flag = cancelled.clone();
+        let mut stream = RigAgent::run_stream(
+            move |tx| {
+                let guard = Cancelled(flag.clone());
+                async move {
+                    let _guard = guard;
+                    let _ = tx.send(Ok(StreamChunk::Text("Hello".to_string()))).await;
+                    // A provider that stalls before its next chunk
+                    futures::future::pending::<()>().await;
+                }
+            },
+            RetryPolicy::default(),
+            STREAM_STALL_TIMEOUT,
+            None,
+        );
+
+        assert!(matches!(stream.next().await, Some(Ok(StreamChunk::Text(text))) if text == "Hello"));
+        drop(stream);
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !cancelled.load(Ordering::SeqCst) {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("dropping the stream should abort the provider task");
+    }
+
+    #[tokio::test]
+    async fn test_stalled_stream_fails_after_the_stall_timeout() {
+        let stream = RigAgent::run_stream(
+            move |tx| async move {
+                let _ = tx.send(Ok(StreamChunk::Text("Hello".to_string()))).await;
+                futures::future::pending::<()>().await;
+            },
+            RetryPolicy::default(),
+            Duration::from_millis(50),
+            None,
+        );
+        let chunks: Vec<_> = tokio::time::timeout(Duration::from_secs(1), stream.collect())
+            .await
+            .expect("a stalled stream should end");
+
+        assert_eq!(chunks.len(), 2);
+        assert!(matches!(&chunks[1], Err(RigAgentError::HttpError(message)) if message.starts_with("No response")));
+    }
+
+    #[tokio::test]
+    async fn test_streams_are_recorded_when_they_finish() {
+        let requests: &'static RecentRequests = Box::leak(Box::new(RecentRequests::new(10, false)));
+        let completion = StreamCompletion {
+            id: "stream-1".to_string(),
+            usage: Some(TokenUsage {
+                prompt_tokens: 5,
+                completion_tokens: 7,
+                total_tokens: 12,
+            }),
+            finish_reason: Some("stop".to_string()),
+        };
+        let chunks =
+            |items: Vec<Result<StreamChunk, RigAgentError>>| -> ChunkStream { Box::pin(futures::stream::iter(items)) };
+        let start = |model: &str| requests.start(RequestSource::Ai, "openai", Some(model), "hello");
+
+        let completed = record_stream(
+            requests,
+            start("completed"),
+            chunks(vec![
+                Ok(StreamChunk::Text("Hi".to_string())),
+                Ok(StreamChunk::Done(completion)),
+            ]),
+        );
+        assert_eq!(completed.collect::<Vec<_>>().await.len(), 2);
+        let failed = record_stream(
+            requests,
+            start("failed"),
+            chunks(vec![Err(RigAgentError::Other("connection reset".to_string()))]),
+        );
+        failed.collect::<Vec<_>>().await;
+        let mut dropped = record_stream(
+            requests,
+            start("dropped"),
+            chunks(vec![
+                Ok(StreamChunk::Text("Hi".to_string())),
+                Ok(StreamChunk::Text(" there".to_string())),
+            ]),
+        );
+        dropped.next().await;
+        drop(dropped);
+
+        let records = requests.recent();
+        let outcomes: Vec<_> = records
+            .iter()
+            .map(|record| {
+                (
+                    record.model.as_deref().unwrap(),
+                    record.success,
+                    record.error.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("completed", true, None),
+                ("failed", false, Some("Other error: connection reset")),
+                ("dropped", false, Some("Stream ended before completing")),
+            ]
+        );
+        assert_eq!(records[0].usage.as_ref().map(|usage| usage.total_tokens), Some(12));
     }
 }
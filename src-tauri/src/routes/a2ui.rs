@@ -3,13 +3,19 @@
 //! This module contains all HTTP handlers for A2UI (Agent-to-UI) service endpoints.
 //! It provides surface management, agent chat with streaming, and plugin generation capabilities.
 
-use crate::a2ui::agent::{A2UIAgent, GeneratedResponse};
+use crate::a2ui::agent::{A2UIAgent, A2UIAgentError};
 use crate::a2ui::plugin_generator::{
-    generate_default_manifest, generate_plugin_code, sanitize_plugin_name, PluginGenerationRequest,
-    PluginGenerationResponse,
+    generate_default_manifest, generate_plugin_code, load_generated_plugin, sanitize_plugin_name,
+    save_generated_plugin, PluginGenerationRequest, PluginGenerationResponse, PluginManifest,
 };
+use crate::a2ui::provider::ProviderError;
 use crate::a2ui::schema::*;
-use crate::rig_agent::{AIOptions, RigAgent};
+use crate::conversation_export::a2ui_session_chunks;
+use crate::diagnostics::{record_error, Subsystem};
+use crate::logging::ai_debug;
+use crate::rig_agent::{AIOptions, ChunkStream, RigAgent, StreamChunk};
+use crate::routes::{markdown_response, rate_limited_response};
+use crate::storage::Storage;
 use axum::{
     extract::{Path, State},
     http::{self},
@@ -17,19 +23,25 @@ use axum::{
     routing::{delete, get, post},
     Json, Router,
 };
-use futures_util::stream;
+use futures_util::StreamExt;
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tauri_plugin_log::log::{info, warn};
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 /// The application state used by A2UI handlers
 #[derive(Clone)]
 pub struct A2UIState {
-    pub surfaces: Arc<Mutex<HashMap<String, SurfaceState>>>,
+    pub surfaces: SurfaceStore,
     pub a2ui_agent: Option<Arc<A2UIAgent>>,
     pub rig_agent: Option<Arc<RigAgent>>,
+    /// Where generated plugins are saved
+    pub plugin_storage: Arc<dyn Storage>,
 }
 
 /// State for a single surface
@@ -40,6 +52,142 @@ pub struct SurfaceState {
     pub data_model: HashMap<String, serde_json::Value>,
 }
 
+/// Surfaces kept when `A2UI_MAX_SURFACES` is unset, so a long-running server stays bounded
+const DEFAULT_MAX_SURFACES: usize = 200;
+
+/// Surface cap for an `A2UI_MAX_SURFACES` value, falling back to the default when unset or invalid
+fn max_surfaces_from(value: Option<&str>) -> Option<usize> {
+    match value.and_then(|value| value.trim().parse().ok()) {
+        Some(0) => None,
+        Some(max) => Some(max),
+        None => Some(DEFAULT_MAX_SURFACES),
+    }
+}
+
+struct SurfaceEntry {
+    surface: Arc<RwLock<SurfaceState>>,
+    /// Agent session the surface belongs to; it is pruned once the session is gone
+    session_id: Option<String>,
+    /// Store clock reading when the surface was added, so pruning can skip newer surfaces
+    created: u64,
+    /// Store clock reading at the surface's last lookup, for LRU eviction
+    last_used: AtomicU64,
+}
+
+/// Surfaces shared across handlers, each behind its own lock
+///
+/// The map is only locked for writing to add or remove a surface, so reads and updates
+/// of different surfaces never wait on each other, and reads of the same surface share it.
+/// With `max_surfaces` set, adding a surface beyond the cap evicts the least recently used.
+#[derive(Clone, Default)]
+pub struct SurfaceStore {
+    surfaces: Arc<RwLock<HashMap<String, SurfaceEntry>>>,
+    clock: Arc<AtomicU64>,
+    /// Maximum number of surfaces kept; `None` means unbounded
+    max_surfaces: Option<usize>,
+}
+
+impl SurfaceStore {
+    pub fn new(max_surfaces: Option<usize>) -> Self {
+        Self {
+            max_surfaces,
+            ..Self::default()
+        }
+    }
+
+    /// Capped by `A2UI_MAX_SURFACES` (default 200); `0` leaves the store unbounded
+    pub fn from_env() -> Self {
+        Self::new(max_surfaces_from(std::env::var("A2UI_MAX_SURFACES").ok().as_deref()))
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The store clock now; surfaces added from here on are newer than the snapshot
+    pub fn snapshot(&self) -> u64 {
+        self.tick()
+    }
+
+    /// Add a surface owned by `session_id`, replacing any with the same id
+    ///
+    /// Returns the ids of surfaces evicted to stay within `max_surfaces`.
+    pub async fn insert(&self, surface: SurfaceState, session_id: Option<String>) -> Vec<String> {
+        let id = surface.id.clone();
+        let mut surfaces = self.surfaces.write().await;
+        let mut evicted = Vec::new();
+        if let Some(max_surfaces) = self.max_surfaces {
+            while !surfaces.contains_key(&id) && surfaces.len() >= max_surfaces {
+                let Some(oldest) = surfaces
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                    .map(|(id, _)| id.clone())
+                else {
+                    break;
+                };
+                surfaces.remove(&oldest);
+                info!("Surface limit {} reached, evicted surface {}", max_surfaces, oldest);
+                evicted.push(oldest);
+            }
+        }
+
+        let created = self.tick();
+        let entry = SurfaceEntry {
+            surface: Arc::new(RwLock::new(surface)),
+            session_id,
+            created,
+            last_used: AtomicU64::new(created),
+        };
+        surfaces.insert(id, entry);
+        evicted
+    }
+
+    /// Remove a surface, returning whether it existed
+    pub async fn remove(&self, surface_id: &str) -> bool {
+        self.surfaces.write().await.remove(surface_id).is_some()
+    }
+
+    /// The surface with this id, to lock for reading or writing
+    pub async fn get(&self, surface_id: &str) -> Option<Arc<RwLock<SurfaceState>>> {
+        let surfaces = self.surfaces.read().await;
+        let entry = surfaces.get(surface_id)?;
+        entry.last_used.store(self.tick(), Ordering::Relaxed);
+        Some(entry.surface.clone())
+    }
+
+    pub async fn ids(&self) -> Vec<String> {
+        self.surfaces.read().await.keys().cloned().collect()
+    }
+
+    /// Remove the surfaces owned by `session_id`, returning their ids
+    pub async fn remove_session(&self, session_id: &str) -> Vec<String> {
+        self.remove_where(|_, owner| owner == session_id).await
+    }
+
+    /// Remove surfaces whose owning session is not in `live_sessions`, returning their ids
+    ///
+    /// `live_sessions` is listed after taking `snapshot`, so surfaces added since may belong
+    /// to sessions it doesn't know about yet; those are kept, as are surfaces created
+    /// without a session.
+    pub async fn prune(&self, live_sessions: &HashSet<String>, snapshot: u64) -> Vec<String> {
+        self.remove_where(|entry, owner| entry.created < snapshot && !live_sessions.contains(owner))
+            .await
+    }
+
+    async fn remove_where(&self, orphaned: impl Fn(&SurfaceEntry, &str) -> bool) -> Vec<String> {
+        let mut surfaces = self.surfaces.write().await;
+        let removed: Vec<String> = surfaces
+            .iter()
+            .filter(|(_, entry)| entry.session_id.as_deref().is_some_and(|owner| orphaned(entry, owner)))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &removed {
+            surfaces.remove(id);
+        }
+        removed
+    }
+}
+
 // Request/Response Types
 
 #[derive(Debug, Deserialize)]
@@ -48,6 +196,9 @@ pub struct CreateSurfaceRequest {
     pub surface_id: Option<String>,
     pub root: String,
     pub styles: Option<Styles>,
+    /// Agent session the surface belongs to, so it is removed along with the session
+    #[serde(rename = "sessionId")]
+    pub session_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -71,6 +222,14 @@ pub struct UserActionRequest {
     pub action: Action,
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct ReplaySessionRequest {
+    /// Stop after the message at this position in the session's messages
+    pub up_to: Option<usize>,
+    /// Regenerate UI (the default) or plain text replies
+    pub use_ui: Option<bool>,
+}
+
 // ============================================================================
 // A2UI Core API Handlers
 // ============================================================================
@@ -79,14 +238,26 @@ pub struct UserActionRequest {
 pub async fn create_surface(State(state): State<A2UIState>, Json(request): Json<CreateSurfaceRequest>) -> Json<Value> {
     let surface_id = request.surface_id.unwrap_or_else(|| Uuid::new_v4().to_string());
 
-    let mut surfaces = state.surfaces.lock().unwrap();
+    if let Some(session_id) = &request.session_id {
+        let session_exists = match &state.a2ui_agent {
+            Some(agent) => agent.get_session(session_id).await.is_ok(),
+            None => false,
+        };
+        if !session_exists {
+            return Json(json!({
+                "error": "Session not found",
+                "sessionId": session_id
+            }));
+        }
+    }
+
     let surface_state = SurfaceState {
         id: surface_id.clone(),
         components: HashMap::new(),
         data_model: HashMap::new(),
     };
 
-    surfaces.insert(surface_id.clone(), surface_state);
+    state.surfaces.insert(surface_state, request.session_id).await;
 
     let message = json!({
         "beginRendering": {
@@ -107,9 +278,8 @@ pub async fn update_components(
     State(state): State<A2UIState>,
     Json(request): Json<UpdateComponentRequest>,
 ) -> Json<Value> {
-    let mut surfaces = state.surfaces.lock().unwrap();
-
-    if let Some(surface) = surfaces.get_mut(&request.surface_id) {
+    if let Some(surface) = state.surfaces.get(&request.surface_id).await {
+        let mut surface = surface.write().await;
         for component in request.components {
             surface.components.insert(component.id.clone(), component);
         }
@@ -138,9 +308,8 @@ pub async fn update_data_model(
     State(state): State<A2UIState>,
     Json(request): Json<UpdateDataModelRequest>,
 ) -> Json<Value> {
-    let mut surfaces = state.surfaces.lock().unwrap();
-
-    if let Some(surface) = surfaces.get_mut(&request.surface_id) {
+    if let Some(surface) = state.surfaces.get(&request.surface_id).await {
+        let mut surface = surface.write().await;
         apply_data_patches(&mut surface.data_model, &request.patches);
 
         let message = json!({
@@ -162,37 +331,113 @@ pub async fn update_data_model(
     }
 }
 
+/// Built-in action that runs another agent turn and returns the resulting A2UI messages
+pub const AGENT_FOLLOWUP_ACTION: &str = "agent_followup";
+
 /// Handle user actions from the UI
 pub async fn handle_user_action(State(state): State<A2UIState>, Json(request): Json<UserActionRequest>) -> Json<Value> {
-    let mut surfaces = state.surfaces.lock().unwrap();
+    let context = {
+        let surface = state.surfaces.get(&request.surface_id).await;
+        let mut surface = match &surface {
+            Some(surface) => Some(surface.write().await),
+            None => None,
+        };
+        let context = action_context(&request.action, surface.as_deref());
+
+        match surface.as_deref_mut() {
+            Some(surface) => {
+                let action_data = json!({
+                    "actionName": request.action.name,
+                    "context": request.action.context,
+                    "timestamp": chrono::Utc::now().to_rfc3339()
+                });
+                surface.data_model.insert("lastAction".to_string(), action_data);
+            }
+            // Follow-ups target agent-rendered surfaces the server may not track
+            None if request.action.name != AGENT_FOLLOWUP_ACTION => {
+                return Json(json!({
+                    "error": "Surface not found",
+                    "surfaceId": request.surface_id
+                }));
+            }
+            None => {}
+        }
+        context
+    };
 
-    if let Some(surface) = surfaces.get_mut(&request.surface_id) {
-        let action_data = json!({
-            "actionName": request.action.name,
-            "context": request.action.context,
-            "timestamp": chrono::Utc::now().to_rfc3339()
-        });
+    if request.action.name == AGENT_FOLLOWUP_ACTION {
+        return Json(agent_followup(&state, &request, &context).await);
+    }
 
-        surface.data_model.insert("lastAction".to_string(), action_data);
+    Json(json!({
+        "success": true,
+        "action": request.action,
+        "message": "Action processed successfully"
+    }))
+}
 
-        Json(json!({
+/// Resolve an action's context to strings, reading `path` values from the surface data model
+fn action_context(action: &Action, surface: Option<&SurfaceState>) -> HashMap<String, String> {
+    action
+        .context
+        .iter()
+        .filter_map(|entry| {
+            let value = match &entry.value {
+                ActionValue::LiteralString(value) => value.clone(),
+                ActionValue::LiteralNumber(value) => value.to_string(),
+                ActionValue::LiteralBoolean(value) => value.to_string(),
+                ActionValue::Path(path) => match surface?.data_model.get(path.trim_start_matches('/'))? {
+                    Value::String(value) => value.clone(),
+                    other => other.to_string(),
+                },
+            };
+            Some((entry.key.clone(), value))
+        })
+        .collect()
+}
+
+/// Run the `agent_followup` action: send the context's `prompt` (or `query`) to the agent
+/// in the given session (defaulting to the surface id) and return the new A2UI messages
+async fn agent_followup(state: &A2UIState, request: &UserActionRequest, context: &HashMap<String, String>) -> Value {
+    let Some(agent) = state.a2ui_agent.as_ref() else {
+        return json!({ "error": "A2UI agent not available" });
+    };
+    let Some(prompt) = context.get("prompt").or_else(|| context.get("query")) else {
+        return json!({ "error": "agent_followup requires a prompt or query in the action context" });
+    };
+    let session_id = context
+        .get("session_id")
+        .or_else(|| context.get("sessionId"))
+        .unwrap_or(&request.surface_id);
+
+    let result = agent.handle_followup(session_id, prompt).await;
+    prune_orphaned_surfaces(state).await;
+    match result {
+        Ok(response) => json!({
             "success": true,
             "action": request.action,
-            "message": "Action processed successfully"
-        }))
-    } else {
-        Json(json!({
-            "error": "Surface not found",
-            "surfaceId": request.surface_id
-        }))
+            "session_id": session_id,
+            "content": response.content,
+            "messages": response.a2ui_messages,
+            "conversion_warnings": response.conversion_warnings,
+            "style_warnings": response.style_warnings,
+            "tool_errors": response.tool_errors,
+            "provider": response.provider
+        }),
+        Err(e) => {
+            record_error(Subsystem::A2ui, format!("agent_followup failed: {}", e));
+            json!({
+                "error": e.to_string(),
+                "retry_after_ms": e.retry_after_ms(),
+                "session_id": session_id
+            })
+        }
     }
 }
 
 /// Delete a surface
 pub async fn delete_surface(State(state): State<A2UIState>, Path(surface_id): Path<String>) -> Json<Value> {
-    let mut surfaces = state.surfaces.lock().unwrap();
-
-    if surfaces.remove(&surface_id).is_some() {
+    if state.surfaces.remove(&surface_id).await {
         let message = json!({
             "deleteSurface": {
                 "surfaceId": surface_id
@@ -211,11 +456,29 @@ pub async fn delete_surface(State(state): State<A2UIState>, Path(surface_id): Pa
     }
 }
 
+/// Remove surfaces whose agent session no longer exists, e.g. after it was evicted
+pub async fn prune_orphaned_surfaces(state: &A2UIState) -> Vec<String> {
+    let snapshot = state.surfaces.snapshot();
+    let live_sessions: HashSet<String> = match &state.a2ui_agent {
+        Some(agent) => agent.list_sessions().await.unwrap_or_default().into_iter().collect(),
+        None => HashSet::new(),
+    };
+    state.surfaces.prune(&live_sessions, snapshot).await
+}
+
+/// Remove surfaces tied to agent sessions that no longer exist
+pub async fn prune_surfaces(State(state): State<A2UIState>) -> Json<Value> {
+    let pruned = prune_orphaned_surfaces(&state).await;
+    Json(json!({
+        "pruned": pruned,
+        "count": pruned.len()
+    }))
+}
+
 /// Get a surface by ID
 pub async fn get_surface(State(state): State<A2UIState>, Path(surface_id): Path<String>) -> Json<Value> {
-    let surfaces = state.surfaces.lock().unwrap();
-
-    if let Some(surface) = surfaces.get(&surface_id) {
+    if let Some(surface) = state.surfaces.get(&surface_id).await {
+        let surface = surface.read().await;
         Json(json!({
             "surfaceId": surface.id,
             "components": surface.components.values().collect::<Vec<_>>(),
@@ -231,9 +494,7 @@ pub async fn get_surface(State(state): State<A2UIState>, Path(surface_id): Path<
 
 /// List all surfaces
 pub async fn list_surfaces(State(state): State<A2UIState>) -> Json<Value> {
-    let surfaces = state.surfaces.lock().unwrap();
-
-    let surface_list: Vec<String> = surfaces.keys().cloned().collect();
+    let surface_list = state.surfaces.ids().await;
 
     Json(json!({
         "surfaces": surface_list,
@@ -241,6 +502,13 @@ pub async fn list_surfaces(State(state): State<A2UIState>) -> Json<Value> {
     }))
 }
 
+/// Get the A2UI JSON schema the agent validates responses against
+pub async fn get_a2ui_schema() -> Result<Json<Value>, http::StatusCode> {
+    a2ui_schema()
+        .map(Json)
+        .map_err(|_| http::StatusCode::INTERNAL_SERVER_ERROR)
+}
+
 // ============================================================================
 // A2UI Agent Handlers
 // ============================================================================
@@ -249,7 +517,7 @@ pub async fn list_surfaces(State(state): State<A2UIState>) -> Json<Value> {
 pub async fn a2ui_agent_chat(
     State(state): State<A2UIState>,
     Json(request): Json<Value>,
-) -> Result<Json<GeneratedResponse>, http::StatusCode> {
+) -> Result<Response, http::StatusCode> {
     let agent = state.a2ui_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
 
     let session_id = request
@@ -272,8 +540,16 @@ pub async fn a2ui_agent_chat(
         });
 
     // Don't need the send_request struct anymore - call agent directly
-    match agent.handle_message(&session_id, &content, true).await {
-        Ok(response) => Ok(Json(response)),
+    let result = agent.handle_message(&session_id, &content, true).await;
+    // A new session may have evicted another one
+    prune_orphaned_surfaces(&state).await;
+    match result {
+        Ok(response) => Ok(Json(response).into_response()),
+        Err(e @ A2UIAgentError::ProviderError(ProviderError::RateLimited { .. })) => {
+            Ok(rate_limited_response(e.to_string(), e.retry_after_ms()))
+        }
+        Err(A2UIAgentError::SessionLimitExceeded(_)) => Err(http::StatusCode::SERVICE_UNAVAILABLE),
+        Err(A2UIAgentError::ProviderError(ProviderError::Timeout(_))) => Err(http::StatusCode::GATEWAY_TIMEOUT),
         Err(_) => Err(http::StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
@@ -303,6 +579,7 @@ pub async fn a2ui_agent_chat_stream(
 
     // Clone session_id for use in spawn
     let session_id_clone = session_id.clone();
+    let state = state.clone();
 
     // Simple SSE implementation that sends all A2UI messages
     let (tx, rx) = tokio::sync::mpsc::channel::<Result<Event, std::convert::Infallible>>(32);
@@ -318,10 +595,27 @@ pub async fn a2ui_agent_chat_stream(
 
         let _ = tx.send(Ok(Event::default().data(processing_data.to_string()).event("update")));
 
+        // A client that disconnects cancels the provider call, letting the turn unwind
+        let cancel = CancellationToken::new();
+        let handling = agent.handle_message_cancellable(&session_id_clone, &content, true, &cancel);
+        tokio::pin!(handling);
+        let result = tokio::select! {
+            result = &mut handling => result,
+            _ = tx.closed() => {
+                cancel.cancel();
+                handling.await
+            }
+        };
+        prune_orphaned_surfaces(&state).await;
+
         // Get response from agent
-        match agent.handle_message(&session_id_clone, &content, true).await {
+        match result {
             Ok(response) => {
                 let message_count = response.a2ui_messages.len();
+                let conversion_warnings = response.conversion_warnings.clone();
+                let style_warnings = response.style_warnings.clone();
+                let tool_errors = response.tool_errors.clone();
+                let provider = response.provider.clone();
 
                 // If there are A2UI messages, send them
                 if !response.a2ui_messages.is_empty() {
@@ -350,16 +644,23 @@ pub async fn a2ui_agent_chat_stream(
                 let completion_data = json!({
                     "type": "completed",
                     "message_count": message_count,
+                    "conversion_warnings": conversion_warnings,
+                    "style_warnings": style_warnings,
+                    "tool_errors": tool_errors,
+                    "provider": provider,
                     "timestamp": chrono::Utc::now().to_rfc3339()
                 });
 
                 let _ = tx.send(Ok(Event::default().data(completion_data.to_string()).event("complete")));
             }
-            Err(_) => {
+            Err(e) => {
+                record_error(Subsystem::A2ui, format!("a2ui_agent_chat_stream failed: {}", e));
+
                 // Send error event
                 let error_data = json!({
                     "type": "error",
                     "message": "Failed to generate response",
+                    "retry_after_ms": e.retry_after_ms(),
                     "timestamp": chrono::Utc::now().to_rfc3339()
                 });
                 let _ = tx.send(Ok(Event::default().data(error_data.to_string()).event("error")));
@@ -383,8 +684,99 @@ pub async fn get_a2ui_session(
             "session_id": session_id,
             "created_at": session.created_at,
             "message_count": session.messages.len(),
-            "last_activity": session.updated_at
+            "last_activity": session.updated_at,
+            "conversation_state": session.context.conversation_state,
+            "allowed_events": session.context.conversation_state.allowed_events(),
+            "recent_transitions": session.context.transitions
+        }))),
+        Err(_) => Err(http::StatusCode::NOT_FOUND),
+    }
+}
+
+/// Fork an A2UI agent session into a new, independent session
+pub async fn fork_a2ui_session(
+    State(state): State<A2UIState>,
+    Path(session_id): Path<String>,
+) -> Result<Json<Value>, http::StatusCode> {
+    let agent = state.a2ui_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+
+    let result = agent.fork_session(&session_id).await;
+    // The source, or another session, may have been evicted to make room
+    prune_orphaned_surfaces(&state).await;
+    match result {
+        Ok(fork_id) => Ok(Json(json!({
+            "session_id": fork_id,
+            "forked_from": session_id,
+            "status": "created"
+        }))),
+        Err(e) => Err(session_error_status(&e)),
+    }
+}
+
+/// Status for a failed operation on an existing session
+///
+/// A full session store under the reject policy is a conflict the client can resolve by
+/// deleting a session, not a missing session.
+fn session_error_status(error: &A2UIAgentError) -> http::StatusCode {
+    match error {
+        A2UIAgentError::SessionNotFound(_) => http::StatusCode::NOT_FOUND,
+        A2UIAgentError::SessionLimitExceeded(_) => http::StatusCode::CONFLICT,
+        _ => http::StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Delete an A2UI agent session and the surfaces created for it
+pub async fn delete_a2ui_session(
+    State(state): State<A2UIState>,
+    Path(session_id): Path<String>,
+) -> Result<Json<Value>, http::StatusCode> {
+    let agent = state.a2ui_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+
+    match agent.delete_session(&session_id).await {
+        Ok(()) => {
+            let pruned = state.surfaces.remove_session(&session_id).await;
+            Ok(Json(json!({
+                "session_id": session_id,
+                "status": "deleted",
+                "pruned_surfaces": pruned
+            })))
+        }
+        Err(_) => Err(http::StatusCode::NOT_FOUND),
+    }
+}
+
+/// Re-run an A2UI agent session's user messages against the current provider and
+/// configuration, returning the regenerated replies next to the stored ones
+pub async fn replay_a2ui_session(
+    State(state): State<A2UIState>,
+    Path(session_id): Path<String>,
+    request: Option<Json<ReplaySessionRequest>>,
+) -> Result<Json<Value>, http::StatusCode> {
+    let agent = state.a2ui_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+
+    match agent
+        .replay_session(&session_id, request.up_to, request.use_ui.unwrap_or(true))
+        .await
+    {
+        Ok(turns) => Ok(Json(json!({
+            "session_id": session_id,
+            "provider": agent.provider.provider_name(),
+            "turns": turns
         }))),
+        Err(e) => Err(session_error_status(&e)),
+    }
+}
+
+/// Export an A2UI agent session as a markdown document, streamed per message
+pub async fn export_a2ui_session_markdown(
+    State(state): State<A2UIState>,
+    Path(session_id): Path<String>,
+) -> Result<Response, http::StatusCode> {
+    let agent = state.a2ui_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+
+    match agent.get_session(&session_id).await {
+        Ok(session) => Ok(markdown_response(a2ui_session_chunks(session))),
         Err(_) => Err(http::StatusCode::NOT_FOUND),
     }
 }
@@ -406,12 +798,10 @@ pub async fn list_a2ui_sessions(State(state): State<A2UIState>) -> Result<Json<V
 // Plugin Generation Handlers
 // ============================================================================
 
-/// Generate a Fleet Chat plugin (non-streaming)
-pub async fn generate_plugin(
-    State(state): State<A2UIState>,
-    Json(request): Json<PluginGenerationRequest>,
-) -> Result<Json<PluginGenerationResponse>, http::StatusCode> {
-    let plugin_type = request.plugin_type.as_deref().unwrap_or("list");
+/// Build the requested plugin, explained with the canned fallback until the model's
+/// explanation replaces it; also returns the plugin type
+fn build_plugin(request: PluginGenerationRequest) -> Result<(PluginGenerationResponse, String), http::StatusCode> {
+    let plugin_type = request.plugin_type.as_deref().unwrap_or("list").to_string();
     let plugin_name = request
         .name
         .as_ref()
@@ -419,47 +809,14 @@ pub async fn generate_plugin(
         .unwrap_or(&request.description);
     let sanitized_name = sanitize_plugin_name(plugin_name);
 
-    let manifest = generate_default_manifest(plugin_name, &request.description, plugin_type);
+    let manifest = generate_default_manifest(plugin_name, &request.description, &plugin_type);
 
     let requirements = request.requirements.unwrap_or_default();
     let include_sample_data = request.include_sample_data.unwrap_or(true);
 
-    let source_code = generate_plugin_code(&manifest, plugin_type, &requirements, include_sample_data)
+    let source_code = generate_plugin_code(&manifest, &plugin_type, &requirements, include_sample_data)
         .map_err(|_| http::StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    // Generate explanation using Rig agent if available
-    let explanation = if let Some(agent) = state.rig_agent.as_ref() {
-        let prompt = format!(
-            "Explain the following plugin that was generated:\n\nName: {}\nDescription: {}\nType: {}\n\nProvide a brief, helpful explanation for the user.",
-            manifest.name, manifest.description, plugin_type
-        );
-
-        match agent
-            .generate(AIOptions {
-                prompt,
-                provider: None,
-                model: None,
-                temperature: None,
-                max_tokens: None,
-                top_p: None,
-                frequency_penalty: None,
-                presence_penalty: None,
-            })
-            .await
-        {
-            Ok(response) => response.text,
-            Err(_) => format!(
-                "Generated a {} plugin named '{}'. {}",
-                plugin_type, manifest.name, manifest.description
-            ),
-        }
-    } else {
-        format!(
-            "Generated a {} plugin named '{}'. {}",
-            plugin_type, manifest.name, manifest.description
-        )
-    };
-
     let warnings = if requirements.is_empty() {
         Some(vec![
             "No specific requirements provided. The plugin uses a generic template.".to_string(),
@@ -469,78 +826,131 @@ pub async fn generate_plugin(
         None
     };
 
-    let response = PluginGenerationResponse {
-        manifest: manifest.clone(),
+    let plugin = PluginGenerationResponse {
+        explanation: format!(
+            "Generated a {} plugin named '{}'. {}",
+            plugin_type, manifest.name, manifest.description
+        ),
+        manifest,
         source_code,
         plugin_id: format!("plugin-{}", Uuid::new_v4()),
         package_name: format!("{}.fcp", sanitized_name),
-        explanation,
         warnings,
     };
+    Ok((plugin, plugin_type))
+}
+
+/// Options asking the Rig agent to explain a generated plugin
+fn explanation_options(manifest: &PluginManifest, plugin_type: &str) -> AIOptions {
+    AIOptions {
+        prompt: format!(
+            "Explain the following plugin that was generated:\n\nName: {}\nDescription: {}\nType: {}\n\nProvide a brief, helpful explanation for the user.",
+            manifest.name, manifest.description, plugin_type
+        ),
+        cacheable: true,
+        ..Default::default()
+    }
+}
+
+/// Save a generated plugin for the preview and regenerate flows; failing to is not fatal
+fn persist_plugin(state: &A2UIState, plugin: &PluginGenerationResponse) {
+    if let Err(e) = save_generated_plugin(state.plugin_storage.as_ref(), plugin) {
+        warn!("Failed to save generated plugin {}: {}", plugin.plugin_id, e);
+    }
+}
+
+/// Generate a Fleet Chat plugin (non-streaming)
+pub async fn generate_plugin(
+    State(state): State<A2UIState>,
+    Json(request): Json<PluginGenerationRequest>,
+) -> Result<Json<PluginGenerationResponse>, http::StatusCode> {
+    let (mut plugin, plugin_type) = build_plugin(request)?;
+
+    // Generate explanation using Rig agent if available
+    if let Some(agent) = state.rig_agent.as_ref() {
+        if let Ok(response) = agent
+            .generate(explanation_options(&plugin.manifest, &plugin_type))
+            .await
+        {
+            plugin.explanation = response.text;
+        }
+    }
 
-    Ok(Json(response))
+    persist_plugin(&state, &plugin);
+    Ok(Json(plugin))
 }
 
 /// Generate a Fleet Chat plugin with SSE streaming
 pub async fn generate_plugin_stream(
-    State(_state): State<A2UIState>,
+    State(state): State<A2UIState>,
     Json(request): Json<PluginGenerationRequest>,
 ) -> Result<Sse<impl futures_util::Stream<Item = Result<Event, std::convert::Infallible>>>, http::StatusCode> {
-    let plugin_type = request.plugin_type.as_deref().unwrap_or("list");
-    let plugin_name = request
-        .name
+    let (plugin, plugin_type) = build_plugin(request)?;
+    let explanation = state
+        .rig_agent
         .as_ref()
-        .map(|s| s.as_str())
-        .unwrap_or(&request.description);
-    let sanitized_name = sanitize_plugin_name(plugin_name);
-    let manifest = generate_default_manifest(plugin_name, &request.description, plugin_type);
+        .map(|agent| agent.generate_stream(explanation_options(&plugin.manifest, &plugin_type)));
 
-    let requirements = request.requirements.unwrap_or_default();
-    let include_sample_data = request.include_sample_data.unwrap_or(true);
+    let events =
+        plugin_generation_events(plugin, explanation, state).map(|data| Ok(Event::default().json_data(data).unwrap()));
+    Ok(Sse::new(events))
+}
 
-    let source_code = generate_plugin_code(&manifest, plugin_type, &requirements, include_sample_data)
-        .map_err(|_| http::StatusCode::INTERNAL_SERVER_ERROR)?;
+/// Event payloads of a streamed plugin generation
+///
+/// Status updates come first, then an `explanation_token` per chunk of the model's
+/// explanation as it streams, then `complete` with the plugin. The plugin is saved with
+/// its final explanation before `complete`; if the explanation fails to stream, the
+/// canned one is kept.
+fn plugin_generation_events(
+    mut plugin: PluginGenerationResponse,
+    explanation: Option<ChunkStream>,
+    state: A2UIState,
+) -> impl futures_util::Stream<Item = Value> {
+    async_stream::stream! {
+        for (message, progress) in [
+            ("Generating plugin manifest...", 25),
+            ("Generating plugin code...", 50),
+            ("Validating plugin structure...", 75),
+        ] {
+            yield json!({ "type": "status", "message": message, "progress": progress });
+        }
 
-    // Create streaming events
-    let events = vec![
-        Event::default()
-            .json_data(json!({
-                "type": "status",
-                "message": "Generating plugin manifest...",
-                "progress": 25
-            }))
-            .unwrap(),
-        Event::default()
-            .json_data(json!({
-                "type": "status",
-                "message": "Generating plugin code...",
-                "progress": 50
-            }))
-            .unwrap(),
-        Event::default()
-            .json_data(json!({
-                "type": "status",
-                "message": "Validating plugin structure...",
-                "progress": 75
-            }))
-            .unwrap(),
-        Event::default()
-            .json_data(json!({
-                "type": "complete",
-                "progress": 100,
-                "data": {
-                    "manifest": manifest,
-                    "source_code": source_code,
-                    "plugin_id": format!("plugin-{}", Uuid::new_v4()),
-                    "package_name": format!("{}.fcp", sanitized_name),
-                    "explanation": format!("Generated a {} plugin named '{}'.", plugin_type, manifest.name),
+        if let Some(mut tokens) = explanation {
+            let mut streamed = String::new();
+            while let Some(chunk) = tokens.next().await {
+                match chunk {
+                    Ok(StreamChunk::Text(token)) => {
+                        streamed.push_str(&token);
+                        yield json!({ "type": "explanation_token", "token": token });
+                    }
+                    Ok(StreamChunk::Reasoning(_)) => {}
+                    Ok(StreamChunk::Done(_)) => break,
+                    Err(e) => {
+                        warn!("Plugin explanation stream failed: {}", e);
+                        streamed.clear();
+                        break;
+                    }
                 }
-            }))
-            .unwrap(),
-    ];
+            }
+            if !streamed.trim().is_empty() {
+                plugin.explanation = streamed;
+            }
+        }
 
-    let stream = stream::iter(events.into_iter().map(Ok));
-    Ok(Sse::new(stream))
+        persist_plugin(&state, &plugin);
+        yield json!({ "type": "complete", "progress": 100, "data": plugin });
+    }
+}
+
+/// A previously generated plugin, with the explanation it was generated with
+pub async fn get_generated_plugin(
+    State(state): State<A2UIState>,
+    Path(plugin_id): Path<String>,
+) -> Result<Json<PluginGenerationResponse>, http::StatusCode> {
+    load_generated_plugin(state.plugin_storage.as_ref(), &plugin_id)
+        .map(Json)
+        .ok_or(http::StatusCode::NOT_FOUND)
 }
 
 // ============================================================================
@@ -559,10 +969,8 @@ fn apply_data_patches(current: &mut HashMap<String, serde_json::Value>, patches:
                     }
                 }
                 _ => {
-                    eprintln!(
-                        "Warning: Attempted to set non-object value at root path. Skipping patch with value: {:?}",
-                        patch.value
-                    );
+                    warn!("Attempted to set non-object value at root path. Skipping patch");
+                    ai_debug!("Skipped root patch value: {:?}", patch.value);
                 }
             }
         } else {
@@ -606,12 +1014,301 @@ pub fn create_a2ui_router() -> Router<A2UIState> {
         .route("/surface/{id}", delete(delete_surface))
         .route("/surface/{id}", get(get_surface))
         .route("/surfaces", get(list_surfaces))
+        .route("/surfaces/prune", post(prune_surfaces))
+        .route("/schema", get(get_a2ui_schema))
         // A2UI Agent API endpoints
         .route("/agent/chat", post(a2ui_agent_chat))
         .route("/agent/chat/stream", post(a2ui_agent_chat_stream))
-        .route("/agent/session/{id}", get(get_a2ui_session))
+        .route("/agent/session/{id}", get(get_a2ui_session).delete(delete_a2ui_session))
+        .route("/agent/session/{id}/fork", post(fork_a2ui_session))
+        .route("/agent/session/{id}/replay", post(replay_a2ui_session))
+        .route("/agent/session/{id}/export/markdown", get(export_a2ui_session_markdown))
         .route("/agent/sessions", get(list_a2ui_sessions))
         // A2UI Plugin Generation API
         .route("/generate-plugin", post(generate_plugin))
         .route("/generate-plugin/stream", post(generate_plugin_stream))
+        .route("/generate-plugin/{id}", get(get_generated_plugin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::tauri_axum::LocalRequest;
+    use jsonschema::JSONSchema;
+
+    fn test_router() -> Router {
+        create_a2ui_router().with_state(A2UIState {
+            surfaces: SurfaceStore::default(),
+            a2ui_agent: None,
+            rig_agent: None,
+            plugin_storage: Arc::new(MemoryStorage::new()),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_schema_route_returns_compilable_schema() {
+        let request = LocalRequest {
+            uri: "/schema".to_string(),
+            method: "GET".to_string(),
+            body: None,
+            headers: HashMap::new(),
+        };
+
+        let response = request.send_to_router(&mut test_router()).await;
+        assert_eq!(response.status_code, 200);
+
+        let schema: Value = serde_json::from_slice(&response.body).unwrap();
+        assert!(JSONSchema::compile(&schema).is_ok());
+        assert_eq!(schema, a2ui_schema().unwrap());
+    }
+
+    fn empty_surface(id: &str) -> SurfaceState {
+        SurfaceState {
+            id: id.to_string(),
+            components: HashMap::new(),
+            data_model: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_surface_reads_do_not_wait_on_other_surfaces() {
+        use std::time::Duration;
+
+        let store = SurfaceStore::default();
+        for id in ["editing", "contacts", "calendar"] {
+            store.insert(empty_surface(id), None).await;
+        }
+
+        // A slow update holds its own surface, and a reader holds another
+        let editing = store.get("editing").await.unwrap();
+        let _writer = editing.write().await;
+        let contacts = store.get("contacts").await.unwrap();
+        let _reader = contacts.read().await;
+
+        let reads = ["contacts", "calendar"].map(|id| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let surface = store.get(id).await.unwrap();
+                let surface = surface.read().await;
+                surface.id.clone()
+            })
+        });
+        let [contacts_read, calendar_read] = reads;
+        let (contacts_read, calendar_read) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(contacts_read, calendar_read)
+        })
+        .await
+        .expect("reads waited on another lock holder");
+        assert_eq!(contacts_read.unwrap(), "contacts");
+        assert_eq!(calendar_read.unwrap(), "calendar");
+
+        let mut ids = tokio::time::timeout(Duration::from_secs(5), store.ids()).await.unwrap();
+        ids.sort();
+        assert_eq!(ids, vec!["calendar", "contacts", "editing"]);
+    }
+
+    #[test]
+    fn test_surface_cap_defaults_when_unset() {
+        assert_eq!(max_surfaces_from(None), Some(DEFAULT_MAX_SURFACES));
+        assert_eq!(max_surfaces_from(Some("not a number")), Some(DEFAULT_MAX_SURFACES));
+        assert_eq!(max_surfaces_from(Some(" 50 ")), Some(50));
+        assert_eq!(max_surfaces_from(Some("0")), None);
+    }
+
+    #[tokio::test]
+    async fn test_prune_removes_orphaned_surfaces_and_cap_evicts_least_recently_used() {
+        let store = SurfaceStore::new(Some(3));
+        store.insert(empty_surface("kept"), Some("live".to_string())).await;
+        store.insert(empty_surface("orphan"), Some("gone".to_string())).await;
+        store.insert(empty_surface("unowned"), None).await;
+
+        let pruned = store
+            .prune(&HashSet::from(["live".to_string()]), store.snapshot())
+            .await;
+        assert_eq!(pruned, vec!["orphan"]);
+        let mut ids = store.ids().await;
+        ids.sort();
+        assert_eq!(ids, vec!["kept", "unowned"]);
+
+        // Using "kept" leaves "unowned" as the least recently used
+        store.insert(empty_surface("second"), None).await;
+        store.get("kept").await.unwrap();
+        assert!(store.insert(empty_surface("second"), None).await.is_empty());
+        assert_eq!(store.insert(empty_surface("third"), None).await, vec!["unowned"]);
+        let mut ids = store.ids().await;
+        ids.sort();
+        assert_eq!(ids, vec!["kept", "second", "third"]);
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_surfaces_added_after_the_snapshot() {
+        let store = SurfaceStore::default();
+        let snapshot = store.snapshot();
+        // The session was created after the live sessions were listed
+        store.insert(empty_surface("fresh"), Some("new".to_string())).await;
+
+        assert!(store.prune(&HashSet::new(), snapshot).await.is_empty());
+        assert_eq!(store.prune(&HashSet::new(), store.snapshot()).await, vec!["fresh"]);
+    }
+
+    #[tokio::test]
+    async fn test_deleting_a_session_removes_its_surfaces() {
+        use crate::a2ui::agent::CreateSessionRequest;
+        use crate::a2ui::provider::MockProvider;
+
+        let agent = Arc::new(A2UIAgent::new(Arc::new(MockProvider::new())).unwrap());
+        let surfaces = SurfaceStore::default();
+        let mut router = create_a2ui_router().with_state(A2UIState {
+            surfaces: surfaces.clone(),
+            a2ui_agent: Some(agent.clone()),
+            rig_agent: None,
+            plugin_storage: Arc::new(MemoryStorage::new()),
+        });
+        for session_id in ["chat", "other"] {
+            let request = CreateSessionRequest {
+                user_id: "default".to_string(),
+                app_name: "Fleet Chat".to_string(),
+                base_url: None,
+                initial_context: None,
+            };
+            agent.create_session_with_id(session_id, request).await.unwrap();
+        }
+
+        let send = |method: &str, uri: &str, body: Option<Value>| LocalRequest {
+            uri: uri.to_string(),
+            method: method.to_string(),
+            body: body.map(|body| body.to_string()),
+            headers: HashMap::from([("content-type".to_string(), "application/json".to_string())]),
+        };
+        for (surface_id, session_id) in [("chat-card", "chat"), ("other-card", "other")] {
+            let body = json!({"surfaceId": surface_id, "root": "root", "sessionId": session_id});
+            send("POST", "/surface", Some(body)).send_to_router(&mut router).await;
+        }
+        let body = json!({"surfaceId": "stray", "root": "root", "sessionId": "missing"});
+        let response = send("POST", "/surface", Some(body)).send_to_router(&mut router).await;
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["error"], "Session not found");
+
+        let response = send("DELETE", "/agent/session/chat", None)
+            .send_to_router(&mut router)
+            .await;
+        assert_eq!(response.status_code, 200);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["pruned_surfaces"], json!(["chat-card"]));
+        assert_eq!(surfaces.ids().await, vec!["other-card"]);
+    }
+
+    fn followup_request(query: &str) -> LocalRequest {
+        let body = json!({
+            "surfaceId": "contacts",
+            "action": {
+                "name": AGENT_FOLLOWUP_ACTION,
+                "context": [
+                    { "key": "query", "value": { "literalString": query } },
+                    { "key": "session_id", "value": { "literalString": "followup-session" } }
+                ]
+            }
+        });
+        LocalRequest {
+            uri: "/surface/contacts/action".to_string(),
+            method: "POST".to_string(),
+            body: Some(body.to_string()),
+            headers: HashMap::from([("content-type".to_string(), "application/json".to_string())]),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_followup_action_runs_agent_turn() {
+        use crate::a2ui::agent::MAX_FOLLOWUP_DEPTH;
+        use crate::a2ui::provider::MockProvider;
+
+        let agent = Arc::new(A2UIAgent::new(Arc::new(MockProvider::new())).unwrap());
+        let mut router = create_a2ui_router().with_state(A2UIState {
+            surfaces: SurfaceStore::default(),
+            a2ui_agent: Some(agent.clone()),
+            rig_agent: None,
+            plugin_storage: Arc::new(MemoryStorage::new()),
+        });
+
+        let response = followup_request("Show more contacts").send_to_router(&mut router).await;
+        assert_eq!(response.status_code, 200);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["success"], true);
+        assert!(!body["messages"].as_array().unwrap().is_empty());
+
+        let session = agent.get_session("followup-session").await.unwrap();
+        assert_eq!(session.messages.len(), 2);
+        assert_eq!(session.messages[0].content, "Show more contacts");
+
+        // Consecutive follow-ups stop at the depth limit until the user sends a message
+        for _ in 1..MAX_FOLLOWUP_DEPTH {
+            followup_request("Show more contacts").send_to_router(&mut router).await;
+        }
+        let response = followup_request("Show more contacts").send_to_router(&mut router).await;
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("Follow-up limit"));
+        assert_eq!(
+            agent.get_session("followup-session").await.unwrap().messages.len(),
+            2 * MAX_FOLLOWUP_DEPTH
+        );
+
+        agent
+            .handle_message("followup-session", "contacts please", true)
+            .await
+            .unwrap();
+        let response = followup_request("Show more contacts").send_to_router(&mut router).await;
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["success"], true);
+    }
+
+    #[tokio::test]
+    async fn test_plugin_stream_emits_explanation_tokens_before_complete() {
+        use crate::rig_agent::StreamCompletion;
+        use futures_util::stream;
+
+        let storage = Arc::new(MemoryStorage::new());
+        let state = A2UIState {
+            surfaces: SurfaceStore::default(),
+            a2ui_agent: None,
+            rig_agent: None,
+            plugin_storage: storage.clone(),
+        };
+        let request: PluginGenerationRequest =
+            serde_json::from_value(json!({ "description": "Track my reading list" })).unwrap();
+        let (plugin, _) = build_plugin(request).unwrap();
+        let plugin_id = plugin.plugin_id.clone();
+        // Stands in for a configured provider streaming its explanation
+        let explanation: ChunkStream = Box::pin(stream::iter(vec![
+            Ok(StreamChunk::Text("Lists your ".to_string())),
+            Ok(StreamChunk::Text("books.".to_string())),
+            Ok(StreamChunk::Done(StreamCompletion {
+                id: "gen-1".to_string(),
+                usage: None,
+                finish_reason: Some("stop".to_string()),
+            })),
+        ]));
+
+        let events: Vec<Value> = plugin_generation_events(plugin, Some(explanation), state)
+            .collect()
+            .await;
+
+        let types: Vec<&str> = events.iter().map(|event| event["type"].as_str().unwrap()).collect();
+        assert_eq!(
+            types,
+            [
+                "status",
+                "status",
+                "status",
+                "explanation_token",
+                "explanation_token",
+                "complete"
+            ]
+        );
+        assert_eq!(events[3]["token"], "Lists your ");
+        assert_eq!(events[5]["data"]["explanation"], "Lists your books.");
+
+        let saved = load_generated_plugin(storage.as_ref(), &plugin_id).unwrap();
+        assert_eq!(saved.explanation, "Lists your books.");
+    }
 }
@@ -3,21 +3,26 @@
 //! This module contains all the HTTP handlers for the AI service endpoints.
 //! It provides text generation, chat, embeddings, image analysis, and other AI capabilities.
 
+use crate::diagnostics::{record_error, Subsystem};
 use crate::rig_agent::{
-    AIOptions, AIResponse, ChatMessage, EmbeddingRequest, ImageAnalysisRequest, ImageGenerationRequest,
-    ModerationRequest, ModerationResponse, RigAgent, RigAgentError, TokenCountRequest,
+    AIOptions, ChatMessage, EmbeddingRequest, ImageAnalysisRequest, ImageGenerationRequest, ModerationRequest,
+    ModerationResponse, RigAgent, RigAgentError, StreamChunk, TokenCountRequest,
 };
+use crate::routes::rate_limited_response;
 use axum::{
-    extract::State,
+    extract::{Query, State},
     http::{self},
     response::{sse::Event, IntoResponse, Response, Sse},
     routing::{get, post},
     Json, Router,
 };
-use futures::stream::StreamExt;
+use futures::stream::{Stream, StreamExt};
+use serde::Deserialize;
 use serde_json::json;
+use std::pin::Pin;
 use std::sync::Arc;
-use tauri_plugin_log::log::{debug, error, info, warn};
+use tauri_plugin_log::log::{debug, info, warn};
+use tokio_stream::wrappers::ReceiverStream;
 
 /// The application state used by AI handlers
 #[derive(Clone)]
@@ -33,6 +38,12 @@ fn rig_error_to_status(error: RigAgentError) -> http::StatusCode {
         RigAgentError::InvalidModel(_) => http::StatusCode::BAD_REQUEST,
         RigAgentError::NotSupported(_) => http::StatusCode::NOT_IMPLEMENTED,
         RigAgentError::RequestFailed(_) => http::StatusCode::BAD_GATEWAY,
+        RigAgentError::ContextTooLong(_) => http::StatusCode::PAYLOAD_TOO_LARGE,
+        RigAgentError::ToolError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        RigAgentError::RateLimited { .. } => http::StatusCode::TOO_MANY_REQUESTS,
+        RigAgentError::InvalidAttachment(_) => http::StatusCode::BAD_REQUEST,
+        RigAgentError::InvalidImage(_) => http::StatusCode::BAD_REQUEST,
+        RigAgentError::CheckpointNotFound(_) => http::StatusCode::NOT_FOUND,
         RigAgentError::PromptError(_) => http::StatusCode::BAD_REQUEST,
         RigAgentError::EmbeddingError(_) => http::StatusCode::BAD_REQUEST,
         RigAgentError::HttpError(_) => http::StatusCode::BAD_GATEWAY,
@@ -42,14 +53,28 @@ fn rig_error_to_status(error: RigAgentError) -> http::StatusCode {
     }
 }
 
+/// Convert a RigAgentError into a response, with a JSON envelope for rate limits
+fn rig_error_response(error: RigAgentError) -> Result<Response, http::StatusCode> {
+    match error {
+        RigAgentError::RateLimited {
+            message,
+            retry_after_ms,
+        } => Ok(rate_limited_response(message, retry_after_ms)),
+        other => Err(rig_error_to_status(other)),
+    }
+}
+
 /// AI Generate endpoint - generates text from a prompt
 pub async fn ai_generate(
     State(state): State<AIState>,
     Json(options): Json<AIOptions>,
-) -> Result<Json<AIResponse>, http::StatusCode> {
+) -> Result<Response, http::StatusCode> {
     let agent = state.rig_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
 
-    agent.generate(options).await.map(Json).map_err(rig_error_to_status)
+    match agent.generate(options).await {
+        Ok(response) => Ok(Json(response).into_response()),
+        Err(e) => rig_error_response(e),
+    }
 }
 
 /// AI Generate Stream endpoint (SSE) - streams text generation
@@ -57,32 +82,81 @@ pub async fn ai_generate_stream(
     State(state): State<AIState>,
     Json(options): Json<AIOptions>,
 ) -> Result<Response, http::StatusCode> {
-    eprintln!("[ai_generate_stream] ====== REQUEST START ======");
-    eprintln!(
+    debug!("[ai_generate_stream] ====== REQUEST START ======");
+    debug!(
         "[ai_generate_stream] Received request, prompt length: {}",
         options.prompt.len()
     );
-    eprintln!(
+    debug!(
         "[ai_generate_stream] Options: model={:?}, temperature={:?}",
         options.model, options.temperature
     );
 
     let agent = state.rig_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
-    eprintln!("[ai_generate_stream] Got RigAgent instance");
+    debug!("[ai_generate_stream] Got RigAgent instance");
+
+    let stream = agent.generate_stream(options);
+    debug!("[ai_generate_stream] Created stream from RigAgent");
+
+    Ok(Sse::new(generation_events(stream)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResumeRequest {
+    pub request_id: String,
+}
+
+/// AI Resume endpoint (SSE) - continues a stream that was interrupted mid-generation
+///
+/// Starts with a `resumed` event carrying `{ partial, continued }`; when `continued`
+/// is true the remaining text follows as `chunk` events ending in `done`.
+pub async fn ai_resume_stream(
+    State(state): State<AIState>,
+    Json(request): Json<ResumeRequest>,
+) -> Result<Response, http::StatusCode> {
+    let agent = state.rig_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
+    let resumed = agent
+        .resume_generation(&request.request_id)
+        .map_err(rig_error_to_status)?;
 
-    let mut stream = agent.generate_stream(options);
-    eprintln!("[ai_generate_stream] Created stream from RigAgent");
+    let data = json!({ "partial": resumed.partial, "continued": resumed.continued });
+    let head = futures::stream::once(async move {
+        Ok::<_, std::convert::Infallible>(Event::default().data(data.to_string()).event("resumed"))
+    });
+    if !resumed.continued {
+        return Ok(Sse::new(head).into_response());
+    }
+    Ok(Sse::new(head.chain(generation_events(resumed.stream))).into_response())
+}
 
+/// Turn a generation stream into SSE events
+///
+/// Emits a `chunk` event per text chunk, then either a `done` event carrying
+/// `{ id, usage, finish_reason }` or an `error` event if generation fails or the
+/// stream ends without completing.
+fn generation_events(
+    mut stream: Pin<Box<dyn Stream<Item = Result<StreamChunk, RigAgentError>> + Send>>,
+) -> ReceiverStream<Result<Event, std::convert::Infallible>> {
     // Create a channel for SSE events
     let (tx, rx) = tokio::sync::mpsc::channel::<Result<Event, std::convert::Infallible>>(32);
-    info!("[ai_generate_stream] Created mpsc channel for SSE events");
 
     // Spawn a task to consume the stream and send SSE events
     tokio::spawn(async move {
         info!("[ai_generate_stream] Task started: consuming stream and sending SSE events");
         let mut chunk_count = 0;
 
-        while let Some(chunk_result) = stream.next().await {
+        loop {
+            // A disconnected client drops the generation stream, cancelling the provider request
+            let chunk_result = tokio::select! {
+                chunk = stream.next() => match chunk {
+                    Some(chunk) => chunk,
+                    None => break,
+                },
+                _ = tx.closed() => {
+                    info!("[ai_generate_stream] Client disconnected after {} chunks, cancelling", chunk_count);
+                    return;
+                }
+            };
             chunk_count += 1;
             debug!(
                 "[ai_generate_stream] Received chunk #{}, result: {:?}",
@@ -91,7 +165,7 @@ pub async fn ai_generate_stream(
             );
 
             match chunk_result {
-                Ok(chunk) => {
+                Ok(StreamChunk::Text(chunk)) => {
                     debug!("[ai_generate_stream] Chunk text length: {}", chunk.len());
                     let data = json!({ "text": chunk });
                     let event = Event::default().data(data.to_string()).event("chunk");
@@ -99,38 +173,57 @@ pub async fn ai_generate_stream(
 
                     if tx.send(Ok(event)).await.is_err() {
                         warn!("[ai_generate_stream] Failed to send SSE chunk, channel closed");
-                        break;
+                        return;
+                    }
+                }
+                Ok(StreamChunk::Reasoning(reasoning)) => {
+                    let data = json!({ "text": reasoning });
+                    let event = Event::default().data(data.to_string()).event("reasoning");
+                    if tx.send(Ok(event)).await.is_err() {
+                        warn!("[ai_generate_stream] Failed to send SSE reasoning, channel closed");
+                        return;
                     }
                 }
+                Ok(StreamChunk::Done(completion)) => {
+                    debug!("[ai_generate_stream] Sending 'done' event");
+                    let data = serde_json::to_string(&completion).unwrap_or_default();
+                    let _ = tx.send(Ok(Event::default().data(data).event("done"))).await;
+                    info!("[ai_generate_stream] Task completed, total chunks: {}", chunk_count);
+                    return;
+                }
                 Err(e) => {
-                    error!("[ai_generate_stream] Stream error: {:?}", e);
-                    let error_data = json!({ "error": format!("{:?}", e) });
+                    record_error(Subsystem::Ai, format!("ai_generate_stream failed: {}", e));
+                    let retry_after_ms = match &e {
+                        RigAgentError::RateLimited { retry_after_ms, .. } => *retry_after_ms,
+                        _ => None,
+                    };
+                    let error_data = json!({ "error": format!("{:?}", e), "retry_after_ms": retry_after_ms });
                     let _ = tx
                         .send(Ok(Event::default().data(error_data.to_string()).event("error")))
                         .await;
-                    break;
+                    return;
                 }
             }
         }
 
-        info!("[ai_generate_stream] Stream ended, total chunks: {}", chunk_count);
-
-        // Send completion event
-        debug!("[ai_generate_stream] Sending 'done' event");
-        let _ = tx.send(Ok(Event::default().event("done"))).await;
-        info!("[ai_generate_stream] Task completed");
+        warn!(
+            "[ai_generate_stream] Stream ended without completing after {} chunks",
+            chunk_count
+        );
+        let error_data = json!({ "error": "Generation stream ended before completion", "retry_after_ms": null });
+        let _ = tx
+            .send(Ok(Event::default().data(error_data.to_string()).event("error")))
+            .await;
     });
 
-    let stream = tokio_stream::wrappers::ReceiverStream::new(rx);
-    info!("[ai_generate_stream] Created ReceiverStream, returning SSE response");
-    Ok(Sse::new(stream).into_response())
+    ReceiverStream::new(rx)
 }
 
 /// AI Chat endpoint - conversational AI with message history
 pub async fn ai_chat(
     State(state): State<AIState>,
     Json(request): Json<serde_json::Value>,
-) -> Result<Json<AIResponse>, http::StatusCode> {
+) -> Result<Response, http::StatusCode> {
     let agent = state.rig_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
 
     let messages: Vec<ChatMessage> = serde_json::from_value(serde_json::Value::Array(
@@ -147,11 +240,10 @@ pub async fn ai_chat(
         .and_then(|v| v.as_object())
         .and_then(|obj| serde_json::from_value(serde_json::Value::Object(obj.clone())).ok());
 
-    agent
-        .chat(messages, options)
-        .await
-        .map(Json)
-        .map_err(rig_error_to_status)
+    match agent.chat(messages, options).await {
+        Ok(response) => Ok(Json(response).into_response()),
+        Err(e) => rig_error_response(e),
+    }
 }
 
 /// AI Embed endpoint - generates embeddings for text
@@ -161,10 +253,7 @@ pub async fn ai_embed(
 ) -> Result<Json<serde_json::Value>, http::StatusCode> {
     let agent = state.rig_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
 
-    let embedding = agent
-        .embed(request.text, request.model)
-        .await
-        .map_err(rig_error_to_status)?;
+    let embedding = agent.embed(request).await.map_err(rig_error_to_status)?;
 
     Ok(Json(json!({ "embedding": embedding })))
 }
@@ -176,11 +265,7 @@ pub async fn ai_moderate(
 ) -> Result<Json<ModerationResponse>, http::StatusCode> {
     let agent = state.rig_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
 
-    agent
-        .moderate(request.content)
-        .await
-        .map(Json)
-        .map_err(rig_error_to_status)
+    agent.moderate(request).await.map(Json).map_err(rig_error_to_status)
 }
 
 /// AI Generate Image endpoint - generates images from text prompts
@@ -222,11 +307,27 @@ pub async fn ai_count_tokens(
     Ok(Json(json!({ "count": count })))
 }
 
+/// Query parameters for the models endpoint
+#[derive(Debug, Deserialize)]
+pub struct ModelsQuery {
+    /// Provider to list models for, overriding the agent's provider
+    pub provider: Option<String>,
+    /// Fetch a fresh list instead of serving the cached one
+    #[serde(default)]
+    pub force: bool,
+}
+
 /// AI Get Models endpoint - lists available models
-pub async fn ai_get_models(State(state): State<AIState>) -> Result<Json<serde_json::Value>, http::StatusCode> {
+pub async fn ai_get_models(
+    State(state): State<AIState>,
+    Query(query): Query<ModelsQuery>,
+) -> Result<Json<serde_json::Value>, http::StatusCode> {
     let agent = state.rig_agent.as_ref().ok_or(http::StatusCode::SERVICE_UNAVAILABLE)?;
 
-    let models = agent.get_models().await.map_err(rig_error_to_status)?;
+    let models = agent
+        .get_models(query.provider.as_deref(), query.force)
+        .await
+        .map_err(rig_error_to_status)?;
 
     Ok(Json(json!({ "models": models })))
 }
@@ -236,6 +337,7 @@ pub fn create_ai_router() -> Router<AIState> {
     Router::new()
         .route("/generate", post(ai_generate))
         .route("/stream", post(ai_generate_stream))
+        .route("/stream/resume", post(ai_resume_stream))
         .route("/chat", post(ai_chat))
         .route("/embed", post(ai_embed))
         .route("/moderate", post(ai_moderate))
@@ -244,3 +346,75 @@ pub fn create_ai_router() -> Router<AIState> {
         .route("/count_tokens", post(ai_count_tokens))
         .route("/models", get(ai_get_models))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rig_agent::{StreamCompletion, TokenUsage};
+
+    async fn collect_events(chunks: Vec<Result<StreamChunk, RigAgentError>>) -> String {
+        let response = Sse::new(generation_events(Box::pin(futures::stream::iter(chunks)))).into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_with_done_event_carrying_usage() {
+        let body = collect_events(vec![
+            Ok(StreamChunk::Text("Hello".to_string())),
+            Ok(StreamChunk::Text(" world".to_string())),
+            Ok(StreamChunk::Done(StreamCompletion {
+                id: "gen-1".to_string(),
+                usage: Some(TokenUsage {
+                    prompt_tokens: 12,
+                    completion_tokens: 2,
+                    total_tokens: 14,
+                }),
+                finish_reason: Some("stop".to_string()),
+            })),
+        ])
+        .await;
+
+        let events: Vec<&str> = body.split("\n\n").filter(|event| !event.is_empty()).collect();
+        assert_eq!(events.len(), 3);
+        assert!(events[0].contains("event: chunk") && events[0].contains("Hello"));
+        let done = events[2];
+        assert!(done.starts_with("event: done"));
+        assert!(done.contains(r#""id":"gen-1""#));
+        assert!(done.contains(r#""total_tokens":14"#));
+        assert!(done.contains(r#""finish_reason":"stop""#));
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_is_sent_as_separate_events() {
+        let body = collect_events(vec![
+            Ok(StreamChunk::Reasoning("Add the digits.".to_string())),
+            Ok(StreamChunk::Text("4".to_string())),
+            Ok(StreamChunk::Done(StreamCompletion {
+                id: "gen-1".to_string(),
+                usage: None,
+                finish_reason: Some("stop".to_string()),
+            })),
+        ])
+        .await;
+
+        let events: Vec<&str> = body.split("\n\n").filter(|event| !event.is_empty()).collect();
+        assert!(events[0].starts_with("event: reasoning") && events[0].contains("Add the digits."));
+        assert!(events[1].starts_with("event: chunk") && !events[1].contains("Add the digits."));
+    }
+
+    #[tokio::test]
+    async fn test_failed_or_dropped_stream_emits_error_event() {
+        let body = collect_events(vec![
+            Ok(StreamChunk::Text("partial".to_string())),
+            Err(RigAgentError::HttpError("connection reset".to_string())),
+        ])
+        .await;
+        assert!(body.contains("event: error") && body.contains("connection reset"));
+        assert!(!body.contains("event: done"));
+
+        let body = collect_events(vec![Ok(StreamChunk::Text("partial".to_string()))]).await;
+        assert!(body.contains("event: error") && body.contains("ended before completion"));
+        assert!(!body.contains("event: done"));
+    }
+}
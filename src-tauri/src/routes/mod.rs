@@ -5,3 +5,35 @@
 
 pub mod a2ui;
 pub mod ai;
+pub mod search;
+
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+/// JSON error body for failures the client can act on
+#[derive(Debug, Serialize)]
+pub struct ErrorEnvelope {
+    pub error: String,
+    /// Milliseconds to wait before retrying, when a provider rate-limited the request
+    pub retry_after_ms: Option<u64>,
+}
+
+/// 429 response carrying the provider's retry delay so the UI can show a countdown
+pub fn rate_limited_response(error: String, retry_after_ms: Option<u64>) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(ErrorEnvelope { error, retry_after_ms }),
+    )
+        .into_response()
+}
+
+/// Stream a markdown document to the client chunk by chunk
+pub fn markdown_response(chunks: impl Iterator<Item = String> + Send + 'static) -> Response {
+    let body = Body::from_stream(futures::stream::iter(chunks.map(Ok::<_, std::convert::Infallible>)));
+    ([(header::CONTENT_TYPE, "text/markdown; charset=utf-8")], body).into_response()
+}
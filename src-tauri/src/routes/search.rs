@@ -0,0 +1,80 @@
+//! Search Routes - streaming launcher search
+//!
+//! This module contains the HTTP handler that streams applications, file matches
+//! and AI insights for a query as a single SSE response.
+
+use crate::rig_agent::RigAgent;
+use crate::search::{
+    gate_insights, gate_query_correction, rig_insight_generator, rig_query_corrector, search_stream,
+    SearchStreamRequest,
+};
+use axum::{
+    extract::State,
+    response::{sse::Event, Sse},
+    routing::post,
+    Json, Router,
+};
+use futures::stream::{Stream, StreamExt};
+use std::sync::Arc;
+
+/// The application state used by search handlers
+#[derive(Clone)]
+pub struct SearchState {
+    pub rig_agent: Option<Arc<RigAgent>>,
+}
+
+/// Streaming search endpoint - emits `apps`, `file_match`, `suggestion`, `insight_token` and `done` events
+pub async fn search_stream_handler(
+    State(state): State<SearchState>,
+    Json(request): Json<SearchStreamRequest>,
+) -> Sse<impl Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let insights = gate_insights(state.rig_agent.clone().map(rig_insight_generator));
+    let corrector = gate_query_correction(state.rig_agent.map(rig_query_corrector));
+
+    let stream = search_stream(request, insights, corrector).map(|event| {
+        let data = serde_json::to_string(&event).unwrap_or_default();
+        Ok(Event::default().event(event.name()).data(data))
+    });
+
+    Sse::new(stream)
+}
+
+/// Create the search router with all search endpoints
+pub fn create_search_router() -> Router<SearchState> {
+    Router::new().route("/stream", post(search_stream_handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tauri_axum::LocalRequest;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_stream_route_emits_sse_events() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("quarterly-report.txt"), "numbers").unwrap();
+
+        let mut router = create_search_router().with_state(SearchState { rig_agent: None });
+        let request = LocalRequest {
+            uri: "/stream".to_string(),
+            method: "POST".to_string(),
+            body: Some(
+                serde_json::json!({ "query": "quarterly-report", "search_path": dir.path().to_string_lossy() })
+                    .to_string(),
+            ),
+            headers: HashMap::from([("content-type".to_string(), "application/json".to_string())]),
+        };
+
+        let response = request.send_to_router(&mut router).await;
+
+        assert_eq!(response.status_code, 200);
+        let body = String::from_utf8(response.body).unwrap();
+        let apps = body.find("event: apps").unwrap();
+        let file_match = body.find("event: file_match").unwrap();
+        let done = body.find("event: done").unwrap();
+        assert!(apps < file_match && file_match < done);
+        assert!(body.contains("quarterly-report.txt"));
+        assert!(!body.contains("event: insight_token"));
+    }
+}
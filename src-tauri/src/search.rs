@@ -1,11 +1,29 @@
-use crate::rig_agent::{AIOptions, AIProvider, RigAgent};
+use crate::app_launches::frecency_scores;
+use crate::diagnostics::{record_error, Subsystem};
+use crate::insights_privacy::{current_insights_privacy, InsightsPrivacy};
+use crate::logging::env_flag;
+use crate::plugins::{get_plugin_commands, PluginCommand, PluginManagerState};
+use crate::rig_agent::{
+    background_model, background_model_from, env_setting, provider_default, supported_providers, AIOptions, AIProvider,
+    ProviderDefault, ProviderDescriptor, RigAgent, StreamChunk,
+};
+use crate::search_scopes::resolve_configured_search_root;
+use crate::storage::{Storage, STORAGE};
+use futures::stream::{Stream, StreamExt};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::env;
+use std::future::Future;
 use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tauri::command;
+use std::time::{Duration, Instant};
+use tauri::{command, State};
 use tokio::sync::RwLock;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,7 +41,13 @@ pub struct FileMatch {
     pub path: String,
     pub line_number: Option<usize>,
     pub line_content: Option<String>,
+    /// Length in characters of the matching line, when `line_content` was truncated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_length: Option<usize>,
     pub match_type: String, // "name" or "content"
+    /// Number of matching lines in the file, when all matches were counted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_count: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,19 +56,70 @@ pub struct SearchResult {
     pub files: Vec<FileMatch>,
 }
 
+/// A result of any kind in a single relevance-ranked list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankedResult {
+    pub score: i64,
+    #[serde(flatten)]
+    pub item: RankedItem,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RankedItem {
+    App(Application),
+    File(FileMatch),
+    Command { plugin_id: String, command: PluginCommand },
+}
+
+/// `unified_search` results, grouped by kind or as one ranked list when `flat` is set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UnifiedSearchResult {
+    Grouped(SearchResult),
+    Flat(Vec<RankedResult>),
+}
+
 // ============================================================================
 // Icon Cache (thread-safe, async-friendly)
 // ============================================================================
 
+/// Storage key prefix of icons persisted across launches
+const ICON_CACHE_PREFIX: &str = "cache/icons";
+
+/// An extracted icon on disk, valid while the app's modification time is unchanged
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedIcon {
+    path: String,
+    modified_ms: u128,
+    icon: Option<String>,
+}
+
 /// Global icon cache for extracted application icons
+///
+/// Icons are kept in memory for the session and, when a storage backend is given, on disk
+/// keyed by app path and modification time, so a relaunch doesn't decode every icon again.
+/// Entries of apps that have since changed or been removed are pruned on the first lookup.
 pub struct IconCache {
     cache: Arc<RwLock<HashMap<String, Option<String>>>>,
+    storage: Option<Arc<dyn Storage>>,
+    pruned: std::sync::Once,
 }
 
 impl IconCache {
     pub fn new() -> Self {
         Self {
             cache: Arc::new(RwLock::new(HashMap::new())),
+            storage: None,
+            pruned: std::sync::Once::new(),
+        }
+    }
+
+    /// Cache that also persists icons in `storage`
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            storage: Some(storage),
+            ..Self::new()
         }
     }
 
@@ -68,6 +143,14 @@ impl IconCache {
 
     /// Get or extract icon (with caching)
     pub async fn get_or_extract(&self, app_path: &str) -> Option<String> {
+        self.get_or_extract_with(app_path, extract_app_icon).await
+    }
+
+    async fn get_or_extract_with(
+        &self,
+        app_path: &str,
+        extract: impl FnOnce(&str) -> Option<String>,
+    ) -> Option<String> {
         // Check cache first
         if self.is_cached(app_path).await {
             if let Some(icon) = self.get(app_path).await {
@@ -77,14 +160,111 @@ impl IconCache {
             return None;
         }
 
-        // Not in cache, extract icon
-        let icon = extract_app_icon(app_path);
+        // Then the disk cache, as long as the app hasn't changed since
+        self.pruned.call_once(|| {
+            self.prune_stale();
+        });
+        let modified_ms = modified_ms(app_path);
+        let icon = match modified_ms.and_then(|modified_ms| self.read_persisted(app_path, modified_ms)) {
+            Some(icon) => icon,
+            None => {
+                // Not in cache, extract icon
+                let icon = extract(app_path);
+                if let Some(modified_ms) = modified_ms {
+                    self.persist(app_path, modified_ms, &icon);
+                }
+                icon
+            }
+        };
 
         // Cache the result (even if None to avoid re-extracting)
         self.set(app_path.to_string(), icon.clone()).await;
 
         icon
     }
+
+    fn read_persisted(&self, app_path: &str, modified_ms: u128) -> Option<Option<String>> {
+        let bytes = self
+            .storage
+            .as_ref()?
+            .read(&icon_cache_key(app_path, modified_ms))
+            .ok()??;
+        let cached: CachedIcon = serde_json::from_slice(&bytes).ok()?;
+        (cached.path == app_path && cached.modified_ms == modified_ms).then_some(cached.icon)
+    }
+
+    fn persist(&self, app_path: &str, modified_ms: u128, icon: &Option<String>) {
+        let Some(storage) = &self.storage else {
+            return;
+        };
+        let cached = CachedIcon {
+            path: app_path.to_string(),
+            modified_ms,
+            icon: icon.clone(),
+        };
+        let result = serde_json::to_vec(&cached)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| {
+                storage
+                    .write(&icon_cache_key(app_path, modified_ms), &bytes)
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            record_error(
+                Subsystem::Cache,
+                format!("Failed to persist icon for {}: {}", app_path, e),
+            );
+        }
+    }
+
+    /// Delete persisted icons of apps that are gone or have changed since, returning how many
+    ///
+    /// Keys include the modification time, so an updated app leaves its old entry behind.
+    fn prune_stale(&self) -> usize {
+        let Some(storage) = &self.storage else {
+            return 0;
+        };
+        let Ok(keys) = storage.list(&format!("{}/", ICON_CACHE_PREFIX)) else {
+            return 0;
+        };
+        keys.into_iter()
+            .filter(|key| {
+                let current = storage
+                    .read(key)
+                    .ok()
+                    .flatten()
+                    .and_then(|bytes| serde_json::from_slice::<CachedIcon>(&bytes).ok())
+                    .is_some_and(|cached| {
+                        modified_ms(&cached.path) == Some(cached.modified_ms)
+                            && icon_cache_key(&cached.path, cached.modified_ms) == *key
+                    });
+                !current && storage.delete(key).is_ok()
+            })
+            .count()
+    }
+}
+
+/// Storage key of the persisted icon for `app_path` as of `modified_ms`
+///
+/// The hash is stable across builds and Rust versions, unlike `DefaultHasher`, so a
+/// relaunch after an update still finds the icons it persisted.
+fn icon_cache_key(app_path: &str, modified_ms: u128) -> String {
+    let digest = Sha256::new()
+        .chain_update(app_path.as_bytes())
+        .chain_update(modified_ms.to_le_bytes())
+        .finalize();
+    format!("{}/{:x}.json", ICON_CACHE_PREFIX, digest)
+}
+
+/// Modification time of `app_path` in milliseconds, if it is a file or bundle on disk
+fn modified_ms(app_path: &str) -> Option<u128> {
+    let modified = std::fs::metadata(app_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|since_epoch| since_epoch.as_millis())
 }
 
 impl Default for IconCache {
@@ -94,7 +274,7 @@ impl Default for IconCache {
 }
 
 // Global icon cache instance
-static GLOBAL_ICON_CACHE: Lazy<IconCache> = Lazy::new(|| IconCache::new());
+static GLOBAL_ICON_CACHE: Lazy<IconCache> = Lazy::new(|| IconCache::with_storage(STORAGE.clone()));
 
 /// Get icon for a specific application path (with caching)
 #[command]
@@ -102,6 +282,90 @@ pub async fn get_application_icon(app_path: String) -> Option<String> {
     GLOBAL_ICON_CACHE.get_or_extract(&app_path).await
 }
 
+/// Icons for arbitrary paths as base64 PNG data URLs, through the icon cache
+///
+/// Applications (`.app` bundles, `.exe`s, `.desktop` entries and executables) get their
+/// own icon and documents the icon of their default application. Missing paths, and
+/// documents no application is registered for, map to `None`.
+#[command]
+pub async fn extract_icons(paths: Vec<String>) -> HashMap<String, Option<String>> {
+    icons_for_paths(&GLOBAL_ICON_CACHE, paths, extract_app_icon, default_application_path).await
+}
+
+async fn icons_for_paths(
+    cache: &IconCache,
+    paths: Vec<String>,
+    extract: impl Fn(&str) -> Option<String> + Copy,
+    default_app: impl Fn(&str) -> Option<String> + Clone + Send + 'static,
+) -> HashMap<String, Option<String>> {
+    let mut icons = HashMap::with_capacity(paths.len());
+    // Looking up a default application can spawn a process, so do it once per extension
+    // and off the async runtime
+    let mut default_apps: HashMap<String, Option<String>> = HashMap::new();
+    for path in paths {
+        let file = Path::new(&path);
+        let icon = if is_application_path(file) {
+            cache.get_or_extract_with(&path, extract).await
+        } else if let Some(extension) = file.extension().filter(|_| file.is_file()) {
+            let extension = extension.to_string_lossy().to_lowercase();
+            let app = match default_apps.get(&extension) {
+                Some(app) => app.clone(),
+                None => {
+                    let lookup = default_app.clone();
+                    let key = extension.clone();
+                    let app = tokio::task::spawn_blocking(move || lookup(&key)).await.ok().flatten();
+                    default_apps.insert(extension, app.clone());
+                    app
+                }
+            };
+            match app {
+                Some(app) => cache.get_or_extract_with(&app, extract).await,
+                None => None,
+            }
+        } else {
+            None
+        };
+        icons.insert(path, icon);
+    }
+    icons
+}
+
+/// Extensions of files Windows runs rather than opens: programs, shortcuts and scripts
+const WINDOWS_PROGRAM_EXTENSIONS: &[&str] = &["exe", "lnk", "com", "bat", "cmd"];
+
+/// Whether `path` exists and is an application as listed by the application scan: an `.app`
+/// bundle, a `.desktop` entry, a Windows program or shortcut, or an executable file
+///
+/// Windows programs count on every platform, so they're never handed out as documents.
+pub(crate) fn is_application_path(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if path.is_dir() {
+        extension == "app"
+    } else {
+        path.is_file()
+            && (extension == "desktop"
+                || WINDOWS_PROGRAM_EXTENSIONS.contains(&extension.as_str())
+                || is_executable(path))
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Without permission bits, only the extension tells a program apart
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    false
+}
+
 // ============================================================================
 // Icon Extraction
 // ============================================================================
@@ -150,6 +414,7 @@ fn extract_app_icon(app_path: &str) -> Option<String> {
     }
 
     // List all files in Resources directory for additional icon files
+    let mut bitmap_icons = Vec::new();
     if let Ok(entries) = fs::read_dir(&resources_dir) {
         for entry in entries.flatten() {
             let file_name = entry.file_name();
@@ -160,12 +425,26 @@ fn extract_app_icon(app_path: &str) -> Option<String> {
                     if let Ok(icon_data) = convert_icns_to_png(&icon_path) {
                         return Some(icon_data);
                     }
+                } else if is_bitmap_icon(name_str) {
+                    bitmap_icons.push(resources_dir.join(name_str));
                 }
             }
         }
     }
 
-    None
+    // Some apps ship their icon as a plain PNG/TIFF instead of an .icns
+    bitmap_icons.sort();
+    bitmap_icons
+        .iter()
+        .find_map(|icon_path| convert_bitmap_to_png(icon_path).ok())
+}
+
+/// PNG/TIFF files in Resources whose name marks them as the app icon
+#[cfg(target_os = "macos")]
+fn is_bitmap_icon(file_name: &str) -> bool {
+    let name = file_name.to_lowercase();
+    let is_bitmap = [".png", ".tiff", ".tif"].iter().any(|ext| name.ends_with(ext));
+    is_bitmap && name.contains("icon")
 }
 
 #[cfg(target_os = "macos")]
@@ -196,12 +475,42 @@ fn convert_icns_to_png(icon_path: &Path) -> Result<String, Box<dyn std::error::E
         }
     }
 
+    // Fall back to whatever else the family holds (ARGB, RLE-compressed RGB, ...), largest first
+    let mut available: Vec<IconType> = icon_family
+        .available_icons()
+        .into_iter()
+        .filter(|icon_type| !icon_type.is_mask())
+        .collect();
+    available.sort_by_key(|icon_type| std::cmp::Reverse(icon_type.pixel_width()));
+
+    for icon_type in available {
+        if let Ok(img) = icon_family.get_icon_with_type(icon_type) {
+            return convert_image_to_base64(img);
+        }
+    }
+
     Err("No suitable icon found in .icns file".into())
 }
 
+/// Re-encode a PNG or TIFF icon file as a base64 PNG data URL
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn convert_bitmap_to_png(icon_path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    encode_png(&image::open(icon_path)?)
+}
+
+/// Encode a decoded icon as a base64 PNG data URL
+#[cfg(any(target_os = "macos", target_os = "windows", target_os = "linux"))]
+fn encode_png(image: &image::DynamicImage) -> Result<String, Box<dyn std::error::Error>> {
+    use std::io::Cursor;
+
+    let mut png_data = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png_data), image::ImageFormat::Png)?;
+
+    Ok(png_data_url(&png_data))
+}
+
 #[cfg(target_os = "macos")]
 fn convert_image_to_base64(image: icns::Image) -> Result<String, Box<dyn std::error::Error>> {
-    use base64::{engine::general_purpose, Engine as _};
     use std::io::Cursor;
 
     // Convert to PNG and encode as base64
@@ -210,11 +519,206 @@ fn convert_image_to_base64(image: icns::Image) -> Result<String, Box<dyn std::er
 
     image.write_png(&mut cursor)?;
 
-    let base64_str = general_purpose::STANDARD.encode(&png_data);
-    Ok(format!("data:image/png;base64,{}", base64_str))
+    Ok(png_data_url(&png_data))
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows", target_os = "linux"))]
+fn png_data_url(png_data: &[u8]) -> String {
+    use base64::{engine::general_purpose, Engine as _};
+
+    let base64_str = general_purpose::STANDARD.encode(png_data);
+    format!("data:image/png;base64,{}", base64_str)
+}
+
+/// The icon group embedded in a Windows executable's resources
+#[cfg(target_os = "windows")]
+fn extract_app_icon(app_path: &str) -> Option<String> {
+    use pelite::{FileMap, PeFile};
+
+    let path = Path::new(app_path);
+    if !path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exe"))
+    {
+        return None;
+    }
+
+    let map = FileMap::open(path).ok()?;
+    let resources = PeFile::from_bytes(&map).ok()?.resources().ok()?;
+
+    // The first icon group is the one Explorer shows for the executable
+    let (_, group) = resources.icons().find_map(Result::ok)?;
+    let mut ico_data = Vec::new();
+    group.write(&mut ico_data).ok()?;
+
+    // The ICO decoder picks the largest image in the group
+    let image = image::load_from_memory_with_format(&ico_data, image::ImageFormat::Ico).ok()?;
+    encode_png(&image).ok()
+}
+
+/// The icon named by a Linux application's `.desktop` entry
+#[cfg(target_os = "linux")]
+fn extract_app_icon(app_path: &str) -> Option<String> {
+    let icon_path = find_linux_icon(app_path, &xdg_data_dirs(), &linux_icon_themes())?;
+    load_icon_file(&icon_path)
+}
+
+/// A PNG, bitmap or SVG icon file as a data URL; SVGs are passed through unrasterized
+#[cfg(target_os = "linux")]
+fn load_icon_file(icon_path: &Path) -> Option<String> {
+    use base64::{engine::general_purpose, Engine as _};
+
+    if icon_path.extension().is_some_and(|extension| extension == "svg") {
+        let svg = std::fs::read(icon_path).ok()?;
+        return Some(format!(
+            "data:image/svg+xml;base64,{}",
+            general_purpose::STANDARD.encode(svg)
+        ));
+    }
+    convert_bitmap_to_png(icon_path).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn load_icon_file(_icon_path: &Path) -> Option<String> {
+    None
+}
+
+/// Icon themes to look in: the active GTK theme, if set, then hicolor
+#[cfg(target_os = "linux")]
+fn linux_icon_themes() -> Vec<String> {
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(std::path::PathBuf::from)
+        .or_else(|| env::var("HOME").ok().map(|home| Path::new(&home).join(".config")));
+    let active = config_home.and_then(|config| {
+        ["gtk-4.0", "gtk-3.0"].iter().find_map(|gtk| {
+            let settings = std::fs::read_to_string(config.join(gtk).join("settings.ini")).ok()?;
+            settings.lines().find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == "gtk-icon-theme-name")
+                    .then(|| value.trim().trim_matches('"').to_string())
+                    .filter(|theme| !theme.is_empty())
+            })
+        })
+    });
+
+    active
+        .into_iter()
+        .filter(|theme| theme != "hicolor")
+        .chain(std::iter::once("hicolor".to_string()))
+        .collect()
+}
+
+/// `$XDG_DATA_HOME` followed by `$XDG_DATA_DIRS`, with the XDG defaults when unset
+#[cfg(target_os = "linux")]
+fn xdg_data_dirs() -> Vec<std::path::PathBuf> {
+    use std::path::PathBuf;
+
+    let data_home = env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var("HOME").ok().map(|home| Path::new(&home).join(".local/share")));
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    data_home
+        .into_iter()
+        .chain(data_dirs.split(':').filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .collect()
+}
+
+/// Icon sizes looked up in the icon theme, largest first
+#[cfg(target_os = "linux")]
+const LINUX_ICON_SIZES: &[&str] = &[
+    "256x256", "128x128", "96x96", "64x64", "48x48", "32x32", "24x24", "16x16",
+];
+
+/// Icon file for `app_path`, which is either a `.desktop` file or the command one launches
+///
+/// The entry's `Icon=` is used as is when absolute, otherwise looked up in each of `themes`
+/// in order, as a sized PNG then a scalable SVG, and finally in pixmaps.
+#[cfg(target_os = "linux")]
+fn find_linux_icon(app_path: &str, data_dirs: &[std::path::PathBuf], themes: &[String]) -> Option<std::path::PathBuf> {
+    let entry = if app_path.ends_with(".desktop") {
+        std::fs::read_to_string(app_path).ok()?
+    } else {
+        find_desktop_entry(app_path, data_dirs)?
+    };
+    let icon = desktop_entry_value(&entry, "Icon")?;
+
+    let icon_path = Path::new(&icon);
+    if icon_path.is_absolute() {
+        return icon_path.is_file().then(|| icon_path.to_path_buf());
+    }
+    let name = icon
+        .strip_suffix(".png")
+        .or_else(|| icon.strip_suffix(".svg"))
+        .unwrap_or(&icon);
+    let (png, svg) = (&format!("{}.png", name), &format!("{}.svg", name));
+    let in_theme = |theme: &String| {
+        let sized = LINUX_ICON_SIZES.iter().flat_map(|size| {
+            data_dirs
+                .iter()
+                .map(move |dir| dir.join("icons").join(theme).join(size).join("apps").join(png))
+        });
+        let scalable = data_dirs
+            .iter()
+            .map(|dir| dir.join("icons").join(theme).join("scalable/apps").join(svg));
+        sized.chain(scalable).collect::<Vec<_>>()
+    };
+    themes
+        .iter()
+        .flat_map(in_theme)
+        .chain(
+            data_dirs
+                .iter()
+                .flat_map(|dir| [dir.join("pixmaps").join(png), dir.join("pixmaps").join(svg)]),
+        )
+        .find(|candidate| candidate.is_file())
+}
+
+/// Contents of the `.desktop` file under `data_dirs` whose `Exec=` launches `command`
+#[cfg(target_os = "linux")]
+fn find_desktop_entry(command: &str, data_dirs: &[std::path::PathBuf]) -> Option<String> {
+    data_dirs
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir.join("applications")).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "desktop"))
+        .filter_map(|entry| std::fs::read_to_string(entry.path()).ok())
+        .find(|contents| desktop_entry_value(contents, "Exec").is_some_and(|exec| exec_command(&exec) == command))
+}
+
+/// Value of `key` in the `[Desktop Entry]` group of a `.desktop` file
+#[cfg(target_os = "linux")]
+fn desktop_entry_value(contents: &str, key: &str) -> Option<String> {
+    let mut in_desktop_entry = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_desktop_entry = line == "[Desktop Entry]";
+        } else if let Some((name, value)) = line.split_once('=').filter(|_| in_desktop_entry) {
+            if name.trim() == key {
+                return Some(value.trim().to_string());
+            }
+        }
+    }
+    None
+}
+
+/// An `Exec=` value without its field codes (`%u`, `%F`, ...), as listed for the application
+#[cfg(target_os = "linux")]
+fn exec_command(exec: &str) -> String {
+    exec.split_whitespace()
+        .take_while(|part| !part.starts_with('%'))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
-#[cfg(not(target_os = "macos"))]
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
 fn extract_app_icon(_app_path: &str) -> Option<String> {
     None
 }
@@ -222,79 +726,141 @@ fn extract_app_icon(_app_path: &str) -> Option<String> {
 /// Search for applications installed on the system
 #[command]
 pub async fn search_applications(query: String) -> Result<Vec<Application>, String> {
-    use applications::{AppInfo, AppInfoContext};
-
-    let query_lower = query.to_lowercase();
+    let apps = APPLICATION_CACHE.applications_or_scan().await.map_err(|message| {
+        record_error(Subsystem::Search, message.clone());
+        message
+    })?;
+
+    // Rank by relevance, extracting icons for the shown results only
+    let mut results = rank_applications(apps, &query, 10, &frecency_scores());
+    for app in results.iter_mut().filter(|app| app.icon_base64.is_none()) {
+        app.icon_base64 = GLOBAL_ICON_CACHE.get_or_extract(&app.path).await;
+    }
+    Ok(results)
+}
 
-    // Create context and refresh apps
-    let mut ctx = AppInfoContext::new(vec![]);
-    ctx.refresh_apps()
-        .map_err(|e| format!("Failed to refresh applications: {}", e))?;
+/// Score boost for an app name equal to the query, putting exact matches first
+const EXACT_MATCH_BOOST: i64 = 1_000_000;
 
-    // Get all applications
-    let apps = ctx.get_all_apps();
+/// Score boost for an app name starting with the query, ahead of any fuzzy-only match
+const PREFIX_MATCH_BOOST: i64 = 100_000;
 
-    // Filter and map to our Application struct
-    let mut results: Vec<Application> = apps
-        .into_iter()
-        .filter(|app| app.name.to_lowercase().contains(&query_lower))
-        .map(|app| {
-            let exe_path = app
-                .app_path_exe
-                .as_ref()
-                .map(|p| p.to_string_lossy().to_string())
-                .unwrap_or_else(|| "Unknown".to_string());
+/// Score boost per unit of frecency (roughly one recent launch)
+const FRECENCY_BOOST: f64 = 40.0;
 
-            // Convert executable path to .app bundle root path
-            let app_bundle_path = if exe_path.contains("/Contents/MacOS/") {
-                if let Some(bundle_end) = exe_path.find(".app/Contents/MacOS/") {
-                    exe_path[..bundle_end + 4].to_string()
-                } else {
-                    exe_path
-                }
-            } else {
-                exe_path
-            };
+/// Cap on the frecency boost, keeping it below the prefix match boost
+const MAX_FRECENCY_BOOST: i64 = 2_000;
 
-            let icon_base64 = extract_app_icon(&app_bundle_path);
+/// Fuzzy-match application names against `query`, best first, keeping at most `limit`
+///
+/// Typos that drop letters ("chrm", "gogle chrom") still match. Exact and prefix matches
+/// are boosted above fuzzy ones, apps with a higher `frecency` (keyed by path) are lifted
+/// within those tiers, and equal scores are ordered by name.
+fn rank_applications(
+    apps: Vec<Application>,
+    query: &str,
+    limit: usize,
+    frecency: &HashMap<String, f64>,
+) -> Vec<Application> {
+    let matcher = SkimMatcherV2::default().ignore_case();
 
-            Application {
-                name: app.name.clone(),
-                path: app_bundle_path,
-                icon_path: None,
-                icon_base64,
-            }
+    let mut scored: Vec<(i64, Application)> = apps
+        .into_iter()
+        .filter_map(|app| {
+            let score = name_score(&matcher, &app.name, query)? + frecency_boost(frecency, &app.path);
+            Some((score, app))
         })
         .collect();
+    scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then_with(|| a.name.cmp(&b.name)));
 
-    // Sort by relevance
-    results.sort_by(|a, b| {
-        let a_lower = a.name.to_lowercase();
-        let b_lower = b.name.to_lowercase();
-
-        if a_lower == query_lower {
-            std::cmp::Ordering::Less
-        } else if b_lower == query_lower {
-            std::cmp::Ordering::Greater
-        } else if a_lower.starts_with(&query_lower) && !b_lower.starts_with(&query_lower) {
-            std::cmp::Ordering::Less
-        } else if !a_lower.starts_with(&query_lower) && b_lower.starts_with(&query_lower) {
-            std::cmp::Ordering::Greater
-        } else {
-            a.name.cmp(&b.name)
+    scored.into_iter().take(limit).map(|(_, app)| app).collect()
+}
+
+/// Fuzzy score of `name` against `query`, with the exact and prefix match boosts
+fn name_score(matcher: &SkimMatcherV2, name: &str, query: &str) -> Option<i64> {
+    let name_lower = name.to_lowercase();
+    let query_lower = query.to_lowercase();
+    let mut score = matcher.fuzzy_match(&name_lower, &query_lower)?;
+    if name_lower == query_lower {
+        score += EXACT_MATCH_BOOST;
+    } else if name_lower.starts_with(&query_lower) {
+        score += PREFIX_MATCH_BOOST;
+    }
+    Some(score)
+}
+
+fn frecency_boost(frecency: &HashMap<String, f64>, path: &str) -> i64 {
+    frecency.get(path).map_or(0, |frecency| {
+        ((frecency * FRECENCY_BOOST) as i64).min(MAX_FRECENCY_BOOST)
+    })
+}
+
+/// Score added to apps in a flat result list, the usual launcher target
+const APP_KIND_WEIGHT: i64 = 50;
+
+/// Score added to plugin commands in a flat result list
+const COMMAND_KIND_WEIGHT: i64 = 30;
+
+/// Rank apps, files and plugin commands together, best first
+///
+/// Apps and commands are scored on their name or title, files on their file name, each
+/// with the boosts used by `rank_applications` plus a weight for its kind. Files found
+/// by content alone still appear, after any match on name. Equal scores keep apps
+/// before commands before files.
+fn rank_unified(
+    query: &str,
+    apps: Vec<Application>,
+    files: Vec<FileMatch>,
+    commands: Vec<(String, PluginCommand)>,
+    frecency: &HashMap<String, f64>,
+) -> Vec<RankedResult> {
+    let matcher = SkimMatcherV2::default().ignore_case();
+
+    let apps = apps.into_iter().filter_map(|app| {
+        let score = name_score(&matcher, &app.name, query)? + APP_KIND_WEIGHT + frecency_boost(frecency, &app.path);
+        Some(RankedResult {
+            score,
+            item: RankedItem::App(app),
+        })
+    });
+    let commands = commands.into_iter().filter_map(|(plugin_id, command)| {
+        let score = std::iter::once(&command.title)
+            .chain(&command.keywords)
+            .filter_map(|name| name_score(&matcher, name, query))
+            .max()?;
+        Some(RankedResult {
+            score: score + COMMAND_KIND_WEIGHT,
+            item: RankedItem::Command { plugin_id, command },
+        })
+    });
+    let files = files.into_iter().map(|file| {
+        let file_name = Path::new(&file.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        RankedResult {
+            score: name_score(&matcher, &file_name, query).unwrap_or(0),
+            item: RankedItem::File(file),
         }
     });
 
-    // Limit results
-    results.truncate(10);
-    Ok(results)
+    // The stable sort keeps the kind order for ties
+    let mut ranked: Vec<RankedResult> = apps.chain(commands).chain(files).collect();
+    ranked.sort_by(|a, b| b.score.cmp(&a.score));
+    ranked
 }
 
 /// Get all applications (for frontend caching)
 /// Note: Icons are NOT extracted here for performance.
 /// Icons should be extracted on-demand for displayed results only.
+/// Served from the application cache once a refresh has populated it.
 #[command]
 pub async fn get_all_applications() -> Result<Vec<Application>, String> {
+    APPLICATION_CACHE.applications_or_scan().await
+}
+
+/// List installed applications without extracting icons
+fn scan_applications() -> Result<Vec<Application>, String> {
     use applications::{AppInfo, AppInfoContext};
 
     // Create context and refresh apps
@@ -307,159 +873,1101 @@ pub async fn get_all_applications() -> Result<Vec<Application>, String> {
 
     // Convert to our Application struct (all apps, no filtering)
     // Icons are NOT extracted here - would be too slow for hundreds of apps
-    let results: Vec<Application> = apps
-        .into_iter()
-        .map(|app| {
-            let exe_path = app
-                .app_path_exe
-                .as_ref()
-                .map(|p| p.to_string_lossy().to_string())
-                .unwrap_or_else(|| "Unknown".to_string());
+    let results: Vec<Application> = apps.into_iter().map(installed_application).collect();
 
-            // Convert executable path to .app bundle root path
-            let app_bundle_path = if exe_path.contains("/Contents/MacOS/") {
-                if let Some(bundle_end) = exe_path.find(".app/Contents/MacOS/") {
-                    exe_path[..bundle_end + 4].to_string()
-                } else {
-                    exe_path
-                }
+    Ok(results)
+}
+
+/// An installed application as scanned, without its icon extracted
+fn installed_application(app: applications::App) -> Application {
+    // On Linux the `.desktop` entry is the application, and the scan has already
+    // resolved its icon through the icon theme
+    #[cfg(target_os = "linux")]
+    let (app_bundle_path, icon_path) = (
+        app.app_desktop_path.to_string_lossy().to_string(),
+        app.icon_path
+            .as_ref()
+            .filter(|icon| icon.is_file())
+            .map(|icon| icon.to_string_lossy().to_string()),
+    );
+
+    #[cfg(not(target_os = "linux"))]
+    let (app_bundle_path, icon_path) = {
+        let exe_path = app
+            .app_path_exe
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|| "Unknown".to_string());
+
+        // Convert executable path to .app bundle root path
+        let app_bundle_path = if exe_path.contains("/Contents/MacOS/") {
+            if let Some(bundle_end) = exe_path.find(".app/Contents/MacOS/") {
+                exe_path[..bundle_end + 4].to_string()
             } else {
                 exe_path
-            };
-
-            Application {
-                name: app.name.clone(),
-                path: app_bundle_path,
-                icon_path: None,
-                icon_base64: None, // Icons extracted on-demand for better performance
             }
-        })
-        .collect();
+        } else {
+            exe_path
+        };
+        (app_bundle_path, None)
+    };
 
-    Ok(results)
+    Application {
+        name: app.name.clone(),
+        path: app_bundle_path,
+        icon_path,
+        icon_base64: None, // Icons extracted on-demand for better performance
+    }
 }
 
-/// Search for files using ripgrep-style search
-#[command]
-pub async fn search_files(
-    query: String,
-    search_path: Option<String>,
-    search_content: bool,
-) -> Result<Vec<FileMatch>, String> {
-    use ignore::WalkBuilder;
-    use std::fs;
-    use std::io::BufRead;
+// ============================================================================
+// Application Cache
+// ============================================================================
 
-    let query_lower = query.to_lowercase();
-    let base_path = search_path.unwrap_or_else(|| {
-        std::env::var("HOME")
-            .or_else(|_| std::env::var("USERPROFILE"))
-            .unwrap_or_else(|_| ".".to_string())
-    });
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshPhase {
+    Scanning,
+    ExtractingIcons,
+    Done,
+}
 
-    let mut results = Vec::new();
-    let max_results = 50;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshProgress {
+    pub phase: RefreshPhase,
+    /// Applications processed so far in this phase
+    pub scanned: usize,
+    pub total: usize,
+}
 
-    // Use ignore crate to respect .gitignore files
-    let walker = WalkBuilder::new(&base_path)
-        .hidden(false) // Show hidden files
-        .git_ignore(true) // Respect .gitignore
-        .max_depth(Some(5)) // Limit depth for performance
-        .build();
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshOutcome {
+    pub indexed: usize,
+    pub total: usize,
+    /// The refresh was aborted; the cache keeps its previous contents
+    pub cancelled: bool,
+}
 
-    for entry in walker {
-        if results.len() >= max_results {
-            break;
-        }
+/// Lists installed applications, blocking while it does
+type ApplicationScanner = Arc<dyn Fn() -> Result<Vec<Application>, String> + Send + Sync>;
+
+/// Installed applications with their icons, indexed in the background
+///
+/// Scans of the system are single-flight: callers arriving while one runs wait for it and
+/// share its result instead of starting their own.
+pub struct ApplicationCache {
+    applications: RwLock<Vec<Application>>,
+    scanner: ApplicationScanner,
+    /// The latest scan, locked while a scan runs
+    last_scan: tokio::sync::Mutex<Option<Vec<Application>>>,
+    /// Scans completed so far, to tell whether one finished while waiting on `last_scan`
+    scans_completed: AtomicUsize,
+    /// Applications in the index, readable without awaiting its lock
+    indexed_count: AtomicUsize,
+}
 
-        let entry = match entry {
-            Ok(e) => e,
-            Err(_) => continue,
-        };
+impl Default for ApplicationCache {
+    fn default() -> Self {
+        Self::with_scanner(scan_applications)
+    }
+}
 
-        if !entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
-            continue;
+impl ApplicationCache {
+    fn with_scanner(scanner: impl Fn() -> Result<Vec<Application>, String> + Send + Sync + 'static) -> Self {
+        Self {
+            applications: RwLock::new(Vec::new()),
+            scanner: Arc::new(scanner),
+            last_scan: tokio::sync::Mutex::new(None),
+            scans_completed: AtomicUsize::new(0),
+            indexed_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether a refresh has indexed any applications
+    pub fn is_populated(&self) -> bool {
+        self.indexed_count.load(Ordering::Acquire) > 0
+    }
+
+    pub async fn applications(&self) -> Vec<Application> {
+        self.applications.read().await.clone()
+    }
+
+    /// The indexed applications, or until a refresh has indexed them, the latest scan
+    /// (without icons), scanning only if there hasn't been one
+    pub async fn applications_or_scan(&self) -> Result<Vec<Application>, String> {
+        let indexed = self.applications().await;
+        if !indexed.is_empty() {
+            return Ok(indexed);
+        }
+        self.scan(true).await
+    }
+
+    /// Scan installed applications, or share the result of a scan already running
+    ///
+    /// With `reuse_previous`, any earlier scan is returned as is; otherwise only one that
+    /// completed while this call waited for it.
+    async fn scan(&self, reuse_previous: bool) -> Result<Vec<Application>, String> {
+        let completed_before = self.scans_completed.load(Ordering::Acquire);
+        let mut last_scan = self.last_scan.lock().await;
+        if let Some(apps) = last_scan.as_ref() {
+            if reuse_previous || self.scans_completed.load(Ordering::Acquire) != completed_before {
+                return Ok(apps.clone());
+            }
+        }
+
+        // Forget the previous scan first, so a rescan that fails or is dropped midway
+        // leaves nothing stale for `applications_or_scan` to reuse
+        *last_scan = None;
+        let scanner = Arc::clone(&self.scanner);
+        let apps = tokio::task::spawn_blocking(move || scanner())
+            .await
+            .map_err(|e| format!("Application scan failed: {}", e))??;
+        *last_scan = Some(apps.clone());
+        self.scans_completed.fetch_add(1, Ordering::Release);
+        Ok(apps)
+    }
+
+    /// Rescan applications, reporting progress and stopping early once `cancel` is set
+    pub async fn refresh_with_progress(
+        &self,
+        progress: Option<tokio::sync::mpsc::Sender<RefreshProgress>>,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<RefreshOutcome, String> {
+        let report = |phase, scanned, total| {
+            let progress = progress.clone();
+            async move {
+                if let Some(progress) = progress {
+                    let _ = progress.send(RefreshProgress { phase, scanned, total }).await;
+                }
+            }
+        };
+
+        let cancelled = |total| RefreshOutcome {
+            indexed: 0,
+            total,
+            cancelled: true,
+        };
+
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(cancelled(0));
+        }
+        report(RefreshPhase::Scanning, 0, 0).await;
+        let apps = self.scan(false).await?;
+        // A scan takes a while; don't start indexing if the refresh was cancelled meanwhile
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(cancelled(apps.len()));
+        }
+
+        self.index(apps, progress, cancel).await
+    }
+
+    /// Extract icons for `apps` and replace the cache contents unless cancelled
+    async fn index(
+        &self,
+        apps: Vec<Application>,
+        progress: Option<tokio::sync::mpsc::Sender<RefreshProgress>>,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<RefreshOutcome, String> {
+        let total = apps.len();
+        let mut indexed = Vec::with_capacity(total);
+
+        for mut app in apps {
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(RefreshOutcome {
+                    indexed: indexed.len(),
+                    total,
+                    cancelled: true,
+                });
+            }
+
+            let icon_path = app.icon_path.clone();
+            app.icon_base64 = GLOBAL_ICON_CACHE
+                .get_or_extract_with(&app.path, |path| {
+                    icon_path
+                        .as_deref()
+                        .and_then(|icon| load_icon_file(Path::new(icon)))
+                        .or_else(|| extract_app_icon(path))
+                })
+                .await;
+            indexed.push(app);
+
+            if let Some(progress) = &progress {
+                let _ = progress
+                    .send(RefreshProgress {
+                        phase: RefreshPhase::ExtractingIcons,
+                        scanned: indexed.len(),
+                        total,
+                    })
+                    .await;
+            }
+        }
+
+        self.indexed_count.store(indexed.len(), Ordering::Release);
+        *self.applications.write().await = indexed;
+        if let Some(progress) = &progress {
+            let _ = progress
+                .send(RefreshProgress {
+                    phase: RefreshPhase::Done,
+                    scanned: total,
+                    total,
+                })
+                .await;
+        }
+
+        Ok(RefreshOutcome {
+            indexed: total,
+            total,
+            cancelled: false,
+        })
+    }
+}
+
+static APPLICATION_CACHE: Lazy<ApplicationCache> = Lazy::new(ApplicationCache::default);
+
+/// Whether the application index has been built, for the diagnostics screen
+pub fn application_index_populated() -> bool {
+    Lazy::get(&APPLICATION_CACHE).is_some_and(ApplicationCache::is_populated)
+}
+
+/// Cancellation flag of the refresh currently in flight
+static REFRESH_CANCEL: Lazy<std::sync::Mutex<Arc<AtomicBool>>> =
+    Lazy::new(|| std::sync::Mutex::new(Arc::new(AtomicBool::new(false))));
+
+/// Refresh the application cache, streaming progress to the frontend
+#[command]
+pub async fn refresh_application_cache(
+    on_progress: tauri::ipc::Channel<RefreshProgress>,
+) -> Result<RefreshOutcome, String> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    if let Ok(mut current) = REFRESH_CANCEL.lock() {
+        // A new refresh supersedes the previous one
+        current.store(true, Ordering::Relaxed);
+        *current = Arc::clone(&cancel);
+    }
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+    let forward = tokio::spawn(async move {
+        while let Some(progress) = rx.recv().await {
+            if on_progress.send(progress).is_err() {
+                break;
+            }
+        }
+    });
+
+    let outcome = APPLICATION_CACHE.refresh_with_progress(Some(tx), cancel).await;
+    let _ = forward.await;
+    outcome
+}
+
+/// Abort the application cache refresh in flight, if any
+#[command]
+pub fn cancel_application_cache_refresh() {
+    if let Ok(current) = REFRESH_CANCEL.lock() {
+        current.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Search for files using ripgrep-style search
+///
+/// `scope` names a configured search scope and takes precedence over `search_path`.
+/// With `count_all_matches`, content search scans each whole file (up to the line cap)
+/// and reports `match_count` instead of stopping at the first matching line. Matching
+/// lines longer than `max_line_length` characters (default `SEARCH_MAX_LINE_LENGTH`,
+/// else 500) are cut down to a window around the match. Once the walk has taken
+/// `timeout_ms` (default `SEARCH_TIMEOUT_MS`, else 10 seconds) it stops and the matches
+/// found so far are returned.
+#[allow(clippy::too_many_arguments)]
+#[command]
+pub async fn search_files(
+    query: String,
+    search_path: Option<String>,
+    search_content: bool,
+    scope: Option<String>,
+    count_all_matches: Option<bool>,
+    max_line_length: Option<usize>,
+    regex: Option<bool>,
+    extensions: Option<Vec<String>>,
+    glob: Option<String>,
+    timeout_ms: Option<u64>,
+) -> Result<Vec<FileMatch>, String> {
+    let search = FileSearch::new(
+        &query,
+        search_path,
+        search_content,
+        scope,
+        count_all_matches,
+        max_line_length,
+        regex,
+        extensions,
+        glob,
+        timeout_ms,
+    )?;
+    Ok(search.stream().collect().await)
+}
+
+/// `search_files`, sending each match over `on_match` as soon as it is found
+///
+/// Returns the number of matches sent. The walk stops once the frontend stops listening.
+/// Starting a search with a `search_id` cancels the previous one that had an id, so the
+/// walk of a query the user has since replaced stops instead of running to its deadline.
+#[allow(clippy::too_many_arguments)]
+#[command]
+pub async fn search_files_stream(
+    query: String,
+    search_path: Option<String>,
+    search_content: bool,
+    scope: Option<String>,
+    count_all_matches: Option<bool>,
+    max_line_length: Option<usize>,
+    regex: Option<bool>,
+    extensions: Option<Vec<String>>,
+    glob: Option<String>,
+    timeout_ms: Option<u64>,
+    search_id: Option<String>,
+    on_match: tauri::ipc::Channel<FileMatch>,
+) -> Result<usize, String> {
+    let mut search = FileSearch::new(
+        &query,
+        search_path,
+        search_content,
+        scope,
+        count_all_matches,
+        max_line_length,
+        regex,
+        extensions,
+        glob,
+        timeout_ms,
+    )?;
+    if let Some(search_id) = &search_id {
+        search = search.with_cancel(supersede_file_search(search_id));
+    }
+    let mut matches = Box::pin(search.stream());
+    let mut sent = 0;
+    while let Some(file_match) = matches.next().await {
+        if on_match.send(file_match).is_err() {
+            break;
+        }
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// Id and cancellation flag of the streamed file search a newer one supersedes
+static ACTIVE_FILE_SEARCH: Lazy<std::sync::Mutex<Option<(String, Arc<AtomicBool>)>>> =
+    Lazy::new(|| std::sync::Mutex::new(None));
+
+/// Make `search_id` the running file search, cancelling the one it replaces
+fn supersede_file_search(search_id: &str) -> Arc<AtomicBool> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    if let Ok(mut active) = ACTIVE_FILE_SEARCH.lock() {
+        if let Some((_, previous)) = active.replace((search_id.to_string(), Arc::clone(&cancel))) {
+            previous.store(true, Ordering::Relaxed);
+        }
+    }
+    cancel
+}
+
+/// Stop the streamed file search started with `search_id`, if it is still the running one
+#[command]
+pub fn cancel_file_search(search_id: String) {
+    if let Ok(mut active) = ACTIVE_FILE_SEARCH.lock() {
+        if active.as_ref().is_some_and(|(id, _)| *id == search_id) {
+            if let Some((_, cancel)) = active.take() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// A file search with its options resolved, as run by `search_files` and `search_files_stream`
+struct FileSearch {
+    pattern: SearchPattern,
+    search_path: String,
+    filter: FileFilter,
+    content_mode: ContentSearch,
+    max_line_length: usize,
+    /// How long the walk may take before it stops with the matches found so far
+    time_budget: Duration,
+    /// Set when a newer search supersedes this one
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl FileSearch {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        query: &str,
+        search_path: Option<String>,
+        search_content: bool,
+        scope: Option<String>,
+        count_all_matches: Option<bool>,
+        max_line_length: Option<usize>,
+        regex: Option<bool>,
+        extensions: Option<Vec<String>>,
+        glob: Option<String>,
+        timeout_ms: Option<u64>,
+    ) -> Result<Self, String> {
+        let pattern = SearchPattern::new(query, regex.unwrap_or(false))?;
+        let search_path =
+            resolve_configured_search_root(scope.as_deref(), search_path)?.unwrap_or_else(default_search_root);
+        let filter = FileFilter::new(Path::new(&search_path), extensions.unwrap_or_default(), glob.as_deref())?;
+        let content_mode = match (search_content, count_all_matches.unwrap_or(false)) {
+            (false, _) => ContentSearch::Off,
+            (true, false) => ContentSearch::FirstMatch,
+            (true, true) => ContentSearch::CountAll,
+        };
+
+        Ok(Self {
+            pattern,
+            search_path,
+            filter,
+            content_mode,
+            max_line_length: max_line_length.unwrap_or_else(configured_max_line_length),
+            time_budget: Duration::from_millis(timeout_ms.unwrap_or_else(configured_search_timeout_ms)),
+            cancel: None,
+        })
+    }
+
+    fn with_cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Walk the tree, handing matches to `on_match` until it returns `false` or `deadline` passes
+    fn run(&self, deadline: Instant, on_match: impl FnMut(FileMatch) -> bool) {
+        walk_files(
+            &self.pattern,
+            WalkOptions {
+                search_path: Some(self.search_path.clone()),
+                filter: &self.filter,
+                content_search: self.content_mode,
+                max_line_length: self.max_line_length,
+                deadline: Some(deadline),
+                cancel: self.cancel.as_deref(),
+            },
+            on_match,
+        );
+    }
+
+    /// Matches as they are found, walking on a blocking thread
+    ///
+    /// Dropping the stream stops the walk at the next match. The stream ends once the time
+    /// budget is spent, even if the walk is stuck waiting on a slow filesystem.
+    fn stream(self) -> impl Stream<Item = FileMatch> + Send {
+        let deadline = Instant::now() + self.time_budget;
+        let (tx, rx) = tokio::sync::mpsc::channel(32);
+        tokio::task::spawn_blocking(move || self.run(deadline, |file_match| tx.blocking_send(file_match).is_ok()));
+        tokio_stream::wrappers::ReceiverStream::new(rx)
+            .take_until(tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)))
+    }
+}
+
+/// Which files `walk_files` considers, checked before names and contents are matched
+#[derive(Debug)]
+struct FileFilter {
+    /// Lowercase extensions without the dot; empty allows any
+    extensions: Vec<String>,
+    /// Gitignore-style glob relative to the search root; a leading `!` excludes
+    glob: Option<ignore::overrides::Override>,
+}
+
+impl FileFilter {
+    fn new(root: &Path, extensions: Vec<String>, glob: Option<&str>) -> Result<Self, String> {
+        use ignore::overrides::OverrideBuilder;
+
+        let extensions = extensions
+            .iter()
+            .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+            .filter(|extension| !extension.is_empty())
+            .collect();
+        let glob = match glob.map(str::trim).filter(|glob| !glob.is_empty()) {
+            Some(glob) => {
+                let invalid = |e: ignore::Error| format!("Invalid glob '{}': {}", glob, e);
+                let mut builder = OverrideBuilder::new(root);
+                builder.add(glob).map_err(invalid)?;
+                Some(builder.build().map_err(invalid)?)
+            }
+            None => None,
+        };
+        Ok(Self { extensions, glob })
+    }
+
+    fn allows_extension(&self, path: &Path) -> bool {
+        self.extensions.is_empty()
+            || path
+                .extension()
+                .is_some_and(|extension| self.extensions.contains(&extension.to_string_lossy().to_lowercase()))
+    }
+}
+
+/// Home directory searched when no path or scope is given
+fn default_search_root() -> String {
+    std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .unwrap_or_else(|_| ".".to_string())
+}
+
+/// How `walk_files` treats file contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentSearch {
+    /// Match file names only
+    Off,
+    /// Report the first matching line of each file
+    FirstMatch,
+    /// Report the first matching line along with the number of matching lines
+    CountAll,
+}
+
+/// What `walk_files` looks for in file names and lines
+#[derive(Debug, Clone)]
+struct SearchPattern {
+    name: NamePattern,
+    /// Line matcher for searching file contents
+    content: grep_regex::RegexMatcher,
+}
+
+/// How a `SearchPattern` matches file names and locates matches in lines
+#[derive(Debug, Clone)]
+enum NamePattern {
+    /// Case-insensitive substring, stored lowercased
+    Literal(String),
+    /// Regular expression, matched as written
+    Regex(regex::Regex),
+}
+
+impl SearchPattern {
+    /// `query` compiled as a regular expression when `regex` is set, otherwise a literal
+    ///
+    /// Both the name pattern and the content matcher are built here, so a query either
+    /// matcher rejects is an error rather than a search that skips file contents.
+    fn new(query: &str, regex: bool) -> Result<Self, String> {
+        let mut builder = grep_regex::RegexMatcherBuilder::new();
+        builder.line_terminator(Some(b'\n'));
+        if !regex {
+            let content = builder
+                .case_insensitive(true)
+                .fixed_strings(true)
+                .build(query)
+                .map_err(|e| format!("Invalid search query '{}': {}", query, e))?;
+            return Ok(Self {
+                name: NamePattern::Literal(query.to_lowercase()),
+                content,
+            });
+        }
+        let invalid = |e: &dyn std::fmt::Display| format!("Invalid regex '{}': {}", query, e);
+        let name = regex::Regex::new(query).map_err(|e| invalid(&e))?;
+        let content = builder.build(query).map_err(|e| invalid(&e))?;
+        Ok(Self {
+            name: NamePattern::Regex(name),
+            content,
+        })
+    }
+
+    /// Character offset and length of the first match in `text`
+    fn find(&self, text: &str) -> Option<(usize, usize)> {
+        match &self.name {
+            NamePattern::Literal(query) => {
+                let text = text.to_lowercase();
+                let start = text.find(query.as_str())?;
+                Some((text[..start].chars().count(), query.chars().count()))
+            }
+            NamePattern::Regex(regex) => {
+                let found = regex.find(text)?;
+                Some((text[..found.start()].chars().count(), found.as_str().chars().count()))
+            }
+        }
+    }
+}
+
+/// Default cap on the characters of a matching line kept in `FileMatch::line_content`
+const DEFAULT_MAX_LINE_LENGTH: usize = 500;
+
+/// Lines of a file searched for content matches; later matches are ignored
+const MAX_CONTENT_LINES: u64 = 1000;
+
+/// Reads the first `MAX_CONTENT_LINES` lines of a file, ending early once the search is out of time
+///
+/// The searcher's sink only sees matching lines, so the limits are applied to the reader; this is
+/// also why file contents are read rather than memory-mapped.
+struct ContentReader<R, F> {
+    inner: R,
+    lines_left: u64,
+    out_of_time: F,
+}
+
+impl<R: std::io::Read, F: Fn() -> bool> ContentReader<R, F> {
+    fn new(inner: R, out_of_time: F) -> Self {
+        Self {
+            inner,
+            lines_left: MAX_CONTENT_LINES,
+            out_of_time,
+        }
+    }
+}
+
+impl<R: std::io::Read, F: Fn() -> bool> std::io::Read for ContentReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.lines_left == 0 || (self.out_of_time)() {
+            return Ok(0);
+        }
+        let read = self.inner.read(buf)?;
+        for (offset, _) in buf[..read].iter().enumerate().filter(|(_, &byte)| byte == b'\n') {
+            self.lines_left -= 1;
+            if self.lines_left == 0 {
+                return Ok(offset + 1);
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// Marks where text was cut from a long matching line
+const TRUNCATION_MARKER: char = '…';
+
+/// Default time budget of a file search, in milliseconds
+const DEFAULT_SEARCH_TIMEOUT_MS: u64 = 10_000;
+
+/// Time budget from `SEARCH_TIMEOUT_MS`, falling back to `DEFAULT_SEARCH_TIMEOUT_MS`
+fn configured_search_timeout_ms() -> u64 {
+    env::var("SEARCH_TIMEOUT_MS")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|&timeout| timeout > 0)
+        .unwrap_or(DEFAULT_SEARCH_TIMEOUT_MS)
+}
+
+/// Line cap from `SEARCH_MAX_LINE_LENGTH`, falling back to `DEFAULT_MAX_LINE_LENGTH`
+fn configured_max_line_length() -> usize {
+    env::var("SEARCH_MAX_LINE_LENGTH")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|&length| length > 0)
+        .unwrap_or(DEFAULT_MAX_LINE_LENGTH)
+}
+
+/// Cut `line` to at most `max_length` characters, keeping the match that starts at
+/// character `match_start` inside the window and marking each cut end
+///
+/// Returns `None` when the line already fits.
+fn truncate_line(line: &str, match_start: usize, match_length: usize, max_length: usize) -> Option<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.len() <= max_length {
+        return None;
+    }
+
+    // Room for a marker at either end; the match is centred in what is left
+    let window = max_length.saturating_sub(2).max(1);
+    let slack = window.saturating_sub(match_length);
+    let start = match_start.saturating_sub(slack / 2).min(chars.len() - window);
+    let end = start + window;
+
+    let mut truncated = String::with_capacity(window + 2);
+    if start > 0 {
+        truncated.push(TRUNCATION_MARKER);
+    }
+    truncated.extend(&chars[start..end]);
+    if end < chars.len() {
+        truncated.push(TRUNCATION_MARKER);
+    }
+    Some(truncated)
+}
+
+/// Filter that lets every file through, for walks without one
+static NO_FILTER: FileFilter = FileFilter {
+    extensions: Vec::new(),
+    glob: None,
+};
+
+/// Where `walk_files` walks, what it reads and when it stops
+struct WalkOptions<'a> {
+    /// Root of the walk; the home directory when `None`
+    search_path: Option<String>,
+    filter: &'a FileFilter,
+    content_search: ContentSearch,
+    /// Matching lines are kept to this many characters
+    max_line_length: usize,
+    deadline: Option<Instant>,
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl Default for WalkOptions<'_> {
+    fn default() -> Self {
+        Self {
+            search_path: None,
+            filter: &NO_FILTER,
+            content_search: ContentSearch::Off,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            deadline: None,
+            cancel: None,
+        }
+    }
+}
+
+/// Walk `options.search_path` and report each match to `on_match`
+///
+/// The walk stops after 50 matches, once the deadline passes, once `cancel` is set or as
+/// soon as `on_match` returns `false`. Symlinks are not followed, so a link back up the tree can't loop it.
+fn walk_files(pattern: &SearchPattern, options: WalkOptions<'_>, mut on_match: impl FnMut(FileMatch) -> bool) {
+    use grep_searcher::sinks::Lossy;
+    use grep_searcher::{BinaryDetection, SearcherBuilder};
+    use ignore::WalkBuilder;
+
+    let WalkOptions {
+        search_path,
+        filter,
+        content_search,
+        max_line_length,
+        deadline,
+        cancel,
+    } = options;
+    let base_path = search_path.unwrap_or_else(default_search_root);
+
+    let mut match_count = 0;
+    let max_results = 50;
+    let out_of_time = || {
+        deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            || cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    };
+
+    // Files containing a NUL byte are treated as binary and their content is skipped
+    let content_matcher = (content_search != ContentSearch::Off).then_some(&pattern.content);
+    let mut searcher = SearcherBuilder::new()
+        .binary_detection(BinaryDetection::quit(b'\0'))
+        .line_number(true)
+        .build();
+
+    // Use ignore crate to respect .gitignore files
+    let mut walker = WalkBuilder::new(&base_path);
+    walker
+        .hidden(false) // Show hidden files
+        .git_ignore(true) // Respect .gitignore
+        .follow_links(false)
+        .max_depth(Some(5)); // Limit depth for performance
+    if let Some(glob) = &filter.glob {
+        walker.overrides(glob.clone());
+    }
+    let walker = walker.build();
+
+    for entry in walker {
+        if match_count >= max_results || out_of_time() {
+            break;
+        }
+
+        let entry = match entry {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+
+        if !entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
+            continue;
         }
 
         let path = entry.path();
+        if !filter.allows_extension(path) {
+            continue;
+        }
         let path_str = path.to_string_lossy().to_string();
 
         // Search by filename
         if let Some(filename) = path.file_name() {
-            let filename_str = filename.to_string_lossy().to_lowercase();
-            if filename_str.contains(&query_lower) {
-                results.push(FileMatch {
+            if pattern.find(&filename.to_string_lossy()).is_some() {
+                match_count += 1;
+                if !on_match(FileMatch {
                     path: path_str.clone(),
                     line_number: None,
                     line_content: None,
+                    line_length: None,
                     match_type: "name".to_string(),
-                });
+                    match_count: None,
+                }) {
+                    return;
+                }
                 continue;
             }
         }
 
         // Search file content if requested
-        if search_content && results.len() < max_results {
-            // Only search text files (skip binary files)
-            if let Ok(file) = fs::File::open(path) {
-                let reader = std::io::BufReader::new(file);
+        if let Some(matcher) = content_matcher {
+            let mut first_match = None;
+            let mut matching_lines = 0;
 
-                for (line_num, line_result) in reader.lines().enumerate().take(1000) {
-                    if results.len() >= max_results {
-                        break;
-                    }
-
-                    if let Ok(line) = line_result {
-                        if line.to_lowercase().contains(&query_lower) {
-                            results.push(FileMatch {
-                                path: path_str.clone(),
-                                line_number: Some(line_num + 1),
-                                line_content: Some(line.trim().to_string()),
-                                match_type: "content".to_string(),
-                            });
-                            break; // Only one match per file for content search
-                        }
+            // Unreadable files are skipped; a read error after a match still reports it
+            let Ok(file) = std::fs::File::open(path) else {
+                continue;
+            };
+            let _ = searcher.search_reader(
+                matcher,
+                ContentReader::new(file, &out_of_time),
+                Lossy(|line_number, line| {
+                    matching_lines += 1;
+                    if first_match.is_none() {
+                        first_match = Some((line_number as usize, line.trim().to_string()));
                     }
+                    // Only one match per file unless all are being counted
+                    Ok(content_search == ContentSearch::CountAll)
+                }),
+            );
+
+            if let Some((line_number, line_content)) = first_match {
+                let (match_start, match_length) = pattern.find(&line_content).unwrap_or((0, 0));
+                let truncated = truncate_line(&line_content, match_start, match_length, max_line_length);
+                let line_length = truncated.is_some().then(|| line_content.chars().count());
+
+                match_count += 1;
+                if !on_match(FileMatch {
+                    path: path_str.clone(),
+                    line_number: Some(line_number),
+                    line_content: Some(truncated.unwrap_or(line_content)),
+                    line_length,
+                    match_type: "content".to_string(),
+                    match_count: (content_search == ContentSearch::CountAll).then_some(matching_lines),
+                }) {
+                    return;
                 }
             }
         }
     }
-
-    Ok(results)
 }
 
 /// Combined search that returns both applications and files
+///
+/// With `flat`, loaded plugin commands are searched too and everything comes back as one
+/// list ranked by relevance.
+#[allow(clippy::too_many_arguments)]
 #[command]
 pub async fn unified_search(
+    plugins: State<'_, PluginManagerState>,
     query: String,
     search_path: Option<String>,
     include_files: bool,
-) -> Result<SearchResult, String> {
+    extensions: Option<Vec<String>>,
+    glob: Option<String>,
+    flat: Option<bool>,
+) -> Result<UnifiedSearchResult, String> {
     let apps_future = search_applications(query.clone());
 
     let (applications, files) = if include_files {
-        let files_future = search_files(query.clone(), search_path, false);
+        let files_future = search_files(
+            query.clone(),
+            search_path,
+            false,
+            None,
+            None,
+            None,
+            None,
+            extensions,
+            glob,
+            None,
+        );
         tokio::join!(apps_future, files_future)
     } else {
         (apps_future.await, Ok(Vec::new()))
     };
 
-    Ok(SearchResult {
-        applications: applications?,
-        files: files?,
+    let (applications, files) = (applications?, files?);
+    if !flat.unwrap_or(false) {
+        return Ok(UnifiedSearchResult::Grouped(SearchResult { applications, files }));
+    }
+
+    let commands = get_plugin_commands(plugins).await?;
+    Ok(UnifiedSearchResult::Flat(rank_unified(
+        &query,
+        applications,
+        files,
+        commands,
+        &frecency_scores(),
+    )))
+}
+
+// ============================================================================
+// Streaming Search
+// ============================================================================
+
+/// Progressive results of a streaming search, in the order they are emitted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    Apps {
+        applications: Vec<Application>,
+    },
+    FileMatch {
+        file: FileMatch,
+    },
+    /// A corrected query to offer as "did you mean"; it is not run
+    Suggestion {
+        suggestion: String,
+    },
+    InsightToken {
+        text: String,
+    },
+    Error {
+        message: String,
+    },
+    Done,
+}
+
+impl StreamEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StreamEvent::Apps { .. } => "apps",
+            StreamEvent::FileMatch { .. } => "file_match",
+            StreamEvent::Suggestion { .. } => "suggestion",
+            StreamEvent::InsightToken { .. } => "insight_token",
+            StreamEvent::Error { .. } => "error",
+            StreamEvent::Done => "done",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchStreamRequest {
+    pub query: String,
+    #[serde(default)]
+    pub search_path: Option<String>,
+    #[serde(default = "default_true")]
+    pub include_files: bool,
+    #[serde(default = "default_true")]
+    pub include_insights: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Streams insight text for an insights prompt
+pub type InsightStream = Pin<Box<dyn Stream<Item = Result<String, String>> + Send>>;
+pub type InsightGenerator = Arc<dyn Fn(String) -> InsightStream + Send + Sync>;
+
+/// Generate insights by streaming from a Rig agent
+pub fn rig_insight_generator(agent: Arc<RigAgent>) -> InsightGenerator {
+    Arc::new(move |prompt| {
+        let (provider, model) = background_model("INSIGHTS", None, None);
+        let stream = agent.generate_stream(insights_options(prompt, provider, model));
+        Box::pin(stream.filter_map(|chunk| async move {
+            match chunk {
+                Ok(StreamChunk::Text(text)) => Some(Ok(text)),
+                Ok(StreamChunk::Reasoning(_) | StreamChunk::Done(_)) => None,
+                Err(e) => Some(Err(e.to_string())),
+            }
+        }))
     })
 }
 
+/// Run applications search, file walk and insight generation as one event stream
+///
+/// Applications are sent first, then each file match as the walk finds it, then a
+/// `suggestion` if the query found few results, then the insight tokens, then `done`.
+/// Dropping the returned stream cancels the pipeline.
+pub fn search_stream(
+    request: SearchStreamRequest,
+    insights: Option<InsightGenerator>,
+    corrector: Option<QueryCorrector>,
+) -> impl Stream<Item = StreamEvent> + Send {
+    let (tx, rx) = tokio::sync::mpsc::channel(32);
+
+    tokio::spawn(async move {
+        let applications = match search_applications(request.query.clone()).await {
+            Ok(applications) => applications,
+            Err(e) => {
+                record_error(Subsystem::Search, format!("search_stream: {}", e));
+                Vec::new()
+            }
+        };
+        if tx
+            .send(StreamEvent::Apps {
+                applications: applications.clone(),
+            })
+            .await
+            .is_err()
+        {
+            return;
+        }
+
+        let mut files = Vec::new();
+        let pattern = match request.include_files.then(|| SearchPattern::new(&request.query, false)) {
+            Some(Err(message)) => {
+                if tx.send(StreamEvent::Error { message }).await.is_err() {
+                    return;
+                }
+                None
+            }
+            pattern => pattern.and_then(Result::ok),
+        };
+        if let Some(pattern) = pattern {
+            let walk_tx = tx.clone();
+            let search_path = request.search_path.clone();
+            let walk = tokio::task::spawn_blocking(move || {
+                let mut files = Vec::new();
+                walk_files(
+                    &pattern,
+                    WalkOptions {
+                        search_path,
+                        deadline: Some(Instant::now() + Duration::from_millis(configured_search_timeout_ms())),
+                        ..WalkOptions::default()
+                    },
+                    |file| {
+                        files.push(file.clone());
+                        walk_tx.blocking_send(StreamEvent::FileMatch { file }).is_ok()
+                    },
+                );
+                files
+            });
+            files = walk.await.unwrap_or_default();
+            if tx.is_closed() {
+                return;
+            }
+        }
+
+        if let Some(corrector) = corrector {
+            let result_count = applications.len() + files.len();
+            if let Some(suggestion) = suggest_correction(&request.query, result_count, &corrector).await {
+                if tx.send(StreamEvent::Suggestion { suggestion }).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        if let Some(generate) = insights.filter(|_| request.include_insights) {
+            let prompt = build_insights_prompt(
+                &request.query,
+                &SearchResult { applications, files },
+                &current_insights_privacy(),
+            );
+            let mut tokens = generate(prompt);
+            while let Some(token) = tokens.next().await {
+                let event = match token {
+                    Ok(text) => StreamEvent::InsightToken { text },
+                    Err(e) => {
+                        let message = format!("Failed to generate AI insights: {}", e);
+                        record_error(Subsystem::Ai, message.clone());
+                        let _ = tx.send(StreamEvent::Error { message }).await;
+                        break;
+                    }
+                };
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        let _ = tx.send(StreamEvent::Done).await;
+    });
+
+    tokio_stream::wrappers::ReceiverStream::new(rx)
+}
+
 /// Get the frontmost application
 #[command]
 pub async fn get_frontmost_application() -> Result<Option<Application>, String> {
     use applications::{AppInfo, AppInfoContext};
 
-    let mut ctx = AppInfoContext::new(vec![]);
-    ctx.refresh_apps()
-        .map_err(|e| format!("Failed to refresh applications: {}", e))?;
+    let frontmost = {
+        let mut ctx = AppInfoContext::new(vec![]);
+        ctx.refresh_apps()
+            .map_err(|e| format!("Failed to refresh applications: {}", e))?;
+        ctx.get_frontmost_application()
+    };
 
-    match ctx.get_frontmost_application() {
+    match frontmost {
         Ok(app) => {
             let exe_path = app
                 .app_path_exe
@@ -477,7 +1985,7 @@ pub async fn get_frontmost_application() -> Result<Option<Application>, String>
                 exe_path
             };
 
-            let icon_base64 = extract_app_icon(&app_bundle_path);
+            let icon_base64 = GLOBAL_ICON_CACHE.get_or_extract(&app_bundle_path).await;
 
             Ok(Some(Application {
                 name: app.name.clone(),
@@ -495,13 +2003,14 @@ pub async fn get_frontmost_application() -> Result<Option<Application>, String>
 pub async fn get_running_applications() -> Result<Vec<Application>, String> {
     use applications::{AppInfo, AppInfoContext};
 
-    let mut ctx = AppInfoContext::new(vec![]);
-    ctx.refresh_apps()
-        .map_err(|e| format!("Failed to refresh applications: {}", e))?;
-
-    let apps = ctx.get_running_apps();
+    let apps = {
+        let mut ctx = AppInfoContext::new(vec![]);
+        ctx.refresh_apps()
+            .map_err(|e| format!("Failed to refresh applications: {}", e))?;
+        ctx.get_running_apps()
+    };
 
-    let results: Vec<Application> = apps
+    let mut results: Vec<Application> = apps
         .into_iter()
         .map(|app| {
             let exe_path = app
@@ -520,131 +2029,560 @@ pub async fn get_running_applications() -> Result<Vec<Application>, String> {
                 exe_path
             };
 
-            let icon_base64 = extract_app_icon(&app_bundle_path);
-
             Application {
                 name: app.name.clone(),
                 path: app_bundle_path,
                 icon_path: None,
-                icon_base64,
+                icon_base64: None,
             }
         })
         .collect();
 
+    for app in results.iter_mut() {
+        app.icon_base64 = GLOBAL_ICON_CACHE.get_or_extract(&app.path).await;
+    }
     Ok(results)
 }
 
 /// Get default application for file extension
 #[command]
 pub async fn get_default_application(extension: String) -> Result<Option<Application>, String> {
-    use applications::{AppInfo, AppInfoContext};
+    let extension = extension.trim().trim_start_matches('.').to_lowercase();
+    if extension.is_empty() {
+        return Err("File extension cannot be empty".to_string());
+    }
 
-    let mut ctx = AppInfoContext::new(vec![]);
-    ctx.refresh_apps()
-        .map_err(|e| format!("Failed to refresh applications: {}", e))?;
+    let Some(path) = default_application_path(&extension) else {
+        return Ok(None);
+    };
+    let icon_base64 = GLOBAL_ICON_CACHE.get_or_extract(&path).await;
+
+    Ok(Some(Application {
+        name: application_name(&path),
+        path,
+        icon_path: None,
+        icon_base64,
+    }))
+}
+
+/// Bundle of the application registered to open files with `extension`
+///
+/// Resolves the extension's UTI, then the bundle id of its default viewer. The content type
+/// based `LSCopyDefaultApplicationURLForContentType` needs macOS 12, and we support 11.
+#[cfg(target_os = "macos")]
+#[allow(non_upper_case_globals)]
+fn default_application_path(extension: &str) -> Option<String> {
+    use core_foundation::array::{CFArray, CFArrayRef};
+    use core_foundation::base::TCFType;
+    use core_foundation::string::{CFString, CFStringRef};
+    use core_foundation::url::CFURL;
+    use std::ffi::c_void;
+
+    #[link(name = "CoreServices", kind = "framework")]
+    extern "C" {
+        static kUTTagClassFilenameExtension: CFStringRef;
+        fn UTTypeCreatePreferredIdentifierForTag(
+            tag_class: CFStringRef,
+            tag: CFStringRef,
+            conforming_to: CFStringRef,
+        ) -> CFStringRef;
+        fn LSCopyDefaultRoleHandlerForContentType(content_type: CFStringRef, role: u32) -> CFStringRef;
+        fn LSCopyApplicationURLsForBundleIdentifier(bundle_id: CFStringRef, error: *mut *mut c_void) -> CFArrayRef;
+    }
+    const K_LS_ROLES_ALL: u32 = 0xFFFF_FFFF;
+
+    let extension = CFString::new(extension);
+    // SAFETY: every returned object follows the create rule and is null-checked before it
+    // is wrapped, so it's released exactly once when dropped
+    unsafe {
+        let uti = UTTypeCreatePreferredIdentifierForTag(
+            kUTTagClassFilenameExtension,
+            extension.as_concrete_TypeRef(),
+            std::ptr::null(),
+        );
+        if uti.is_null() {
+            return None;
+        }
+        let uti = CFString::wrap_under_create_rule(uti);
+
+        let bundle_id = LSCopyDefaultRoleHandlerForContentType(uti.as_concrete_TypeRef(), K_LS_ROLES_ALL);
+        if bundle_id.is_null() {
+            return None;
+        }
+        let bundle_id = CFString::wrap_under_create_rule(bundle_id);
+
+        let urls = LSCopyApplicationURLsForBundleIdentifier(bundle_id.as_concrete_TypeRef(), std::ptr::null_mut());
+        if urls.is_null() {
+            return None;
+        }
+        let urls: CFArray<CFURL> = CFArray::wrap_under_create_rule(urls);
+        let path = urls.get(0)?.to_path()?;
+        Some(path.to_string_lossy().to_string())
+    }
+}
+
+/// Executable of the program Explorer opens files with `extension` in
+///
+/// The user's choice under `FileExts` wins over the machine-wide class of the extension.
+/// Packaged (UWP) apps have no `shell\open\command` and resolve to `None`.
+#[cfg(target_os = "windows")]
+fn default_application_path(extension: &str) -> Option<String> {
+    use winreg::enums::{HKEY_CLASSES_ROOT, HKEY_CURRENT_USER};
+    use winreg::RegKey;
+
+    let extension = format!(".{}", extension);
+    let classes = RegKey::predef(HKEY_CLASSES_ROOT);
+    let user_choice = RegKey::predef(HKEY_CURRENT_USER)
+        .open_subkey(format!(
+            r"Software\Microsoft\Windows\CurrentVersion\Explorer\FileExts\{}\UserChoice",
+            extension
+        ))
+        .and_then(|key| key.get_value::<String, _>("ProgId"))
+        .ok();
+    let prog_id = user_choice.or_else(|| {
+        classes
+            .open_subkey(&extension)
+            .and_then(|key| key.get_value::<String, _>(""))
+            .ok()
+    })?;
+
+    let command: String = classes
+        .open_subkey(format!(r"{}\shell\open\command", prog_id))
+        .and_then(|key| key.get_value(""))
+        .ok()?;
+    command_executable(&command)
+}
+
+/// The executable an `open` command line runs, with `%VAR%` references expanded
+#[cfg(target_os = "windows")]
+fn command_executable(command: &str) -> Option<String> {
+    let command = command.trim();
+    let executable = match command.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next()?,
+        None => match command.to_lowercase().find(".exe") {
+            Some(end) => &command[..end + 4],
+            None => command.split_whitespace().next()?,
+        },
+    };
+
+    let mut expanded = String::new();
+    for (index, part) in executable.split('%').enumerate() {
+        match env::var(part) {
+            Ok(value) if index % 2 == 1 => expanded.push_str(&value),
+            _ if index % 2 == 1 => {
+                expanded.push('%');
+                expanded.push_str(part);
+                expanded.push('%');
+            }
+            _ => expanded.push_str(part),
+        }
+    }
+    Path::new(&expanded).is_file().then_some(expanded)
+}
+
+/// `.desktop` entry `xdg-mime` reports as the default for the extension's MIME type
+#[cfg(target_os = "linux")]
+fn default_application_path(extension: &str) -> Option<String> {
+    let data_dirs = xdg_data_dirs();
+    let mime_type = mime_type_for_extension(extension, &data_dirs)?;
+    let output = std::process::Command::new("xdg-mime")
+        .args(["query", "default", &mime_type])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let desktop_id = stdout.split(';').next()?.trim();
+    if desktop_id.is_empty() {
+        return None;
+    }
 
-    // Note: The applications crate doesn't seem to have get_default_app method
-    // This is a placeholder implementation that returns None
-    // In a real implementation, you would need to use platform-specific APIs
-    // to get the default application for a file extension
-    println!("get_default_application called with extension: {}", extension);
+    data_dirs
+        .iter()
+        .map(|dir| dir.join("applications").join(desktop_id))
+        .find(|candidate| candidate.is_file())
+        .map(|path| path.to_string_lossy().to_string())
+}
 
-    Ok(None)
+/// MIME type of `*.extension` in the shared MIME database's `globs2`, highest weight first
+///
+/// Earlier data dirs take precedence on equal weights, as they do for everything in XDG.
+#[cfg(target_os = "linux")]
+fn mime_type_for_extension(extension: &str, data_dirs: &[std::path::PathBuf]) -> Option<String> {
+    let pattern = format!("*.{}", extension);
+    let mut best: Option<(u32, String)> = None;
+    for globs in data_dirs
+        .iter()
+        .filter_map(|dir| std::fs::read_to_string(dir.join("mime/globs2")).ok())
+    {
+        for line in globs.lines().filter(|line| !line.starts_with('#')) {
+            let mut fields = line.split(':');
+            let (Some(weight), Some(mime_type), Some(glob)) = (fields.next(), fields.next(), fields.next()) else {
+                continue;
+            };
+            let Ok(weight) = weight.parse::<u32>() else {
+                continue;
+            };
+            if glob.eq_ignore_ascii_case(&pattern) && best.as_ref().map_or(true, |(best, _)| weight > *best) {
+                best = Some((weight, mime_type.to_string()));
+            }
+        }
+    }
+    best.map(|(_, mime_type)| mime_type)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+fn default_application_path(_extension: &str) -> Option<String> {
+    None
+}
+
+/// Display name of the application at `app_path`: its `.desktop` entry's `Name=` on Linux,
+/// otherwise the bundle or executable name
+fn application_name(app_path: &str) -> String {
+    #[cfg(target_os = "linux")]
+    if let Some(name) = std::fs::read_to_string(app_path)
+        .ok()
+        .and_then(|entry| desktop_entry_value(&entry, "Name"))
+    {
+        return name;
+    }
+
+    Path::new(app_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| app_path.to_string())
+}
+
+/// Generate AI-powered insights for search results
+///
+/// `provider` and `model` pick a different (e.g. cheaper) model than the main chat; without
+/// them the pair comes from `AI_INSIGHTS_*`, then `AI_BACKGROUND_*`, then the chat's own.
+#[command]
+pub async fn generate_search_insights(
+    query: String,
+    search_results: SearchResult,
+    provider: Option<String>,
+    model: Option<String>,
+) -> Result<String, String> {
+    generate_insights_with(
+        ai_insights_enabled(),
+        query,
+        search_results,
+        provider,
+        model,
+        env_setting,
+        generate_with_agent,
+    )
+    .await
+}
+
+/// Generate insights through `generate` when `enabled`, resolving the background model from `setting`
+async fn generate_insights_with<F, Fut>(
+    enabled: bool,
+    query: String,
+    search_results: SearchResult,
+    provider: Option<String>,
+    model: Option<String>,
+    setting: impl Fn(&str) -> Option<String>,
+    generate: F,
+) -> Result<String, String>
+where
+    F: FnOnce(AIOptions) -> Fut,
+    Fut: Future<Output = Result<String, String>>,
+{
+    // Nothing leaves the machine when insights are disabled
+    if !enabled {
+        return Err("AI insights disabled".to_string());
+    }
+
+    let (provider, model) = background_model_from("INSIGHTS", provider, model, setting);
+    let prompt = build_insights_prompt(&query, &search_results, &current_insights_privacy());
+    let ai_options = AIOptions {
+        cacheable: true,
+        ..insights_options(prompt, provider, model)
+    };
+
+    generate(ai_options).await.map_err(|e| {
+        let message = format!("Failed to generate AI insights: {}", e);
+        record_error(Subsystem::Ai, message.clone());
+        message
+    })
+}
+
+/// Run `ai_options` on an agent for the requested provider
+async fn generate_with_agent(ai_options: AIOptions) -> Result<String, String> {
+    // Only the credentials of the provider actually used are required
+    let agent = match ai_options.provider.as_deref() {
+        Some(name) => {
+            let provider = AIProvider::parse(name).ok_or_else(|| format!("Unknown AI provider '{}'", name))?;
+            RigAgent::with_provider(provider)
+        }
+        None => RigAgent::new(),
+    }
+    .map_err(|e| format!("Failed to initialize AI agent: {}", e))?;
+
+    let response = agent.generate(ai_options).await.map_err(|e| e.to_string())?;
+    Ok(response.text)
+}
+
+/// Request options for insights on the resolved `provider` and `model`
+fn insights_options(prompt: String, provider: Option<String>, model: Option<String>) -> AIOptions {
+    AIOptions {
+        prompt,
+        provider,
+        model,
+        temperature: Some(0.7),
+        max_tokens: Some(200),
+        ..Default::default()
+    }
 }
 
-/// Generate AI-powered insights for search results
-#[command]
-pub async fn generate_search_insights(query: String, search_results: SearchResult) -> Result<String, String> {
-    // Initialize the Rig agent
-    let agent = RigAgent::new().map_err(|e| format!("Failed to initialize AI agent: {}", e))?;
-
+/// Build the prompt asking the AI to summarize a set of search results
+///
+/// `privacy` limits the results and line content included, and the context is
+/// redacted before it becomes part of the prompt.
+fn build_insights_prompt(query: &str, search_results: &SearchResult, privacy: &InsightsPrivacy) -> String {
     // Build a context from the search results
     let app_count = search_results.applications.len();
     let file_count = search_results.files.len();
+    let max_results = privacy.max_results();
 
     let mut context = format!("User searched for: '{}'\n\nSearch Results Summary:\n", query);
 
     if app_count > 0 {
         context.push_str(&format!("- {} application(s) found:\n", app_count));
-        for (i, app) in search_results.applications.iter().take(5).enumerate() {
+        for (i, app) in search_results.applications.iter().take(max_results).enumerate() {
             context.push_str(&format!("  {}. {} ({})\n", i + 1, app.name, app.path));
         }
-        if app_count > 5 {
-            context.push_str(&format!("  ... and {} more\n", app_count - 5));
+        if app_count > max_results {
+            context.push_str(&format!("  ... and {} more\n", app_count - max_results));
         }
     }
 
     if file_count > 0 {
         context.push_str(&format!("- {} file(s) found:\n", file_count));
-        for (i, file) in search_results.files.iter().take(5).enumerate() {
+        for (i, file) in search_results.files.iter().take(max_results).enumerate() {
             let file_name = file.path.split('/').last().unwrap_or(&file.path);
             context.push_str(&format!("  {}. {}", i + 1, file_name));
-            if let Some(line) = &file.line_content {
+            if let Some(line) = file.line_content.as_deref().and_then(|line| privacy.line_content(line)) {
                 context.push_str(&format!(" - {}", line));
             }
             context.push_str("\n");
         }
-        if file_count > 5 {
-            context.push_str(&format!("  ... and {} more\n", file_count - 5));
+        if file_count > max_results {
+            context.push_str(&format!("  ... and {} more\n", file_count - max_results));
         }
     }
+    let context = privacy.redact(&context);
 
     // Create a prompt for the AI
-    let prompt = format!(
+    format!(
         "{}\n\nProvide a brief, helpful summary of these search results. \
         Suggest what the user might want to do with these results. \
         If there are interesting patterns or insights, mention them. \
         Keep it concise (2-3 sentences).",
         context
-    );
+    )
+}
 
-    // Generate the AI response
-    let ai_options = AIOptions {
-        prompt,
-        provider: None,
-        model: None, // Use default model
-        temperature: Some(0.7),
-        max_tokens: Some(200),
-        top_p: None,
-        frequency_penalty: None,
-        presence_penalty: None,
-    };
+/// Get available AI providers
+#[command]
+pub async fn get_available_ai_providers() -> Result<Vec<String>, String> {
+    Ok(configured_ai_providers())
+}
 
-    let response = agent
-        .generate(ai_options)
-        .await
-        .map_err(|e| format!("Failed to generate AI insights: {}", e))?;
+/// Names of the AI providers with an API key in the environment
+pub fn configured_ai_providers() -> Vec<String> {
+    AIProvider::ALL
+        .iter()
+        .filter(|provider| provider.has_api_key())
+        .map(|provider| provider.display_name().to_string())
+        .collect()
+}
 
-    Ok(response.text)
+/// Every supported AI provider with its key, default model and capabilities
+#[command]
+pub fn get_supported_ai_providers() -> Vec<ProviderDescriptor> {
+    supported_providers()
 }
 
-/// Get available AI providers
+/// A provider's default model, context window and capabilities, for setting up a new chat
 #[command]
-pub async fn get_available_ai_providers() -> Result<Vec<String>, String> {
-    let mut providers = Vec::new();
+pub fn get_provider_default(provider: String) -> Result<ProviderDefault, String> {
+    AIProvider::parse(&provider)
+        .map(|provider| provider_default(&provider))
+        .ok_or_else(|| format!("Unknown AI provider: {}", provider))
+}
 
-    if env::var("OPENAI_API_KEY").is_ok() {
-        providers.push("OpenAI".to_string());
-    }
-    if env::var("ANTHROPIC_API_KEY").is_ok() {
-        providers.push("Anthropic".to_string());
+// ============================================================================
+// AI Insights Setting
+// ============================================================================
+
+/// Whether search content may be sent to an AI provider for insights
+///
+/// Defaults to `AI_INSIGHTS_ENABLED` when set, otherwise to whether any provider is configured.
+static AI_INSIGHTS_ENABLED: Lazy<AtomicBool> = Lazy::new(|| {
+    let enabled = env_flag("AI_INSIGHTS_ENABLED").unwrap_or_else(|| !configured_ai_providers().is_empty());
+    AtomicBool::new(enabled)
+});
+
+pub fn ai_insights_enabled() -> bool {
+    AI_INSIGHTS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Drop the insight stage entirely when AI insights are disabled
+pub fn gate_insights(insights: Option<InsightGenerator>) -> Option<InsightGenerator> {
+    gate_insights_when(ai_insights_enabled(), insights)
+}
+
+fn gate_insights_when(enabled: bool, insights: Option<InsightGenerator>) -> Option<InsightGenerator> {
+    insights.filter(|_| enabled)
+}
+
+/// Get whether AI insights are enabled
+#[command]
+pub fn get_ai_insights_enabled() -> bool {
+    ai_insights_enabled()
+}
+
+/// Enable or disable AI insights globally
+#[command]
+pub fn set_ai_insights_enabled(enabled: bool) {
+    AI_INSIGHTS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// ============================================================================
+// Query Correction
+// ============================================================================
+
+/// Searches returning fewer results than this are offered a corrected query
+const CORRECTION_RESULT_THRESHOLD: usize = 3;
+const MAX_CACHED_CORRECTIONS: usize = 256;
+
+/// Suggests a corrected query from a correction prompt
+pub type QueryCorrector =
+    Arc<dyn Fn(String) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send>> + Send + Sync>;
+
+/// Opt-in via `AI_QUERY_CORRECTION_ENABLED`; also requires AI insights to be enabled
+static QUERY_CORRECTION_ENABLED: Lazy<AtomicBool> =
+    Lazy::new(|| AtomicBool::new(env_flag("AI_QUERY_CORRECTION_ENABLED").unwrap_or(false)));
+
+/// Corrections by normalized query; `None` records that the provider had no suggestion
+static CORRECTION_CACHE: Lazy<std::sync::Mutex<HashMap<String, Option<String>>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+pub fn query_correction_enabled() -> bool {
+    QUERY_CORRECTION_ENABLED.load(Ordering::Relaxed) && ai_insights_enabled()
+}
+
+/// Drop query correction entirely when it is disabled or AI is off
+pub fn gate_query_correction(corrector: Option<QueryCorrector>) -> Option<QueryCorrector> {
+    corrector.filter(|_| query_correction_enabled())
+}
+
+/// Correct queries with a short, deterministic Rig completion
+pub fn rig_query_corrector(agent: Arc<RigAgent>) -> QueryCorrector {
+    Arc::new(move |prompt| {
+        let agent = Arc::clone(&agent);
+        Box::pin(async move {
+            let (provider, model) = background_model("QUERY_CORRECTION", None, None);
+            agent
+                .generate(AIOptions {
+                    prompt,
+                    provider,
+                    model,
+                    temperature: Some(0.0),
+                    max_tokens: Some(16),
+                    cacheable: true,
+                    ..Default::default()
+                })
+                .await
+                .map(|response| response.text)
+                .map_err(|e| e.to_string())
+        })
+    })
+}
+
+fn build_correction_prompt(query: &str) -> String {
+    format!(
+        "A user typed this into a launcher that searches application and file names: '{}'\n\n\
+        If it contains a spelling mistake, reply with only the corrected query. \
+        If it is already correct, reply with only NONE.",
+        query
+    )
+}
+
+/// Extract a suggestion from the provider reply, ignoring `NONE` and echoes of the query
+fn parse_correction(query: &str, reply: &str) -> Option<String> {
+    let suggestion = reply
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?
+        .trim_matches(|c| matches!(c, '\'' | '"' | '`' | '.'))
+        .trim();
+    if suggestion.is_empty() || suggestion.eq_ignore_ascii_case("none") || suggestion.eq_ignore_ascii_case(query.trim())
+    {
+        return None;
     }
-    if env::var("GEMINI_API_KEY").is_ok() {
-        providers.push("Gemini".to_string());
+    Some(suggestion.to_string())
+}
+
+/// "Did you mean" suggestion for a query that found `result_count` results
+///
+/// Provider failures (e.g. when offline) are logged and yield no suggestion; they are not cached.
+pub async fn suggest_correction(query: &str, result_count: usize, corrector: &QueryCorrector) -> Option<String> {
+    let key = query.trim().to_lowercase();
+    if result_count >= CORRECTION_RESULT_THRESHOLD || key.chars().count() < 3 {
+        return None;
     }
-    if env::var("DEEPSEEK_API_KEY").is_ok() {
-        providers.push("DeepSeek".to_string());
+
+    let cache = || CORRECTION_CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(cached) = cache().get(&key) {
+        return cached.clone();
     }
-    if env::var("OPENROUTER_API_KEY").is_ok() {
-        providers.push("OpenRouter".to_string());
+
+    let reply = match corrector(build_correction_prompt(query.trim())).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            record_error(Subsystem::Ai, format!("Query correction failed: {}", e));
+            return None;
+        }
+    };
+    let suggestion = parse_correction(query, &reply);
+
+    let mut cache = cache();
+    if cache.len() >= MAX_CACHED_CORRECTIONS {
+        cache.clear();
     }
+    cache.insert(key, suggestion.clone());
+    suggestion
+}
+
+/// Suggest a corrected query after a search returned few results
+///
+/// Meant to be called once the raw results are shown; the suggestion is never run automatically.
+#[command]
+pub async fn suggest_query_correction(query: String, result_count: usize) -> Result<Option<String>, String> {
+    let Some(corrector) = gate_query_correction(RigAgent::new().ok().map(|agent| rig_query_corrector(Arc::new(agent))))
+    else {
+        return Ok(None);
+    };
+    Ok(suggest_correction(&query, result_count, &corrector).await)
+}
+
+/// Get whether "did you mean" query correction is enabled
+#[command]
+pub fn get_query_correction_enabled() -> bool {
+    QUERY_CORRECTION_ENABLED.load(Ordering::Relaxed)
+}
 
-    Ok(providers)
+/// Enable or disable "did you mean" query correction
+#[command]
+pub fn set_query_correction_enabled(enabled: bool) {
+    QUERY_CORRECTION_ENABLED.store(enabled, Ordering::Relaxed);
 }
 
-/// Ask AI a question with a specific provider
+/// Ask AI a question with a specific provider, and optionally a model other than its default
 #[command]
-pub async fn ask_ai_provider(query: String, provider_name: String) -> Result<String, String> {
+pub async fn ask_ai_provider(query: String, provider_name: String, model: Option<String>) -> Result<String, String> {
     // Map provider name to AIProvider enum
     let provider = match provider_name.as_str() {
         "OpenAI" => AIProvider::OpenAI,
@@ -662,13 +2600,10 @@ pub async fn ask_ai_provider(query: String, provider_name: String) -> Result<Str
     // Create the AI options
     let ai_options = AIOptions {
         prompt: query,
-        provider: None,
-        model: None, // Use default model
+        model,
         temperature: Some(0.8),
         max_tokens: Some(500),
-        top_p: None,
-        frequency_penalty: None,
-        presence_penalty: None,
+        ..Default::default()
     };
 
     // Generate the AI response
@@ -685,7 +2620,6 @@ pub async fn ask_ai_provider(query: String, provider_name: String) -> Result<Str
 pub async fn search_app_suggestions(query: String, limit: Option<usize>) -> Result<Vec<Application>, String> {
     use applications::{AppInfo, AppInfoContext};
 
-    let query_lower = query.to_lowercase();
     let result_limit = limit.unwrap_or(10);
 
     // Create context and refresh apps
@@ -696,58 +2630,9 @@ pub async fn search_app_suggestions(query: String, limit: Option<usize>) -> Resu
     // Get all applications
     let apps = ctx.get_all_apps();
 
-    // Filter and map to our Application struct
-    let mut results: Vec<Application> = apps
-        .into_iter()
-        .filter(|app| app.name.to_lowercase().contains(&query_lower))
-        .map(|app| {
-            let exe_path = app
-                .app_path_exe
-                .as_ref()
-                .map(|p| p.to_string_lossy().to_string())
-                .unwrap_or_else(|| "Unknown".to_string());
-
-            // Convert executable path to .app bundle root path
-            let app_bundle_path = if exe_path.contains("/Contents/MacOS/") {
-                if let Some(bundle_end) = exe_path.find(".app/Contents/MacOS/") {
-                    exe_path[..bundle_end + 4].to_string()
-                } else {
-                    exe_path
-                }
-            } else {
-                exe_path
-            };
-
-            Application {
-                name: app.name.clone(),
-                path: app_bundle_path,
-                icon_path: None,
-                icon_base64: None, // Icons loaded separately on-demand
-            }
-        })
-        .collect();
-
-    // Sort by relevance
-    results.sort_by(|a, b| {
-        let a_lower = a.name.to_lowercase();
-        let b_lower = b.name.to_lowercase();
-
-        if a_lower == query_lower {
-            std::cmp::Ordering::Less
-        } else if b_lower == query_lower {
-            std::cmp::Ordering::Greater
-        } else if a_lower.starts_with(&query_lower) && !b_lower.starts_with(&query_lower) {
-            std::cmp::Ordering::Less
-        } else if !a_lower.starts_with(&query_lower) && b_lower.starts_with(&query_lower) {
-            std::cmp::Ordering::Greater
-        } else {
-            a.name.cmp(&b.name)
-        }
-    });
+    let apps: Vec<Application> = apps.into_iter().map(installed_application).collect();
 
-    // Limit results
-    results.truncate(result_limit);
-    Ok(results)
+    Ok(rank_applications(apps, &query, result_limit, &frecency_scores()))
 }
 
 /// Search files for mention suggestions (optimized for autocomplete)
@@ -773,6 +2658,7 @@ pub async fn search_file_suggestions(
     let walker = WalkBuilder::new(&base_path)
         .hidden(false) // Show hidden files
         .git_ignore(true) // Respect .gitignore
+        .follow_links(false)
         .max_depth(Some(5)) // Limit depth for performance
         .build();
 
@@ -801,7 +2687,9 @@ pub async fn search_file_suggestions(
                     path: path_str.clone(),
                     line_number: None,
                     line_content: None,
+                    line_length: None,
                     match_type: "name".to_string(),
+                    match_count: None,
                 });
             }
         }
@@ -809,3 +2697,1186 @@ pub async fn search_file_suggestions(
 
     Ok(results)
 }
+
+// ============================================================================
+// Copy Result
+// ============================================================================
+
+/// A search result to copy, as returned by the search commands
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ResultRef {
+    App(Application),
+    File(FileMatch),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyFormat {
+    Path,
+    Name,
+    MarkdownLink,
+    FileUrl,
+}
+
+impl ResultRef {
+    fn path(&self) -> &str {
+        match self {
+            ResultRef::App(app) => &app.path,
+            ResultRef::File(file) => &file.path,
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            ResultRef::App(app) => app.name.clone(),
+            ResultRef::File(file) => Path::new(&file.path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| file.path.clone()),
+        }
+    }
+
+    fn file_url(&self) -> String {
+        tauri::Url::from_file_path(self.path())
+            .map(String::from)
+            .unwrap_or_else(|_| format!("file://{}", self.path()))
+    }
+}
+
+/// Format a search result for the clipboard
+///
+/// Markdown links to a content match point at the matched line.
+pub fn format_result(result: &ResultRef, format: CopyFormat) -> String {
+    match format {
+        CopyFormat::Path => result.path().to_string(),
+        CopyFormat::Name => result.name(),
+        CopyFormat::FileUrl => result.file_url(),
+        CopyFormat::MarkdownLink => match result {
+            ResultRef::File(FileMatch {
+                line_number: Some(line),
+                ..
+            }) => format!("[{}:{}]({}#L{})", result.name(), line, result.file_url(), line),
+            _ => format!("[{}]({})", result.name(), result.file_url()),
+        },
+    }
+}
+
+/// Copy a search result to the clipboard in the given format, returning the copied text
+#[command]
+pub fn copy_result(
+    clipboard: tauri::State<'_, tauri_plugin_clipboard::Clipboard>,
+    result: ResultRef,
+    format: CopyFormat,
+) -> Result<String, String> {
+    let text = format_result(&result, format);
+    clipboard.write_text(text.clone())?;
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_targets_named_scope() {
+        use crate::search_scopes::{resolve_search_root, SearchScopes};
+        use crate::storage::MemoryStorage;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("scoped-invoice.pdf"), "pdf").unwrap();
+
+        let mut scopes = SearchScopes::load(Arc::new(MemoryStorage::new()));
+        scopes.set("invoices", &dir.path().to_string_lossy()).unwrap();
+        let root = resolve_search_root(&scopes, Some("invoices"), Some("/nonexistent".to_string())).unwrap();
+
+        let mut matches = Vec::new();
+        walk_files(
+            &SearchPattern::new("scoped-invoice", false).unwrap(),
+            WalkOptions {
+                search_path: root,
+                ..WalkOptions::default()
+            },
+            |file_match| {
+                matches.push(file_match);
+                true
+            },
+        );
+
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0].path.ends_with("scoped-invoice.pdf"));
+    }
+
+    #[test]
+    fn test_superseded_search_walk_stops() {
+        let dir = tempfile::tempdir().unwrap();
+        for index in 0..10 {
+            std::fs::write(dir.path().join(format!("report-{}.txt", index)), "report").unwrap();
+        }
+
+        let first = supersede_file_search("search-1");
+        let mut matches = 0;
+        walk_files(
+            &SearchPattern::new("report", false).unwrap(),
+            WalkOptions {
+                search_path: Some(dir.path().to_string_lossy().to_string()),
+                cancel: Some(&first),
+                ..WalkOptions::default()
+            },
+            |_| {
+                matches += 1;
+                // The user types on while the first walk is running
+                let second = supersede_file_search("search-2");
+                cancel_file_search("search-1".to_string());
+                assert!(!second.load(Ordering::Relaxed));
+                true
+            },
+        );
+
+        assert!(first.load(Ordering::Relaxed));
+        assert_eq!(matches, 1);
+        cancel_file_search("search-2".to_string());
+    }
+
+    #[test]
+    fn test_content_reader_stops_at_the_line_cap_and_deadline() {
+        use std::io::Read;
+
+        let text = "line\n".repeat(MAX_CONTENT_LINES as usize * 2);
+        let mut kept = String::new();
+        ContentReader::new(text.as_bytes(), || false)
+            .read_to_string(&mut kept)
+            .unwrap();
+        assert_eq!(kept.lines().count() as u64, MAX_CONTENT_LINES);
+
+        let mut kept = String::new();
+        ContentReader::new(text.as_bytes(), || true)
+            .read_to_string(&mut kept)
+            .unwrap();
+        assert!(kept.is_empty());
+    }
+
+    #[test]
+    fn test_content_search_counts_all_matching_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("notes.txt"),
+            "intro\nTODO: first\nmiddle\ntodo: second\nTODO third\n",
+        )
+        .unwrap();
+
+        let search = |content_search| {
+            let mut matches = Vec::new();
+            walk_files(
+                &SearchPattern::new("todo", false).unwrap(),
+                WalkOptions {
+                    search_path: Some(dir.path().to_string_lossy().to_string()),
+                    content_search,
+                    ..WalkOptions::default()
+                },
+                |file_match| {
+                    matches.push(file_match);
+                    true
+                },
+            );
+            matches
+        };
+        let counted = search(ContentSearch::CountAll);
+        let first_only = search(ContentSearch::FirstMatch);
+
+        assert_eq!(counted.len(), 1);
+        assert_eq!(counted[0].match_count, Some(3));
+        assert_eq!(counted[0].line_number, Some(2));
+        assert_eq!(counted[0].line_content.as_deref(), Some("TODO: first"));
+
+        assert_eq!(first_only.len(), 1);
+        assert_eq!(first_only[0].match_count, None);
+        assert_eq!(first_only[0].line_number, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_file_search_streams_matches_as_found() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("nested")).unwrap();
+        for name in [
+            "report-2023.txt",
+            "report-2024.txt",
+            "nested/report-draft.md",
+            "notes.txt",
+        ] {
+            std::fs::write(dir.path().join(name), "text").unwrap();
+        }
+        let search = || {
+            FileSearch::new(
+                "report",
+                Some(dir.path().to_string_lossy().to_string()),
+                false,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+        };
+
+        let mut collected = Vec::new();
+        search().run(Instant::now() + Duration::from_secs(60), |file_match| {
+            collected.push(file_match.path);
+            true
+        });
+        let mut streamed: Vec<String> = search().stream().map(|file_match| file_match.path).collect().await;
+        let mut first = Box::pin(search().stream());
+        let first = first.next().await;
+
+        collected.sort();
+        streamed.sort();
+        assert_eq!(streamed.len(), 3);
+        assert_eq!(streamed, collected);
+        assert!(first.unwrap().path.contains("report"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_skips_symlink_loops_and_stops_at_deadline() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("needle.txt"), "text").unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+        // A link back up the tree, and one to a directory outside it with another match
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("nested").join("loop")).unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("needle-outside.txt"), "text").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("linked")).unwrap();
+        let walk = |deadline| {
+            let mut paths = Vec::new();
+            walk_files(
+                &SearchPattern::new("needle", false).unwrap(),
+                WalkOptions {
+                    search_path: Some(dir.path().to_string_lossy().to_string()),
+                    deadline: Some(deadline),
+                    ..WalkOptions::default()
+                },
+                |file_match| {
+                    paths.push(file_match.path);
+                    true
+                },
+            );
+            paths
+        };
+
+        let within_budget = walk(Instant::now() + Duration::from_secs(60));
+        let out_of_time = walk(Instant::now());
+
+        // Neither link is followed: the loop isn't walked and the outside match isn't reported
+        assert_eq!(
+            within_budget,
+            vec![dir.path().join("needle.txt").to_string_lossy().to_string()]
+        );
+        assert!(out_of_time.is_empty());
+    }
+
+    #[test]
+    fn test_content_search_skips_binary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("image.dat"), b"\x89PNG\0\0 invoice total\n").unwrap();
+        std::fs::write(dir.path().join("ledger.csv"), "date,amount\n2024-01-02,Invoice Total\n").unwrap();
+
+        let mut matches = Vec::new();
+        walk_files(
+            &SearchPattern::new("invoice total", false).unwrap(),
+            WalkOptions {
+                search_path: Some(dir.path().to_string_lossy().to_string()),
+                content_search: ContentSearch::FirstMatch,
+                ..WalkOptions::default()
+            },
+            |file_match| {
+                matches.push(file_match);
+                true
+            },
+        );
+
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0].path.ends_with("ledger.csv"));
+        assert_eq!(matches[0].line_number, Some(2));
+        assert_eq!(matches[0].line_content.as_deref(), Some("2024-01-02,Invoice Total"));
+    }
+
+    #[test]
+    fn test_regex_pattern_matches_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("routes.rs"),
+            "use axum;\n\npub async fn login_handler() {}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "the fn handler is documented elsewhere\n").unwrap();
+
+        let mut matches = Vec::new();
+        walk_files(
+            &SearchPattern::new(r"fn \w+_handler", true).unwrap(),
+            WalkOptions {
+                search_path: Some(dir.path().to_string_lossy().to_string()),
+                content_search: ContentSearch::FirstMatch,
+                ..WalkOptions::default()
+            },
+            |file_match| {
+                matches.push(file_match);
+                true
+            },
+        );
+
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0].path.ends_with("routes.rs"));
+        assert_eq!(matches[0].line_number, Some(3));
+        assert_eq!(
+            matches[0].line_content.as_deref(),
+            Some("pub async fn login_handler() {}")
+        );
+
+        let invalid = SearchPattern::new("fn (", true).unwrap_err();
+        assert!(invalid.starts_with("Invalid regex 'fn ('"), "{}", invalid);
+        assert!(SearchPattern::new("fn (", false).is_ok());
+
+        // The regex crate accepts a line break but the content matcher cannot match across lines
+        let invalid = SearchPattern::new(r"fn\nmain", true).unwrap_err();
+        assert!(invalid.starts_with("Invalid regex"), "{}", invalid);
+    }
+
+    #[test]
+    fn test_extension_and_glob_filters_restrict_walked_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("node_modules")).unwrap();
+        std::fs::write(dir.path().join("widget.rs"), "struct Widget;").unwrap();
+        std::fs::write(dir.path().join("widget.PNG"), "png").unwrap();
+        std::fs::write(dir.path().join("node_modules").join("widget-lock.json"), "{}").unwrap();
+
+        let search = |extensions: &[&str], glob: Option<&str>| {
+            let filter = FileFilter::new(
+                dir.path(),
+                extensions.iter().map(|extension| extension.to_string()).collect(),
+                glob,
+            )
+            .unwrap();
+            let mut names = Vec::new();
+            walk_files(
+                &SearchPattern::new("widget", false).unwrap(),
+                WalkOptions {
+                    search_path: Some(dir.path().to_string_lossy().to_string()),
+                    filter: &filter,
+                    ..WalkOptions::default()
+                },
+                |file_match| {
+                    names.push(
+                        Path::new(&file_match.path)
+                            .file_name()
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string(),
+                    );
+                    true
+                },
+            );
+            names.sort();
+            names
+        };
+        let by_extension = search(&["rs", ".png"], None);
+        let by_glob = search(&[], Some("!node_modules/"));
+        let unfiltered = search(&[], None);
+        let invalid = FileFilter::new(dir.path(), Vec::new(), Some("src/{a,b"));
+
+        assert_eq!(by_extension, ["widget.PNG", "widget.rs"]);
+        assert_eq!(by_glob, ["widget.PNG", "widget.rs"]);
+        assert_eq!(unfiltered.len(), 3);
+        assert!(invalid.unwrap_err().starts_with("Invalid glob"));
+    }
+
+    #[test]
+    fn test_long_matching_line_is_truncated_around_the_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let line = format!("{}needle{}", "a".repeat(5000), "b".repeat(5000));
+        std::fs::write(dir.path().join("bundle.min.js"), &line).unwrap();
+
+        let mut matches = Vec::new();
+        walk_files(
+            &SearchPattern::new("NEEDLE", false).unwrap(),
+            WalkOptions {
+                search_path: Some(dir.path().to_string_lossy().to_string()),
+                content_search: ContentSearch::FirstMatch,
+                max_line_length: 100,
+                ..WalkOptions::default()
+            },
+            |file_match| {
+                matches.push(file_match);
+                true
+            },
+        );
+
+        assert_eq!(matches.len(), 1);
+        let content = matches[0].line_content.as_deref().unwrap();
+        assert_eq!(content.chars().count(), 100);
+        assert!(content.starts_with(TRUNCATION_MARKER) && content.ends_with(TRUNCATION_MARKER));
+        assert!(content.contains("needle"));
+        assert_eq!(matches[0].line_length, Some(10006));
+
+        assert_eq!(truncate_line("short line", 0, 5, 100), None);
+        assert_eq!(truncate_line("needle and more", 0, 6, 10).as_deref(), Some("needle a…"));
+    }
+
+    #[test]
+    fn test_app_ranking_tolerates_typos() {
+        let apps = [
+            "Chromium",
+            "Google Chrome",
+            "Chrome",
+            "Calculator",
+            "Google Chrome Canary",
+        ]
+        .into_iter()
+        .map(|name| Application {
+            name: name.to_string(),
+            path: format!("/Applications/{}.app", name),
+            icon_path: None,
+            icon_base64: None,
+        })
+        .collect::<Vec<_>>();
+        let names = |query, limit| {
+            rank_applications(apps.clone(), query, limit, &HashMap::new())
+                .into_iter()
+                .map(|app| app.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names("chrome", 10),
+            vec!["Chrome", "Google Chrome", "Google Chrome Canary"]
+        );
+        assert_eq!(names("chrom", 2), vec!["Chrome", "Chromium"]);
+        assert!(names("chrm", 10).contains(&"Chrome".to_string()));
+        assert_eq!(names("gogle chrom", 10), vec!["Google Chrome", "Google Chrome Canary"]);
+        assert!(names("xyz", 10).is_empty());
+        assert_eq!(names("", 10).len(), 5);
+    }
+
+    #[test]
+    fn test_frecency_lifts_frequently_launched_apps() {
+        let apps = ["Calculator", "Calendar", "Chrome", "Google Chrome"]
+            .into_iter()
+            .map(|name| Application {
+                name: name.to_string(),
+                path: format!("/Applications/{}.app", name),
+                icon_path: None,
+                icon_base64: None,
+            })
+            .collect::<Vec<_>>();
+        let frecency = HashMap::from([
+            ("/Applications/Calendar.app".to_string(), 3.0),
+            ("/Applications/Google Chrome.app".to_string(), 1000.0),
+        ]);
+        let names = |query, frecency: &HashMap<String, f64>| {
+            rank_applications(apps.clone(), query, 10, frecency)
+                .into_iter()
+                .map(|app| app.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("cal", &HashMap::new()), vec!["Calculator", "Calendar"]);
+        assert_eq!(names("cal", &frecency), vec!["Calendar", "Calculator"]);
+        // However often an app is launched, an exact match still ranks first
+        assert_eq!(names("chrome", &frecency), vec!["Chrome", "Google Chrome"]);
+    }
+
+    #[test]
+    fn test_flat_results_interleave_kinds_by_score() {
+        let apps = ["Visual Studio Code", "Notes"]
+            .into_iter()
+            .map(|name| Application {
+                name: name.to_string(),
+                path: format!("/Applications/{}.app", name),
+                icon_path: None,
+                icon_base64: None,
+            })
+            .collect::<Vec<_>>();
+        let files = ["/home/me/code.rs", "/home/me/notes-archive.txt"]
+            .into_iter()
+            .map(|path| FileMatch {
+                path: path.to_string(),
+                line_number: None,
+                line_content: None,
+                line_length: None,
+                match_type: "name".to_string(),
+                match_count: None,
+            })
+            .collect::<Vec<_>>();
+        let label = |result: &RankedResult| match &result.item {
+            RankedItem::App(app) => format!("app:{}", app.name),
+            RankedItem::File(file) => format!("file:{}", file.path),
+            RankedItem::Command { command, .. } => format!("command:{}", command.name),
+        };
+        let ranked = |query| {
+            rank_unified(query, apps.clone(), files.clone(), Vec::new(), &HashMap::new())
+                .iter()
+                .map(label)
+                .collect::<Vec<_>>()
+        };
+
+        // The file name starts with the query while the app only fuzzy-matches it
+        let code = ranked("code");
+        assert_eq!(code[0], "file:/home/me/code.rs");
+        assert!(code.contains(&"app:Visual Studio Code".to_string()));
+        // An exact app name beats a file that only starts with the query
+        let notes = ranked("notes");
+        assert_eq!(notes[..2], ["app:Notes", "file:/home/me/notes-archive.txt"]);
+
+        let json = serde_json::to_value(rank_unified("notes", apps, Vec::new(), Vec::new(), &HashMap::new())).unwrap();
+        assert_eq!(json[0]["kind"], "app");
+        assert_eq!(json[0]["name"], "Notes");
+    }
+
+    #[tokio::test]
+    async fn test_search_stream_event_order() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("nested")).unwrap();
+        std::fs::write(dir.path().join("budget-2024.txt"), "numbers").unwrap();
+        std::fs::write(dir.path().join("nested").join("budget-notes.md"), "notes").unwrap();
+        std::fs::write(dir.path().join("unrelated.txt"), "other").unwrap();
+
+        let insights: InsightGenerator = Arc::new(|prompt: String| {
+            assert!(prompt.contains("2 file(s) found"));
+            Box::pin(futures::stream::iter(vec![
+                Ok("Two ".to_string()),
+                Ok("budgets.".to_string()),
+            ]))
+        });
+        let request = SearchStreamRequest {
+            query: "budget".to_string(),
+            search_path: Some(dir.path().to_string_lossy().into_owned()),
+            include_files: true,
+            include_insights: true,
+        };
+
+        let events: Vec<StreamEvent> = search_stream(request, Some(insights), None).collect().await;
+
+        let names: Vec<&str> = events.iter().map(StreamEvent::name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "apps",
+                "file_match",
+                "file_match",
+                "insight_token",
+                "insight_token",
+                "done"
+            ]
+        );
+        assert!(matches!(&events[3], StreamEvent::InsightToken { text } if text == "Two "));
+    }
+
+    #[tokio::test]
+    async fn test_misspelled_query_offers_suggestion() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("document.txt"), "text").unwrap();
+
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let corrector: QueryCorrector = Arc::new(move |prompt: String| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            assert!(prompt.contains("'docmentzq'"));
+            Box::pin(async { Ok("\"documentzq\"\n".to_string()) })
+        });
+        let request = || SearchStreamRequest {
+            query: "docmentzq".to_string(),
+            search_path: Some(dir.path().to_string_lossy().into_owned()),
+            include_files: true,
+            include_insights: false,
+        };
+
+        let events: Vec<StreamEvent> = search_stream(request(), None, Some(corrector.clone())).collect().await;
+        let cached: Vec<StreamEvent> = search_stream(request(), None, Some(corrector)).collect().await;
+
+        // The raw (empty) results come first and the suggestion is not searched
+        let names: Vec<&str> = events.iter().map(StreamEvent::name).collect();
+        assert_eq!(names, vec!["apps", "suggestion", "done"]);
+        assert!(matches!(&events[0], StreamEvent::Apps { applications } if applications.is_empty()));
+        assert!(matches!(&events[1], StreamEvent::Suggestion { suggestion } if suggestion == "documentzq"));
+        assert!(matches!(&cached[1], StreamEvent::Suggestion { suggestion } if suggestion == "documentzq"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(parse_correction("chrome", "NONE"), None);
+        assert_eq!(parse_correction("chrome", "Chrome."), None);
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_extracts_png_icon_from_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("Fixture.app");
+        let resources = app.join("Contents/Resources");
+        std::fs::create_dir_all(&resources).unwrap();
+        image::RgbaImage::from_pixel(16, 16, image::Rgba([255, 0, 0, 255]))
+            .save(resources.join("AppIcon.png"))
+            .unwrap();
+        std::fs::write(resources.join("Credits.rtf"), "credits").unwrap();
+
+        let icon = extract_app_icon(&app.to_string_lossy());
+
+        assert!(icon.unwrap().starts_with("data:image/png;base64,"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mime_type_for_extension_prefers_highest_weight() {
+        let root = tempfile::tempdir().unwrap();
+        let dirs = ["user", "system"].map(|name| root.path().join(name));
+        for (dir, globs) in dirs.iter().zip([
+            "# comment\n50:text/x-user:*.md\n",
+            "50:text/markdown:*.md\n60:text/plain:*.TXT\n40:text/x-log:*.txt\n",
+        ]) {
+            std::fs::create_dir_all(dir.join("mime")).unwrap();
+            std::fs::write(dir.join("mime/globs2"), globs).unwrap();
+        }
+
+        let txt = mime_type_for_extension("txt", &dirs);
+        let md = mime_type_for_extension("md", &dirs);
+        let unknown = mime_type_for_extension("zzz", &dirs);
+
+        assert_eq!(txt.as_deref(), Some("text/plain"));
+        assert_eq!(md.as_deref(), Some("text/x-user"), "earlier data dirs win ties");
+        assert_eq!(unknown, None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_finds_linux_icon_through_desktop_entry() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let applications = data_dir.path().join("applications");
+        std::fs::create_dir_all(&applications).unwrap();
+        std::fs::write(
+            applications.join("notes.desktop"),
+            "[Desktop Entry]\nName=Notes\nExec=/opt/notes/bin/notes %U\nIcon=notes\n\n[Desktop Action new]\nIcon=other\n",
+        )
+        .unwrap();
+        for size in ["48x48", "128x128"] {
+            let apps = data_dir.path().join("icons/hicolor").join(size).join("apps");
+            std::fs::create_dir_all(&apps).unwrap();
+            image::RgbaImage::from_pixel(16, 16, image::Rgba([0, 0, 255, 255]))
+                .save(apps.join("notes.png"))
+                .unwrap();
+        }
+
+        let data_dirs = [data_dir.path().to_path_buf()];
+        let hicolor = ["hicolor".to_string()];
+        let icon = find_linux_icon("/opt/notes/bin/notes", &data_dirs, &hicolor);
+        let from_entry = find_linux_icon(
+            &applications.join("notes.desktop").to_string_lossy(),
+            &data_dirs,
+            &hicolor,
+        );
+        let unknown = find_linux_icon("/usr/bin/unknown", &data_dirs, &hicolor);
+        let data_url = icon.as_deref().map(|icon| convert_bitmap_to_png(icon).unwrap());
+
+        assert_eq!(icon, Some(data_dir.path().join("icons/hicolor/128x128/apps/notes.png")));
+        assert_eq!(from_entry, icon);
+        assert_eq!(unknown, None);
+        assert!(data_url.unwrap().starts_with("data:image/png;base64,"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_linux_icons_come_from_the_active_theme_then_scalable_svgs() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let entry = data_dir.path().join("applications/editor.desktop");
+        std::fs::create_dir_all(entry.parent().unwrap()).unwrap();
+        std::fs::write(&entry, "[Desktop Entry]\nName=Editor\nExec=editor\nIcon=editor\n").unwrap();
+        let scalable = data_dir.path().join("icons/hicolor/scalable/apps/editor.svg");
+        std::fs::create_dir_all(scalable.parent().unwrap()).unwrap();
+        std::fs::write(&scalable, r#"<svg xmlns="http://www.w3.org/2000/svg"/>"#).unwrap();
+        let themed = data_dir.path().join("icons/Papirus/64x64/apps/editor.png");
+        std::fs::create_dir_all(themed.parent().unwrap()).unwrap();
+        image::RgbaImage::from_pixel(4, 4, image::Rgba([0, 255, 0, 255]))
+            .save(&themed)
+            .unwrap();
+
+        let data_dirs = [data_dir.path().to_path_buf()];
+        let entry = entry.to_string_lossy().to_string();
+        let hicolor_only = find_linux_icon(&entry, &data_dirs, &["hicolor".to_string()]);
+        let with_theme = find_linux_icon(&entry, &data_dirs, &["Papirus".to_string(), "hicolor".to_string()]);
+        let svg_url = hicolor_only.as_deref().and_then(load_icon_file);
+
+        assert_eq!(hicolor_only, Some(scalable));
+        assert_eq!(with_theme, Some(themed));
+        assert!(svg_url.unwrap().starts_with("data:image/svg+xml;base64,"));
+    }
+
+    #[tokio::test]
+    async fn test_icons_persist_on_disk_until_the_app_changes() {
+        use crate::storage::MemoryStorage;
+        use std::sync::atomic::AtomicUsize;
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("Notes.app");
+        std::fs::write(&bundle, "bundle").unwrap();
+        let app_path = bundle.to_string_lossy().to_string();
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let extractions = AtomicUsize::new(0);
+        let extract = |_: &str| {
+            let count = extractions.fetch_add(1, Ordering::Relaxed) + 1;
+            Some(format!("data:image/png;base64,icon{}", count))
+        };
+
+        let first = IconCache::with_storage(Arc::clone(&storage));
+        let icon = first.get_or_extract_with(&app_path, extract).await;
+        // A fresh cache, as after a relaunch, reads the icon back instead of decoding it
+        let relaunched = IconCache::with_storage(Arc::clone(&storage));
+        let persisted = relaunched.get_or_extract_with(&app_path, extract).await;
+
+        // An updated app gets its icon extracted again
+        let later = SystemTime::now() + Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&bundle)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let updated = IconCache::with_storage(Arc::clone(&storage))
+            .get_or_extract_with(&app_path, extract)
+            .await;
+
+        assert_eq!(icon.as_deref(), Some("data:image/png;base64,icon1"));
+        assert_eq!(persisted, icon);
+        assert_eq!(updated.as_deref(), Some("data:image/png;base64,icon2"));
+        assert_eq!(extractions.load(Ordering::Relaxed), 2);
+        assert_eq!(storage.list(ICON_CACHE_PREFIX).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_icons_of_removed_apps_are_pruned() {
+        use crate::storage::MemoryStorage;
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let cache = IconCache::with_storage(Arc::clone(&storage));
+        for name in ["Notes.app", "Mail.app"] {
+            let bundle = dir.path().join(name);
+            std::fs::write(&bundle, "bundle").unwrap();
+            let icon = Some(format!("data:image/png;base64,{}", name));
+            cache
+                .get_or_extract_with(&bundle.to_string_lossy(), |_: &str| icon)
+                .await;
+        }
+        assert_eq!(storage.list(ICON_CACHE_PREFIX).unwrap().len(), 2);
+
+        std::fs::remove_file(dir.path().join("Mail.app")).unwrap();
+        let relaunched = IconCache::with_storage(Arc::clone(&storage));
+        assert_eq!(relaunched.prune_stale(), 1);
+        assert_eq!(relaunched.prune_stale(), 0);
+        assert_eq!(storage.list(ICON_CACHE_PREFIX).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_extract_icons_for_apps_and_missing_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = if cfg!(target_os = "macos") {
+            let app = dir.path().join("Notes.app");
+            std::fs::create_dir_all(&app).unwrap();
+            app
+        } else {
+            let app = dir.path().join(if cfg!(target_os = "windows") {
+                "notes.exe"
+            } else {
+                "notes"
+            });
+            std::fs::write(&app, "binary").unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&app, std::fs::Permissions::from_mode(0o755)).unwrap();
+            }
+            app
+        };
+        let document = dir.path().join("notes.TXT");
+        std::fs::write(&document, "text").unwrap();
+        let unknown = dir.path().join("notes.zzz");
+        std::fs::write(&unknown, "data").unwrap();
+        let paths =
+            [&app, &document, &unknown, &dir.path().join("missing.app")].map(|path| path.to_string_lossy().to_string());
+
+        let icons = icons_for_paths(
+            &IconCache::new(),
+            paths.to_vec(),
+            |path| Some(format!("data:image/png;base64,{}", path.len())),
+            {
+                let app = paths[0].clone();
+                move |extension: &str| (extension == "txt").then(|| app.clone())
+            },
+        )
+        .await;
+
+        let app_icon = Some(format!("data:image/png;base64,{}", paths[0].len()));
+        assert_eq!(icons.len(), 4);
+        assert_eq!(icons[&paths[0]], app_icon);
+        assert_eq!(icons[&paths[1]], app_icon, "documents use their default app's icon");
+        assert_eq!(icons[&paths[2]], None);
+        assert_eq!(icons[&paths[3]], None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_callers_share_one_scan() {
+        let scans = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&scans);
+        let cache = Arc::new(ApplicationCache::with_scanner(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            Ok(vec![Application {
+                name: "Calendar".to_string(),
+                path: "/Applications/Calendar.app".to_string(),
+                icon_path: None,
+                icon_base64: None,
+            }])
+        }));
+
+        let lookups = (0..4).map(|_| {
+            let cache = Arc::clone(&cache);
+            tokio::spawn(async move { cache.applications_or_scan().await })
+        });
+        for lookup in futures::future::join_all(lookups).await {
+            assert_eq!(lookup.unwrap().unwrap().len(), 1);
+        }
+        assert_eq!(scans.load(Ordering::SeqCst), 1, "startup lookups share one scan");
+
+        // Concurrent refreshes rescan once between them, not once each
+        let refreshes = (0..3).map(|_| {
+            let cache = Arc::clone(&cache);
+            tokio::spawn(async move { cache.scan(false).await })
+        });
+        for refresh in futures::future::join_all(refreshes).await {
+            assert_eq!(refresh.unwrap().unwrap().len(), 1);
+        }
+        assert_eq!(scans.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_refresh_stops_early() {
+        let apps: Vec<Application> = (0..100)
+            .map(|i| Application {
+                name: format!("App {}", i),
+                path: format!("/Applications/App {}.app", i),
+                icon_path: None,
+                icon_base64: None,
+            })
+            .collect();
+        let apps_to_index = apps[..2].to_vec();
+        let cache = ApplicationCache::default();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<RefreshProgress>(1);
+
+        let watcher_cancel = Arc::clone(&cancel);
+        let watcher = tokio::spawn(async move {
+            let mut reports = Vec::new();
+            while let Some(progress) = rx.recv().await {
+                if progress.scanned == 3 {
+                    watcher_cancel.store(true, Ordering::Relaxed);
+                }
+                reports.push(progress);
+            }
+            reports
+        });
+
+        let outcome = cache.index(apps, Some(tx), cancel).await.unwrap();
+        let reports = watcher.await.unwrap();
+
+        assert!(outcome.cancelled);
+        assert_eq!(outcome.total, 100);
+        assert!(outcome.indexed >= 3 && outcome.indexed < 100);
+        assert!(reports
+            .iter()
+            .all(|progress| progress.phase == RefreshPhase::ExtractingIcons));
+        assert_eq!(reports.last().unwrap().scanned, outcome.indexed);
+        assert!(cache.applications().await.is_empty());
+        assert!(!cache.is_populated());
+
+        let outcome = cache
+            .index(apps_to_index, None, Arc::new(AtomicBool::new(false)))
+            .await
+            .unwrap();
+        assert!(!outcome.cancelled);
+        assert!(cache.is_populated());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_checks_cancel_around_the_scan() {
+        let scans = Arc::new(AtomicUsize::new(0));
+        let cancel = Arc::new(AtomicBool::new(true));
+        let (counter, scan_cancel) = (Arc::clone(&scans), Arc::clone(&cancel));
+        let cache = ApplicationCache::with_scanner(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            // Cancelled while the scan runs
+            scan_cancel.store(true, Ordering::Relaxed);
+            Ok(vec![Application {
+                name: "Calendar".to_string(),
+                path: "/Applications/Calendar.app".to_string(),
+                icon_path: None,
+                icon_base64: None,
+            }])
+        });
+
+        let before = cache.refresh_with_progress(None, Arc::clone(&cancel)).await.unwrap();
+        assert!(before.cancelled);
+        assert_eq!(scans.load(Ordering::SeqCst), 0, "a cancelled refresh doesn't scan");
+
+        cancel.store(false, Ordering::Relaxed);
+        let during = cache.refresh_with_progress(None, Arc::clone(&cancel)).await.unwrap();
+        assert!(during.cancelled);
+        assert_eq!((during.indexed, during.total), (0, 1));
+        assert_eq!(scans.load(Ordering::SeqCst), 1);
+        assert!(!cache.is_populated());
+    }
+
+    #[tokio::test]
+    async fn test_failed_rescan_is_not_reused() {
+        let scans = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&scans);
+        let cache = ApplicationCache::with_scanner(move || match counter.fetch_add(1, Ordering::SeqCst) {
+            1 => Err("scan failed".to_string()),
+            n => Ok(vec![Application {
+                name: format!("App {}", n),
+                path: format!("/Applications/App {}.app", n),
+                icon_path: None,
+                icon_base64: None,
+            }]),
+        });
+
+        assert_eq!(cache.applications_or_scan().await.unwrap()[0].name, "App 0");
+        let refresh = cache
+            .refresh_with_progress(None, Arc::new(AtomicBool::new(false)))
+            .await;
+        assert!(refresh.is_err());
+
+        let apps = cache.applications_or_scan().await.unwrap();
+        assert_eq!(apps[0].name, "App 2", "the scan before the failed one isn't reused");
+        assert_eq!(scans.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_insights_skip_provider() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let insights: InsightGenerator = Arc::new(move |_prompt: String| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(futures::stream::iter(vec![Ok("insight".to_string())]))
+        });
+
+        // The setting is passed in rather than toggled, so parallel tests never see it flip
+        let generate_calls = Arc::clone(&calls);
+        let result = generate_insights_with(
+            false,
+            "budget".to_string(),
+            SearchResult {
+                applications: Vec::new(),
+                files: Vec::new(),
+            },
+            None,
+            None,
+            |_: &str| None,
+            move |_: AIOptions| async move {
+                generate_calls.fetch_add(1, Ordering::SeqCst);
+                Ok("insight".to_string())
+            },
+        )
+        .await;
+        let request = SearchStreamRequest {
+            query: "budget".to_string(),
+            search_path: Some(
+                tempfile::tempdir()
+                    .unwrap()
+                    .path()
+                    .join("missing")
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            include_files: false,
+            include_insights: true,
+        };
+        let events: Vec<StreamEvent> = search_stream(request, gate_insights_when(false, Some(insights)), None)
+            .collect()
+            .await;
+
+        assert_eq!(result.unwrap_err(), "AI insights disabled");
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        let names: Vec<&str> = events.iter().map(StreamEvent::name).collect();
+        assert_eq!(names, vec!["apps", "done"]);
+    }
+
+    #[test]
+    fn test_background_model_falls_back_as_a_pair() {
+        let settings = |pairs: &[(&str, &str)]| {
+            let pairs: HashMap<String, String> = pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            move |name: &str| pairs.get(name).cloned()
+        };
+        let background = [
+            ("AI_BACKGROUND_PROVIDER", "openai"),
+            ("AI_BACKGROUND_MODEL", "gpt-4.1-nano"),
+        ];
+        let resolve = |provider: Option<&str>, model: Option<&str>, pairs: &[(&str, &str)]| {
+            background_model_from(
+                "INSIGHTS",
+                provider.map(String::from),
+                model.map(String::from),
+                settings(pairs),
+            )
+        };
+        let pair = |provider: Option<&str>, model: Option<&str>| (provider.map(String::from), model.map(String::from));
+
+        // A requested provider keeps the agent's default model rather than borrowing one
+        assert_eq!(
+            resolve(Some("anthropic"), None, &background),
+            pair(Some("anthropic"), None)
+        );
+        // A task provider without a model doesn't pick up the background model
+        let task_provider = [("AI_INSIGHTS_PROVIDER", "anthropic"), background[0], background[1]];
+        assert_eq!(resolve(None, None, &task_provider), pair(Some("anthropic"), None));
+        assert_eq!(
+            resolve(None, None, &background),
+            pair(Some("openai"), Some("gpt-4.1-nano"))
+        );
+        assert_eq!(resolve(None, None, &[]), pair(None, None));
+    }
+
+    #[tokio::test]
+    async fn test_insights_are_generated_on_the_background_model() {
+        let sent = Arc::new(std::sync::Mutex::new(None));
+        let recorder = Arc::clone(&sent);
+        let text = generate_insights_with(
+            true,
+            "budget".to_string(),
+            SearchResult {
+                applications: Vec::new(),
+                files: Vec::new(),
+            },
+            None,
+            None,
+            |name: &str| match name {
+                "AI_BACKGROUND_PROVIDER" => Some("openai".to_string()),
+                "AI_BACKGROUND_MODEL" => Some("gpt-4.1-nano".to_string()),
+                _ => None,
+            },
+            move |options: AIOptions| async move {
+                *recorder.lock().unwrap() = Some(options);
+                Ok("insight".to_string())
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(text, "insight");
+        let options = sent.lock().unwrap().take().unwrap();
+        assert_eq!(options.provider.as_deref(), Some("openai"));
+        assert_eq!(options.model.as_deref(), Some("gpt-4.1-nano"));
+        assert!(options.cacheable);
+        assert_eq!(options.max_tokens, Some(200));
+        assert!(options.prompt.contains("User searched for: 'budget'"));
+    }
+
+    #[test]
+    fn test_insights_prompt_truncates_and_redacts_content() {
+        use crate::insights_privacy::InsightsPrivacyConfig;
+
+        let file = |name: &str, line: &str| FileMatch {
+            path: format!("/home/me/{}", name),
+            line_number: Some(1),
+            line_content: Some(line.to_string()),
+            line_length: None,
+            match_type: "content".to_string(),
+            match_count: None,
+        };
+        let results = SearchResult {
+            applications: Vec::new(),
+            files: vec![
+                file("deploy.env", "ACME_TOKEN=tok_8f3k2j1h contact ops@example.com"),
+                file("notes.md", &"x".repeat(50)),
+                file("todo.md", "third result"),
+            ],
+        };
+        let privacy = InsightsPrivacy::new(InsightsPrivacyConfig {
+            max_results: 2,
+            max_line_chars: 10,
+            redaction_patterns: vec![r"tok_[a-z0-9]+".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+
+        let prompt = build_insights_prompt("deploy", &results, &privacy);
+        assert!(prompt.contains("1. deploy.env - ACME_TOKEN…"));
+        assert!(prompt.contains(&format!("2. notes.md - {}…", "x".repeat(10))));
+        assert!(!prompt.contains("todo.md"));
+        assert!(prompt.contains("... and 1 more"));
+
+        // With room for the whole line, the secrets in it are masked
+        let privacy = InsightsPrivacy::new(InsightsPrivacyConfig {
+            max_line_chars: 200,
+            redaction_patterns: vec![r"tok_[a-z0-9]+".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let prompt = build_insights_prompt("deploy", &results, &privacy);
+        assert!(prompt.contains("ACME_TOKEN=[REDACTED] contact [REDACTED]"));
+        assert!(!prompt.contains("tok_8f3k2j1h") && !prompt.contains("ops@example.com"));
+
+        let names_only = InsightsPrivacy::new(InsightsPrivacyConfig {
+            names_only: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let prompt = build_insights_prompt("deploy", &results, &names_only);
+        assert!(prompt.contains("1. deploy.env\n"));
+        assert!(!prompt.contains("ACME_TOKEN"));
+    }
+
+    #[test]
+    fn test_copy_result_formats() {
+        let app = ResultRef::App(Application {
+            name: "Visual Studio Code".to_string(),
+            path: "/Applications/Visual Studio Code.app".to_string(),
+            icon_path: None,
+            icon_base64: None,
+        });
+        let file = ResultRef::File(FileMatch {
+            path: "/home/me/notes/todo list.md".to_string(),
+            line_number: Some(12),
+            line_content: Some("- ship it".to_string()),
+            line_length: None,
+            match_type: "content".to_string(),
+            match_count: None,
+        });
+
+        assert_eq!(
+            format_result(&app, CopyFormat::Path),
+            "/Applications/Visual Studio Code.app"
+        );
+        assert_eq!(format_result(&app, CopyFormat::Name), "Visual Studio Code");
+        assert_eq!(
+            format_result(&app, CopyFormat::FileUrl),
+            "file:///Applications/Visual%20Studio%20Code.app"
+        );
+        assert_eq!(
+            format_result(&app, CopyFormat::MarkdownLink),
+            "[Visual Studio Code](file:///Applications/Visual%20Studio%20Code.app)"
+        );
+
+        assert_eq!(format_result(&file, CopyFormat::Path), "/home/me/notes/todo list.md");
+        assert_eq!(format_result(&file, CopyFormat::Name), "todo list.md");
+        assert_eq!(
+            format_result(&file, CopyFormat::FileUrl),
+            "file:///home/me/notes/todo%20list.md"
+        );
+        assert_eq!(
+            format_result(&file, CopyFormat::MarkdownLink),
+            "[todo list.md:12](file:///home/me/notes/todo%20list.md#L12)"
+        );
+    }
+}
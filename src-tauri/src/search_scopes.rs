@@ -0,0 +1,179 @@
+//! Named file search scopes
+//!
+//! Maps short names like `projects` or `docs` to directories so searches can target
+//! them by name instead of a raw path. Scopes are persisted under the
+//! `search_scopes.json` storage key; paths may use `~` and `$VAR` / `${VAR}`.
+
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tauri::command;
+
+use crate::storage::{Storage, STORAGE};
+
+const STORAGE_KEY: &str = "search_scopes.json";
+
+pub struct SearchScopes {
+    storage: Arc<dyn Storage>,
+    scopes: BTreeMap<String, String>,
+}
+
+impl SearchScopes {
+    /// Load scopes from `storage`, starting empty if none have been saved yet
+    pub fn load(storage: Arc<dyn Storage>) -> Self {
+        let scopes = storage
+            .read(STORAGE_KEY)
+            .ok()
+            .flatten()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Self { storage, scopes }
+    }
+
+    /// Scope names and their paths as configured (unexpanded)
+    pub fn list(&self) -> BTreeMap<String, String> {
+        self.scopes.clone()
+    }
+
+    /// Add or replace a scope and persist the config
+    pub fn set(&mut self, name: &str, path: &str) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Search scope name cannot be empty".to_string());
+        }
+        self.scopes.insert(name.to_string(), path.trim().to_string());
+        self.save()
+    }
+
+    /// Resolve a scope name to an expanded directory path
+    pub fn resolve(&self, name: &str) -> Result<String, String> {
+        let path = self.scopes.get(name).ok_or_else(|| {
+            let known = self.scopes.keys().cloned().collect::<Vec<_>>().join(", ");
+            format!("Unknown search scope: {} (configured scopes: {})", name, known)
+        })?;
+        Ok(expand_path(path))
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let content = serde_json::to_vec_pretty(&self.scopes).map_err(|e| e.to_string())?;
+        self.storage
+            .write(STORAGE_KEY, &content)
+            .map_err(|e| format!("Failed to save search scopes: {}", e))
+    }
+}
+
+static SEARCH_SCOPES: Lazy<Mutex<SearchScopes>> = Lazy::new(|| Mutex::new(SearchScopes::load(STORAGE.clone())));
+
+/// Expand a leading `~` and any `$VAR` / `${VAR}` references; unset variables are left as-is
+pub fn expand_path(path: &str) -> String {
+    let path = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with('\\') => match dirs::home_dir() {
+            Some(home) => format!("{}{}", home.to_string_lossy(), rest),
+            None => path.to_string(),
+        },
+        _ => path.to_string(),
+    };
+
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path.as_str();
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, consumed) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+
+        match std::env::var(name) {
+            Ok(value) if !name.is_empty() => expanded.push_str(&value),
+            _ => expanded.push_str(&rest[start..start + 1 + consumed]),
+        }
+        rest = &after[consumed..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Pick the directory a file search should walk
+///
+/// A named `scope` takes precedence over `search_path`; `None` means the default root.
+pub fn resolve_search_root(
+    scopes: &SearchScopes,
+    scope: Option<&str>,
+    search_path: Option<String>,
+) -> Result<Option<String>, String> {
+    match scope {
+        Some(name) => scopes.resolve(name).map(Some),
+        None => Ok(search_path.map(|path| expand_path(&path))),
+    }
+}
+
+/// Resolve a search root against the persisted scopes
+pub fn resolve_configured_search_root(
+    scope: Option<&str>,
+    search_path: Option<String>,
+) -> Result<Option<String>, String> {
+    let scopes = SEARCH_SCOPES.lock().map_err(|e| e.to_string())?;
+    resolve_search_root(&scopes, scope, search_path)
+}
+
+/// List configured search scopes as name to path
+#[command]
+pub fn list_search_scopes() -> Result<BTreeMap<String, String>, String> {
+    let scopes = SEARCH_SCOPES.lock().map_err(|e| e.to_string())?;
+    Ok(scopes.list())
+}
+
+/// Add or update a named search scope
+#[command]
+pub fn set_search_scope(name: String, path: String) -> Result<(), String> {
+    let mut scopes = SEARCH_SCOPES.lock().map_err(|e| e.to_string())?;
+    scopes.set(&name, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn temp_scopes() -> SearchScopes {
+        SearchScopes::load(Arc::new(MemoryStorage::new()))
+    }
+
+    #[test]
+    fn test_scope_resolution_expands_tilde_and_env() {
+        let mut scopes = temp_scopes();
+        scopes.set("projects", "~/dev").unwrap();
+        scopes.set("manifest", "${CARGO_MANIFEST_DIR}/src").unwrap();
+
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            scopes.resolve("projects").unwrap(),
+            format!("{}/dev", home.to_string_lossy())
+        );
+        assert_eq!(
+            scopes.resolve("manifest").unwrap(),
+            format!("{}/src", env!("CARGO_MANIFEST_DIR"))
+        );
+        assert_eq!(expand_path("$FLEET_UNSET_SCOPE_VAR/x"), "$FLEET_UNSET_SCOPE_VAR/x");
+
+        // Scopes survive a reload from storage
+        let reloaded = SearchScopes::load(scopes.storage.clone());
+        assert_eq!(reloaded.list().get("projects").map(String::as_str), Some("~/dev"));
+    }
+
+    #[test]
+    fn test_unknown_scope_errors() {
+        let scopes = temp_scopes();
+        let err = resolve_search_root(&scopes, Some("nope"), Some("/tmp".to_string())).unwrap_err();
+        assert!(err.contains("Unknown search scope: nope"));
+    }
+}
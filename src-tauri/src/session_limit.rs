@@ -0,0 +1,213 @@
+//! Bound on the number of in-memory agent sessions
+//!
+//! Each session keeps its full message history in memory, so long-running instances
+//! cap how many can exist. When a new session would exceed the cap, the
+//! least-recently-updated session is evicted or the new one is rejected. Sessions
+//! left idle longer than an optional TTL expire, both before new ones are added and
+//! on a periodic sweep.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use tauri_plugin_log::log::info;
+
+/// What to do when creating a session would exceed the limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionLimitPolicy {
+    /// Drop the least-recently-updated session to make room
+    #[default]
+    Evict,
+    /// Refuse to create the new session
+    Reject,
+}
+
+impl SessionLimitPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "evict" => Some(Self::Evict),
+            "reject" => Some(Self::Reject),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionLimit {
+    /// Maximum number of live sessions; `None` means unbounded
+    pub max_sessions: Option<usize>,
+    pub policy: SessionLimitPolicy,
+    /// Seconds a session may go without updates before it expires; `None` keeps idle sessions
+    pub idle_ttl_secs: Option<u64>,
+}
+
+impl SessionLimit {
+    /// Read `{prefix}_MAX_SESSIONS`, `{prefix}_SESSION_LIMIT_POLICY` and
+    /// `{prefix}_SESSION_IDLE_TTL_SECS` from the environment
+    pub fn from_env(prefix: &str) -> Self {
+        Self {
+            max_sessions: std::env::var(format!("{}_MAX_SESSIONS", prefix))
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .filter(|&max| max > 0),
+            policy: std::env::var(format!("{}_SESSION_LIMIT_POLICY", prefix))
+                .ok()
+                .and_then(|value| SessionLimitPolicy::parse(&value))
+                .unwrap_or_default(),
+            idle_ttl_secs: std::env::var(format!("{}_SESSION_IDLE_TTL_SECS", prefix))
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .filter(|&secs| secs > 0),
+        }
+    }
+
+    /// Remove sessions last updated more than `idle_ttl_secs` before `now`, returning their ids
+    pub fn expire_idle<S>(
+        &self,
+        sessions: &mut HashMap<String, S>,
+        updated_at: impl Fn(&S) -> DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Vec<String> {
+        let Some(idle_ttl_secs) = self.idle_ttl_secs else {
+            return Vec::new();
+        };
+        // A TTL reaching back past the representable range means nothing ever expires
+        let Some(cutoff) = i64::try_from(idle_ttl_secs)
+            .ok()
+            .and_then(chrono::Duration::try_seconds)
+            .and_then(|ttl| now.checked_sub_signed(ttl))
+        else {
+            return Vec::new();
+        };
+
+        let expired: Vec<String> = sessions
+            .iter()
+            .filter(|(_, session)| updated_at(session) < cutoff)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            sessions.remove(id);
+            info!("Session {} idle for over {}s, expired", id, idle_ttl_secs);
+        }
+        expired
+    }
+
+    /// Bring sessions loaded from storage within the limit, returning the ids dropped
+    ///
+    /// Idle sessions expire first, then the least-recently-updated are dropped until at
+    /// most `max_sessions` remain, whatever the policy.
+    pub fn trim<S>(
+        &self,
+        sessions: &mut HashMap<String, S>,
+        updated_at: impl Fn(&S) -> DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Vec<String> {
+        let mut dropped = self.expire_idle(sessions, &updated_at, now);
+        let Some(max_sessions) = self.max_sessions else {
+            return dropped;
+        };
+        let mut by_age: Vec<(DateTime<Utc>, String)> = sessions
+            .iter()
+            .map(|(id, session)| (updated_at(session), id.clone()))
+            .collect();
+        by_age.sort();
+        let excess = sessions.len().saturating_sub(max_sessions);
+        for (_, id) in by_age.into_iter().take(excess) {
+            sessions.remove(&id);
+            dropped.push(id);
+        }
+        dropped
+    }
+
+    /// Make room for one more session in `sessions`
+    ///
+    /// Returns the ids of evicted sessions, or `Err(max_sessions)` when the policy rejects.
+    pub fn make_room<S>(
+        &self,
+        sessions: &mut HashMap<String, S>,
+        updated_at: impl Fn(&S) -> DateTime<Utc>,
+    ) -> Result<Vec<String>, usize> {
+        let Some(max_sessions) = self.max_sessions else {
+            return Ok(Vec::new());
+        };
+        if sessions.len() < max_sessions {
+            return Ok(Vec::new());
+        }
+        if self.policy == SessionLimitPolicy::Reject {
+            return Err(max_sessions);
+        }
+
+        let mut evicted = Vec::new();
+        while sessions.len() >= max_sessions.max(1) {
+            let Some(oldest) = sessions
+                .iter()
+                .min_by_key(|(_, session)| updated_at(session))
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            sessions.remove(&oldest);
+            info!("Session limit {} reached, evicted session {}", max_sessions, oldest);
+            evicted.push(oldest);
+        }
+        Ok(evicted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_sessions_expire_after_ttl() {
+        let now = Utc::now();
+        let mut sessions = HashMap::from([
+            ("fresh".to_string(), now - chrono::Duration::seconds(30)),
+            ("idle".to_string(), now - chrono::Duration::seconds(120)),
+        ]);
+
+        let unbounded = SessionLimit::default();
+        assert!(unbounded.expire_idle(&mut sessions, |at| *at, now).is_empty());
+
+        let limit = SessionLimit {
+            idle_ttl_secs: Some(60),
+            ..Default::default()
+        };
+        assert_eq!(limit.expire_idle(&mut sessions, |at| *at, now), vec!["idle"]);
+        assert_eq!(sessions.keys().collect::<Vec<_>>(), vec!["fresh"]);
+
+        for idle_ttl_secs in [u64::MAX, i64::MAX as u64, 10_000_000_000_000_000] {
+            let forever = SessionLimit {
+                idle_ttl_secs: Some(idle_ttl_secs),
+                ..Default::default()
+            };
+            assert!(forever.expire_idle(&mut sessions, |at| *at, now).is_empty());
+        }
+        assert_eq!(sessions.len(), 1);
+    }
+
+    #[test]
+    fn test_trim_keeps_the_most_recent_sessions() {
+        let now = Utc::now();
+        let mut sessions: HashMap<String, DateTime<Utc>> = (1..=4)
+            .map(|minutes| (format!("{}m", minutes), now - chrono::Duration::minutes(minutes)))
+            .collect();
+        let limit = SessionLimit {
+            max_sessions: Some(2),
+            policy: SessionLimitPolicy::Reject,
+            idle_ttl_secs: Some(210),
+        };
+
+        let mut dropped = limit.trim(&mut sessions, |at| *at, now);
+        dropped.sort();
+        assert_eq!(dropped, vec!["3m", "4m"]);
+        let mut kept: Vec<_> = sessions.keys().cloned().collect();
+        kept.sort();
+        assert_eq!(kept, vec!["1m", "2m"]);
+
+        sessions.insert("0m".to_string(), now);
+        assert_eq!(limit.trim(&mut sessions, |at| *at, now), vec!["2m"]);
+    }
+}
@@ -0,0 +1,301 @@
+//! Disk persistence for agent sessions
+//!
+//! Each session is stored as JSON under `{namespace}/{session_id}.json` and rewritten
+//! whenever it changes, so conversation history survives a restart. Agents load the
+//! sessions in their namespace, within their session limit, when the store is attached.
+//! Persistence is best effort: failures are logged rather than failing the chat turn
+//! that caused them.
+//!
+//! Sessions are serialized by the caller, but written behind: a single writer thread
+//! applies writes and deletes in the order they were queued, so agents never block on
+//! file I/O while holding their sessions lock. [`flush_all`] drains every store's queue
+//! and runs when the app exits. If the writer thread can't be started, changes are
+//! written synchronously instead.
+
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use tauri_plugin_log::log::warn;
+
+use crate::storage::Storage;
+
+/// Storage change queued for the writer thread
+enum StoreOp {
+    Write {
+        key: String,
+        bytes: Vec<u8>,
+    },
+    Delete {
+        key: String,
+    },
+    /// Reply once every earlier change has been applied
+    Flush(Sender<()>),
+}
+
+/// Queue of the writer thread, which exits once the last store holding it is dropped
+struct Writer(Sender<StoreOp>);
+
+/// Writers of every live store, for [`flush_all`]
+static WRITERS: Lazy<Mutex<Vec<Weak<Writer>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Block until every store's queued saves and deletes have reached storage
+///
+/// Called on app exit so the last turns of a conversation aren't lost.
+pub fn flush_all() {
+    let writers: Vec<Arc<Writer>> = {
+        let writers = WRITERS.lock().unwrap_or_else(|e| e.into_inner());
+        writers.iter().filter_map(Weak::upgrade).collect()
+    };
+    for writer in writers {
+        flush_writer(&writer);
+    }
+}
+
+fn flush_writer(writer: &Writer) {
+    let (done, finished) = mpsc::channel();
+    if writer.0.send(StoreOp::Flush(done)).is_ok() {
+        let _ = finished.recv();
+    }
+}
+
+fn apply(storage: &dyn Storage, op: StoreOp) {
+    match op {
+        StoreOp::Write { key, bytes } => {
+            if let Err(e) = storage.write(&key, &bytes) {
+                warn!("Failed to save session {}: {}", key, e);
+            }
+        }
+        StoreOp::Delete { key } => {
+            if let Err(e) = storage.delete(&key) {
+                warn!("Failed to delete saved session {}: {}", key, e);
+            }
+        }
+        StoreOp::Flush(done) => {
+            let _ = done.send(());
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionStore {
+    storage: Arc<dyn Storage>,
+    namespace: String,
+    /// `None` when the writer thread couldn't be started; changes are then written in place
+    writer: Option<Arc<Writer>>,
+}
+
+impl std::fmt::Debug for SessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionStore")
+            .field("namespace", &self.namespace)
+            .finish()
+    }
+}
+
+impl SessionStore {
+    /// Store sessions in `storage` under the `namespace` key prefix, e.g. `sessions/a2ui`
+    pub fn new(storage: Arc<dyn Storage>, namespace: &str) -> Self {
+        let namespace = namespace.trim_end_matches('/').to_string();
+        let (sender, queue) = mpsc::channel();
+        let thread_storage = Arc::clone(&storage);
+        let spawned = std::thread::Builder::new()
+            .name("session-store".to_string())
+            .spawn(move || {
+                for op in queue {
+                    apply(thread_storage.as_ref(), op);
+                }
+            });
+
+        let writer = match spawned {
+            Ok(_) => {
+                let writer = Arc::new(Writer(sender));
+                let mut writers = WRITERS.lock().unwrap_or_else(|e| e.into_inner());
+                writers.retain(|writer| writer.strong_count() > 0);
+                writers.push(Arc::downgrade(&writer));
+                Some(writer)
+            }
+            Err(e) => {
+                warn!(
+                    "Writing sessions in {} synchronously, no writer thread: {}",
+                    namespace, e
+                );
+                None
+            }
+        };
+
+        Self {
+            storage,
+            namespace,
+            writer,
+        }
+    }
+
+    fn enqueue(&self, op: StoreOp) {
+        let Some(writer) = &self.writer else {
+            return apply(self.storage.as_ref(), op);
+        };
+        if let Err(mpsc::SendError(op)) = writer.0.send(op) {
+            warn!(
+                "Session store writer for {} has stopped, writing in place",
+                self.namespace
+            );
+            apply(self.storage.as_ref(), op);
+        }
+    }
+
+    fn key(&self, session_id: &str) -> String {
+        format!("{}/{}.json", self.namespace, session_id)
+    }
+
+    /// Every saved session, keyed by session id; unreadable ones are skipped
+    pub fn load_all<S: DeserializeOwned>(&self) -> HashMap<String, S> {
+        let keys = match self.storage.list(&format!("{}/", self.namespace)) {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Failed to list saved sessions in {}: {}", self.namespace, e);
+                return HashMap::new();
+            }
+        };
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let session_id = key
+                    .strip_prefix(&format!("{}/", self.namespace))?
+                    .strip_suffix(".json")?
+                    .to_string();
+                let bytes = self.storage.read(&key).ok().flatten()?;
+                match serde_json::from_slice(&bytes) {
+                    Ok(session) => Some((session_id, session)),
+                    Err(e) => {
+                        warn!("Skipping unreadable session {}: {}", key, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Serialize `session` now and queue it to replace the saved copy under `session_id`
+    pub fn save<S: Serialize>(&self, session_id: &str, session: &S) {
+        match serde_json::to_vec(session) {
+            Ok(bytes) => self.enqueue(StoreOp::Write {
+                key: self.key(session_id),
+                bytes,
+            }),
+            Err(e) => warn!("Failed to save session {}: {}", session_id, e),
+        }
+    }
+
+    /// Queue removal of the saved copy of `session_id`
+    pub fn delete(&self, session_id: &str) {
+        self.enqueue(StoreOp::Delete {
+            key: self.key(session_id),
+        });
+    }
+
+    /// Block until every save and delete queued so far has reached storage
+    #[cfg(test)]
+    pub fn flush(&self) {
+        if let Some(writer) = &self.writer {
+            flush_writer(writer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn test_sessions_round_trip_per_namespace() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let a2ui = SessionStore::new(Arc::clone(&storage), "sessions/a2ui");
+        let gemini = SessionStore::new(Arc::clone(&storage), "sessions/gemini/");
+
+        a2ui.save("first", &vec!["hello".to_string()]);
+        a2ui.save("second", &vec!["hi".to_string(), "there".to_string()]);
+        gemini.save("first", &vec!["你好".to_string()]);
+        storage.write("sessions/a2ui/broken.json", b"{not json").unwrap();
+        // Ids that can't be storage keys are not saved
+        a2ui.save("../escape", &Vec::<String>::new());
+        a2ui.flush();
+        gemini.flush();
+
+        let loaded: HashMap<String, Vec<String>> = a2ui.load_all();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded["second"], vec!["hi", "there"]);
+
+        a2ui.delete("first");
+        a2ui.delete("missing");
+        a2ui.flush();
+        let loaded: HashMap<String, Vec<String>> = a2ui.load_all();
+        assert_eq!(loaded.keys().collect::<Vec<_>>(), vec!["second"]);
+        let loaded: HashMap<String, Vec<String>> = gemini.load_all();
+        assert_eq!(loaded["first"], vec!["你好"]);
+    }
+
+    /// Storage whose writes wait until the test lets them through
+    struct GatedStorage {
+        inner: MemoryStorage,
+        gate: std::sync::Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl Storage for GatedStorage {
+        fn read(&self, key: &str) -> Result<Option<Vec<u8>>, crate::storage::StorageError> {
+            self.inner.read(key)
+        }
+
+        fn write(&self, key: &str, bytes: &[u8]) -> Result<(), crate::storage::StorageError> {
+            let _ = self.gate.lock().unwrap().recv();
+            self.inner.write(key, bytes)
+        }
+
+        fn delete(&self, key: &str) -> Result<(), crate::storage::StorageError> {
+            self.inner.delete(key)
+        }
+
+        fn list(&self, prefix: &str) -> Result<Vec<String>, crate::storage::StorageError> {
+            self.inner.list(prefix)
+        }
+    }
+
+    #[test]
+    fn test_saves_do_not_wait_for_storage() {
+        let (open, gate) = mpsc::channel();
+        let storage = Arc::new(GatedStorage {
+            inner: MemoryStorage::new(),
+            gate: std::sync::Mutex::new(gate),
+        });
+        let store = SessionStore::new(storage.clone(), "sessions/a2ui");
+
+        // Returns while the write is still held at the gate
+        store.save("first", &vec!["hello".to_string()]);
+        store.delete("first");
+        store.save("first", &vec!["again".to_string()]);
+        assert!(storage.read("sessions/a2ui/first.json").unwrap().is_none());
+
+        open.send(()).unwrap();
+        open.send(()).unwrap();
+        store.flush();
+        let loaded: HashMap<String, Vec<String>> = store.load_all();
+        assert_eq!(loaded["first"], vec!["again"]);
+    }
+
+    #[test]
+    fn test_flush_all_drains_every_store() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let a2ui = SessionStore::new(Arc::clone(&storage), "sessions/a2ui");
+        let gemini = SessionStore::new(Arc::clone(&storage), "sessions/gemini");
+
+        a2ui.save("first", &vec!["hello".to_string()]);
+        gemini.save("second", &vec!["hi".to_string()]);
+        flush_all();
+
+        assert!(storage.read("sessions/a2ui/first.json").unwrap().is_some());
+        assert!(storage.read("sessions/gemini/second.json").unwrap().is_some());
+    }
+}
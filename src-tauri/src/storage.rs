@@ -0,0 +1,262 @@
+//! Persistent storage backend
+//!
+//! All persisted state goes through the [`Storage`] trait as opaque bytes under a
+//! `/`-separated key, so location and error handling live in one place. The app uses
+//! [`FileStorage`] rooted at the fleet-chat data dir (`~/.fleet-chat`); tests use
+//! [`MemoryStorage`].
+
+use once_cell::sync::Lazy;
+#[cfg(test)]
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("Invalid storage key: {0}")]
+    InvalidKey(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+pub trait Storage: Send + Sync {
+    /// Read the value stored under `key`, or `None` if there is none
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Store `bytes` under `key`, replacing any previous value
+    fn write(&self, key: &str, bytes: &[u8]) -> Result<(), StorageError>;
+
+    /// Remove `key`; deleting a missing key is not an error
+    fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    /// All keys starting with `prefix`, sorted
+    fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError>;
+}
+
+/// Appended to a file's full name while [`FileStorage`] writes it
+const TEMP_SUFFIX: &str = ".tmp~";
+
+/// Reject keys that are empty, could escape the storage root, or look like a write in progress
+fn validate_key(key: &str) -> Result<(), StorageError> {
+    let valid = !key.is_empty()
+        && !key.starts_with('/')
+        && !key.ends_with(TEMP_SUFFIX)
+        && key
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != ".." && !part.contains('\\'));
+    if valid {
+        Ok(())
+    } else {
+        Err(StorageError::InvalidKey(key.to_string()))
+    }
+}
+
+/// Stores each key as a file under a root directory
+pub struct FileStorage {
+    root: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path_for(&self, key: &str) -> Result<PathBuf, StorageError> {
+        validate_key(key)?;
+        Ok(self.root.join(key))
+    }
+
+    /// Sibling of `path` a write goes to first; keeps the whole file name so that
+    /// `a.json` and `a.txt` never share one
+    fn temp_path(path: &Path) -> PathBuf {
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(TEMP_SUFFIX);
+        PathBuf::from(temp)
+    }
+
+    fn collect_keys(&self, dir: &Path, keys: &mut Vec<String>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.collect_keys(&path, keys)?;
+            } else if let Ok(relative) = path.strip_prefix(&self.root) {
+                let key = relative
+                    .components()
+                    .map(|part| part.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                keys.push(key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match std::fs::read(self.path_for(key)?) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, key: &str, bytes: &[u8]) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write to a sibling file first so a crash never leaves a half-written value
+        let temp = Self::temp_path(&path);
+        std::fs::write(&temp, bytes)?;
+        std::fs::rename(&temp, &path)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match std::fs::remove_file(self.path_for(key)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        let mut keys = Vec::new();
+        match self.collect_keys(&self.root, &mut keys) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        keys.retain(|key| key.starts_with(prefix) && !key.ends_with(TEMP_SUFFIX));
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// Keeps values in memory; used as a test double for [`FileStorage`]
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStorage {
+    entries: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+#[cfg(test)]
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Vec<u8>>> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+impl Storage for MemoryStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        validate_key(key)?;
+        Ok(self.entries().get(key).cloned())
+    }
+
+    fn write(&self, key: &str, bytes: &[u8]) -> Result<(), StorageError> {
+        validate_key(key)?;
+        self.entries().insert(key.to_string(), bytes.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), StorageError> {
+        validate_key(key)?;
+        self.entries().remove(key);
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, StorageError> {
+        Ok(self
+            .entries()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+}
+
+/// The fleet-chat data directory, `~/.fleet-chat`
+pub fn data_dir() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(".fleet-chat")
+}
+
+/// Storage shared by everything the app persists
+pub static STORAGE: Lazy<Arc<dyn Storage>> = Lazy::new(|| Arc::new(FileStorage::new(data_dir())));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise(storage: &dyn Storage) {
+        assert_eq!(storage.read("config/scopes.json").unwrap(), None);
+
+        storage
+            .write("config/scopes.json", b"{\"docs\":\"~/Documents\"}")
+            .unwrap();
+        storage.write("config/theme.json", b"dark").unwrap();
+        storage.write("sessions/1.json", b"[]").unwrap();
+        storage.write("config/theme.json", b"light").unwrap();
+
+        assert_eq!(
+            storage.read("config/scopes.json").unwrap().as_deref(),
+            Some(&b"{\"docs\":\"~/Documents\"}"[..])
+        );
+        assert_eq!(
+            storage.read("config/theme.json").unwrap().as_deref(),
+            Some(&b"light"[..])
+        );
+        assert_eq!(
+            storage.list("config/").unwrap(),
+            vec!["config/scopes.json".to_string(), "config/theme.json".to_string()]
+        );
+
+        storage.delete("config/theme.json").unwrap();
+        storage.delete("config/theme.json").unwrap();
+        assert_eq!(storage.read("config/theme.json").unwrap(), None);
+        assert_eq!(storage.list("").unwrap().len(), 2);
+
+        assert!(matches!(storage.read("../escape"), Err(StorageError::InvalidKey(_))));
+        assert!(matches!(storage.write("/abs", b""), Err(StorageError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_file_storage_round_trip() {
+        let root = tempfile::tempdir().unwrap();
+
+        exercise(&FileStorage::new(root.path()));
+        assert!(root.path().join("config").join("scopes.json").is_file());
+    }
+
+    #[test]
+    fn test_file_storage_temp_files_keep_the_full_name() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(root.path());
+
+        let json = FileStorage::temp_path(&storage.path_for("notes/a.json").unwrap());
+        let text = FileStorage::temp_path(&storage.path_for("notes/a.txt").unwrap());
+        assert_ne!(json, text);
+        assert!(json.ends_with("a.json.tmp~"));
+
+        storage.write("notes/a.json", b"{}").unwrap();
+        storage.write("notes/a.txt", b"hi").unwrap();
+        assert_eq!(storage.list("notes/").unwrap(), vec!["notes/a.json", "notes/a.txt"]);
+        assert!(matches!(
+            storage.write("notes/a.json.tmp~", b""),
+            Err(StorageError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_memory_storage_matches_file_storage() {
+        exercise(&MemoryStorage::new());
+    }
+}
@@ -0,0 +1,168 @@
+//! Checkpoints of in-flight streamed generations
+//!
+//! A stream started with a `request_id` periodically saves its original options and the
+//! text produced so far, so a run that drops mid-stream can be picked up again by
+//! `RigAgent::resume_generation` instead of starting over. A checkpoint is removed once
+//! its stream completes, and unfinished ones expire after a TTL.
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::rig_agent::AIOptions;
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Text chunks streamed between checkpoint saves
+pub const CHECKPOINT_INTERVAL: usize = 8;
+
+pub static STREAM_CHECKPOINTS: Lazy<Arc<CheckpointStore>> = Lazy::new(|| Arc::new(CheckpointStore::default()));
+
+/// The request behind an interrupted stream and the text it produced
+#[derive(Debug, Clone)]
+pub struct StreamCheckpoint {
+    pub options: AIOptions,
+    pub text: String,
+}
+
+struct Entry {
+    checkpoint: StreamCheckpoint,
+    saved_at: Instant,
+}
+
+pub struct CheckpointStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl Default for CheckpointStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL)
+    }
+}
+
+impl CheckpointStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Save (or replace) the checkpoint for `request_id`, dropping expired ones
+    pub fn save(&self, request_id: &str, checkpoint: StreamCheckpoint) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        entries.retain(|_, entry| entry.saved_at.elapsed() < self.ttl);
+        entries.insert(
+            request_id.to_string(),
+            Entry {
+                checkpoint,
+                saved_at: Instant::now(),
+            },
+        );
+    }
+
+    pub fn get(&self, request_id: &str) -> Option<StreamCheckpoint> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .get(request_id)
+            .filter(|entry| entry.saved_at.elapsed() < self.ttl)
+            .map(|entry| entry.checkpoint.clone())
+    }
+
+    pub fn remove(&self, request_id: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.remove(request_id);
+        }
+    }
+}
+
+/// Accumulates a stream's text and saves it to a store every [`CHECKPOINT_INTERVAL`] chunks
+pub struct Checkpointer {
+    store: Arc<CheckpointStore>,
+    request_id: String,
+    checkpoint: StreamCheckpoint,
+    unsaved_chunks: usize,
+}
+
+impl Checkpointer {
+    /// Start checkpointing `options`' run, continuing from already produced `text`
+    pub fn new(store: Arc<CheckpointStore>, request_id: String, options: AIOptions, text: String) -> Self {
+        Self {
+            store,
+            request_id,
+            checkpoint: StreamCheckpoint { options, text },
+            unsaved_chunks: 0,
+        }
+    }
+
+    pub fn push(&mut self, chunk: &str) {
+        self.checkpoint.text.push_str(chunk);
+        self.unsaved_chunks += 1;
+        if self.unsaved_chunks >= CHECKPOINT_INTERVAL {
+            self.save();
+        }
+    }
+
+    /// Save the text produced so far, e.g. when the stream fails or is dropped
+    pub fn save(&mut self) {
+        self.unsaved_chunks = 0;
+        self.store.save(&self.request_id, self.checkpoint.clone());
+    }
+
+    /// The stream completed, so there is nothing left to resume
+    pub fn finish(self) {
+        self.store.remove(&self.request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(prompt: &str) -> AIOptions {
+        AIOptions {
+            prompt: prompt.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_checkpointer_saves_periodically() {
+        let store = Arc::new(CheckpointStore::default());
+        let mut checkpointer = Checkpointer::new(store.clone(), "run-1".to_string(), options("Count"), String::new());
+
+        for _ in 0..CHECKPOINT_INTERVAL - 1 {
+            checkpointer.push("a");
+        }
+        assert!(store.get("run-1").is_none());
+
+        checkpointer.push("a");
+        assert_eq!(store.get("run-1").unwrap().text.len(), CHECKPOINT_INTERVAL);
+
+        checkpointer.push("b");
+        checkpointer.save();
+        let checkpoint = store.get("run-1").unwrap();
+        assert!(checkpoint.text.ends_with("ab"));
+        assert_eq!(checkpoint.options.prompt, "Count");
+
+        checkpointer.finish();
+        assert!(store.get("run-1").is_none());
+    }
+
+    #[test]
+    fn test_expired_checkpoints_are_not_returned() {
+        let store = CheckpointStore::new(Duration::ZERO);
+        store.save(
+            "run-1",
+            StreamCheckpoint {
+                options: options("Count"),
+                text: "1 2 3".to_string(),
+            },
+        );
+        assert!(store.get("run-1").is_none());
+    }
+}
@@ -564,14 +564,21 @@ export class A2UIRenderer extends LitElement {
       }
     }
 
-    return html`<div class="list" style="${weight ? `flex: ${weight};` : ''}"></div>`
+    return html`
+      <div class="list" style="${weight ? `flex: ${weight};` : ''}">${this.renderChildren(props.children)}</div>
+    `
   }
 
   private renderCard(props: any, weight?: number): any {
-    const childComponent = this.components.get(props.child)
-    const child: any = childComponent ? this.renderComponent(childComponent) : nothing
+    let content: any = nothing
+    if (props.children) {
+      content = this.renderChildren(props.children)
+    } else {
+      const childComponent = this.components.get(props.child)
+      content = childComponent ? this.renderComponent(childComponent) : nothing
+    }
 
-    return html` <div class="card" style="${weight ? `flex: ${weight};` : ''}">${child}</div> `
+    return html` <div class="card" style="${weight ? `flex: ${weight};` : ''}">${content}</div> `
   }
 
   private renderText(props: any, weight?: number) {
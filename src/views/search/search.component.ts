@@ -1,4 +1,4 @@
-import { invoke } from "@tauri-apps/api/core";
+import { Channel, invoke } from "@tauri-apps/api/core";
 import { openPath } from "@tauri-apps/plugin-opener";
 import { css, html, LitElement } from "lit";
 import { customElement, state } from "lit/decorators.js";
@@ -18,7 +18,9 @@ interface FileMatch {
   path: string;
   line_number?: number;
   line_content?: string;
+  line_length?: number;
   match_type: string;
+  match_count?: number;
 }
 
 interface SearchResult {
@@ -87,6 +89,9 @@ export class ViewSearch extends LitElement {
   private prefetchCache: Map<string, SearchResult> = new Map();
   private aiInsightsDebounceTimer: number | null = null;
   private currentModal: HTMLElement | null = null;
+  // Id of the latest file search; starting a new one cancels the previous walk
+  private fileSearchCount = 0;
+  private currentFileSearchId: string | null = null;
 
   async connectedCallback() {
     super.connectedCallback();
@@ -1605,6 +1610,7 @@ export class ViewSearch extends LitElement {
     }
 
     this.loading = true;
+    let superseded = false;
 
     try {
       let applications: Application[] = [];
@@ -1621,14 +1627,32 @@ export class ViewSearch extends LitElement {
         applications = this._sortByFrecency(applications, actualQuery);
       }
 
-      // Backend search for files (still needs backend)
+      // Backend search for files, shown progressively as matches are found
       if (includeFiles) {
-        const fileResult = await invoke<FileMatch[]>("search_files", {
+        const searchId = `file-search-${++this.fileSearchCount}`;
+        this.currentFileSearchId = searchId;
+        const onMatch = new Channel<FileMatch>();
+        onMatch.onmessage = (file) => {
+          // Ignore matches of a search the user has since replaced
+          if (this.currentFileSearchId !== searchId) return;
+          files = [...files, file];
+          this.results = {
+            applications: this.searchMode === "files" ? [] : applications,
+            files,
+          };
+        };
+        await invoke<number>("search_files_stream", {
           query: actualQuery,
           searchPath: null,
           searchContent: false,
+          searchId,
+          onMatch,
         });
-        files = fileResult;
+        // A newer search cancelled this one and shows its own results
+        if (this.currentFileSearchId !== searchId) {
+          superseded = true;
+          return;
+        }
       }
 
       // Combine results
@@ -1660,7 +1684,7 @@ export class ViewSearch extends LitElement {
       this.aiInsights = "";
       this.showAiInsights = false;
     } finally {
-      this.loading = false;
+      if (!superseded) this.loading = false;
     }
   }
 
@@ -1755,10 +1779,14 @@ export class ViewSearch extends LitElement {
 
   private async _openApplication(app: Application) {
     try {
-      // Use Tauri opener plugin to launch the application
-      await openPath(app.path);
+      // Launched by the backend, which runs executables rather than opening them as files
+      await invoke("open_target", { target: { kind: "app", value: app.path } });
       console.log("Opened application:", app.name);
       this._addToRecentSearches(this.query);
+      // Feeds frecency ranking of app search results
+      invoke("record_launch", { path: app.path }).catch((error) =>
+        console.error("Failed to record application launch:", error),
+      );
     } catch (error) {
       console.error("Failed to open application:", error);
     }